@@ -0,0 +1,16 @@
+// Generate the gRPC service from proto/gateway.proto with a bundled protoc,
+// so building needs no protobuf tooling on the host. Builds without the "grpc"
+// feature have no service to generate.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/gateway.proto");
+    if std::env::var_os("CARGO_FEATURE_GRPC").is_none() {
+        return Ok(());
+    }
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/gateway.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use crate::cityindex::normalize;
+
+// Short forms of the instructions the alert feed sends with its alerts, which
+// otherwise take up to a third of a LoRa packet
+const BUILTIN_ABBREVIATIONS: [(&str, &str); 9] = [
+    ("היכנסו למרחב המוגן ושהו בו 10 דקות", "Shelter 10min"),
+    ("היכנסו למרחב המוגן ושהו בו עד להודעה חדשה", "Shelter until further notice"),
+    ("היכנסו מייד למרחב המוגן", "Shelter now"),
+    ("היכנסו למרחב המוגן", "Shelter"),
+    ("מרחב מוגן 10 דק", "Shelter 10min"),
+    ("היכנסו למבנה, נעלו את הדלתות וסגרו את החלונות", "Go indoors, lock doors, shut windows"),
+    ("צאו לשטח פתוח", "Go to open ground"),
+    ("התרחקו מחוף הים", "Leave the shore"),
+    ("סגרו את החלונות והדלתות", "Shut windows and doors"),
+];
+
+// Parse an `--abbreviation` value of the form FULL=SHORT; an empty SHORT keeps the text in full
+pub fn parse_abbreviation(value: &str) -> Result<(String, String), String> {
+    let (full, short) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected FULL=SHORT, got {}", value))?;
+    let full = normalize(full.trim());
+    if full.is_empty() {
+        return Err(format!("Nothing to abbreviate in {}", value));
+    }
+    Ok((full, short.trim().to_string()))
+}
+
+// Instruction texts and what to send instead, longest first so a full sentence
+// wins over the phrase it starts with
+#[derive(Debug, Default)]
+pub struct Abbreviations {
+    entries: Vec<(String, String)>,
+}
+
+impl Abbreviations {
+    pub fn new(builtin: bool, overrides: &[(String, String)]) -> Self {
+        let mut entries: Vec<(String, String)> = if builtin {
+            BUILTIN_ABBREVIATIONS
+                .iter()
+                .map(|(full, short)| (full.to_string(), short.to_string()))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        for (full, short) in overrides {
+            entries.retain(|(existing, _)| existing != full);
+            entries.push((full.clone(), short.clone()));
+        }
+        entries.retain(|(_, short)| !short.is_empty());
+        entries.sort_by_key(|(full, _)| std::cmp::Reverse(full.chars().count()));
+        Abbreviations { entries }
+    }
+
+    // The instructions with every known text replaced by its short form
+    pub fn apply(&self, instructions: &str) -> String {
+        if self.entries.is_empty() {
+            return instructions.to_string();
+        }
+        let mut text = normalize(instructions);
+        for (full, short) in &self.entries {
+            if text.contains(full.as_str()) {
+                text = text.replace(full.as_str(), short);
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_text_wins() {
+        let abbreviations = Abbreviations::new(true, &[]);
+        assert_eq!(abbreviations.apply("היכנסו למרחב המוגן ושהו בו 10 דקות"), "Shelter 10min");
+        assert_eq!(abbreviations.apply("היכנסו למרחב המוגן"), "Shelter");
+        assert_eq!(abbreviations.apply("something else"), "something else");
+    }
+
+    #[test]
+    fn overrides_replace_and_turn_off_builtins() {
+        let overrides = [
+            parse_abbreviation("היכנסו למרחב המוגן=Take shelter").unwrap(),
+            parse_abbreviation("צאו לשטח פתוח=").unwrap(),
+        ];
+        let abbreviations = Abbreviations::new(true, &overrides);
+        assert_eq!(abbreviations.apply("היכנסו  למרחב המוגן"), "Take shelter");
+        assert_eq!(abbreviations.apply("צאו לשטח פתוח"), "צאו לשטח פתוח");
+        assert!(parse_abbreviation("=short").is_err());
+        assert!(parse_abbreviation("no separator").is_err());
+    }
+
+    #[test]
+    fn nothing_changes_without_entries() {
+        assert_eq!(Abbreviations::new(false, &[]).apply("היכנסו למרחב המוגן"), "היכנסו למרחב המוגן");
+    }
+}
@@ -0,0 +1,131 @@
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+// How long a city stays active after it was last seen in the feed
+pub const ACTIVE_ALERT_TTL: Duration = Duration::from_secs(10 * 60);
+
+pub type SharedActiveAlerts = Arc<Mutex<ActiveAlerts>>;
+
+// Lock the shared state, still usable if a task panicked while holding it
+pub fn lock_active(active: &SharedActiveAlerts) -> MutexGuard<'_, ActiveAlerts> {
+    active.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// A city currently under alert
+#[derive(Debug, Clone)]
+pub struct ActiveCity {
+    pub name: String,
+    pub name_en: String,
+    pub zones: Vec<u32>,
+    pub alert_type: String,
+    // Only served as GeoJSON by the HTTP server
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub lat: f64,
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub lng: f64,
+    pub since: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+// Cities alerted recently, keyed by their Hebrew name
+#[derive(Debug, Default)]
+pub struct ActiveAlerts {
+    cities: HashMap<String, ActiveCity>,
+}
+
+impl ActiveAlerts {
+    pub fn new() -> Self {
+        ActiveAlerts::default()
+    }
+
+    // Mark a city as alerted now, keeping the original start time if it was already active
+    pub fn record(&mut self, city: ActiveCity) {
+        match self.cities.get_mut(&city.name) {
+            Some(existing) => {
+                existing.alert_type = city.alert_type;
+                existing.zones = city.zones;
+                existing.last_seen = city.last_seen;
+            }
+            None => {
+                self.cities.insert(city.name.clone(), city);
+            }
+        }
+    }
+
+    // Drop cities that have not been seen for longer than the TTL
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        let ttl = chrono::Duration::from_std(ACTIVE_ALERT_TTL).unwrap_or_default();
+        self.cities.retain(|_, city| now - city.last_seen <= ttl);
+    }
+
+    // Currently active cities, sorted by name
+    pub fn cities(&self) -> Vec<&ActiveCity> {
+        let mut cities: Vec<&ActiveCity> = self.cities.values().collect();
+        cities.sort_by(|a, b| a.name.cmp(&b.name));
+        cities
+    }
+
+    // Owned copy of the active cities, for use across await points
+    #[cfg_attr(
+        not(any(feature = "web", feature = "mqtt", feature = "grpc", all(target_os = "linux", feature = "dbus"))),
+        allow(dead_code)
+    )]
+    pub fn snapshot(&self) -> Vec<ActiveCity> {
+        self.cities().into_iter().cloned().collect()
+    }
+
+    // Active cities with their timers, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let cities: Vec<Value> = self
+            .cities()
+            .into_iter()
+            .map(|city| {
+                json!({
+                    "name": city.name,
+                    "name_en": city.name_en,
+                    "zones": city.zones,
+                    "alert_type": city.alert_type,
+                    "since": city.since.to_rfc3339(),
+                    "last_seen": city.last_seen.to_rfc3339(),
+                })
+            })
+            .collect();
+        Value::Array(cities)
+    }
+
+    // GeoJSON FeatureCollection with the centroid of every active city
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn to_geojson(&self) -> Value {
+        let features: Vec<Value> = self
+            .cities()
+            .into_iter()
+            // Entries without coordinates would all land on (0, 0)
+            .filter(|city| city.lat != 0.0 || city.lng != 0.0)
+            .map(|city| {
+                json!({
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Point",
+                        "coordinates": [city.lng, city.lat],
+                    },
+                    "properties": {
+                        "name": city.name,
+                        "name_en": city.name_en,
+                        "zones": city.zones,
+                        "alert_type": city.alert_type,
+                        "since": city.since.to_rfc3339(),
+                        "last_seen": city.last_seen.to_rfc3339(),
+                    },
+                })
+            })
+            .collect();
+
+        json!({
+            "type": "FeatureCollection",
+            "features": features,
+        })
+    }
+}
@@ -0,0 +1,171 @@
+use crate::meshmqtt::{format_node_id, parse_node_num};
+use crate::signing;
+use clap::Args;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// First word of every admin command, so other direct messages are left alone
+const ADMIN_PREFIX: &str = "admin";
+
+// Bounds of the feed poll interval an operator can set
+const MIN_POLL: u64 = 2;
+const MAX_POLL: u64 = 300;
+
+#[derive(Args, Debug)]
+pub struct AdminArgs {
+    /// Number of the command; must be higher than the last one the gateway accepted from the node
+    #[arg(long)]
+    pub counter: u64,
+
+    /// Command to sign: pause, resume, poll SECONDS, test or stats
+    #[arg(required = true, num_args = 1..)]
+    pub command: Vec<String>,
+}
+
+// What an operator can ask of the gateway over the mesh
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminCommand {
+    Pause,
+    Resume,
+    // Seconds between polls of the alert feed
+    Poll(u64),
+    // Broadcast a test message on the channel the command came in on
+    Test,
+    // Reply with uptime, state and load
+    Stats,
+}
+
+// Parse "pause", "resume", "poll 10", "test" or "stats"
+fn parse_command(words: &[&str]) -> Result<AdminCommand, String> {
+    match words {
+        ["pause"] => Ok(AdminCommand::Pause),
+        ["resume"] => Ok(AdminCommand::Resume),
+        ["test"] => Ok(AdminCommand::Test),
+        ["stats"] => Ok(AdminCommand::Stats),
+        ["poll", seconds] => {
+            let seconds: u64 = seconds
+                .parse()
+                .map_err(|_| format!("Invalid poll interval: {}", seconds))?;
+            if !(MIN_POLL..=MAX_POLL).contains(&seconds) {
+                return Err(format!("Poll interval must be {}-{} seconds", MIN_POLL, MAX_POLL));
+            }
+            Ok(AdminCommand::Poll(seconds))
+        }
+        _ => Err(format!("Unknown command: {}", words.join(" "))),
+    }
+}
+
+// Whether a direct message is meant as an admin command
+pub fn is_admin_text(text: &str) -> bool {
+    text.split_whitespace()
+        .next()
+        .is_some_and(|word| word.eq_ignore_ascii_case(ADMIN_PREFIX))
+}
+
+// Checks admin commands sent by direct message: "admin <command> [arg] <counter> ~<signature>",
+// signed with the admin key. The counter must grow with every command from a node, so a
+// command overheard on the mesh can't be replayed; the counters are saved, so not
+// even after a restart.
+#[derive(Debug)]
+pub struct AdminAuth {
+    key: String,
+    nodes: HashSet<u32>,
+    path: PathBuf,
+    // Counter of the last command accepted from each node
+    last_counter: BTreeMap<u32, u64>,
+}
+
+impl AdminAuth {
+    // Load the counters accepted so far from `path`, starting over if it doesn't exist yet
+    pub fn new(key: String, nodes: &[String], path: &Path) -> Result<Self, String> {
+        let nodes = nodes
+            .iter()
+            .map(|node| parse_node_num(node))
+            .collect::<Result<HashSet<u32>, String>>()?;
+        let last_counter = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("Failed to parse admin counters {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("Failed to read admin counters {}: {}", path.display(), e)),
+        };
+        Ok(AdminAuth {
+            key,
+            nodes,
+            path: path.to_path_buf(),
+            last_counter,
+        })
+    }
+
+    // Write to a temporary file first so a crash can't leave a truncated file behind
+    fn save(&self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let temp = self.path.with_extension("tmp");
+        let contents = serde_json::to_string(&self.last_counter).map_err(|e| e.to_string())?;
+        std::fs::write(&temp, contents).map_err(|e| e.to_string())?;
+        std::fs::rename(&temp, &self.path).map_err(|e| e.to_string())
+    }
+
+    // The command in a direct message, if it comes from an admin node, is signed and is not a replay
+    pub fn authorize(&mut self, from: u32, text: &str) -> Result<AdminCommand, String> {
+        if !self.nodes.contains(&from) {
+            return Err(format!("{} is not an admin node", format_node_id(from)));
+        }
+        let message = signing::verify(&self.key, text.trim())?;
+
+        let words: Vec<&str> = message.split_whitespace().collect();
+        let Some((counter, words)) = words.split_last() else {
+            return Err("Empty command".to_string());
+        };
+        let counter: u64 = counter
+            .parse()
+            .map_err(|_| format!("Invalid command counter: {}", counter))?;
+        let command = parse_command(words.get(1..).unwrap_or_default())?;
+
+        if self.last_counter.get(&from).is_some_and(|last| counter <= *last) {
+            return Err(format!("Command counter {} was already used", counter));
+        }
+        self.last_counter.insert(from, counter);
+        // A command whose counter can't be kept could be replayed after a restart
+        if let Err(e) = self.save() {
+            return Err(format!("Failed to save admin counters to {}: {}", self.path.display(), e));
+        }
+        Ok(command)
+    }
+}
+
+// Print the signed text of a command, to send to the gateway from an admin node
+pub fn run(args: &AdminArgs, key: Option<&str>) -> Result<(), String> {
+    let key = key.ok_or("Signing admin commands needs the admin secret (--admin-key)")?;
+    let words: Vec<String> = args.command.iter().map(|word| word.to_lowercase()).collect();
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    parse_command(&words)?;
+
+    let message = format!("{} {} {}", ADMIN_PREFIX, words.join(" "), args.counter);
+    println!("{}", signing::sign(key, &message));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("admin-counters-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let nodes = ["!0000abcd".to_string()];
+        let command = signing::sign("secret", "admin pause 7");
+
+        let mut auth = AdminAuth::new("secret".to_string(), &nodes, &path).unwrap();
+        assert_eq!(auth.authorize(0xabcd, &command), Ok(AdminCommand::Pause));
+        assert!(auth.authorize(0xabcd, &command).is_err());
+
+        let mut restarted = AdminAuth::new("secret".to_string(), &nodes, &path).unwrap();
+        assert!(restarted.authorize(0xabcd, &command).is_err());
+        let next = signing::sign("secret", "admin resume 8");
+        assert_eq!(restarted.authorize(0xabcd, &next), Ok(AdminCommand::Resume));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,71 +1,468 @@
-use std::error::Error;
+use crate::country::{AlertsFuture, CountryProfile, Language, Regions};
+use crate::error::RedAlertError;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Asia::Jerusalem;
 use reqwest::header::{HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use serde_json::{json, Value};
-use tokio::time::Duration;
 
 const CONFIG_API: &str = "https://www.oref.org.il/WarningMessages/alert/alerts.json";
 const CONFIG_HISTORY_API: &str = "https://www.oref.org.il/WarningMessages/alert/alertsHistory.json";
 
-// Alert type structure
-#[derive(Debug, Deserialize, Serialize)]
+// Archive of past alerts, queried by date range
+pub const ALARMS_HISTORY_API: &str = "https://alerts-history.oref.org.il/Shared/Ajax/GetAlarmsHistory.aspx";
+
+// Formats of alertDate in Israel local time: the history feed's, the archive's, and
+// the same with fractional seconds or without seconds, tried in order
+const HISTORY_DATE_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+];
+
+// How far back the history feed is read on each poll
+const HISTORY_WINDOW: chrono::Duration = chrono::Duration::seconds(120);
+
+// How often the alert archive is downloaded; it holds the whole day, so polls in
+// between reuse the last download. Well within the history window, so no event is missed.
+const ARCHIVE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+// Slack for the server's clock as seen through the Date header, which has whole
+// seconds and arrives up to a few seconds late on a slow link
+const CLOCK_SKEW_TOLERANCE: chrono::Duration = chrono::Duration::seconds(5);
+
+// Offsets beyond this are worth a warning
+const CLOCK_SKEW_WARNING: chrono::Duration = chrono::Duration::seconds(30);
+
+// How far the oref server's clock is ahead of ours, from the Date header of its last response
+static CLOCK_OFFSET: Mutex<Option<chrono::Duration>> = Mutex::new(None);
+
+// Format of the archive's fromDate and toDate parameters
+const ARCHIVE_QUERY_DATE_FORMAT: &str = "%d.%m.%Y";
+
+// One client for the process lifetime, so polls reuse the pooled connection instead of a new TLS handshake
+static HFC_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    let mut headers = HeaderMap::new();
+    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
+    headers.insert("Referer", HeaderValue::from_static("https://www.oref.org.il/11226-he/pakar.aspx"));
+    headers.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
+    headers.insert(
+        "User-Agent",
+        HeaderValue::from_static("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_13_6) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/75.0.3770.100 Safari/537.36"),
+    );
+
+    reqwest::Client::builder()
+        .default_headers(headers)
+        .pool_idle_timeout(Duration::from_secs(90))
+        .tcp_keepalive(Duration::from_secs(30))
+        .build()
+        .expect("Failed to build the HTTP client")
+});
+
+// Number of recent API responses kept for the state dump
+const RECENT_RESPONSES: usize = 5;
+
+// Longest response body kept for the state dump
+const MAX_RECORDED_BODY: usize = 2000;
+
+// The most recent API responses, newest last
+static RECENT: Mutex<VecDeque<Value>> = Mutex::new(VecDeque::new());
+
+// Wait used when a rate-limited response carries no usable Retry-After
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+// Longest Retry-After honored, so a bogus header can't silence the gateway
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+
+// How long a rate-limited response asks us to wait: Retry-After in seconds or as an
+// HTTP date, on a 429 or a 503 that carries one. None if the response isn't rate limited.
+pub fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let header = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim);
+    let status = response.status();
+    if status != reqwest::StatusCode::TOO_MANY_REQUESTS
+        && !(status == reqwest::StatusCode::SERVICE_UNAVAILABLE && header.is_some())
+    {
+        return None;
+    }
+
+    let wait = header.and_then(|value| match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => DateTime::parse_from_rfc2822(value)
+            .ok()
+            .map(|date| (date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default()),
+    });
+    Some(wait.unwrap_or(DEFAULT_RETRY_AFTER).clamp(Duration::from_secs(1), MAX_RETRY_AFTER))
+}
+
+// Track the server's clock from the Date header, so the history window follows
+// the feed's time rather than a local clock that may be off
+fn record_server_time(response: &reqwest::Response) {
+    let Some(server_time) = response
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value.trim()).ok())
+    else {
+        return;
+    };
+    let offset = server_time.with_timezone(&Utc) - Utc::now();
+    let mut last = CLOCK_OFFSET.lock().unwrap_or_else(PoisonError::into_inner);
+    let was_off = last.is_some_and(|last| last.abs() > CLOCK_SKEW_WARNING);
+    if offset.abs() > CLOCK_SKEW_WARNING && !was_off {
+        log::warn!("The local clock is {}s off the alert server's; reading the history feed by the server's time", -offset.num_seconds());
+    }
+    *last = Some(offset);
+}
+
+// The current time by the alert server's clock, or ours before it answered
+pub fn server_now() -> DateTime<Utc> {
+    let offset = *CLOCK_OFFSET.lock().unwrap_or_else(PoisonError::into_inner);
+    Utc::now() + offset.unwrap_or_default()
+}
+
+// Remember an API response (or failure) for the state dump
+pub fn record_response(url: &str, status: Option<u16>, body: &str) {
+    let mut cut = body.len().min(MAX_RECORDED_BODY);
+    while !body.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    let mut recent = RECENT.lock().unwrap_or_else(PoisonError::into_inner);
+    if recent.len() == RECENT_RESPONSES {
+        recent.pop_front();
+    }
+    recent.push_back(json!({
+        "time": Utc::now().to_rfc3339(),
+        "url": url,
+        "status": status,
+        "body": &body[..cut],
+    }));
+}
+
+// The most recent API responses, oldest first
+pub fn recent_responses() -> Vec<Value> {
+    RECENT.lock().unwrap_or_else(PoisonError::into_inner).iter().cloned().collect()
+}
+
+// An alert category code. The live feed sends it as a string ("1") and the
+// history feed as a number (1); either has been seen in both over time.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum CategoryCode {
+    Number(serde_json::Number),
+    Text(String),
+}
+
+impl CategoryCode {
+    // The code as the lookup tables expect it, e.g. "1" for 1, 1.0 and " 1"
+    fn code(&self) -> String {
+        match self {
+            CategoryCode::Number(number) => match (number.as_u64(), number.as_f64()) {
+                (Some(code), _) => code.to_string(),
+                (None, Some(code)) if code.fract() == 0.0 && code >= 0.0 => (code as u64).to_string(),
+                _ => number.to_string(),
+            },
+            CategoryCode::Text(text) => text.trim().to_string(),
+        }
+    }
+}
+
+// The cities of an alert: a list in the live feed and a single city in the
+// history feed. Entries that are not strings are left out.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum CityList {
+    List(Vec<Value>),
+    One(String),
+}
+
+impl CityList {
+    fn into_cities(self) -> Vec<String> {
+        match self {
+            CityList::List(cities) => cities
+                .into_iter()
+                .filter_map(|city| match city {
+                    Value::String(city) => Some(city),
+                    _ => None,
+                })
+                .collect(),
+            CityList::One(city) => vec![city],
+        }
+    }
+}
+
+// Versions of the shapes the feeds have sent alerts in, each parsed on its own
+// path. The first alert of each version is logged, so a change of shape shows up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum FeedVersion {
+    // Live: "cat" a string and "data" a list of city names
+    LiveV1,
+    // Live: "cat" or "category" a number, or "data" a single city, missing or with other entries
+    LiveV2,
+    // History: rows with "alertDate", "category" a number and "data" a single city
+    HistoryV1,
+    // History: rows with the category as a string or under "cat", or "data" a list
+    HistoryV2,
+}
+
+// Versions seen since the start
+static SEEN_VERSIONS: Mutex<BTreeSet<FeedVersion>> = Mutex::new(BTreeSet::new());
+
+impl FeedVersion {
+    fn of_live(json: &Value) -> Self {
+        let cities = json.get("data").and_then(Value::as_array);
+        if json.get("cat").is_some_and(Value::is_string) && cities.is_some_and(|cities| cities.iter().all(Value::is_string)) {
+            FeedVersion::LiveV1
+        } else {
+            FeedVersion::LiveV2
+        }
+    }
+
+    fn of_history(json: &Value) -> Self {
+        let v1 = |row: &Value| {
+            row.get("alertDate").is_some_and(Value::is_string)
+                && row.get("category").is_some_and(Value::is_u64)
+                && row.get("data").is_some_and(Value::is_string)
+        };
+        match json.as_array() {
+            Some(rows) if rows.iter().all(v1) => FeedVersion::HistoryV1,
+            _ => FeedVersion::HistoryV2,
+        }
+    }
+
+    fn note(self) {
+        if SEEN_VERSIONS.lock().unwrap_or_else(PoisonError::into_inner).insert(self) {
+            log::info!("Parsing {:?} alerts", self);
+        }
+    }
+}
+
+// Live alert, version 1
+#[derive(Debug, Deserialize)]
+struct AlertV1 {
+    cat: String,
+    data: Vec<String>,
+    #[serde(default)]
+    desc: Option<String>,
+}
+
+impl From<AlertV1> for Alert {
+    fn from(alert: AlertV1) -> Self {
+        Alert {
+            cities: Some(CityList::List(alert.data.into_iter().map(Value::String).collect())),
+            category: Some(CategoryCode::Text(alert.cat)),
+            instructions: alert.desc,
+        }
+    }
+}
+
+// Live alert, version 2 and anything else, as tolerantly as possible. Fields the
+// feed adds later (id, title, ...) are ignored.
+#[derive(Debug, Deserialize)]
 struct Alert {
-    #[serde(rename = "data")]
-    cities: Option<Vec<String>>,
-    #[serde(rename = "cat")]
-    category: Option<String>,
-    #[serde(rename = "desc")]
+    #[serde(rename = "data", default)]
+    cities: Option<CityList>,
+    #[serde(rename = "cat", alias = "category", default)]
+    category: Option<CategoryCode>,
+    #[serde(rename = "desc", default)]
     instructions: Option<String>,
 }
 
-// History alert structure
+// History row, version 1
+#[derive(Debug, Deserialize)]
+struct HistoryAlertV1 {
+    #[serde(rename = "alertDate")]
+    alert_date: String,
+    data: String,
+    category: u64,
+}
+
+impl From<HistoryAlertV1> for HistoryAlert {
+    fn from(row: HistoryAlertV1) -> Self {
+        HistoryAlert {
+            alert_date: Some(row.alert_date),
+            data: Some(CityList::One(row.data)),
+            category: Some(CategoryCode::Number(row.category.into())),
+            rid: None,
+            city_id: None,
+            area_id: None,
+            area_name: None,
+        }
+    }
+}
+
+// History row, version 2 and archive rows
 #[derive(Debug, Deserialize)]
 struct HistoryAlert {
-    alertDate: Option<String>,
-    data: Option<String>,
-    category: Option<String>,
+    #[serde(rename = "alertDate")]
+    alert_date: Option<String>,
+    #[serde(default)]
+    data: Option<CityList>,
+    #[serde(alias = "cat", default)]
+    category: Option<CategoryCode>,
+    // Row ID in the archive
+    #[serde(default)]
+    rid: Option<Value>,
+    // Locality ID and district of the row, in the district-level archive rows
+    #[serde(rename = "cityId", alias = "city_id", default)]
+    city_id: Option<Value>,
+    #[serde(rename = "areaid", alias = "areaId", alias = "area_id", default)]
+    area_id: Option<Value>,
+    #[serde(rename = "areaname", alias = "areaName", alias = "area_name", default)]
+    area_name: Option<String>,
+}
+
+// An ID the feeds give as a number (511) or a string ("511")
+fn feed_id(value: Option<&Value>) -> Option<u32> {
+    match value? {
+        Value::Number(number) => number.as_u64().and_then(|id| u32::try_from(id).ok()),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+impl HistoryAlert {
+    // Where the row places its cities, if it says
+    fn area(&self) -> Option<CityArea> {
+        let area = CityArea {
+            city_id: feed_id(self.city_id.as_ref()),
+            district_id: feed_id(self.area_id.as_ref()),
+            district: self
+                .area_name
+                .as_deref()
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string),
+        };
+        (area != CityArea::default()).then_some(area)
+    }
+}
+
+// Where the feed places a city: its locality ID, as in the city data, and its
+// district, so a city missing from the city data can still be routed and stored
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CityArea {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub city_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub district_id: Option<u32>,
+    // District name in the language of the feed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub district: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AlertResult {
     pub alert_type: String,
+    #[serde(default)]
     pub cities: Vec<String>,
+    #[serde(default)]
     pub instructions: Option<String>,
+    // Zones targeted directly, in addition to those resolved from the cities
+    #[serde(default)]
+    pub zones: Vec<u32>,
+    // Official time of the event, known for alerts from the history feed
+    #[serde(default)]
+    pub alert_date: Option<DateTime<Utc>>,
+    // Locality and district of cities, for rows of the archive that give them
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub areas: BTreeMap<String, CityArea>,
 }
 
-// Main async function to fetch and extract the alert
-pub async fn fetch_alert(alert_history: bool) -> Result<AlertResult, Box<dyn std::error::Error>> {
+// Main async function to fetch and extract the alerts; the history feed can hold several events
+pub async fn fetch_alerts(alert_history: bool) -> Result<Vec<AlertResult>, RedAlertError> {
     let json = get_hfc_alerts_json(alert_history).await?;
-    let alert = extract_alert_from_json(json).await?;
-    Ok(alert)
+    let alerts = extract_alerts_from_json(json).await?;
+    Ok(alerts)
+}
+
+// Israel: alerts from the Home Front Command (oref) live or history feed, naming
+// cities of the city data, which are given in English in messages
+pub struct Oref {
+    history: bool,
+    // Read the history from the alert archive, whose rows can name districts
+    archive: bool,
+    // The last download of the archive and when it was made
+    archive_cache: Option<(Instant, Vec<AlertResult>)>,
+}
+
+impl Oref {
+    pub fn new(history: bool, archive: bool) -> Self {
+        Oref {
+            history,
+            archive,
+            archive_cache: None,
+        }
+    }
+}
+
+// The recent events of the alert archive, downloaded again only every ARCHIVE_POLL_INTERVAL
+async fn fetch_cached_archive(oref: &mut Oref) -> Result<Vec<AlertResult>, RedAlertError> {
+    if let Some((fetched, alerts)) = &oref.archive_cache {
+        if fetched.elapsed() < ARCHIVE_POLL_INTERVAL {
+            return Ok(alerts.clone());
+        }
+    }
+    let alerts = fetch_recent_archive().await?;
+    oref.archive_cache = Some((Instant::now(), alerts.clone()));
+    Ok(alerts)
+}
+
+impl CountryProfile for Oref {
+    fn source(&self) -> &'static str {
+        if self.history {
+            "oref_history"
+        } else {
+            "oref"
+        }
+    }
+
+    fn fetch_alerts(&mut self) -> AlertsFuture<'_> {
+        if self.history && self.archive {
+            return Box::pin(fetch_cached_archive(self));
+        }
+        Box::pin(fetch_alerts(self.history))
+    }
+
+    fn regions(&self) -> Regions {
+        Regions::Cities
+    }
+
+    fn language(&self) -> Language {
+        Language::English
+    }
 }
 
 // Async function to perform the HTTP request to HFC API
-async fn get_hfc_alerts_json(alert_history: bool) -> Result<Value, Box<dyn Error>> {
+async fn get_hfc_alerts_json(alert_history: bool) -> Result<Value, RedAlertError> {
     let api_url = if alert_history { CONFIG_HISTORY_API } else { CONFIG_API };
 
     let unix_timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .unwrap()
+        .unwrap_or_default()
         .as_secs();
 
     let url = format!("{}?{}", api_url, unix_timestamp);
-    let mut headers = HeaderMap::new();
-    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
-    headers.insert("Referer", HeaderValue::from_static("https://www.oref.org.il/11226-he/pakar.aspx"));
-    headers.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
-    headers.insert(
-        "User-Agent",
-        HeaderValue::from_static("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_13_6) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/75.0.3770.100 Safari/537.36"),
-    );
+    let response = HFC_CLIENT.get(&url).send().await;
 
-    let client = reqwest::Client::new();
-    let response = client.get(&url).headers(headers).send().await;
+    if let Ok(res) = &response {
+        record_server_time(res);
+    }
 
     match response {
         Ok(res) if res.status() == reqwest::StatusCode::OK => {
-            let body = res.text().await?;
+            let body = res
+                .text()
+                .await
+                .map_err(|e| RedAlertError::ApiUnreachable(format!("Failed to read the response body: {}", e)))?;
+            record_response(api_url, Some(200), &body);
 
             if body.trim().is_empty() {
                 return Ok(json!({
@@ -75,7 +472,7 @@ async fn get_hfc_alerts_json(alert_history: bool) -> Result<Value, Box<dyn Error
             }
 
             let json: Value = serde_json::from_str(&body).map_err(|e| {
-                format!("Failed to parse the response body as JSON: {}. Body was: {}", e, body)
+                RedAlertError::ParseError(format!("Failed to parse the response body as JSON: {}. Body was: {}", e, body))
             })?;
 
             if json.get("data").is_none() {
@@ -88,42 +485,63 @@ async fn get_hfc_alerts_json(alert_history: bool) -> Result<Value, Box<dyn Error
             Ok(json)
         }
         Ok(res) => {
-            log::error!("Failed to retrieve alerts from HFC API: {} {}", res.status().as_u16(), res.status().canonical_reason().unwrap_or("Unknown"));
-            // Return a default JSON object indicating failure
-            Ok(json!({
-                "type": "none",
-                "cities": []
-            }))
+            record_response(api_url, Some(res.status().as_u16()), "");
+            if let Some(retry_after) = retry_after(&res) {
+                return Err(RedAlertError::RateLimited { retry_after });
+            }
+            Err(RedAlertError::ApiUnreachable(format!(
+                "Failed to retrieve alerts from HFC API: {} {}",
+                res.status().as_u16(),
+                res.status().canonical_reason().unwrap_or("Unknown")
+            )))
         }
         Err(e) => {
-            log::error!("Error making request to HFC API: {}", e);
-            // Return a default JSON object indicating failure
-            Ok(json!({
-                "type": "none",
-                "cities": []
-            }))
+            record_response(api_url, None, &e.to_string());
+            Err(RedAlertError::ApiUnreachable(format!("Error making request to HFC API: {}", e)))
         }
     }
 }
 
 
+// Parse alert JSON from an external source: either the crate's normalized
+// AlertResult shape or a raw oref live/history payload
+pub async fn parse_alert_json(json: Value) -> Result<Vec<AlertResult>, RedAlertError> {
+    if json.get("alert_type").is_some() {
+        return Ok(vec![serde_json::from_value(json)?]);
+    }
+    extract_alerts_from_json(json).await
+}
+
 // Async function to extract the alert data from the JSON
-async fn extract_alert_from_json(json: serde_json::Value) -> Result<AlertResult, Box<dyn std::error::Error>> {
+async fn extract_alerts_from_json(json: serde_json::Value) -> Result<Vec<AlertResult>, RedAlertError> {
     // Check if it is an array (History JSON)
     if json.is_array() {
-        return extract_alert_from_history_json(json).await;
+        return extract_alerts_from_history_json(json).await;
     }
 
-    let alert_data: Alert = serde_json::from_value(json)?;
+    Ok(vec![parse_live_alert(json)?])
+}
+
+// One alert of the live feed
+fn parse_live_alert(json: Value) -> Result<AlertResult, RedAlertError> {
+    let version = FeedVersion::of_live(&json);
+    version.note();
+    let alert_data: Alert = match version {
+        FeedVersion::LiveV1 => serde_json::from_value::<AlertV1>(json)?.into(),
+        _ => serde_json::from_value(json)?,
+    };
 
     let mut alert = AlertResult {
         alert_type: "none".to_string(),
         cities: vec![],
         instructions: alert_data.instructions,
+        zones: vec![],
+        alert_date: None,
+        areas: BTreeMap::new(),
     };
 
     if let Some(cities) = alert_data.cities {
-        for mut city in cities {
+        for mut city in cities.into_cities() {
             city = city.trim().to_string();
             // Skip "test" alerts (Hebrew check)
             if city.contains("בדיקה") {
@@ -136,46 +554,194 @@ async fn extract_alert_from_json(json: serde_json::Value) -> Result<AlertResult,
     }
 
     if let Some(category) = alert_data.category {
-        alert.alert_type = get_alert_type_by_category(&category);
+        alert.alert_type = get_alert_type_by_category(&category.code());
+        if alert.alert_type == "unknown" {
+            log::warn!("Unknown alert category {:?} in the live feed", category);
+        }
     }
 
     Ok(alert)
 }
 
-// Extract alert from history JSON
-async fn extract_alert_from_history_json(json: serde_json::Value) -> Result<AlertResult, Box<dyn std::error::Error>> {
-    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-    let mut alert = AlertResult {
-        alert_type: "none".to_string(),
-        cities: vec![],
-        instructions: None,
+// Parse a history or archive alertDate, given in Israel local time unless it
+// carries an offset of its own
+pub fn parse_history_date(alert_date: &str) -> Result<DateTime<Utc>, RedAlertError> {
+    let alert_date = alert_date.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(alert_date) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    let naive = HISTORY_DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(alert_date, format).ok())
+        .ok_or_else(|| RedAlertError::ParseError(format!("Invalid alertDate {}", alert_date)))?;
+    Jerusalem
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|date| date.with_timezone(&Utc))
+        .ok_or_else(|| RedAlertError::ParseError(format!("alertDate {} does not exist in Israel time", alert_date)))
+}
+
+// Extract the recent alerts from history JSON, one per distinct event (alertDate and category)
+async fn extract_alerts_from_history_json(json: serde_json::Value) -> Result<Vec<AlertResult>, RedAlertError> {
+    let version = FeedVersion::of_history(&json);
+    version.note();
+    let history: Vec<HistoryAlert> = match version {
+        FeedVersion::HistoryV1 => serde_json::from_value::<Vec<HistoryAlertV1>>(json)?
+            .into_iter()
+            .map(HistoryAlert::from)
+            .collect(),
+        _ => serde_json::from_value(json)?,
     };
+    group_history(history, Some(history_since(server_now())))
+}
 
-    let history: Vec<HistoryAlert> = serde_json::from_value(json)?;
+// Oldest event time a poll at `now` reads from the history feed
+fn history_since(now: DateTime<Utc>) -> DateTime<Utc> {
+    now - HISTORY_WINDOW - CLOCK_SKEW_TOLERANCE
+}
 
-    for item in history {
-        if let (Some(alert_date), Some(city), Some(category)) = (item.alertDate, item.data, item.category) {
-            let alert_time = (chrono::DateTime::parse_from_rfc3339(&alert_date)?.timestamp() as u64) / 1000;
+// Group history rows into one alert per distinct event (alertDate and category),
+// leaving out events before `since`
+fn group_history(history: Vec<HistoryAlert>, since: Option<DateTime<Utc>>) -> Result<Vec<AlertResult>, RedAlertError> {
+    let mut alerts: Vec<AlertResult> = Vec::new();
 
-            if now - alert_time > 120 {
+    for item in history {
+        let area = item.area();
+        if let (Some(alert_date), Some(cities), Some(category)) = (item.alert_date, item.data, item.category) {
+            // One malformed row shouldn't hide the rest of the feed
+            let alert_date = match parse_history_date(&alert_date) {
+                Ok(alert_date) => alert_date,
+                Err(e) => {
+                    log::warn!("Skipping a history row: {}", e);
+                    continue;
+                }
+            };
+
+            if since.is_some_and(|since| alert_date < since) {
                 continue;
             }
 
-            let trimmed_city = city.trim().to_string();
-
-            if trimmed_city.contains("בדיקה") {
+            // Skip "test" alerts (Hebrew check)
+            let cities: Vec<String> = cities
+                .into_cities()
+                .into_iter()
+                .map(|city| city.trim().to_string())
+                .filter(|city| !city.contains("בדיקה"))
+                .collect();
+            if cities.is_empty() {
                 continue;
             }
 
-            if !alert.cities.contains(&trimmed_city) {
-                alert.cities.push(trimmed_city);
+            let alert_type = get_alert_type_by_historical_category(&category.code());
+            if alert_type == "unknown" {
+                log::warn!("Unknown alert category {:?} in the history feed", category);
             }
 
-            alert.alert_type = get_alert_type_by_historical_category(&category);
+            let index = match alerts
+                .iter()
+                .position(|alert| alert.alert_date == Some(alert_date) && alert.alert_type == alert_type)
+            {
+                Some(index) => index,
+                None => {
+                    alerts.push(AlertResult {
+                        alert_type,
+                        cities: vec![],
+                        instructions: None,
+                        zones: vec![],
+                        alert_date: Some(alert_date),
+                        areas: BTreeMap::new(),
+                    });
+                    alerts.len() - 1
+                }
+            };
+
+            for city in cities {
+                if let Some(area) = &area {
+                    alerts[index].areas.insert(city.clone(), area.clone());
+                }
+                if !alerts[index].cities.contains(&city) {
+                    alerts[index].cities.push(city);
+                }
+            }
         }
     }
 
-    Ok(alert)
+    // Oldest event first, so they go out in the order they happened
+    alerts.sort_by_key(|alert| alert.alert_date);
+    Ok(alerts)
+}
+
+// Every alert of the archive between two dates (inclusive, Israel time), one per event, oldest first
+pub async fn fetch_archive(archive_url: &str, from: NaiveDate, to: NaiveDate) -> Result<Vec<AlertResult>, RedAlertError> {
+    group_history(fetch_archive_rows(archive_url, from, to).await?, None)
+}
+
+// The recent events of the alert archive, read by the days of the server's clock
+// in Israel time, so just after midnight yesterday is asked for as well
+async fn fetch_recent_archive() -> Result<Vec<AlertResult>, RedAlertError> {
+    let now = server_now();
+    let since = history_since(now);
+    let from = since.with_timezone(&Jerusalem).date_naive();
+    let to = now.with_timezone(&Jerusalem).date_naive();
+    group_history(fetch_archive_rows(ALARMS_HISTORY_API, from, to).await?, Some(since))
+}
+
+// The event of the archive row with the given ID, with every city alerted at the same time
+pub async fn fetch_archive_event(
+    archive_url: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+    rid: &str,
+) -> Result<Option<AlertResult>, RedAlertError> {
+    let rows = fetch_archive_rows(archive_url, from, to).await?;
+    let id = |row: &HistoryAlert| match &row.rid {
+        Some(Value::String(rid)) => Some(rid.trim().to_string()),
+        Some(rid) => Some(rid.to_string()),
+        None => None,
+    };
+    let Some(row) = rows.iter().find(|row| id(row).as_deref() == Some(rid.trim())) else {
+        return Ok(None);
+    };
+    let event = (row.alert_date.clone(), row.category.clone());
+    let rows = rows
+        .into_iter()
+        .filter(|row| (row.alert_date.clone(), row.category.clone()) == event)
+        .collect();
+    Ok(group_history(rows, None)?.into_iter().next())
+}
+
+async fn fetch_archive_rows(archive_url: &str, from: NaiveDate, to: NaiveDate) -> Result<Vec<HistoryAlert>, RedAlertError> {
+    let url = format!(
+        "{}?lang=he&fromDate={}&toDate={}&mode=0",
+        archive_url,
+        from.format(ARCHIVE_QUERY_DATE_FORMAT),
+        to.format(ARCHIVE_QUERY_DATE_FORMAT)
+    );
+    let response = HFC_CLIENT
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| RedAlertError::ApiUnreachable(format!("Error making request to the alert archive: {}", e)))?;
+    record_server_time(&response);
+    if let Some(retry_after) = retry_after(&response) {
+        return Err(RedAlertError::RateLimited { retry_after });
+    }
+    if !response.status().is_success() {
+        return Err(RedAlertError::ApiUnreachable(format!(
+            "Failed to retrieve alerts from the alert archive: {}",
+            response.status()
+        )));
+    }
+
+    let body = response
+        .text()
+        .await
+        .map_err(|e| RedAlertError::ApiUnreachable(format!("Failed to read the response body: {}", e)))?;
+    if body.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    serde_json::from_str(&body)
+        .map_err(|e| RedAlertError::ParseError(format!("Failed to parse the alert archive response: {}", e)))
 }
 
 // Function to get alert type by category
@@ -217,4 +783,170 @@ fn get_alert_type_by_historical_category(category: &str) -> String {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn live(json: Value) -> AlertResult {
+        parse_live_alert(json).expect("alert should parse")
+    }
+
+    fn history(json: Value) -> Vec<AlertResult> {
+        let rows: Vec<HistoryAlert> = serde_json::from_value(json).expect("history should parse");
+        group_history(rows, None).expect("history should group")
+    }
+
+    #[test]
+    fn feed_versions() {
+        assert_eq!(FeedVersion::of_live(&json!({ "cat": "1", "data": ["Sderot"] })), FeedVersion::LiveV1);
+        assert_eq!(FeedVersion::of_live(&json!({ "cat": 1, "data": ["Sderot"] })), FeedVersion::LiveV2);
+        assert_eq!(FeedVersion::of_live(&json!({ "cat": "1", "data": "Sderot" })), FeedVersion::LiveV2);
+        let v1 = json!([{ "alertDate": "2024-04-14 01:02:03", "data": "Sderot", "category": 1 }]);
+        let v2 = json!([{ "alertDate": "2024-04-14 01:02:03", "data": ["Sderot"], "category": "1" }]);
+        assert_eq!(FeedVersion::of_history(&v1), FeedVersion::HistoryV1);
+        assert_eq!(FeedVersion::of_history(&v2), FeedVersion::HistoryV2);
+    }
+
+    #[test]
+    fn live_versions_parse_alike() {
+        let v1 = live(json!({ "id": "1", "cat": "1", "data": ["Sderot", "Nir Am"], "desc": "היכנסו למרחב המוגן" }));
+        let v2 = live(json!({ "category": 1, "data": ["Sderot", "Nir Am"], "desc": "היכנסו למרחב המוגן" }));
+        assert_eq!(v1.alert_type, v2.alert_type);
+        assert_eq!(v1.cities, v2.cities);
+        assert_eq!(v1.instructions, v2.instructions);
+    }
+
+    #[tokio::test]
+    async fn history_versions_parse_alike() {
+        let now = server_now().with_timezone(&Jerusalem).format("%Y-%m-%d %H:%M:%S").to_string();
+        let v1 = extract_alerts_from_history_json(json!([{ "alertDate": now, "data": "Sderot", "category": 1 }]))
+            .await
+            .unwrap();
+        let v2 = extract_alerts_from_history_json(json!([{ "alertDate": now, "data": ["Sderot"], "cat": "1" }]))
+            .await
+            .unwrap();
+        assert_eq!(v1[0].alert_type, "missiles");
+        assert_eq!((&v1[0].alert_type, &v1[0].cities, v1[0].alert_date), (&v2[0].alert_type, &v2[0].cities, v2[0].alert_date));
+    }
+
+    #[test]
+    fn live_category_as_string_or_number() {
+        let as_string = live(json!({ "cat": "1", "data": ["Sderot"] }));
+        let as_number = live(json!({ "cat": 1, "data": ["Sderot"] }));
+        let as_float = live(json!({ "cat": 6.0, "data": ["Sderot"] }));
+        assert_eq!(as_string.alert_type, "missiles");
+        assert_eq!(as_number.alert_type, "missiles");
+        assert_eq!(as_float.alert_type, "hostileAircraftIntrusion");
+    }
+
+    #[test]
+    fn live_cities_absent_string_or_list() {
+        assert!(live(json!({ "cat": "1" })).cities.is_empty());
+        assert!(live(json!({ "cat": "1", "data": null })).cities.is_empty());
+        assert_eq!(live(json!({ "cat": "1", "data": " Sderot " })).cities, vec!["Sderot"]);
+        assert_eq!(
+            live(json!({ "cat": "1", "data": ["Sderot", 7, "Sderot", "Nir Am"] })).cities,
+            vec!["Sderot", "Nir Am"]
+        );
+    }
+
+    #[test]
+    fn live_ignores_unknown_fields() {
+        let alert = live(json!({
+            "id": "133713371337",
+            "cat": "1",
+            "title": "ירי רקטות וטילים",
+            "data": ["Sderot"],
+            "desc": "היכנסו למרחב המוגן",
+            "severity": { "level": 3 }
+        }));
+        assert_eq!(alert.alert_type, "missiles");
+        assert_eq!(alert.instructions.as_deref(), Some("היכנסו למרחב המוגן"));
+    }
+
+    #[test]
+    fn live_unknown_or_missing_category() {
+        assert_eq!(live(json!({ "cat": "999", "data": ["Sderot"] })).alert_type, "unknown");
+        assert_eq!(live(json!({ "data": ["Sderot"] })).alert_type, "none");
+    }
+
+    #[test]
+    fn history_category_and_data_shapes() {
+        let alerts = history(json!([
+            { "alertDate": "2024-04-14 01:02:03", "title": "x", "data": "Sderot", "category": 1 },
+            { "alertDate": "2024-04-14 01:02:03", "data": ["Nir Am", "בדיקה"], "category": "1" },
+            { "alertDate": "2024-04-14 01:02:03", "data": "בדיקה", "category": 1 },
+            { "alertDate": "2024-04-14 01:05:00", "data": "Eilat", "cat": 2 },
+            { "alertDate": "2024-04-14 01:06:00", "category": 1 }
+        ]));
+        assert_eq!(alerts.len(), 2);
+        assert_eq!(alerts[0].alert_type, "missiles");
+        assert_eq!(alerts[0].cities, vec!["Sderot", "Nir Am"]);
+        assert_eq!(alerts[1].alert_type, "hostileAircraftIntrusion");
+        assert_eq!(alerts[1].cities, vec!["Eilat"]);
+    }
 
+    #[test]
+    fn history_dates_in_israel_time_with_fallbacks() {
+        let expected = Utc.with_ymd_and_hms(2024, 4, 13, 22, 2, 3).unwrap();
+        assert_eq!(parse_history_date("2024-04-14 01:02:03").unwrap(), expected);
+        assert_eq!(parse_history_date(" 2024-04-14T01:02:03 ").unwrap(), expected);
+        assert_eq!(parse_history_date("2024-04-14 01:02:03.250").unwrap(), expected + chrono::Duration::milliseconds(250));
+        assert_eq!(parse_history_date("2024-04-14T01:02:03+03:00").unwrap(), expected);
+        assert_eq!(parse_history_date("2024-04-14 01:02").unwrap(), expected - chrono::Duration::seconds(3));
+        // Winter time is two hours ahead of UTC
+        assert_eq!(
+            parse_history_date("2024-01-14 01:02:03").unwrap(),
+            Utc.with_ymd_and_hms(2024, 1, 13, 23, 2, 3).unwrap()
+        );
+        assert!(parse_history_date("14/04/2024 01:02").is_err());
+    }
+
+    #[test]
+    fn history_window_edge_allows_for_clock_skew() {
+        let now = Utc.with_ymd_and_hms(2024, 4, 13, 22, 2, 3).unwrap();
+        let since = history_since(now);
+        assert_eq!(since, now - chrono::Duration::seconds(125));
+        let row = |date: DateTime<Utc>, city: &str| {
+            let date = date.with_timezone(&Jerusalem).format("%Y-%m-%d %H:%M:%S").to_string();
+            json!({ "alertDate": date, "data": city, "category": 1 })
+        };
+        // A server clock up to the tolerance behind ours still gets its events read
+        let rows: Vec<HistoryAlert> = serde_json::from_value(json!([
+            row(since - chrono::Duration::seconds(1), "Sderot"),
+            row(since, "Nir Am"),
+            row(now - HISTORY_WINDOW - chrono::Duration::seconds(4), "Netivot"),
+        ]))
+        .unwrap();
+        let alerts = group_history(rows, Some(since)).unwrap();
+        let cities: Vec<&str> = alerts.iter().flat_map(|alert| alert.cities.iter().map(String::as_str)).collect();
+        assert_eq!(cities, vec!["Nir Am", "Netivot"]);
+    }
+
+    #[test]
+    fn history_skips_rows_with_bad_dates() {
+        let alerts = history(json!([
+            { "alertDate": "not a date", "data": "Sderot", "category": 1 },
+            { "alertDate": "2024-04-14 01:02:03", "data": "Nir Am", "category": 1 }
+        ]));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].cities, vec!["Nir Am"]);
+    }
+
+    #[test]
+    fn archive_rows_with_districts() {
+        let alerts = history(json!([
+            { "alertDate": "2024-04-14T01:02:03", "data": "נחל עוז", "category": 1, "cityId": "511", "areaid": 2, "areaname": " עוטף עזה " },
+            { "alertDate": "2024-04-14T01:02:03", "data": "שדרות", "category": 1, "area_id": "x", "area_name": "" },
+            { "alertDate": "2024-04-14T01:02:03", "data": "כפר עזה", "category": 1, "areaId": 2 }
+        ]));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].cities, vec!["נחל עוז", "שדרות", "כפר עזה"]);
+        assert_eq!(
+            alerts[0].areas.get("נחל עוז"),
+            Some(&CityArea { city_id: Some(511), district_id: Some(2), district: Some("עוטף עזה".to_string()) })
+        );
+        assert_eq!(alerts[0].areas.get("שדרות"), None);
+        assert_eq!(alerts[0].areas.get("כפר עזה").and_then(|area| area.district_id), Some(2));
+    }
+}
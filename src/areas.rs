@@ -0,0 +1,154 @@
+use crate::channels::{ChannelNames, ChannelRef};
+use crate::City;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+// One entry of an area map file: a group of alert areas sent on one channel
+#[derive(Debug, Deserialize)]
+struct AreaGroupConfig {
+    #[serde(default)]
+    name: Option<String>,
+    // Channel index or name on the radio
+    channel: ChannelRef,
+    // Areas by Hebrew name, English name or "id:<n>"
+    #[serde(default)]
+    areas: Vec<String>,
+    // Whole districts (zone_en values) to include
+    #[serde(default)]
+    districts: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AreaMapConfig {
+    #[serde(rename = "group")]
+    groups: Vec<AreaGroupConfig>,
+}
+
+#[derive(Debug)]
+struct AreaGroup {
+    name: String,
+    channel: u32,
+    // Hebrew names of the areas in the group
+    areas: HashSet<String>,
+}
+
+// Routes alerts by official alert area instead of the 7 coarse zones
+#[derive(Debug)]
+pub struct AreaMap {
+    groups: Vec<AreaGroup>,
+}
+
+// Find the area a reference in the map file points to
+fn resolve_area<'a>(cities: &'a [City], reference: &str) -> Option<&'a City> {
+    if let Some(id) = reference.strip_prefix("id:") {
+        let id: u32 = id.trim().parse().ok()?;
+        return cities.iter().find(|city| city.id == id);
+    }
+    cities
+        .iter()
+        .find(|city| city.name == reference)
+        .or_else(|| cities.iter().find(|city| city.name_en.eq_ignore_ascii_case(reference)))
+}
+
+fn read_config(path: &str) -> Result<AreaMapConfig, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read area map {}: {}", path, e))?;
+    toml::from_str(&contents).map_err(|e| format!("Failed to parse area map {}: {}", path, e))
+}
+
+impl AreaMap {
+    // Load a TOML area map, resolving every reference against the city data
+    pub fn load(path: &str, cities: &[City], names: &mut ChannelNames) -> Result<Self, String> {
+        let config = read_config(path)?;
+
+        let mut groups = Vec::new();
+        for (index, group) in config.groups.into_iter().enumerate() {
+            let name = group.name.unwrap_or_else(|| format!("group {}", index + 1));
+            let channel = names.resolve(&group.channel).map_err(|e| format!("{} in {} ({})", e, path, name))?;
+            let mut areas = HashSet::new();
+
+            for reference in &group.areas {
+                let city = resolve_area(cities, reference)
+                    .ok_or_else(|| format!("Unknown alert area \"{}\" in {} ({})", reference, path, name))?;
+                areas.insert(city.name.clone());
+            }
+            for district in &group.districts {
+                let before = areas.len();
+                areas.extend(
+                    cities
+                        .iter()
+                        .filter(|city| city.zone_en.eq_ignore_ascii_case(district))
+                        .map(|city| city.name.clone()),
+                );
+                if areas.len() == before {
+                    return Err(format!("District \"{}\" in {} ({}) matches no alert areas", district, path, name));
+                }
+            }
+
+            log::info!("Area group {}: {} alert area(s) on channel {}", name, areas.len(), channel);
+            groups.push(AreaGroup {
+                name,
+                channel,
+                areas,
+            });
+        }
+
+        Ok(AreaMap { groups })
+    }
+
+    // Load a TOML area map for a country without city data; areas are region
+    // names exactly as the alert source reports them
+    pub fn load_regions(path: &str, names: &mut ChannelNames) -> Result<Self, String> {
+        let config = read_config(path)?;
+
+        let mut groups = Vec::new();
+        for (index, group) in config.groups.into_iter().enumerate() {
+            let name = group.name.unwrap_or_else(|| format!("group {}", index + 1));
+            let channel = names.resolve(&group.channel).map_err(|e| format!("{} in {} ({})", e, path, name))?;
+            if !group.districts.is_empty() {
+                return Err(format!("Districts are only known for Israeli alerts; list the regions as areas in {} ({})", path, name));
+            }
+            let areas: HashSet<String> = group.areas.iter().map(|area| area.trim().to_string()).collect();
+            if areas.is_empty() {
+                return Err(format!("Group {} in {} has no areas", name, path));
+            }
+
+            log::info!("Region group {}: {} region(s) on channel {}", name, areas.len(), channel);
+            groups.push(AreaGroup {
+                name,
+                channel,
+                areas,
+            });
+        }
+
+        Ok(AreaMap { groups })
+    }
+
+    // Channels of every group containing the given alert area
+    pub fn channels_for(&self, area: &str) -> Vec<u32> {
+        let mut channels: Vec<u32> = self
+            .groups
+            .iter()
+            .filter(|group| group.areas.contains(area))
+            .map(|group| group.channel)
+            .collect();
+        channels.sort();
+        channels.dedup();
+        channels
+    }
+
+    // Channels of all groups
+    pub fn channels(&self) -> Vec<u32> {
+        let mut channels: Vec<u32> = self.groups.iter().map(|group| group.channel).collect();
+        channels.sort();
+        channels.dedup();
+        channels
+    }
+
+    // Name of the first group sent on the channel
+    pub fn name_for(&self, channel: u32) -> Option<&str> {
+        self.groups
+            .iter()
+            .find(|group| group.channel == channel)
+            .map(|group| group.name.as_str())
+    }
+}
@@ -0,0 +1,76 @@
+use crate::api::{self, ALARMS_HISTORY_API};
+use crate::error::RedAlertError;
+use crate::retention::{Retention, Stored};
+use crate::store::AlertStore;
+use chrono::{Days, NaiveDate};
+use clap::Args;
+use std::time::Duration;
+use tokio::time::sleep;
+
+// Pause between archive requests, to go easy on the server
+const REQUEST_GAP: Duration = Duration::from_secs(1);
+
+#[derive(Args, Debug)]
+pub struct BackfillArgs {
+    /// First day to import, e.g. 2023-10-07
+    #[arg(long)]
+    pub from: NaiveDate,
+
+    /// Last day to import (default: the --from day)
+    #[arg(long)]
+    pub to: Option<NaiveDate>,
+
+    /// SQLite database to import into; created if missing
+    #[arg(long, default_value = "alerts.sqlite3")]
+    pub store: String,
+
+    /// URL of the alert archive
+    #[arg(long, default_value = ALARMS_HISTORY_API)]
+    pub archive_url: String,
+}
+
+// Import past alerts from the archive into the local store, a day at a time so
+// busy days aren't cut short by the archive's response limit. Nothing is transmitted.
+pub async fn run(args: &BackfillArgs, retention: Option<Retention>) -> Result<(), String> {
+    let to = args.to.unwrap_or(args.from);
+    if to < args.from {
+        return Err(format!("--to {} is before --from {}", to, args.from));
+    }
+    let mut store = AlertStore::open(&args.store)?;
+
+    let (mut events, mut added) = (0, 0);
+    let mut day = args.from;
+    while day <= to {
+        let alerts = loop {
+            match api::fetch_archive(&args.archive_url, day, day).await {
+                Ok(alerts) => break alerts,
+                Err(RedAlertError::RateLimited { retry_after }) => {
+                    log::warn!("The alert archive is rate limiting; waiting {}s", retry_after.as_secs());
+                    sleep(retry_after).await;
+                }
+                Err(e) => return Err(format!("Failed to fetch the alerts of {}: {}", day, e)),
+            }
+        };
+        let new_rows = store.insert(&alerts, "oref_archive")?;
+        log::info!("{}: {} alert(s), {} new row(s)", day, alerts.len(), new_rows);
+        events += alerts.len();
+        added += new_rows;
+
+        day = day + Days::new(1);
+        if day <= to {
+            sleep(REQUEST_GAP).await;
+        }
+    }
+
+    println!("Imported {} alert(s) from {} to {} into {} ({} new row(s))", events, args.from, to, args.store, added);
+
+    // An import is when the store grows, so keep it within the retention limits then
+    if let Some(retention) = retention {
+        drop(store);
+        let pruned = Stored::AlertStore(args.store.clone().into()).prune(&retention)?;
+        if pruned > 0 {
+            println!("Pruned {} row(s) past the retention limits", pruned);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,223 @@
+use crate::device::{cli_wanted, lock_cli, Device};
+use crate::events::{self, Event};
+use crate::SharedPause;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+// How often a probe waiting for its ack checks whether an alert wants the radio
+const ACK_POLL: Duration = Duration::from_millis(100);
+
+// Results of the probes, warning once when they start failing and again when they recover
+#[derive(Debug, Default)]
+struct CanaryHealth {
+    failures: u32,
+    last_ok: Option<DateTime<Utc>>,
+}
+
+impl CanaryHealth {
+    fn record(&mut self, channel: u32, result: Result<Duration, String>) {
+        events::emit(Event::CanaryChecked {
+            channel,
+            ok: result.is_ok(),
+            latency_ms: result.as_ref().ok().map(|latency| latency.as_millis() as u64),
+            error: result.as_ref().err().cloned(),
+        });
+        match result {
+            Ok(latency) => {
+                if self.failures > 0 {
+                    log::info!(
+                        "Canary on channel {} got through again after {} failed probe(s)",
+                        channel,
+                        self.failures
+                    );
+                } else {
+                    log::debug!("Canary on channel {} got through in {:?}", channel, latency);
+                }
+                self.failures = 0;
+                self.last_ok = Some(Utc::now());
+            }
+            Err(e) => {
+                self.failures += 1;
+                if self.failures == 1 {
+                    log::error!(
+                        "Canary on channel {} failed: {}; alerts may not be reaching the mesh",
+                        channel,
+                        e
+                    );
+                } else {
+                    log::warn!("Canary on channel {} failed again ({} in a row): {}", channel, self.failures, e);
+                }
+            }
+        }
+    }
+}
+
+// Text of a probe: short, and different every time so an old one can't pass for it
+fn probe_text() -> String {
+    format!("canary {:04x}", rand::random::<u16>())
+}
+
+// A probe waiting to be heard back
+#[derive(Debug)]
+struct Probe {
+    text: String,
+    sent: Instant,
+}
+
+// Probes sent through the gateway's own send path and counted as delivered once
+// a node other than the gateway uplinks them back to the broker
+#[derive(Debug)]
+pub struct Canary {
+    channel: u32,
+    every: Duration,
+    timeout: Duration,
+    // The node that has to hear the probe; any other gateway if unset
+    listener: Option<u32>,
+    next: Instant,
+    pending: Option<Probe>,
+    health: CanaryHealth,
+}
+
+impl Canary {
+    pub fn new(channel: u32, every: Duration, timeout: Duration, listener: Option<u32>) -> Self {
+        Canary {
+            channel,
+            every,
+            timeout,
+            listener,
+            next: Instant::now(),
+            pending: None,
+            health: CanaryHealth::default(),
+        }
+    }
+
+    pub fn channel(&self) -> u32 {
+        self.channel
+    }
+
+    // Report a probe that wasn't heard in time, then return the text of the next one if it is due
+    pub fn due(&mut self, now: Instant) -> Option<String> {
+        if let Some(probe) = &self.pending {
+            if now.duration_since(probe.sent) < self.timeout {
+                return None;
+            }
+            let error = match self.listener {
+                Some(listener) => format!("{} did not hear it within {:?}", crate::meshmqtt::format_node_id(listener), self.timeout),
+                None => format!("no node heard it within {:?}", self.timeout),
+            };
+            self.pending = None;
+            self.health.record(self.channel, Err(error));
+        }
+        (now >= self.next).then(probe_text)
+    }
+
+    pub fn sent(&mut self, text: String, now: Instant) {
+        self.pending = Some(Probe { text, sent: now });
+        self.next = now + self.every;
+    }
+
+    pub fn send_failed(&mut self, error: String, now: Instant) {
+        self.next = now + self.every;
+        self.health.record(self.channel, Err(error));
+    }
+
+    // Whether a text uplinked by `gateway` is the pending probe coming back. The
+    // text on air may carry a sequence number and a signature around it.
+    pub fn heard(&mut self, text: &str, gateway: u32, now: Instant) -> bool {
+        let Some(probe) = &self.pending else {
+            return false;
+        };
+        if !text.contains(&probe.text) || self.listener.is_some_and(|listener| listener != gateway) {
+            return false;
+        }
+        let latency = now.duration_since(probe.sent);
+        self.pending = None;
+        self.health.record(self.channel, Ok(latency));
+        true
+    }
+
+    pub fn debug_state(&self) -> Value {
+        json!({
+            "channel": self.channel,
+            "pending": self.pending.as_ref().map(|probe| probe.text.clone()),
+            "failures": self.health.failures,
+            "last_ok": self.health.last_ok.map(|time| time.to_rfc3339()),
+        })
+    }
+}
+
+// Send a probe through the attached radio and wait for the mesh to acknowledge it.
+// The CLI keeps the radio while it waits, so it is stopped as soon as another run,
+// such as an alert, wants the radio; the probe then counts neither way (None).
+fn send_with_ack(device: &Device, channel: u32, text: &str, timeout: Duration) -> Option<Result<(), String>> {
+    let mut cmd = Command::new("meshtastic");
+    device.apply(&mut cmd);
+    cmd.arg("--ch-index").arg(channel.to_string());
+    cmd.arg("--sendtext").arg(text);
+    cmd.arg("--ack");
+    cmd.arg("--timeout").arg(timeout.as_secs().max(1).to_string());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::null());
+
+    let _radio = lock_cli();
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => return Some(Err(format!("Failed to execute meshtastic --sendtext --ack: {}", e))),
+    };
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if cli_wanted() => {
+                log::debug!("Canary probe on channel {} gave the radio way to another run", channel);
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+            Ok(None) => std::thread::sleep(ACK_POLL),
+            Err(e) => return Some(Err(format!("Failed to wait for meshtastic --sendtext --ack: {}", e))),
+        }
+    }
+    let mut stdout = String::new();
+    if let Some(mut out) = child.stdout.take() {
+        let _ = out.read_to_string(&mut stdout);
+    }
+    Some(check_ack(&stdout, timeout))
+}
+
+// The outcome of a probe from what the CLI printed
+fn check_ack(stdout: &str, timeout: Duration) -> Result<(), String> {
+    if stdout.contains("Received a NAK") {
+        return Err("the radio reported a NAK".to_string());
+    }
+    if stdout.contains("ACK") {
+        Ok(())
+    } else {
+        Err(format!("no acknowledgment within {:?}", timeout))
+    }
+}
+
+// Send a probe through the attached radio every so often and check the mesh
+// acknowledges it, so a broken send path shows up before a real alert needs it
+pub async fn monitor_ack(device: Device, channel: u32, every: Duration, timeout: Duration, paused: SharedPause) {
+    let mut health = CanaryHealth::default();
+    log::info!("Sending a canary probe on channel {} every {:?}", channel, every);
+
+    loop {
+        if !paused.load(Ordering::SeqCst) {
+            let (device, text) = (device.clone(), probe_text());
+            let started = Instant::now();
+            let result = tokio::task::spawn_blocking(move || send_with_ack(&device, channel, &text, timeout))
+                .await
+                .unwrap_or_else(|e| Some(Err(e.to_string())));
+            if let Some(result) = result {
+                health.record(channel, result.map(|()| started.elapsed()));
+            }
+        }
+        sleep(every).await;
+    }
+}
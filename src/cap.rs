@@ -0,0 +1,240 @@
+use crate::api::{record_response, AlertResult};
+use crate::country::{AlertsFuture, CountryProfile, Language, Regions};
+use crate::error::RedAlertError;
+use chrono::{DateTime, Utc};
+use roxmltree::{Document, Node};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// One warning of the feed, reduced to what the gateway routes and sends
+#[derive(Debug, Clone)]
+struct CapAlert {
+    event: String,
+    headline: Option<String>,
+    sent: Option<DateTime<Utc>>,
+    // Area codes as "VALUENAME:VALUE" (e.g. "UGC:TXZ211"), or area descriptions without codes
+    areas: Vec<String>,
+}
+
+// Child element by local name, ignoring the namespace (CAP feeds mix cap:, atom and none)
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn children<'a, 'input>(node: Node<'a, 'input>, name: &'a str) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name)
+        .and_then(|child| child.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+}
+
+fn parse_date(text: Option<&str>) -> Option<DateTime<Utc>> {
+    text.and_then(|text| DateTime::parse_from_rfc3339(text).ok())
+        .map(|date| date.with_timezone(&Utc))
+}
+
+// Geocodes of an area or feed entry; valueName and value come in pairs
+fn geocodes(node: Node) -> Vec<String> {
+    let mut codes = Vec::new();
+    for geocode in children(node, "geocode") {
+        let mut value_name = None;
+        for part in geocode.children().filter(Node::is_element) {
+            let text = part.text().map(str::trim).unwrap_or_default();
+            match part.tag_name().name() {
+                "valueName" => value_name = Some(text),
+                "value" => {
+                    if let (Some(name), false) = (value_name, text.is_empty()) {
+                        // NWS lists several codes separated by spaces
+                        codes.extend(text.split_whitespace().map(|value| format!("{}:{}", name, value)));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    codes
+}
+
+// Whether an alert of this status and message type should reach the mesh
+fn is_live(status: Option<&str>, msg_type: Option<&str>) -> bool {
+    status.is_none_or(|status| status.eq_ignore_ascii_case("Actual"))
+        && msg_type.is_none_or(|msg_type| !msg_type.eq_ignore_ascii_case("Cancel"))
+}
+
+// A full CAP <alert> document, or None if it is a test, a cancellation or expired
+fn parse_alert(alert: Node, now: DateTime<Utc>) -> Option<CapAlert> {
+    if !is_live(child_text(alert, "status"), child_text(alert, "msgType")) {
+        return None;
+    }
+    // Feeds in several languages repeat the warning per language; the first one is used
+    let info = child(alert, "info")?;
+    if parse_date(child_text(info, "expires")).is_some_and(|expires| expires < now) {
+        return None;
+    }
+
+    let mut areas = Vec::new();
+    for area in children(info, "area") {
+        let codes = geocodes(area);
+        if codes.is_empty() {
+            areas.extend(child_text(area, "areaDesc").map(str::to_string));
+        } else {
+            areas.extend(codes);
+        }
+    }
+
+    Some(CapAlert {
+        event: child_text(info, "event")?.to_string(),
+        headline: child_text(info, "headline").map(str::to_string),
+        sent: parse_date(child_text(info, "effective")).or(parse_date(child_text(alert, "sent"))),
+        areas,
+    })
+}
+
+// An ATOM entry summarizing a CAP alert with inline cap: elements (NWS, MeteoAlarm)
+fn parse_entry(entry: Node, now: DateTime<Utc>) -> Option<CapAlert> {
+    if !is_live(child_text(entry, "status"), child_text(entry, "msgType")) {
+        return None;
+    }
+    if parse_date(child_text(entry, "expires")).is_some_and(|expires| expires < now) {
+        return None;
+    }
+
+    let mut areas = geocodes(entry);
+    if areas.is_empty() {
+        areas.extend(
+            child_text(entry, "areaDesc")
+                .into_iter()
+                .flat_map(|desc| desc.split(';'))
+                .map(str::trim)
+                .filter(|desc| !desc.is_empty())
+                .map(str::to_string),
+        );
+    }
+
+    Some(CapAlert {
+        event: child_text(entry, "event")?.to_string(),
+        headline: child_text(entry, "headline").or(child_text(entry, "title")).map(str::to_string),
+        sent: parse_date(child_text(entry, "effective"))
+            .or(parse_date(child_text(entry, "sent")))
+            .or(parse_date(child_text(entry, "updated"))),
+        areas,
+    })
+}
+
+// The live alerts of a CAP document or an ATOM feed of CAP alerts
+fn parse_feed(xml: &str, now: DateTime<Utc>) -> Result<Vec<CapAlert>, String> {
+    let document = Document::parse(xml).map_err(|e| format!("Invalid XML: {}", e))?;
+    let root = document.root_element();
+
+    let alerts = match root.tag_name().name() {
+        "alert" => parse_alert(root, now).into_iter().collect(),
+        "feed" => children(root, "entry")
+            .filter_map(|entry| match entry.descendants().find(|node| node.tag_name().name() == "alert") {
+                // Entries may carry the whole CAP document as content
+                Some(alert) => parse_alert(alert, now),
+                None => parse_entry(entry, now),
+            })
+            .collect(),
+        other => return Err(format!("Expected a CAP <alert> or an ATOM <feed>, found <{}>", other)),
+    };
+    Ok(alerts)
+}
+
+// Polls a CAP document or ATOM feed of CAP alerts (NWS, MeteoAlarm, national
+// feeds). Alerts are routed by the area codes in an area map (e.g. "UGC:TXZ211").
+pub struct CapSource {
+    client: reqwest::Client,
+    url: String,
+    poll_every: Duration,
+    last_poll: Option<Instant>,
+    // Live alerts from the last answer
+    alerts: Vec<CapAlert>,
+}
+
+impl CapSource {
+    pub fn new(url: String, poll_every: Duration) -> Self {
+        CapSource {
+            // Feeds such as the NWS API reject requests without a user agent
+            client: reqwest::Client::builder()
+                .user_agent(concat!("red-alert-meshtastic/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .unwrap_or_default(),
+            url,
+            poll_every,
+            last_poll: None,
+            alerts: Vec::new(),
+        }
+    }
+
+    // One alert per CAP warning that is in effect and names an area
+    async fn current_alerts(&mut self) -> Result<Vec<AlertResult>, RedAlertError> {
+        if self.last_poll.is_none_or(|last_poll| last_poll.elapsed() >= self.poll_every) {
+            self.last_poll = Some(Instant::now());
+            self.poll().await?;
+        }
+
+        Ok(self
+            .alerts
+            .iter()
+            .filter(|alert| !alert.areas.is_empty())
+            .map(|alert| AlertResult {
+                alert_type: alert.event.clone(),
+                cities: alert.areas.clone(),
+                instructions: alert.headline.clone(),
+                zones: vec![],
+                alert_date: alert.sent,
+                areas: BTreeMap::new(),
+            })
+            .collect())
+    }
+
+    async fn poll(&mut self) -> Result<(), RedAlertError> {
+        let response = self.client.get(&self.url).send().await.map_err(|e| {
+            record_response(&self.url, None, &e.to_string());
+            RedAlertError::ApiUnreachable(format!("Error making request to {}: {}", self.url, e))
+        })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            record_response(&self.url, Some(status.as_u16()), "");
+            return Err(RedAlertError::ApiUnreachable(format!(
+                "Failed to retrieve the CAP feed: {} {}",
+                status.as_u16(),
+                status.canonical_reason().unwrap_or("Unknown")
+            )));
+        }
+        let body = response
+            .text()
+            .await
+            .map_err(|e| RedAlertError::ApiUnreachable(format!("Failed to read the response body: {}", e)))?;
+        record_response(&self.url, Some(status.as_u16()), &body);
+
+        self.alerts = parse_feed(&body, Utc::now())
+            .map_err(|e| RedAlertError::ParseError(format!("Failed to parse the CAP feed {}: {}", self.url, e)))?;
+        log::debug!("{} live CAP alert(s) in {}", self.alerts.len(), self.url);
+        Ok(())
+    }
+}
+
+impl CountryProfile for CapSource {
+    fn source(&self) -> &'static str {
+        "cap"
+    }
+
+    fn fetch_alerts(&mut self) -> AlertsFuture<'_> {
+        Box::pin(self.current_alerts())
+    }
+
+    fn regions(&self) -> Regions {
+        Regions::Areas("the CAP area codes")
+    }
+
+    fn language(&self) -> Language {
+        Language::Local
+    }
+}
@@ -0,0 +1,192 @@
+use crate::api::AlertResult;
+use crate::outbox::{Delivery, DeliveryFuture, Outbox, OutboxItem, OutboxSettings};
+use crate::ratelimit;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// Escape text for an XML element or HTML
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// CAP category of an alert category
+fn cap_category(category: &str) -> &'static str {
+    match category {
+        "missiles" | "terroristInfiltration" | "hostileAircraftIntrusion" => "Security",
+        "earthQuake" | "tsunami" => "Geo",
+        "radiologicalEvent" | "hazardousMaterials" => "CBRNE",
+        _ => "Safety",
+    }
+}
+
+// CAP severity from the gateway's own ranking
+fn cap_severity(category: &str) -> &'static str {
+    match ratelimit::severity(category) {
+        3 => "Extreme",
+        2 => "Severe",
+        1 => "Moderate",
+        _ => "Unknown",
+    }
+}
+
+// Posts queued CAP documents to their endpoint
+struct CapDelivery {
+    client: reqwest::Client,
+}
+
+impl Delivery for CapDelivery {
+    fn deliver<'a>(&'a mut self, item: &'a OutboxItem) -> DeliveryFuture<'a> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .post(&item.target)
+                .header(reqwest::header::CONTENT_TYPE, "application/cap+xml")
+                .body(item.body.clone())
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(format!("the endpoint answered {}", response.status()))
+            }
+        })
+    }
+}
+
+// A channel an alert went to, with its name and the cities it covers
+pub struct CapArea {
+    pub channel: u32,
+    pub name: String,
+    pub cities: Vec<String>,
+}
+
+// Writes every processed alert as a CAP 1.2 document to a directory and/or
+// POSTs it to an endpoint, for emergency-management software downstream
+pub struct CapPublisher {
+    dir: Option<PathBuf>,
+    url: Option<String>,
+    sender: String,
+    outbox: Option<Outbox>,
+    // Tells apart documents created within the same millisecond
+    counter: AtomicU32,
+}
+
+impl CapPublisher {
+    pub fn new(dir: Option<String>, url: Option<String>, sender: String, outbox: &OutboxSettings) -> Result<Self, String> {
+        if let Some(dir) = &dir {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create CAP output directory {}: {}", dir, e))?;
+        }
+        let outbox = match &url {
+            Some(_) => {
+                let delivery = CapDelivery {
+                    client: reqwest::Client::builder()
+                        .timeout(std::time::Duration::from_secs(30))
+                        .build()
+                        .map_err(|e| e.to_string())?,
+                };
+                Some(Outbox::start("cap", outbox, delivery)?)
+            }
+            None => None,
+        };
+        Ok(CapPublisher {
+            dir: dir.map(PathBuf::from),
+            url,
+            sender,
+            outbox,
+            counter: AtomicU32::new(0),
+        })
+    }
+
+    // The CAP document of an alert and its identifier
+    fn render(
+        &self,
+        alert: &AlertResult,
+        areas: &[CapArea],
+        sent: DateTime<Utc>,
+        expires: DateTime<Utc>,
+    ) -> (String, String) {
+        let identifier = format!(
+            "{}-{}-{}",
+            self.sender,
+            sent.timestamp_millis(),
+            self.counter.fetch_add(1, Ordering::Relaxed)
+        );
+        let sent = sent.to_rfc3339_opts(SecondsFormat::Secs, false);
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<alert xmlns=\"urn:oasis:names:tc:emergency:cap:1.2\">\n");
+        xml.push_str(&format!("  <identifier>{}</identifier>\n", escape(&identifier)));
+        xml.push_str(&format!("  <sender>{}</sender>\n", escape(&self.sender)));
+        xml.push_str(&format!("  <sent>{}</sent>\n", sent));
+        xml.push_str("  <status>Actual</status>\n  <msgType>Alert</msgType>\n  <scope>Public</scope>\n");
+        xml.push_str("  <info>\n");
+        xml.push_str(&format!("    <category>{}</category>\n", cap_category(&alert.alert_type)));
+        xml.push_str(&format!("    <event>{}</event>\n", escape(&alert.alert_type)));
+        xml.push_str("    <urgency>Immediate</urgency>\n");
+        xml.push_str(&format!("    <severity>{}</severity>\n", cap_severity(&alert.alert_type)));
+        xml.push_str("    <certainty>Observed</certainty>\n");
+        if let Some(alert_date) = alert.alert_date {
+            xml.push_str(&format!(
+                "    <effective>{}</effective>\n",
+                alert_date.to_rfc3339_opts(SecondsFormat::Secs, false)
+            ));
+        }
+        xml.push_str(&format!(
+            "    <expires>{}</expires>\n",
+            expires.to_rfc3339_opts(SecondsFormat::Secs, false)
+        ));
+        xml.push_str(&format!("    <headline>{}</headline>\n", escape(&alert.alert_type)));
+        if let Some(instructions) = &alert.instructions {
+            xml.push_str(&format!("    <instruction>{}</instruction>\n", escape(instructions)));
+        }
+        for area in areas {
+            let description = if area.cities.is_empty() {
+                area.name.clone()
+            } else {
+                format!("{}: {}", area.name, area.cities.join(", "))
+            };
+            xml.push_str("    <area>\n");
+            xml.push_str(&format!("      <areaDesc>{}</areaDesc>\n", escape(&description)));
+            xml.push_str(&format!(
+                "      <geocode>\n        <valueName>meshtastic-channel</valueName>\n        <value>{}</value>\n      </geocode>\n",
+                area.channel
+            ));
+            xml.push_str("    </area>\n");
+        }
+        xml.push_str("  </info>\n</alert>\n");
+        (identifier, xml)
+    }
+
+    // Publish an alert that is being sent to the given channels
+    pub fn publish(&self, alert: &AlertResult, areas: &[CapArea], expires: DateTime<Utc>) {
+        let (identifier, xml) = self.render(alert, areas, Utc::now(), expires);
+
+        if let Some(dir) = &self.dir {
+            // Written under a temporary name first so readers never see half a document
+            let path = dir.join(format!("{}.xml", identifier));
+            let tmp = dir.join(format!(".{}.xml.tmp", identifier));
+            if let Err(e) = std::fs::write(&tmp, &xml).and_then(|_| std::fs::rename(&tmp, &path)) {
+                log::error!("Failed to write CAP document {}: {}", path.display(), e);
+            }
+        }
+
+        // Posted from the outbox so a slow or unreachable endpoint never delays the transmission
+        if let (Some(url), Some(outbox)) = (&self.url, &self.outbox) {
+            outbox.push(url, xml, false, Some(expires));
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::device::Device;
+use crate::meshmqtt::MeshChannel;
+use crate::nodedb;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// Marker printed by `meshtastic --info` right before the channel table
+const CHANNELS_MARKER: &str = "Channels:";
+
+// A channel in a zone or area map, either by index or by its name on the radio
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ChannelRef {
+    Index(u32),
+    Name(String),
+}
+
+// Extract channel names and indexes from `meshtastic --info` output, e.g.
+//   Index 1: SECONDARY psk=secret { "psk": "...", "name": "North", ... }
+pub fn parse_channel_table(info: &str) -> HashMap<String, u32> {
+    let mut channels = HashMap::new();
+    let Some(start) = info.find(CHANNELS_MARKER) else {
+        return channels;
+    };
+
+    for line in info[start + CHANNELS_MARKER.len()..].lines() {
+        let Some((index, rest)) = line.trim().strip_prefix("Index ").and_then(|l| l.split_once(':')) else {
+            continue;
+        };
+        let Ok(index) = index.trim().parse::<u32>() else {
+            continue;
+        };
+        let settings = rest
+            .find('{')
+            .and_then(|json_start| serde_json::from_str::<serde_json::Value>(&rest[json_start..]).ok());
+        let name = settings
+            .as_ref()
+            .and_then(|settings| settings.get("name"))
+            .and_then(|name| name.as_str())
+            .unwrap_or_default();
+        if !name.is_empty() {
+            channels.insert(name.to_string(), index);
+        }
+    }
+    channels
+}
+
+// Resolves channel names to indexes, reading the radio's channel table on first use
+pub struct ChannelNames {
+    device: Device,
+    table: Option<HashMap<String, u32>>,
+}
+
+impl ChannelNames {
+    // Names come from the channel table of the attached radio
+    pub fn from_device(device: Device) -> Self {
+        ChannelNames { device, table: None }
+    }
+
+    // Names come from the channels configured with --mesh-channel
+    pub fn from_mesh_channels(channels: &[(u32, MeshChannel)]) -> Self {
+        let table = channels
+            .iter()
+            .map(|(index, channel)| (channel.name.clone(), *index))
+            .collect();
+        ChannelNames {
+            device: Device::Default,
+            table: Some(table),
+        }
+    }
+
+    pub fn resolve(&mut self, channel: &ChannelRef) -> Result<u32, String> {
+        let name = match channel {
+            ChannelRef::Index(index) => return Ok(*index),
+            ChannelRef::Name(name) => name,
+        };
+
+        if self.table.is_none() {
+            let table = parse_channel_table(&nodedb::run_info(&self.device)?);
+            if table.is_empty() {
+                return Err("Could not read the channel table from the radio to resolve channel names".to_string());
+            }
+            log::info!("Radio channels: {:?}", table);
+            self.table = Some(table);
+        }
+
+        let table = self.table.as_ref().unwrap();
+        table
+            .get(name)
+            .or_else(|| {
+                table
+                    .iter()
+                    .find(|(known, _)| known.eq_ignore_ascii_case(name))
+                    .map(|(_, index)| index)
+            })
+            .copied()
+            .ok_or_else(|| {
+                let mut known: Vec<&String> = table.keys().collect();
+                known.sort();
+                format!("Channel \"{}\" is not configured on the radio (known: {:?})", name, known)
+            })
+    }
+}
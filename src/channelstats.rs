@@ -0,0 +1,108 @@
+use crate::dutycycle::ModemPreset;
+use crate::influx;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// What went out on one channel since the gateway started
+#[derive(Debug, Default, Clone)]
+struct ChannelCounters {
+    messages: u64,
+    bytes: u64,
+    airtime: Duration,
+    failures: u64,
+    // Sum and number of the delays from the official alert time to the send
+    latency: Duration,
+    latency_samples: u32,
+}
+
+impl ChannelCounters {
+    fn average_latency(&self) -> Option<Duration> {
+        (self.latency_samples > 0).then(|| self.latency / self.latency_samples)
+    }
+}
+
+// Transmission counters per channel, for planning how many channels and which
+// zones the channel layout can carry
+#[derive(Debug)]
+pub struct ChannelStats {
+    preset: ModemPreset,
+    channels: BTreeMap<u32, ChannelCounters>,
+}
+
+impl ChannelStats {
+    pub fn new(preset: ModemPreset) -> Self {
+        ChannelStats {
+            preset,
+            channels: BTreeMap::new(),
+        }
+    }
+
+    // Account for a packet that was handed to the transport
+    pub fn record_sent(&mut self, channel: u32, category: &str, message: &str) {
+        let airtime = self.preset.text_airtime(message);
+        let counters = self.channels.entry(channel).or_default();
+        counters.messages += 1;
+        counters.bytes += message.len() as u64;
+        counters.airtime += airtime;
+        influx::record_transmission(channel, category, message.len(), airtime);
+    }
+
+    pub fn record_failed(&mut self, channel: u32) {
+        self.channels.entry(channel).or_default().failures += 1;
+    }
+
+    // Delay from the official time of an alert until it went out on the channel
+    pub fn record_alert_latency(&mut self, channel: u32, category: &str, latency: Duration) {
+        let counters = self.channels.entry(channel).or_default();
+        counters.latency += latency;
+        counters.latency_samples += 1;
+        influx::record_latency("alert_to_send", Some(channel), category, latency);
+    }
+
+    // One line per channel for a reply over the mesh, e.g. "ch1 12 sent 3.4s air 0 failed 2.1s avg"
+    pub fn summary(&self) -> String {
+        if self.channels.is_empty() {
+            return "nothing sent yet".to_string();
+        }
+        self.channels
+            .iter()
+            .map(|(channel, counters)| {
+                let latency = counters
+                    .average_latency()
+                    .map(|latency| format!(" {:.1}s avg", latency.as_secs_f64()))
+                    .unwrap_or_default();
+                format!(
+                    "ch{} {} sent {:.1}s air {} failed{}",
+                    channel,
+                    counters.messages,
+                    counters.airtime.as_secs_f64(),
+                    counters.failures,
+                    latency
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // Counters per channel, for the state dump and the stats endpoint
+    pub fn debug_state(&self) -> Value {
+        let channels: serde_json::Map<String, Value> = self
+            .channels
+            .iter()
+            .map(|(channel, counters)| {
+                (
+                    channel.to_string(),
+                    json!({
+                        "messages": counters.messages,
+                        "bytes": counters.bytes,
+                        "airtime_secs": counters.airtime.as_secs_f64(),
+                        "failures": counters.failures,
+                        "average_latency_secs": counters.average_latency().map(|latency| latency.as_secs_f64()),
+                    }),
+                )
+            })
+            .collect();
+        json!({ "preset": format!("{:?}", self.preset), "channels": channels })
+    }
+}
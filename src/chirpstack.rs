@@ -0,0 +1,144 @@
+use base64::Engine;
+use chrono::Utc;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// Version byte leading every payload, so decoders can tell formats apart
+const PAYLOAD_VERSION: u8 = 1;
+
+// Where downlinks for a channel go on the ChirpStack server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChirpstackTarget {
+    // Multicast group ID; every device of the group receives the alert
+    Multicast(String),
+    // A single device by DevEUI
+    Device(String),
+}
+
+// Parse CHANNEL=multicast:ID or CHANNEL=device:DEVEUI
+pub fn parse_chirpstack_target(value: &str) -> Result<(u32, ChirpstackTarget), String> {
+    let (channel, target) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected CHANNEL=multicast:ID or CHANNEL=device:DEVEUI, got {}", value))?;
+    let channel: u32 = channel
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid channel index in {}", value))?;
+    let target = match target.trim().split_once(':') {
+        Some(("multicast", id)) if !id.is_empty() => ChirpstackTarget::Multicast(id.to_string()),
+        Some(("device", dev_eui)) if dev_eui.len() == 16 && dev_eui.chars().all(|c| c.is_ascii_hexdigit()) => {
+            ChirpstackTarget::Device(dev_eui.to_lowercase())
+        }
+        _ => return Err(format!("Expected multicast:ID or device:DEVEUI (16 hex digits), got {}", target)),
+    };
+    Ok((channel, target))
+}
+
+// oref category number of an alert category, 0 for anything else
+fn category_code(category: &str) -> u8 {
+    match category {
+        "missiles" => 1,
+        "general" => 2,
+        "earthQuake" => 3,
+        "radiologicalEvent" => 4,
+        "tsunami" => 5,
+        "hostileAircraftIntrusion" => 6,
+        "hazardousMaterials" => 7,
+        "terroristInfiltration" => 13,
+        _ => 0,
+    }
+}
+
+// Compact payload for small LoRaWAN frames: version, category code, channel,
+// Unix time (4 bytes, big endian), then the message text cut to max_text bytes
+pub fn encode_payload(category: &str, channel: u32, message: &str, max_text: usize) -> Vec<u8> {
+    let mut cut = message.len().min(max_text);
+    while !message.is_char_boundary(cut) {
+        cut -= 1;
+    }
+
+    let mut payload = vec![PAYLOAD_VERSION, category_code(category), channel.min(255) as u8];
+    payload.extend_from_slice(&(Utc::now().timestamp() as u32).to_be_bytes());
+    payload.extend_from_slice(&message.as_bytes()[..cut]);
+    payload
+}
+
+// Sends alerts as downlinks through the REST API of a ChirpStack v4 server,
+// for LoRaWAN sensor networks running alongside the mesh
+pub struct ChirpstackTransport {
+    client: reqwest::Client,
+    url: String,
+    token: String,
+    targets: BTreeMap<u32, ChirpstackTarget>,
+    f_port: u8,
+    max_text: usize,
+}
+
+impl ChirpstackTransport {
+    pub fn new(url: &str, token: &str, targets: BTreeMap<u32, ChirpstackTarget>, f_port: u8, max_text: usize) -> Self {
+        ChirpstackTransport {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            url: url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            targets,
+            f_port,
+            max_text,
+        }
+    }
+
+    // Targets of a channel; alerts on channel 0 cover every zone and go to every target unless it has its own
+    fn targets_for(&self, channel: u32) -> Vec<&ChirpstackTarget> {
+        match self.targets.get(&channel) {
+            Some(target) => vec![target],
+            None if channel == 0 => {
+                let mut targets: Vec<&ChirpstackTarget> = self.targets.values().collect();
+                targets.dedup();
+                targets
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // Enqueue the alert as a downlink for every target of the channel
+    pub async fn send(&self, channel: u32, category: &str, message: &str) -> Result<(), String> {
+        let targets = self.targets_for(channel);
+        if targets.is_empty() {
+            log::debug!("No ChirpStack target for channel {}; not sending", channel);
+            return Ok(());
+        }
+
+        let data = base64::engine::general_purpose::STANDARD.encode(encode_payload(category, channel, message, self.max_text));
+        for target in targets {
+            let (url, body) = match target {
+                ChirpstackTarget::Multicast(id) => (
+                    format!("{}/api/multicast-groups/{}/queue", self.url, id),
+                    json!({ "queueItem": { "data": data, "fPort": self.f_port } }),
+                ),
+                ChirpstackTarget::Device(dev_eui) => (
+                    format!("{}/api/devices/{}/queue", self.url, dev_eui),
+                    json!({ "queueItem": { "confirmed": false, "data": data, "fPort": self.f_port } }),
+                ),
+            };
+
+            let response = self
+                .client
+                .post(&url)
+                .header("Grpc-Metadata-Authorization", format!("Bearer {}", self.token))
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string())
+                .send()
+                .await
+                .map_err(|e| format!("Failed to reach ChirpStack at {}: {}", url, e))?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let text = response.text().await.unwrap_or_default();
+                return Err(format!("ChirpStack rejected the downlink to {:?}: {} {}", target, status, text));
+            }
+        }
+        Ok(())
+    }
+}
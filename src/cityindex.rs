@@ -0,0 +1,282 @@
+use crate::api::CityArea;
+use crate::zones::ZoneScheme;
+use crate::City;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+
+// Separates a settlement from its neighbourhood or sub-area, e.g. "אשדוד - א,ב,ד,ה"
+const AREA_SEPARATOR: &str = " - ";
+
+// Fold the spelling variants the feed uses for the same city: Hebrew geresh and
+// gershayim vs ASCII quotes, dash variants, spacing around dashes, niqqud and
+// runs of whitespace
+pub fn normalize(name: &str) -> String {
+    let folded: String = name
+        .chars()
+        .filter(|c| !is_hebrew_point(*c))
+        .map(|c| match c {
+            '\u{05F3}' | '\u{2019}' | '`' => '\'',
+            '\u{05F4}' | '\u{201D}' | '\u{201C}' => '"',
+            '\u{2013}' | '\u{2014}' | '\u{05BE}' => '-',
+            c if c.is_whitespace() => ' ',
+            c => c,
+        })
+        .collect();
+    // "אשדוד -א" and "אשדוד- א" are the same area as "אשדוד - א"
+    folded
+        .replace(" -", " - ")
+        .replace("- ", " - ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Vowel points and cantillation marks, which some sources add to names
+fn is_hebrew_point(c: char) -> bool {
+    matches!(c, '\u{0591}'..='\u{05BD}' | '\u{05BF}' | '\u{05C1}' | '\u{05C2}' | '\u{05C4}' | '\u{05C5}' | '\u{05C7}')
+}
+
+// Mean radius of the earth, for distances between coordinates
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+// Great-circle distance in km between two points given as (latitude, longitude)
+pub fn distance_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lng = (to.1 - from.1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lng / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+// A city near a point, with the zones it is routed to
+#[derive(Debug)]
+pub struct NearbyCity<'a> {
+    pub city: &'a City,
+    pub distance_km: f64,
+    pub zones: &'a [u32],
+}
+
+// The city data with the zones of every city resolved once at startup, so a
+// barrage of hundreds of cities is routed without scanning the list per city
+#[derive(Debug)]
+pub struct CityIndex {
+    cities: Vec<City>,
+    // Normalized Hebrew name to position in `cities`; the first entry of a duplicated name wins
+    by_name: HashMap<String, usize>,
+    // Lowercase English name to position in `cities`, for lookups by operators
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    by_name_en: HashMap<String, usize>,
+    // Locality ID to position in `cities`, for feeds that give one
+    by_id: HashMap<u32, usize>,
+    // Zone channels of each city, by position in `cities`
+    zones: Vec<Vec<u32>>,
+    // Zone channels of each district, by normalized Hebrew and lowercase English name
+    districts: HashMap<String, Vec<u32>>,
+    // Zone channels of each settlement split into sub-areas, by normalized name before " - "
+    settlements: HashMap<String, Vec<u32>>,
+}
+
+// Settlement part of an area name: "אשדוד" for "אשדוד - א,ב,ד,ה"
+fn settlement(name: &str) -> &str {
+    name.split(AREA_SEPARATOR).next().unwrap_or(name).trim()
+}
+
+impl CityIndex {
+    pub fn new(cities: Vec<City>, zones: &ZoneScheme) -> Self {
+        let mut by_name = HashMap::with_capacity(cities.len());
+        let mut by_name_en = HashMap::with_capacity(cities.len());
+        let mut by_id = HashMap::with_capacity(cities.len());
+        for (index, city) in cities.iter().enumerate() {
+            by_name.entry(normalize(&city.name)).or_insert(index);
+            if city.id != 0 {
+                by_id.entry(city.id).or_insert(index);
+            }
+            if !city.name_en.is_empty() {
+                by_name_en.entry(normalize(&city.name_en).to_lowercase()).or_insert(index);
+            }
+        }
+        let city_zones: Vec<Vec<u32>> = cities.iter().map(|city| zones.zones_for_city(city)).collect();
+
+        let mut districts = HashMap::new();
+        let mut settlements: HashMap<String, BTreeSet<u32>> = HashMap::new();
+        for (city, channels) in cities.iter().zip(&city_zones) {
+            if city.zone_en.is_empty() {
+                continue;
+            }
+            let district_zones = zones.zones_for_area(&city.zone_en);
+            if !city.zone.is_empty() {
+                districts.entry(normalize(&city.zone)).or_insert_with(|| district_zones.clone());
+            }
+            districts.entry(city.zone_en.to_lowercase()).or_insert(district_zones);
+            if city.name.contains(AREA_SEPARATOR) {
+                settlements
+                    .entry(normalize(settlement(&city.name)))
+                    .or_default()
+                    .extend(channels);
+            }
+        }
+        let settlements = settlements
+            .into_iter()
+            .map(|(name, channels)| (name, channels.into_iter().collect()))
+            .collect();
+
+        CityIndex {
+            cities,
+            by_name,
+            by_name_en,
+            by_id,
+            zones: city_zones,
+            districts,
+            settlements,
+        }
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.by_name.get(&normalize(name)).copied()
+    }
+
+    // City by Hebrew name, as the feed reports it
+    pub fn get(&self, name: &str) -> Option<&City> {
+        self.position(name).map(|index| &self.cities[index])
+    }
+
+    // Distance in km from a point to a city by Hebrew name, if its location is known
+    pub fn distance_from(&self, name: &str, point: (f64, f64)) -> Option<f64> {
+        self.get(name)?.coordinates().map(|city| distance_km(point, city))
+    }
+
+    // The cities closest to a point, nearest first; entries without coordinates are skipped
+    pub fn nearest(&self, point: (f64, f64), count: usize) -> Vec<NearbyCity<'_>> {
+        let mut nearby: Vec<NearbyCity> = self
+            .cities
+            .iter()
+            .zip(&self.zones)
+            .filter_map(|(city, zones)| {
+                let distance_km = distance_km(point, city.coordinates()?);
+                Some(NearbyCity { city, distance_km, zones })
+            })
+            .collect();
+        nearby.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+        nearby.truncate(count);
+        nearby
+    }
+
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn cities(&self) -> &[City] {
+        &self.cities
+    }
+
+    // Everything known about a city, by Hebrew or English name: district, zones,
+    // coordinates and shelter time
+    #[cfg_attr(not(feature = "web"), allow(dead_code))]
+    pub fn describe(&self, name: &str) -> Option<Value> {
+        let index = self
+            .position(name)
+            .or_else(|| self.by_name_en.get(&normalize(name).to_lowercase()).copied())?;
+        let city = &self.cities[index];
+        Some(json!({
+            "id": city.id,
+            "name": city.name,
+            "name_en": city.name_en,
+            "district": city.zone,
+            "district_en": city.zone_en,
+            "zones": self.zones[index],
+            "coordinates": city.coordinates().map(|(lat, lng)| json!({ "lat": lat, "lng": lng })),
+            "shelter_secs": city.shelter_time().map(|time| time.as_secs()),
+        }))
+    }
+
+    // Zone channels of a city by Hebrew name, sorted. Localities missing from the
+    // city data (new settlements, outposts) fall back to a district of that name,
+    // then to the settlement the name starts with; empty if nothing matches.
+    pub fn zones(&self, name: &str) -> &[u32] {
+        if let Some(index) = self.position(name) {
+            return &self.zones[index];
+        }
+
+        let normalized = normalize(name);
+        self.districts
+            .get(&normalized)
+            .or_else(|| self.districts.get(&normalized.to_lowercase()))
+            .or_else(|| {
+                let settlement = settlement(&normalized);
+                self.settlements
+                    .get(settlement)
+                    .or_else(|| self.position(settlement).map(|index| &self.zones[index]))
+            })
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    // Zone channels of a locality the feed places by ID or district, for names the
+    // city data doesn't know; empty if neither is known either
+    pub fn zones_of_area(&self, area: &CityArea) -> &[u32] {
+        if let Some(index) = area.city_id.and_then(|id| self.by_id.get(&id)) {
+            return &self.zones[*index];
+        }
+        area.district
+            .as_deref()
+            .map(normalize)
+            .and_then(|district| {
+                self.districts
+                    .get(&district)
+                    .or_else(|| self.districts.get(&district.to_lowercase()))
+            })
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> CityIndex {
+        let cities = serde_json::json!([
+            { "name": "שדרות", "name_en": "Sderot", "zone": "עוטף עזה", "zone_en": "Gaza Envelope" },
+            { "name": "ג'ת", "name_en": "Jatt", "zone_en": "Wadi Ara" },
+            { "name": "בית ג\"ן", "name_en": "Beit Jann", "zone_en": "Upper Galilee" },
+            { "name": "תל אביב - מרכז העיר", "name_en": "Tel Aviv - City Center", "zone_en": "Dan" },
+            { "name": "כפר-סבא", "name_en": "Kfar Saba", "zone_en": "Sharon" },
+        ]);
+        CityIndex::new(serde_json::from_value(cities).unwrap(), &ZoneScheme::builtin())
+    }
+
+    #[test]
+    fn finds_cities_by_spelling_variant() {
+        let index = index();
+        let cases = [
+            // Niqqud and cantillation
+            ("ש\u{05C2}\u{05B0}ד\u{05B5}רו\u{05B9}ת", "שדרות"),
+            ("שְׂדֵרוֹת", "שדרות"),
+            // Geresh and gershayim vs ASCII quotes
+            ("ג\u{05F3}ת", "ג'ת"),
+            ("ג\u{2019}ת", "ג'ת"),
+            ("בית ג\u{05F4}ן", "בית ג\"ן"),
+            ("בית ג\u{201D}ן", "בית ג\"ן"),
+            // Dash and space variants
+            ("תל אביב – מרכז העיר", "תל אביב - מרכז העיר"),
+            ("תל אביב -מרכז העיר", "תל אביב - מרכז העיר"),
+            ("תל  אביב-  מרכז העיר ", "תל אביב - מרכז העיר"),
+            ("כפר\u{05BE}סבא", "כפר-סבא"),
+            ("כפר\u{2013}סבא", "כפר-סבא"),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(index.get(query).map(|city| city.name.as_str()), Some(expected), "{}", query);
+        }
+        assert!(index.get("כפר סבא").is_none());
+        assert!(index.get("אשדוד").is_none());
+    }
+
+    #[test]
+    fn falls_back_to_district_and_settlement() {
+        let index = index();
+        assert_eq!(index.zones("שדרות"), [2]);
+        assert_eq!(index.zones("עוטף  עזה"), [2]);
+        assert_eq!(index.zones("gaza envelope"), [2]);
+        // A sub-area missing from the data is routed by its settlement
+        assert_eq!(index.zones("תל אביב -דרום העיר"), [7]);
+        assert!(index.zones("אשדוד").is_empty());
+        assert_eq!(index.describe("KFAR SABA").unwrap()["zones"], serde_json::json!([7]));
+    }
+}
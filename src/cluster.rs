@@ -0,0 +1,243 @@
+use crate::api::AlertResult;
+use crate::dedup::AlertDedup;
+use crate::lifecycle::TrackedAlert;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Script};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+// How long startup waits for Redis
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long the shared dedup keys are kept, as for the local dedup
+const DEDUP_TTL_SECS: u64 = 3600;
+
+// How long the alert state survives without a leader writing it
+const STATE_TTL_SECS: u64 = 24 * 3600;
+
+// Injected alerts forwarded to the leader in one go
+const MAX_FORWARDED: usize = 16;
+
+// Take the lease if it is free, renew it if we hold it
+const LEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1
+end
+if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+    return 1
+end
+return 0
+"#;
+
+// Gateways on several hosts sharing one Redis: only the holder of the leader
+// lease transmits, and the dedup keys and alert state it writes let a standby
+// take over without repeating or losing alerts
+#[derive(Clone)]
+pub struct Cluster {
+    conn: ConnectionManager,
+    prefix: String,
+    instance: String,
+    lease: Duration,
+    leader: Arc<AtomicBool>,
+    // Last time the lease was taken or renewed
+    renewed: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Cluster {
+    pub async fn connect(url: &str, prefix: &str, lease: Duration) -> Result<Self, String> {
+        let client = redis::Client::open(url).map_err(|e| format!("Invalid Redis URL {}: {}", url, e))?;
+        let conn = tokio::time::timeout(CONNECT_TIMEOUT, ConnectionManager::new(client))
+            .await
+            .map_err(|_| format!("Timed out connecting to Redis at {}", url))?
+            .map_err(|e| format!("Failed to connect to Redis at {}: {}", url, e))?;
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "gateway".to_string());
+        Ok(Cluster {
+            conn,
+            prefix: prefix.to_string(),
+            instance: format!("{}-{}-{:04x}", host, std::process::id(), rand::random::<u16>()),
+            lease: lease.max(Duration::from_secs(3)),
+            leader: Arc::new(AtomicBool::new(false)),
+            renewed: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}:{}", self.prefix, name)
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::SeqCst)
+    }
+
+    // Take or renew the leader lease
+    async fn renew(&mut self) -> redis::RedisResult<bool> {
+        let held: i32 = Script::new(LEASE_SCRIPT)
+            .key(self.key("leader"))
+            .arg(&self.instance)
+            .arg(self.lease.as_millis() as u64)
+            .invoke_async(&mut self.conn)
+            .await?;
+        Ok(held == 1)
+    }
+
+    // Keep taking or renewing the leader lease, three times per lease, apart from
+    // the alert loop so long transmissions never let it lapse. While Redis is
+    // unreachable a standby stays one, and a leader steps down before its lease
+    // runs out, since a standby may take over as soon as it has.
+    pub async fn hold_lease(mut self) {
+        let every = self.lease / 3;
+        loop {
+            let held = match self.renew().await {
+                Ok(held) => {
+                    if held {
+                        *self.renewed.lock().unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+                    }
+                    held
+                }
+                Err(e) => {
+                    let renewed = *self.renewed.lock().unwrap_or_else(PoisonError::into_inner);
+                    let lapsing = renewed.is_none_or(|renewed| renewed.elapsed() + every >= self.lease);
+                    log::error!("Failed to renew the leader lease: {}", e);
+                    self.is_leader() && !lapsing
+                }
+            };
+            match (self.leader.swap(held, Ordering::SeqCst), held) {
+                (false, true) => log::info!("{} is now the leader; transmitting alerts", self.instance),
+                (true, false) => log::warn!("{} lost the leader lease; standing by", self.instance),
+                _ => {}
+            }
+            sleep(every).await;
+        }
+    }
+
+    fn dedup_key(&self, alert_type: &str, city: &str, alert_date: DateTime<Utc>) -> String {
+        self.key(&format!("dedup:{}", AlertDedup::key(alert_type, city, alert_date)))
+    }
+
+    // Drop the cities of a dated alert that any gateway of the cluster already
+    // sent and return whether any are left. Unreachable Redis sends them anyway.
+    pub async fn retain_unsent(&mut self, alert: &mut AlertResult) -> bool {
+        let Some(alert_date) = alert.alert_date else {
+            return true;
+        };
+
+        let mut unsent = Vec::new();
+        for city in std::mem::take(&mut alert.cities) {
+            let key = self.dedup_key(&alert.alert_type, &city, alert_date);
+            let sent: redis::RedisResult<bool> = self.conn.exists(&key).await;
+            match sent {
+                Ok(true) => log::debug!("{} in {} was already sent by the cluster", alert.alert_type, city),
+                Ok(false) => unsent.push(city),
+                Err(e) => {
+                    log::error!("Failed to check the shared dedup key {}: {}", key, e);
+                    unsent.push(city);
+                }
+            }
+        }
+        alert.cities = unsent;
+        !alert.cities.is_empty()
+    }
+
+    // Remember across the cluster that the cities of a dated alert went out, once
+    // they have; a city whose send failed is left for the next leader to send
+    pub async fn mark_sent(&mut self, alert_type: &str, cities: &[String], alert_date: Option<DateTime<Utc>>) {
+        let Some(alert_date) = alert_date else {
+            return;
+        };
+        for city in cities {
+            let key = self.dedup_key(alert_type, city, alert_date);
+            let result: redis::RedisResult<()> = self.conn.set_ex(&key, &self.instance, DEDUP_TTL_SECS).await;
+            if let Err(e) = result {
+                log::error!("Failed to set the shared dedup key {}: {}", key, e);
+            }
+        }
+    }
+
+    // Store the alerts in effect so the next leader sends their updates and all clears
+    pub async fn save_state(&mut self, alerts: &[TrackedAlert]) {
+        let state = match serde_json::to_string(alerts) {
+            Ok(state) => state,
+            Err(e) => {
+                log::error!("Failed to serialize the alert state: {}", e);
+                return;
+            }
+        };
+        let key = self.key("lifecycle");
+        let result: redis::RedisResult<()> = self.conn.set_ex(&key, state, STATE_TTL_SECS).await;
+        if let Err(e) = result {
+            log::error!("Failed to store the alert state in Redis: {}", e);
+        }
+    }
+
+    // Alerts in effect as stored by the previous leader
+    pub async fn load_state(&mut self) -> Vec<TrackedAlert> {
+        let key = self.key("lifecycle");
+        let state: redis::RedisResult<Option<String>> = self.conn.get(&key).await;
+        match state {
+            Ok(Some(state)) => serde_json::from_str(&state).unwrap_or_else(|e| {
+                log::error!("Ignoring the alert state stored in Redis: {}", e);
+                Vec::new()
+            }),
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                log::error!("Failed to load the alert state from Redis: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    // Hand an alert injected on a standby (HTTP, stdin) to the leader
+    pub async fn forward(&mut self, source: &str, alert: &AlertResult) -> Result<(), String> {
+        let payload = json!({ "source": source, "alert": alert }).to_string();
+        let key = self.key("injected");
+        self.conn
+            .rpush::<_, _, ()>(&key, payload)
+            .await
+            .map_err(|e| format!("Failed to forward the alert to the leader: {}", e))
+    }
+
+    // Alerts forwarded by standbys, oldest first
+    pub async fn take_forwarded(&mut self) -> Vec<(String, AlertResult)> {
+        let key = self.key("injected");
+        let payloads: redis::RedisResult<Option<Vec<String>>> =
+            self.conn.lpop(&key, std::num::NonZeroUsize::new(MAX_FORWARDED)).await;
+        let payloads = match payloads {
+            Ok(payloads) => payloads.unwrap_or_default(),
+            Err(e) => {
+                log::error!("Failed to read forwarded alerts from Redis: {}", e);
+                return Vec::new();
+            }
+        };
+
+        payloads
+            .into_iter()
+            .filter_map(|payload| {
+                let mut value: Value = serde_json::from_str(&payload).ok()?;
+                let source = value.get("source")?.as_str()?.to_string();
+                match serde_json::from_value(value.get_mut("alert")?.take()) {
+                    Ok(alert) => Some((source, alert)),
+                    Err(e) => {
+                        log::error!("Ignoring a malformed forwarded alert: {}", e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // Role and lease, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let renewed = *self.renewed.lock().unwrap_or_else(PoisonError::into_inner);
+        json!({
+            "instance": self.instance,
+            "leader": self.is_leader(),
+            "lease_secs": self.lease.as_secs(),
+            "renewed_secs_ago": renewed.map(|renewed| renewed.elapsed().as_secs()),
+        })
+    }
+}
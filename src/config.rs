@@ -0,0 +1,145 @@
+use crate::dedicated::CategoryChannelConfig;
+use crate::zones::{RouteConfig, ZoneConfig};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use std::ffi::OsString;
+use toml::{Table, Value};
+
+// Keys of the config file that are not command-line options
+const RESERVED_KEYS: [&str; 5] = ["profile", "default_profile", "zone", "route", "category"];
+
+// Settings of a config file after applying the selected profile.
+//
+// Top-level keys apply to every profile and `[profile.<name>]` tables override
+// them. Option keys are the long option names (with dashes or underscores).
+pub struct ConfigFile {
+    path: String,
+    settings: Table,
+}
+
+impl ConfigFile {
+    pub fn load(path: &str, profile: Option<&str>) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+        let file: Table = contents
+            .parse()
+            .map_err(|e| format!("Failed to parse config file {}: {}", path, e))?;
+
+        let mut settings: Table = file
+            .iter()
+            .filter(|(key, _)| !["profile", "default_profile"].contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        let profiles = match file.get("profile") {
+            Some(Value::Table(profiles)) => profiles.clone(),
+            Some(_) => return Err(format!("[profile] in {} must be a table of profiles", path)),
+            None => Table::new(),
+        };
+
+        let selected = profile.or_else(|| file.get("default_profile").and_then(Value::as_str));
+        match selected {
+            Some(name) => {
+                let overrides = profiles
+                    .get(name)
+                    .and_then(Value::as_table)
+                    .ok_or_else(|| {
+                        let available: Vec<&String> = profiles.keys().collect();
+                        format!("Profile {} not found in {} (available: {:?})", name, path, available)
+                    })?;
+                settings.extend(overrides.iter().map(|(key, value)| (key.clone(), value.clone())));
+            }
+            None if !profiles.is_empty() => {
+                return Err(format!(
+                    "{} defines profiles; select one with --profile or set default_profile",
+                    path
+                ));
+            }
+            None => {}
+        }
+
+        Ok(ConfigFile {
+            path: path.to_string(),
+            settings,
+        })
+    }
+
+    // Turn the settings into command-line arguments. Options given on the
+    // actual command line win over the file, so they are left out.
+    pub fn args(&self, command: &Command, cli_matches: &ArgMatches) -> Result<Vec<OsString>, String> {
+        let path = &self.path;
+        let mut args = Vec::new();
+        for (key, value) in &self.settings {
+            if RESERVED_KEYS.contains(&key.as_str()) {
+                continue;
+            }
+
+            let id = key.replace('-', "_");
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_id().as_str() == id && arg.get_long().is_some())
+                .ok_or_else(|| format!("Unknown option {} in {}", key, path))?;
+            if cli_matches.value_source(&id) == Some(ValueSource::CommandLine) {
+                continue;
+            }
+
+            let flag = format!("--{}", arg.get_long().unwrap_or_default());
+            match value {
+                Value::Boolean(true) => args.push(flag.into()),
+                Value::Boolean(false) => {}
+                Value::Array(values) => {
+                    for value in values {
+                        args.push(format!("{}={}", flag, scalar(value, key, path)?).into());
+                    }
+                }
+                value => args.push(format!("{}={}", flag, scalar(value, key, path)?).into()),
+            }
+        }
+
+        Ok(args)
+    }
+
+    // Custom zone scheme from [[zone]] tables, if the file defines one
+    pub fn zones(&self) -> Result<Option<Vec<ZoneConfig>>, String> {
+        match self.settings.get("zone") {
+            Some(zones) => zones
+                .clone()
+                .try_into()
+                .map(Some)
+                .map_err(|e| format!("Invalid [[zone]] in {}: {}", self.path, e)),
+            None => Ok(None),
+        }
+    }
+
+    // Zone overrides for specific cities or districts from [[route]] tables
+    pub fn routes(&self) -> Result<Vec<RouteConfig>, String> {
+        match self.settings.get("route") {
+            Some(routes) => routes
+                .clone()
+                .try_into()
+                .map_err(|e| format!("Invalid [[route]] in {}: {}", self.path, e)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // Dedicated channels for alert categories from [[category]] tables
+    pub fn categories(&self) -> Result<Vec<CategoryChannelConfig>, String> {
+        match self.settings.get("category") {
+            Some(categories) => categories
+                .clone()
+                .try_into()
+                .map_err(|e| format!("Invalid [[category]] in {}: {}", self.path, e)),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+// Render a TOML value as a single command-line value
+fn scalar(value: &Value, key: &str, path: &str) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(format!("Unsupported value for {} in {}", key, path)),
+    }
+}
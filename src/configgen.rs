@@ -0,0 +1,338 @@
+use clap::{Arg, ArgAction, Args, CommandFactory, Subcommand};
+use serde_json::{json, Map, Value};
+use std::any::TypeId;
+
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Write a config file listing every option with its description and default, all commented out
+    Init {
+        /// File to write instead of printing the config
+        #[arg(long, short)]
+        output: Option<String>,
+        /// Overwrite the output file if it exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print a JSON Schema of the config file, for validation and completion in editors
+    Schema {
+        /// File to write instead of printing the schema
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+}
+
+// Command-line options that can't be set from the config file
+const NOT_IN_FILE: [&str; 4] = ["help", "version", "config", "profile"];
+
+// Options that can be set in the config file, in the order of --help
+fn file_options(command: &clap::Command) -> Vec<&Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| arg.get_long().is_some() && !arg.is_hide_set())
+        .filter(|arg| !NOT_IN_FILE.contains(&arg.get_id().as_str()))
+        .collect()
+}
+
+// JSON Schema type of one value of an option
+fn value_type(arg: &Arg) -> &'static str {
+    let type_id = arg.get_value_parser().type_id();
+    let integers = [
+        TypeId::of::<u8>(),
+        TypeId::of::<u16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<u64>(),
+        TypeId::of::<usize>(),
+        TypeId::of::<i32>(),
+        TypeId::of::<i64>(),
+    ];
+    if matches!(arg.get_action(), ArgAction::SetTrue) {
+        "boolean"
+    } else if integers.iter().any(|id| type_id == *id) {
+        "integer"
+    } else if type_id == TypeId::of::<f32>() || type_id == TypeId::of::<f64>() {
+        "number"
+    } else {
+        "string"
+    }
+}
+
+fn is_list(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Append)
+}
+
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+fn description(arg: &Arg) -> String {
+    arg.get_long_help()
+        .or(arg.get_help())
+        .map(|help| help.to_string())
+        .unwrap_or_default()
+}
+
+// A command-line value as a JSON value of the option's type
+fn typed_value(arg: &Arg, value: &str) -> Value {
+    match value_type(arg) {
+        "boolean" => value.parse().map(Value::Bool).unwrap_or_else(|_| json!(value)),
+        "integer" => value.parse::<i64>().map(Value::from).unwrap_or_else(|_| json!(value)),
+        "number" => value.parse::<f64>().map(Value::from).unwrap_or_else(|_| json!(value)),
+        _ => json!(value),
+    }
+}
+
+fn default_value(arg: &Arg) -> Option<Value> {
+    let defaults: Vec<Value> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| typed_value(arg, &value.to_string_lossy()))
+        .collect();
+    if is_list(arg) {
+        Some(Value::Array(defaults))
+    } else if matches!(arg.get_action(), ArgAction::SetTrue) {
+        Some(Value::Bool(false))
+    } else {
+        defaults.into_iter().next()
+    }
+}
+
+fn option_schema(arg: &Arg) -> Value {
+    let mut value = json!({ "type": value_type(arg) });
+    let choices = possible_values(arg);
+    if !choices.is_empty() && value_type(arg) != "boolean" {
+        value["enum"] = json!(choices);
+    }
+
+    let mut schema = if is_list(arg) {
+        json!({ "type": "array", "items": value })
+    } else {
+        value
+    };
+    schema["description"] = json!(description(arg));
+    if let Some(default) = default_value(arg) {
+        schema["default"] = default;
+    }
+    schema
+}
+
+// Tables of the config file that are not command-line options
+fn table_definitions() -> Value {
+    let channel = json!({
+        "type": ["integer", "string"],
+        "description": "Channel index, or the channel's name on the radio"
+    });
+    let names = json!({ "type": "array", "items": { "type": "string" } });
+    json!({
+        "zone": {
+            "type": "object",
+            "description": "A zone of the custom zone scheme: the districts it covers and its channel",
+            "properties": {
+                "channel": channel,
+                "name": { "type": "string" },
+                "districts": names
+            },
+            "required": ["channel", "districts"],
+            "additionalProperties": false
+        },
+        "route": {
+            "type": "object",
+            "description": "Extra (or replacement) channels for specific cities or districts",
+            "properties": {
+                "cities": names,
+                "districts": names,
+                "channels": { "type": "array", "items": channel },
+                "name": { "type": "string", "description": "Name of a dedicated channel, for messages and MQTT topics" },
+                "replace": { "type": "boolean", "description": "Send only on these channels instead of adding them to the zones" }
+            },
+            "required": ["channels"],
+            "additionalProperties": false
+        },
+        "category": {
+            "type": "object",
+            "description": "A dedicated channel for an alert category",
+            "properties": {
+                "category": { "type": "string" },
+                "channel": channel,
+                "template": { "type": "string", "description": "Message text with {category}, {time}, {cities} and {count} filled in" },
+                "min_gap": { "type": "integer", "minimum": 0, "description": "Minimum seconds between messages on the channel for the category" }
+            },
+            "required": ["category", "channel"],
+            "additionalProperties": false
+        }
+    })
+}
+
+// JSON Schema of the config file. Options are listed by their long name with
+// dashes; the underscore spelling the loader also accepts is not in the schema.
+pub fn schema() -> Value {
+    let command = crate::Args::command();
+    let mut settings: Map<String, Value> = file_options(&command)
+        .into_iter()
+        .map(|arg| (arg.get_long().unwrap_or_default().to_string(), option_schema(arg)))
+        .collect();
+    for table in ["zone", "route", "category"] {
+        settings.insert(
+            table.to_string(),
+            json!({ "type": "array", "items": { "$ref": format!("#/definitions/{}", table) } }),
+        );
+    }
+
+    let mut definitions = table_definitions();
+    definitions["settings"] = json!({
+        "type": "object",
+        "properties": settings.clone(),
+        "additionalProperties": false
+    });
+
+    settings.insert(
+        "default_profile".to_string(),
+        json!({ "type": "string", "description": "Profile to use when --profile is not given" }),
+    );
+    settings.insert(
+        "profile".to_string(),
+        json!({
+            "type": "object",
+            "description": "Named profiles, selected with --profile, overriding the top-level settings",
+            "additionalProperties": { "$ref": "#/definitions/settings" }
+        }),
+    );
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "red-alert-meshtastic config file",
+        "type": "object",
+        "properties": settings,
+        "additionalProperties": false,
+        "definitions": definitions
+    })
+}
+
+// A value as it would be written in the config file
+fn toml_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => toml::Value::String(s.clone()).to_string(),
+        Value::Array(values) => format!("[{}]", values.iter().map(toml_value).collect::<Vec<_>>().join(", ")),
+        value => value.to_string(),
+    }
+}
+
+// Example value for an option without a default
+fn placeholder(arg: &Arg) -> String {
+    if is_list(arg) {
+        return "[]".to_string();
+    }
+    match (value_type(arg), possible_values(arg).first()) {
+        (_, Some(choice)) => toml_value(&json!(choice)),
+        ("boolean", _) => "false".to_string(),
+        ("integer", _) => "0".to_string(),
+        ("number", _) => "0.0".to_string(),
+        _ => "\"\"".to_string(),
+    }
+}
+
+const TABLE_EXAMPLES: &str = r#"# Named profiles override the settings above; select one with --profile or default_profile.
+# default_profile = "north"
+#
+# [profile.north]
+# host = "192.168.1.50:4403"
+
+# A custom zone scheme replacing the built-in zones: the districts of each zone and its channel
+# (index or name on the radio).
+# [[zone]]
+# channel = 1
+# name = "North"
+# districts = ["HaCarmel", "Confrontation Line"]
+
+# Extra channels for specific cities or districts, added to their zones (or replacing them
+# with replace = true).
+# [[route]]
+# cities = ["Sderot"]
+# districts = []
+# channels = [3]
+# name = "Sderot"
+# replace = false
+
+# A dedicated channel for an alert category. template may use {category}, {time}, {cities}
+# and {count}; min_gap is the minimum number of seconds between messages.
+# [[category]]
+# category = "earthQuake"
+# channel = 4
+# template = "{category} {time}"
+# min_gap = 60
+"#;
+
+// A config file with every option commented out at its default
+pub fn template() -> String {
+    let command = crate::Args::command();
+    let mut text = String::from(
+        "# red-alert-meshtastic config file\n\
+         #\n\
+         # Every option is listed with its default value, or an example value if it has none;\n\
+         # uncomment and edit the ones to change.\n\
+         # Options given on the command line win over this file. Editors can validate it against\n\
+         # the schema printed by `red-alert-meshtastic config schema`.\n",
+    );
+
+    for arg in file_options(&command) {
+        text.push('\n');
+        for line in description(arg).lines() {
+            text.push_str(format!("# {}", line).trim_end());
+            text.push('\n');
+        }
+        let choices = possible_values(arg);
+        if !choices.is_empty() && value_type(arg) != "boolean" {
+            text.push_str(&format!("# One of: {}\n", choices.join(", ")));
+        }
+        let value = match default_value(arg) {
+            Some(Value::Array(values)) if values.is_empty() => placeholder(arg),
+            Some(value) => toml_value(&value),
+            None => placeholder(arg),
+        };
+        text.push_str(&format!("# {} = {}\n", arg.get_long().unwrap_or_default(), value));
+    }
+
+    text.push('\n');
+    text.push_str(TABLE_EXAMPLES);
+    text
+}
+
+fn write_or_print(output: Option<&str>, contents: &str) -> Result<(), String> {
+    match output {
+        Some(path) => {
+            std::fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+            log::info!("Wrote {}", path);
+            Ok(())
+        }
+        None => {
+            print!("{}", contents);
+            Ok(())
+        }
+    }
+}
+
+pub fn run(args: &ConfigArgs) -> Result<(), String> {
+    match &args.command {
+        ConfigCommand::Init { output, force } => {
+            if let Some(path) = output {
+                if !force && std::path::Path::new(path).exists() {
+                    return Err(format!("{} exists; pass --force to overwrite it", path));
+                }
+            }
+            write_or_print(output.as_deref(), &template())
+        }
+        ConfigCommand::Schema { output } => {
+            let schema = serde_json::to_string_pretty(&schema()).map_err(|e| e.to_string())?;
+            write_or_print(output.as_deref(), &format!("{}\n", schema))
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use crate::api::AlertResult;
+use crate::error::RedAlertError;
+use clap::ValueEnum;
+use std::future::Future;
+use std::pin::Pin;
+
+pub type AlertsFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<AlertResult>, RedAlertError>> + Send + 'a>>;
+
+// How a country's alerts name the places they cover
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Regions {
+    // Cities of the city data, routed to the zones of their district
+    Cities,
+    // Regions of the feed, routed through an --area-map listing these areas for each channel
+    Areas(&'static str),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Language {
+    /// English names from the city data
+    English,
+    /// Names as the alert source gives them, e.g. Hebrew for Israel
+    Local,
+}
+
+// Everything specific to one country's alert system: where its alerts come from,
+// how its categories are named, how its places map to channels and which language
+// place names in messages are given in
+pub trait CountryProfile: Send {
+    // Name of the alert source in events and logs
+    fn source(&self) -> &'static str;
+
+    // Alerts currently in effect, with their categories mapped to the gateway's names
+    fn fetch_alerts(&mut self) -> AlertsFuture<'_>;
+
+    fn regions(&self) -> Regions;
+
+    // Default language of place names in messages, unless --language is given
+    fn language(&self) -> Language;
+}
@@ -0,0 +1,120 @@
+use crate::active::{lock_active, SharedActiveAlerts};
+use crate::debug::StateRequest;
+use crate::events::{self, Event};
+use crate::SharedPause;
+use crate::DbusBus;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot};
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+
+const BUS_NAME: &str = "io.github.rcgv1.RedAlertMeshtastic";
+const OBJECT_PATH: &str = "/io/github/rcgv1/RedAlertMeshtastic";
+
+// State the D-Bus methods read
+#[derive(Clone)]
+pub struct DbusState {
+    pub state_tx: mpsc::Sender<StateRequest>,
+    pub active: SharedActiveAlerts,
+    pub paused: SharedPause,
+}
+
+struct GatewayInterface {
+    state: DbusState,
+}
+
+#[zbus::interface(name = "io.github.rcgv1.RedAlertMeshtastic1")]
+impl GatewayInterface {
+    // The full state dump as JSON, as served on /debug/state
+    async fn status(&self) -> fdo::Result<String> {
+        let (reply, dump) = oneshot::channel();
+        self.state
+            .state_tx
+            .send(reply)
+            .await
+            .map_err(|_| fdo::Error::Failed("alert pipeline is not running".to_string()))?;
+        // The alert loop answers between alerts; it may be busy sending
+        match tokio::time::timeout(Duration::from_secs(10), dump).await {
+            Ok(Ok(state)) => Ok(state.to_string()),
+            _ => Err(fdo::Error::Failed("alert loop is busy; try again".to_string())),
+        }
+    }
+
+    // Cities currently under alert
+    async fn active_cities(&self) -> Vec<String> {
+        let mut cities: Vec<String> = lock_active(&self.state.active)
+            .snapshot()
+            .into_iter()
+            .map(|city| city.name)
+            .collect();
+        cities.sort();
+        cities
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn paused(&self) -> bool {
+        self.state.paused.load(Ordering::SeqCst)
+    }
+
+    // An alert came in from a source, before any routing
+    #[zbus(signal)]
+    async fn alert_received(
+        emitter: &SignalEmitter<'_>,
+        source: &str,
+        alert_type: &str,
+        cities: &[String],
+        instructions: &str,
+    ) -> zbus::Result<()>;
+
+    // A message was handed to the transport
+    #[zbus(signal)]
+    async fn alert_sent(emitter: &SignalEmitter<'_>, channel: u32, category: &str, message: &str) -> zbus::Result<()>;
+}
+
+// Emit the D-Bus signal of an event, if it has one
+async fn emit_signal(emitter: &SignalEmitter<'_>, event: &Event) -> zbus::Result<()> {
+    match event {
+        Event::AlertFetched { source, alert_type, cities, instructions } => {
+            GatewayInterface::alert_received(emitter, source, alert_type, cities, instructions.as_deref().unwrap_or_default()).await
+        }
+        Event::SendSucceeded { channel, category, message, .. } => {
+            GatewayInterface::alert_sent(emitter, *channel, category, message).await
+        }
+        _ => Ok(()),
+    }
+}
+
+// Offer the gateway's status on D-Bus and signal alerts as they come in and go out,
+// for desktop applets and system daemons
+pub async fn serve(bus: DbusBus, state: DbusState) -> Result<(), String> {
+    let (builder, bus) = match bus {
+        DbusBus::System => (zbus::connection::Builder::system(), "system"),
+        DbusBus::Session => (zbus::connection::Builder::session(), "session"),
+    };
+    let connection = builder
+        .and_then(|builder| builder.name(BUS_NAME))
+        .and_then(|builder| builder.serve_at(OBJECT_PATH, GatewayInterface { state }))
+        .map_err(|e| format!("Failed to set up the D-Bus service: {}", e))?
+        .build()
+        .await
+        .map_err(|e| format!("Failed to register {} on the {} bus: {}", BUS_NAME, bus, e))?;
+    let emitter = SignalEmitter::new(&connection, OBJECT_PATH).map_err(|e| e.to_string())?;
+    log::info!("Offering {} on the {} bus", BUS_NAME, bus);
+
+    let mut live = events::subscribe();
+    loop {
+        match live.recv().await {
+            Ok((_, event)) => {
+                if let Err(e) = emit_signal(&emitter, &event).await {
+                    log::warn!("Failed to emit a D-Bus signal: {}", e);
+                }
+            }
+            Err(RecvError::Lagged(missed)) => {
+                log::warn!("D-Bus signals fell behind; {} event(s) skipped", missed);
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+// A request for a dump of the gateway state, answered with the dump
+pub type StateRequest = oneshot::Sender<Value>;
+
+// Request a state dump on every SIGUSR1; the alert loop logs it
+#[cfg(unix)]
+pub async fn dump_on_sigusr1(requests: mpsc::Sender<StateRequest>) -> Result<(), String> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = signal(SignalKind::user_defined1()).map_err(|e| format!("Failed to listen for SIGUSR1: {}", e))?;
+    while signals.recv().await.is_some() {
+        let (reply, _) = oneshot::channel();
+        if requests.send(reply).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
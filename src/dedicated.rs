@@ -0,0 +1,109 @@
+use crate::channels::{ChannelNames, ChannelRef};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// A [[category]] table of the config file: an extra channel that gets every alert
+// of a category on top of its zones, e.g. UAV incursions tracked on their own channel
+#[derive(Debug, Clone, Deserialize)]
+pub struct CategoryChannelConfig {
+    pub category: String,
+    pub channel: ChannelRef,
+    // Message text with {category}, {time}, {cities} and {count} filled in
+    #[serde(default)]
+    pub template: Option<String>,
+    // Minimum seconds between messages on the channel for the category
+    #[serde(default)]
+    pub min_gap: u64,
+}
+
+// A resolved [[category]] table
+#[derive(Debug, Clone)]
+pub struct CategoryChannel {
+    pub category: String,
+    pub channel: u32,
+    pub template: Option<String>,
+    pub min_gap: Duration,
+}
+
+// Dedicated channels per alert category, with when each last carried its category
+#[derive(Debug, Default)]
+pub struct CategoryChannels {
+    channels: Vec<CategoryChannel>,
+    last_sent: HashMap<(String, u32), Instant>,
+}
+
+// Fill in the placeholders of a [[category]] template
+pub fn render(template: &str, category: &str, time: &str, cities: &str, count: usize) -> String {
+    template
+        .replace("{category}", category)
+        .replace("{time}", time)
+        .replace("{cities}", cities)
+        .replace("{count}", &count.to_string())
+}
+
+impl CategoryChannels {
+    pub fn new(configs: Vec<CategoryChannelConfig>, names: &mut ChannelNames) -> Result<Self, String> {
+        let mut channels = Vec::with_capacity(configs.len());
+        for config in configs {
+            let channel = names.resolve(&config.channel)?;
+            if channel == 0 {
+                return Err(format!(
+                    "[[category]] {} can't use channel 0, which already gets country-wide alerts",
+                    config.category
+                ));
+            }
+            channels.push(CategoryChannel {
+                category: config.category,
+                channel,
+                template: config.template,
+                min_gap: Duration::from_secs(config.min_gap),
+            });
+        }
+        Ok(CategoryChannels {
+            channels,
+            last_sent: HashMap::new(),
+        })
+    }
+
+    pub fn channels(&self) -> &[CategoryChannel] {
+        &self.channels
+    }
+
+    // Dedicated channels of a category
+    pub fn channels_for(&self, category: &str) -> Vec<u32> {
+        self.channels
+            .iter()
+            .filter(|channel| channel.category == category)
+            .map(|channel| channel.channel)
+            .collect()
+    }
+
+    // Template of the category on a dedicated channel
+    pub fn template_for(&self, channel: u32, category: &str) -> Option<&str> {
+        self.find(channel, category).and_then(|channel| channel.template.as_deref())
+    }
+
+    fn find(&self, channel: u32, category: &str) -> Option<&CategoryChannel> {
+        self.channels
+            .iter()
+            .find(|dedicated| dedicated.channel == channel && dedicated.category == category)
+    }
+
+    // Whether the category may go out on the channel now; channels that are not
+    // dedicated to the category are never held back here
+    pub fn allows(&self, channel: u32, category: &str) -> bool {
+        let Some(dedicated) = self.find(channel, category) else {
+            return true;
+        };
+        self.last_sent
+            .get(&(category.to_string(), channel))
+            .is_none_or(|last| last.elapsed() >= dedicated.min_gap)
+    }
+
+    pub fn record(&mut self, channel: u32, category: &str) {
+        if self.find(channel, category).is_some() {
+            self.last_sent.insert((category.to_string(), channel), Instant::now());
+        }
+    }
+}
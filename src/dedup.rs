@@ -0,0 +1,111 @@
+use crate::api::AlertResult;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+// How long an event is remembered after it was last seen
+const DEDUP_TTL: ChronoDuration = ChronoDuration::hours(1);
+
+// When an event was last seen, and whether it was seen again after it was first handled
+#[derive(Debug, Clone, Copy)]
+struct Seen {
+    last_seen: DateTime<Utc>,
+    repeated: bool,
+}
+
+// What the dedup left of an alert
+#[derive(Debug, Clone, Copy)]
+pub struct Deduped {
+    // Whether anything is left to send
+    pub new: bool,
+    // Cities dropped as repeats for the first time; an event listed on every poll counts once
+    pub first_repeats: usize,
+}
+
+// Remembers dated events (history and combined feeds) so each one is sent once
+#[derive(Debug, Default)]
+pub struct AlertDedup {
+    seen: HashMap<String, Seen>,
+}
+
+impl AlertDedup {
+    pub fn new() -> Self {
+        AlertDedup::default()
+    }
+
+    // One event: a category at a city at its official time
+    pub fn key(alert_type: &str, city: &str, alert_date: DateTime<Utc>) -> String {
+        format!("{}|{}|{}", alert_type, city, alert_date.timestamp())
+    }
+
+    // Drop the cities of a dated alert that were already handled. Alerts without an
+    // official time are kept as they are.
+    pub fn retain_new(&mut self, alert: &mut AlertResult, now: DateTime<Utc>) -> Deduped {
+        self.seen.retain(|_, seen| now - seen.last_seen <= DEDUP_TTL);
+
+        let Some(alert_date) = alert.alert_date else {
+            return Deduped { new: true, first_repeats: 0 };
+        };
+
+        let mut first_repeats = 0;
+        alert.cities.retain(|city| {
+            let key = AlertDedup::key(&alert.alert_type, city, alert_date);
+            match self.seen.get_mut(&key) {
+                Some(seen) => {
+                    seen.last_seen = now;
+                    if !seen.repeated {
+                        seen.repeated = true;
+                        first_repeats += 1;
+                    }
+                    false
+                }
+                None => {
+                    self.seen.insert(key, Seen { last_seen: now, repeated: false });
+                    true
+                }
+            }
+        });
+        Deduped {
+            new: !alert.cities.is_empty(),
+            first_repeats,
+        }
+    }
+
+    // Remembered events with when they were last seen, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let mut keys: Vec<(&String, &Seen)> = self.seen.iter().collect();
+        keys.sort_by_key(|(key, _)| *key);
+        let keys: Vec<Value> = keys
+            .into_iter()
+            .map(|(key, seen)| json!({ "key": key, "last_seen": seen.last_seen.to_rfc3339(), "repeated": seen.repeated }))
+            .collect();
+        Value::Array(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_each_repeated_event_once() {
+        let alert_date = Utc::now();
+        let alert = || AlertResult {
+            alert_type: "missiles".to_string(),
+            cities: vec!["Sderot".to_string()],
+            instructions: None,
+            zones: Vec::new(),
+            alert_date: Some(alert_date),
+            areas: Default::default(),
+        };
+        let mut dedup = AlertDedup::new();
+        let deduped = dedup.retain_new(&mut alert(), alert_date);
+        assert!(deduped.new);
+        assert_eq!(deduped.first_repeats, 0);
+        for repeat in [1, 0] {
+            let deduped = dedup.retain_new(&mut alert(), alert_date);
+            assert!(!deduped.new);
+            assert_eq!(deduped.first_repeats, repeat);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+// USB vendor IDs of boards and USB-serial bridges Meshtastic devices use,
+// the same heuristic the Python CLI applies when looking for ports
+const MESHTASTIC_USB_VENDORS: [(u16, &str); 8] = [
+    (0x10c4, "Silicon Labs CP210x"),
+    (0x1a86, "WCH CH340/CH9102"),
+    (0x0403, "FTDI"),
+    (0x303a, "Espressif ESP32"),
+    (0x239a, "Adafruit nRF52 (RAK4631)"),
+    (0x2886, "Seeed Studio"),
+    (0x2e8a, "Raspberry Pi RP2040"),
+    (0x1915, "Nordic Semiconductor"),
+];
+
+// How long a probe may take before the port is considered unresponsive
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The meshtastic CLI holds the radio's port for its whole run, so runs go one at a
+// time; an alert send waits for a background command instead of racing it for the port
+static CLI: Mutex<()> = Mutex::new(());
+
+// Runs waiting for the radio; a long run that can be cut short gives way to them
+static WAITING: AtomicUsize = AtomicUsize::new(0);
+
+// Hold the radio for a meshtastic run; blocks, so async code takes it on a blocking thread
+pub fn lock_cli() -> MutexGuard<'static, ()> {
+    WAITING.fetch_add(1, Ordering::SeqCst);
+    let radio = CLI.lock().unwrap_or_else(PoisonError::into_inner);
+    WAITING.fetch_sub(1, Ordering::SeqCst);
+    radio
+}
+
+// Whether another run is waiting for the radio
+pub fn cli_wanted() -> bool {
+    WAITING.load(Ordering::SeqCst) > 0
+}
+
+// Run a meshtastic command to completion while holding the radio
+pub fn run_cli(cmd: &mut Command) -> std::io::Result<Output> {
+    let _radio = lock_cli();
+    cmd.output()
+}
+
+// How the meshtastic CLI reaches the radio
+#[derive(Debug, Clone)]
+pub enum Device {
+    // Let the meshtastic CLI pick a device itself
+    Default,
+    Host(String),
+    Port(String),
+    #[cfg(feature = "ble")]
+    Ble(String),
+}
+
+impl Device {
+    // Add the connection arguments to a meshtastic command
+    pub fn apply(&self, cmd: &mut Command) {
+        match self {
+            Device::Default => {}
+            Device::Host(host) => {
+                cmd.arg("--host").arg(host);
+            }
+            Device::Port(port) => {
+                cmd.arg("--port").arg(port);
+            }
+            #[cfg(feature = "ble")]
+            Device::Ble(ble) => {
+                cmd.arg("--ble").arg(ble);
+            }
+        }
+    }
+}
+
+// A serial port that looks like a Meshtastic device
+#[derive(Debug, Clone)]
+pub struct SerialCandidate {
+    pub port: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub vendor: &'static str,
+}
+
+// Read a hex ID (idVendor/idProduct) from sysfs
+fn read_usb_id(dir: &Path, name: &str) -> Option<u16> {
+    let value = std::fs::read_to_string(dir.join(name)).ok()?;
+    u16::from_str_radix(value.trim(), 16).ok()
+}
+
+// Walk up from a tty's device node to the USB device holding its IDs
+fn usb_ids(tty: &Path) -> Option<(u16, u16)> {
+    let mut dir: PathBuf = std::fs::canonicalize(tty.join("device")).ok()?;
+    for _ in 0..4 {
+        if let (Some(vendor), Some(product)) = (read_usb_id(&dir, "idVendor"), read_usb_id(&dir, "idProduct")) {
+            return Some((vendor, product));
+        }
+        dir = dir.parent()?.to_path_buf();
+    }
+    None
+}
+
+// USB serial ports whose vendor is known to ship Meshtastic devices (Linux sysfs)
+pub fn find_serial_candidates() -> Vec<SerialCandidate> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/tty") else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<SerialCandidate> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with("ttyUSB") && !name.starts_with("ttyACM") {
+                return None;
+            }
+            let (vendor_id, product_id) = usb_ids(&entry.path())?;
+            let (_, vendor) = MESHTASTIC_USB_VENDORS.iter().find(|(id, _)| *id == vendor_id)?;
+            Some(SerialCandidate {
+                port: format!("/dev/{}", name),
+                vendor_id,
+                product_id,
+                vendor,
+            })
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.port.cmp(&b.port));
+    candidates
+}
+
+// Check that a Meshtastic node answers on the port
+pub fn probe_port(port: &str) -> bool {
+    let mut cmd = Command::new("meshtastic");
+    cmd.arg("--port").arg(port).arg("--info");
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::null());
+
+    let _radio = lock_cli();
+    let Ok(mut child) = cmd.spawn() else {
+        return false;
+    };
+    let Some(stdout) = child.stdout.take() else {
+        return false;
+    };
+
+    // Only the first line matters; read it on a thread so a port that never answers can't block startup
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        let _ = BufReader::new(stdout).read_line(&mut line);
+        let _ = tx.send(line);
+    });
+    let first_line = rx.recv_timeout(PROBE_TIMEOUT).unwrap_or_default();
+
+    let _ = child.kill();
+    let _ = child.wait();
+    first_line.trim_end() == "Connected to radio"
+}
+
+// Pick the first candidate port with a responding node
+pub fn detect_serial_port() -> Option<String> {
+    let candidates = find_serial_candidates();
+    if candidates.is_empty() {
+        log::warn!("No USB serial port that looks like a Meshtastic device was found; leaving the choice to the meshtastic CLI");
+        return None;
+    }
+
+    for candidate in candidates {
+        log::info!(
+            "Probing {} ({} {:04x}:{:04x})",
+            candidate.port,
+            candidate.vendor,
+            candidate.vendor_id,
+            candidate.product_id
+        );
+        if probe_port(&candidate.port) {
+            log::info!("Using Meshtastic device on {}", candidate.port);
+            return Some(candidate.port);
+        }
+        log::warn!("No Meshtastic node answered on {}", candidate.port);
+    }
+    None
+}
@@ -0,0 +1,118 @@
+use crate::active::ACTIVE_ALERT_TTL;
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, Utc};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::Duration;
+
+// Window covered by the digest
+const DIGEST_WINDOW: ChronoDuration = ChronoDuration::hours(24);
+
+// Longest digest we try to fit in a single LoRa text message
+const MAX_DIGEST_LEN: usize = 200;
+
+// One alert counted in the digest: a zone newly alerted for a category
+#[derive(Debug, Clone)]
+struct LoggedAlert {
+    time: DateTime<Utc>,
+    category: String,
+    zone: u32,
+}
+
+// Rolling 24h log of alerts for the daily digest
+#[derive(Debug, Default)]
+pub struct AlertLog {
+    alerts: VecDeque<LoggedAlert>,
+    // Last time each (category, zone) was seen, so an ongoing alert is counted once
+    last_seen: HashMap<(String, u32), DateTime<Utc>>,
+    // Local date the last digest was sent for
+    last_digest: Option<NaiveDate>,
+}
+
+impl AlertLog {
+    pub fn new() -> Self {
+        AlertLog::default()
+    }
+
+    // Record the zones an alert was routed to
+    pub fn record(&mut self, category: &str, zones: &[u32], now: DateTime<Utc>) {
+        let ttl = ChronoDuration::from_std(ACTIVE_ALERT_TTL).unwrap_or_default();
+        for zone in zones {
+            let key = (category.to_string(), *zone);
+            let is_new = match self.last_seen.get(&key) {
+                Some(seen) => now - *seen > ttl,
+                None => true,
+            };
+            if is_new {
+                self.alerts.push_back(LoggedAlert {
+                    time: now,
+                    category: category.to_string(),
+                    zone: *zone,
+                });
+            }
+            self.last_seen.insert(key, now);
+        }
+        self.prune(now);
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        while self.alerts.front().is_some_and(|alert| now - alert.time > DIGEST_WINDOW) {
+            self.alerts.pop_front();
+        }
+        self.last_seen.retain(|_, seen| now - *seen <= DIGEST_WINDOW);
+    }
+
+    // Whether the digest for the given local date and hour is due
+    pub fn digest_due(&self, today: NaiveDate, hour: u32, digest_hour: u32) -> bool {
+        hour == digest_hour && self.last_digest != Some(today)
+    }
+
+    pub fn mark_digest_sent(&mut self, today: NaiveDate) {
+        self.last_digest = Some(today);
+    }
+
+    // Summary of the last 24h, e.g. "📊24h up 2d3h: missiles 5 (Z1:3 Z4:2)"
+    pub fn digest(&mut self, now: DateTime<Utc>, uptime: Duration) -> String {
+        self.prune(now);
+
+        let mut by_category: BTreeMap<&str, BTreeMap<u32, u32>> = BTreeMap::new();
+        for alert in &self.alerts {
+            *by_category
+                .entry(alert.category.as_str())
+                .or_default()
+                .entry(alert.zone)
+                .or_default() += 1;
+        }
+
+        let summary = if by_category.is_empty() {
+            "no alerts".to_string()
+        } else {
+            by_category
+                .iter()
+                .map(|(category, zones)| {
+                    let total: u32 = zones.values().sum();
+                    let zones: Vec<String> = zones.iter().map(|(zone, count)| format!("Z{}:{}", zone, count)).collect();
+                    format!("{} {} ({})", category, total, zones.join(" "))
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let uptime_hours = uptime.as_secs() / 3600;
+        let mut digest = format!(
+            "📊24h up {}d{}h: {}",
+            uptime_hours / 24,
+            uptime_hours % 24,
+            summary
+        );
+
+        // Keep the digest to a single message, cutting on a character boundary
+        if digest.len() > MAX_DIGEST_LEN {
+            let mut cut = MAX_DIGEST_LEN - '…'.len_utf8();
+            while !digest.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            digest.truncate(cut);
+            digest.push('…');
+        }
+        digest
+    }
+}
@@ -0,0 +1,256 @@
+use crate::device::Device;
+use crate::nodedb;
+use clap::Args;
+#[cfg(feature = "mdns")]
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+#[cfg(feature = "ble")]
+use std::process::{Command, Stdio};
+#[cfg(feature = "mdns")]
+use std::time::Instant;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+// Port of the Meshtastic TCP API
+const MESHTASTIC_TCP_PORT: u16 = 4403;
+
+// Service Meshtastic nodes advertise over mDNS
+#[cfg(feature = "mdns")]
+const MDNS_SERVICE: &str = "_meshtastic._tcp.local.";
+
+// How long to wait for a host to accept a connection during the LAN probe
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Largest subnet the LAN probe accepts (a /22, 1022 hosts)
+const MIN_SUBNET_PREFIX: u8 = 22;
+
+#[derive(Args, Debug)]
+pub struct DiscoverArgs {
+    /// Seconds to spend browsing mDNS
+    #[cfg(feature = "mdns")]
+    #[arg(long, default_value_t = 5)]
+    pub timeout: u64,
+
+    /// IPv4 subnet to probe for the Meshtastic TCP port, e.g. 192.168.1.0/24 (default: the local /24)
+    #[arg(long)]
+    pub subnet: Option<String>,
+
+    /// Skip the BLE scan
+    #[cfg(feature = "ble")]
+    #[arg(long)]
+    pub no_ble: bool,
+
+    /// Skip mDNS and the LAN probe
+    #[arg(long)]
+    pub no_lan: bool,
+}
+
+// Parse `meshtastic --ble-scan` output lines like
+//   Found: name='Meshtastic_1a2b' address='C8:2E:18:01:1A:2B'
+#[cfg(feature = "ble")]
+fn parse_ble_scan(output: &str) -> Vec<(String, String)> {
+    let quoted = |line: &str, key: &str| -> Option<String> {
+        let start = line.find(key)? + key.len();
+        let end = line[start..].find('\'')?;
+        Some(line[start..start + end].to_string())
+    };
+
+    output
+        .lines()
+        .filter_map(|line| Some((quoted(line, "name='")?, quoted(line, "address='")?)))
+        .collect()
+}
+
+// Nearby BLE devices, by address
+#[cfg(feature = "ble")]
+fn scan_ble() -> Result<Vec<(String, String)>, String> {
+    log::info!("Scanning for BLE devices...");
+    let mut cmd = Command::new("meshtastic");
+    cmd.arg("--ble-scan").stdout(Stdio::piped()).stderr(Stdio::null());
+    let output = crate::device::run_cli(&mut cmd).map_err(|e| format!("Failed to execute meshtastic --ble-scan: {}", e))?;
+    Ok(parse_ble_scan(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// Nodes advertising the Meshtastic service over mDNS, as (instance name, address)
+#[cfg(feature = "mdns")]
+fn browse_mdns(timeout: Duration) -> Result<Vec<(String, SocketAddr)>, String> {
+    log::info!("Browsing mDNS for Meshtastic nodes ({})", MDNS_SERVICE);
+    let daemon = ServiceDaemon::new().map_err(|e| format!("Failed to start mDNS: {}", e))?;
+    let events = daemon
+        .browse(MDNS_SERVICE)
+        .map_err(|e| format!("Failed to browse mDNS: {}", e))?;
+
+    let mut found = Vec::new();
+    let deadline = Instant::now() + timeout;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        let Ok(event) = events.recv_timeout(remaining) else {
+            break;
+        };
+        if let ServiceEvent::ServiceResolved(info) = event {
+            let name = info
+                .get_fullname()
+                .trim_end_matches(MDNS_SERVICE)
+                .trim_end_matches('.')
+                .to_string();
+            for address in info.get_addresses() {
+                found.push((name.clone(), SocketAddr::new(*address, info.get_port())));
+            }
+        }
+    }
+
+    let _ = daemon.shutdown();
+    Ok(found)
+}
+
+// IPv4 address the host uses for outgoing traffic; no packet is sent
+fn local_ipv4() -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(ip) if !ip.is_loopback() && !ip.is_unspecified() => Some(ip),
+        _ => None,
+    }
+}
+
+// Host addresses of an IPv4 subnet written as ADDRESS/PREFIX
+fn subnet_hosts(subnet: &str) -> Result<Vec<Ipv4Addr>, String> {
+    let (address, prefix) = subnet
+        .split_once('/')
+        .ok_or_else(|| format!("Expected ADDRESS/PREFIX, got {}", subnet))?;
+    let address: Ipv4Addr = address
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid IPv4 address in {}", subnet))?;
+    let prefix: u8 = prefix
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid prefix length in {}", subnet))?;
+    if !(MIN_SUBNET_PREFIX..=30).contains(&prefix) {
+        return Err(format!(
+            "Subnet {} must have a prefix between /{} and /30",
+            subnet, MIN_SUBNET_PREFIX
+        ));
+    }
+
+    let mask = u32::MAX << (32 - prefix);
+    let network = u32::from(address) & mask;
+    let broadcast = network | !mask;
+    Ok(((network + 1)..broadcast).map(Ipv4Addr::from).collect())
+}
+
+// Hosts in the subnet accepting connections on the Meshtastic TCP port
+async fn probe_lan(hosts: Vec<Ipv4Addr>) -> Vec<SocketAddr> {
+    log::info!("Probing {} hosts for TCP port {}...", hosts.len(), MESHTASTIC_TCP_PORT);
+    let mut probes = JoinSet::new();
+    for host in hosts {
+        probes.spawn(async move {
+            let address = SocketAddr::new(IpAddr::V4(host), MESHTASTIC_TCP_PORT);
+            match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await {
+                Ok(Ok(_)) => Some(address),
+                _ => None,
+            }
+        });
+    }
+
+    let mut open = Vec::new();
+    while let Some(result) = probes.join_next().await {
+        if let Ok(Some(address)) = result {
+            open.push(address);
+        }
+    }
+    open
+}
+
+// Owner name reported by `meshtastic --info`, e.g. "Owner: Gateway North (GWN)"
+fn node_owner(host: &str) -> Option<String> {
+    let info = nodedb::run_info(&Device::Host(host.to_string())).ok()?;
+    info.lines()
+        .find_map(|line| line.strip_prefix("Owner:"))
+        .map(|owner| owner.trim().to_string())
+}
+
+// `meshtastic --host` value for an address, leaving out the default port
+fn host_arg(address: &SocketAddr) -> String {
+    if address.port() == MESHTASTIC_TCP_PORT {
+        address.ip().to_string()
+    } else {
+        address.to_string()
+    }
+}
+
+// List reachable Meshtastic nodes with the option to reach each of them
+pub async fn run(args: &DiscoverArgs) -> Result<(), String> {
+    // Option to use -> name of the node
+    let mut found: BTreeMap<String, String> = BTreeMap::new();
+
+    #[cfg(feature = "ble")]
+    if !args.no_ble {
+        match tokio::task::spawn_blocking(scan_ble).await.map_err(|e| e.to_string())? {
+            Ok(devices) => {
+                for (name, address) in devices {
+                    found.insert(format!("--ble {}", address), name);
+                }
+            }
+            Err(e) => log::warn!("BLE scan failed: {}", e),
+        }
+    }
+
+    if !args.no_lan {
+        let mut hosts: BTreeMap<String, Option<String>> = BTreeMap::new();
+
+        #[cfg(feature = "mdns")]
+        match tokio::task::spawn_blocking({
+            let timeout = Duration::from_secs(args.timeout);
+            move || browse_mdns(timeout)
+        })
+            .await
+            .map_err(|e| e.to_string())?
+        {
+            Ok(services) => {
+                for (name, address) in services {
+                    hosts.insert(host_arg(&address), Some(name));
+                }
+            }
+            Err(e) => log::warn!("mDNS discovery failed: {}", e),
+        }
+
+        let subnet = match &args.subnet {
+            Some(subnet) => Some(subnet.clone()),
+            None => local_ipv4().map(|ip| format!("{}/24", ip)),
+        };
+        match subnet {
+            Some(subnet) => {
+                for address in probe_lan(subnet_hosts(&subnet)?).await {
+                    hosts.entry(host_arg(&address)).or_insert(None);
+                }
+            }
+            None => log::warn!("Could not determine the local subnet; use --subnet to probe the LAN"),
+        }
+
+        // Ask every node for its owner name, in parallel since each takes a few seconds
+        let mut lookups = JoinSet::new();
+        for (host, name) in hosts {
+            lookups.spawn_blocking(move || {
+                let owner = node_owner(&host);
+                (host, owner.or(name).unwrap_or_else(|| "unknown".to_string()))
+            });
+        }
+        while let Some(result) = lookups.join_next().await {
+            if let Ok((host, name)) = result {
+                found.insert(format!("--host {}", host), name);
+            }
+        }
+    }
+
+    if found.is_empty() {
+        println!("No Meshtastic nodes found");
+        return Ok(());
+    }
+    let width = found.values().map(|name| name.chars().count()).max().unwrap_or(0);
+    for (option, name) in &found {
+        println!("{:width$}  {}", name, option, width = width);
+    }
+    Ok(())
+}
@@ -0,0 +1,381 @@
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Preamble length Meshtastic uses on every modem preset
+const PREAMBLE_SYMBOLS: f64 = 16.0;
+
+// Meshtastic header in front of every packet (to, from, id, flags, channel hash, ...)
+const PACKET_HEADER_BYTES: usize = 16;
+
+// Data protobuf around the text: portnum field and payload tag with length
+const DATA_OVERHEAD_BYTES: usize = 5;
+
+// Window the duty cycle is measured over, as in ETSI EN 300 220
+pub const DUTY_CYCLE_WINDOW: Duration = Duration::from_secs(3600);
+
+// LoRa region of the radio, named as in the Meshtastic firmware
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Region {
+    #[value(name = "US")]
+    Us,
+    #[value(name = "EU_433")]
+    Eu433,
+    #[value(name = "EU_868")]
+    Eu868,
+    #[value(name = "CN")]
+    Cn,
+    #[value(name = "JP")]
+    Jp,
+    #[value(name = "ANZ")]
+    Anz,
+    #[value(name = "KR")]
+    Kr,
+    #[value(name = "TW")]
+    Tw,
+    #[value(name = "RU")]
+    Ru,
+    #[value(name = "IN")]
+    In,
+    #[value(name = "NZ_865")]
+    Nz865,
+    #[value(name = "TH")]
+    Th,
+    #[value(name = "UA_433")]
+    Ua433,
+    #[value(name = "UA_868")]
+    Ua868,
+    #[value(name = "MY_433")]
+    My433,
+    #[value(name = "MY_919")]
+    My919,
+    #[value(name = "SG_923")]
+    Sg923,
+    #[value(name = "LORA_24")]
+    Lora24,
+}
+
+impl Region {
+    // Legal duty cycle in percent, the same limits the firmware applies
+    pub fn duty_cycle_percent(&self) -> f64 {
+        match self {
+            Region::Eu433 | Region::Eu868 | Region::Ua433 => 10.0,
+            Region::Ua868 => 1.0,
+            _ => 100.0,
+        }
+    }
+}
+
+// Modem preset of the channel, which sets how long each byte stays on the air
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ModemPreset {
+    LongFast,
+    LongModerate,
+    LongSlow,
+    VeryLongSlow,
+    MediumSlow,
+    MediumFast,
+    ShortSlow,
+    ShortFast,
+    ShortTurbo,
+}
+
+impl ModemPreset {
+    // Spreading factor, bandwidth in kHz and coding rate denominator (4/x)
+    fn params(&self) -> (i32, f64, f64) {
+        match self {
+            ModemPreset::LongFast => (11, 250.0, 5.0),
+            ModemPreset::LongModerate => (11, 125.0, 8.0),
+            ModemPreset::LongSlow => (12, 125.0, 8.0),
+            ModemPreset::VeryLongSlow => (12, 62.5, 8.0),
+            ModemPreset::MediumSlow => (10, 250.0, 5.0),
+            ModemPreset::MediumFast => (9, 250.0, 5.0),
+            ModemPreset::ShortSlow => (8, 250.0, 5.0),
+            ModemPreset::ShortFast => (7, 250.0, 5.0),
+            ModemPreset::ShortTurbo => (7, 500.0, 5.0),
+        }
+    }
+
+    // Time on air of a LoRa packet with explicit header and CRC (Semtech AN1200.13)
+    pub fn airtime(&self, payload_bytes: usize) -> Duration {
+        let (sf, bandwidth_khz, coding_rate) = self.params();
+        let symbol_time = 2f64.powi(sf) / (bandwidth_khz * 1000.0);
+        let low_data_rate = if symbol_time > 0.016 { 1.0 } else { 0.0 };
+
+        let numerator = 8.0 * payload_bytes as f64 - 4.0 * sf as f64 + 28.0 + 16.0;
+        let payload_symbols =
+            8.0 + ((numerator / (4.0 * (sf as f64 - 2.0 * low_data_rate))).ceil() * coding_rate).max(0.0);
+        Duration::from_secs_f64((PREAMBLE_SYMBOLS + 4.25 + payload_symbols) * symbol_time)
+    }
+
+    // Time on air of a text message sent by the gateway
+    pub fn text_airtime(&self, text: &str) -> Duration {
+        self.airtime(PACKET_HEADER_BYTES + DATA_OVERHEAD_BYTES + text.len())
+    }
+}
+
+// What to do with a transmission under the duty cycle
+#[derive(Debug, PartialEq, Eq)]
+pub enum Plan {
+    Send,
+    // Hold it back this long
+    HoldBack(Duration),
+    // Don't send it, for this reason
+    Drop(String),
+}
+
+// Tracks the airtime of recent transmissions against the regional duty cycle
+#[derive(Debug)]
+pub struct DutyCycle {
+    percent: f64,
+    preset: ModemPreset,
+    // Airtime of the transmissions within the window, oldest first
+    sent: VecDeque<(Instant, Duration)>,
+}
+
+impl DutyCycle {
+    pub fn new(percent: f64, preset: ModemPreset) -> Self {
+        DutyCycle {
+            percent,
+            preset,
+            sent: VecDeque::new(),
+        }
+    }
+
+    fn budget(&self) -> Duration {
+        DUTY_CYCLE_WINDOW.mul_f64(self.percent / 100.0)
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some((sent_at, _)) = self.sent.front() {
+            if now.duration_since(*sent_at) < DUTY_CYCLE_WINDOW {
+                break;
+            }
+            self.sent.pop_front();
+        }
+    }
+
+    // Airtime used within the window
+    pub fn used(&self) -> Duration {
+        let now = Instant::now();
+        self.sent
+            .iter()
+            .filter(|(sent_at, _)| now.duration_since(*sent_at) < DUTY_CYCLE_WINDOW)
+            .map(|(_, airtime)| *airtime)
+            .sum()
+    }
+
+    pub fn airtime(&self, message: &str) -> Duration {
+        self.preset.text_airtime(message)
+    }
+
+    // How long until a transmission of this length fits the budget, or None if it never can
+    pub fn wait_for(&mut self, airtime: Duration) -> Option<Duration> {
+        let budget = self.budget();
+        if airtime > budget {
+            return None;
+        }
+
+        let now = Instant::now();
+        self.prune(now);
+        let mut used: Duration = self.sent.iter().map(|(_, airtime)| *airtime).sum();
+        for (sent_at, sent_airtime) in &self.sent {
+            if used + airtime <= budget {
+                break;
+            }
+            // Wait for this transmission to leave the window
+            used -= *sent_airtime;
+            if used + airtime <= budget {
+                return Some((*sent_at + DUTY_CYCLE_WINDOW).saturating_duration_since(now));
+            }
+        }
+        Some(Duration::ZERO)
+    }
+
+    // Whether a transmission goes out now, is held back until it fits, or has to be
+    // dropped because it wouldn't fit by the deadline
+    pub fn plan(&mut self, airtime: Duration, now: tokio::time::Instant, deadline: tokio::time::Instant) -> Plan {
+        match self.wait_for(airtime) {
+            Some(wait) if wait.is_zero() => Plan::Send,
+            Some(wait) if now + wait <= deadline => Plan::HoldBack(wait),
+            Some(wait) => Plan::Drop(format!("the message would have to wait {}s for airtime", wait.as_secs())),
+            None => Plan::Drop(format!(
+                "the message needs {}ms of airtime, more than the hourly budget",
+                airtime.as_millis()
+            )),
+        }
+    }
+
+    // Account for a transmission that was just made
+    pub fn record(&mut self, airtime: Duration) {
+        self.sent.push_back((Instant::now(), airtime));
+    }
+
+    // Budget and use of the window, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let used = self.used();
+        json!({
+            "percent": self.percent,
+            "preset": format!("{:?}", self.preset),
+            "budget_secs": self.budget().as_secs_f64(),
+            "used_secs": used.as_secs_f64(),
+            "transmissions": self.sent.len(),
+        })
+    }
+}
+
+// A message held back until it fits the duty cycle, sent from the alert loop once
+// it is due so other zones and the feed are handled in the meantime
+#[derive(Debug, Clone)]
+pub struct DeferredSend {
+    pub due: tokio::time::Instant,
+    // Dropped if it still doesn't fit by then
+    pub deadline: tokio::time::Instant,
+    pub channel: u32,
+    pub to: u32,
+    pub category: String,
+    pub portnum: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct DeferredSends {
+    pending: Vec<DeferredSend>,
+}
+
+impl DeferredSends {
+    pub fn push(&mut self, send: DeferredSend) {
+        self.pending.push(send);
+    }
+
+    // How many messages are held back
+    pub fn held(&self) -> usize {
+        self.pending.len()
+    }
+
+    // When the next held back message is due
+    pub fn next_due(&self) -> Option<tokio::time::Instant> {
+        self.pending.iter().map(|send| send.due).min()
+    }
+
+    // Take the messages that are due, in the order they were held back
+    pub fn take_due(&mut self) -> Vec<DeferredSend> {
+        let now = tokio::time::Instant::now();
+        let (due, pending) = self.pending.drain(..).partition(|send| send.due <= now);
+        self.pending = pending;
+        due
+    }
+
+    // Messages held back and when they are due, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let now = tokio::time::Instant::now();
+        json!(self
+            .pending
+            .iter()
+            .map(|send| json!({
+                "channel": send.channel,
+                "category": send.category,
+                "due_in_secs": send.due.saturating_duration_since(now).as_secs(),
+            }))
+            .collect::<Vec<_>>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3.6s of airtime per hour
+    fn tight() -> DutyCycle {
+        DutyCycle::new(0.1, ModemPreset::LongFast)
+    }
+
+    fn deferred(channel: u32, due: tokio::time::Instant) -> DeferredSend {
+        DeferredSend {
+            due,
+            deadline: due + Duration::from_secs(600),
+            channel,
+            to: 0xffff_ffff,
+            category: "missiles".to_string(),
+            portnum: 1,
+            message: "Red alert".to_string(),
+        }
+    }
+
+    #[test]
+    fn budget_and_airtime() {
+        let eu868 = DutyCycle::new(Region::Eu868.duty_cycle_percent(), ModemPreset::LongFast);
+        assert_eq!(eu868.budget(), Duration::from_secs(360));
+        assert_eq!(tight().budget(), Duration::from_millis(3600));
+        // 26 bytes at SF11/250kHz/CR4/5: 53.25 symbols of 8.192ms
+        assert_eq!(ModemPreset::LongFast.text_airtime("hello"), Duration::from_micros(436_224));
+        assert!(ModemPreset::VeryLongSlow.text_airtime("hello") > ModemPreset::LongSlow.text_airtime("hello"));
+    }
+
+    #[test]
+    fn waits_for_older_transmissions_to_leave_the_window() {
+        let mut duty_cycle = tight();
+        assert_eq!(duty_cycle.wait_for(Duration::from_secs(1)), Some(Duration::ZERO));
+
+        let now = Instant::now();
+        duty_cycle.sent.push_back((now - Duration::from_secs(100), Duration::from_secs(2)));
+        duty_cycle.sent.push_back((now - Duration::from_secs(50), Duration::from_millis(1500)));
+        assert_eq!(duty_cycle.used(), Duration::from_millis(3500));
+        assert_eq!(duty_cycle.wait_for(Duration::from_millis(100)), Some(Duration::ZERO));
+
+        // Half a second more only fits once the first transmission is an hour old
+        let wait = duty_cycle.wait_for(Duration::from_millis(500)).unwrap();
+        assert!(wait > Duration::from_secs(3499) && wait <= Duration::from_secs(3500));
+        // More than the whole budget never fits
+        assert_eq!(duty_cycle.wait_for(Duration::from_secs(4)), None);
+    }
+
+    #[test]
+    fn transmissions_older_than_the_window_are_forgotten() {
+        let mut duty_cycle = tight();
+        let Some(old) = Instant::now().checked_sub(DUTY_CYCLE_WINDOW + Duration::from_secs(1)) else {
+            return;
+        };
+        duty_cycle.sent.push_back((old, Duration::from_millis(3600)));
+        assert_eq!(duty_cycle.used(), Duration::ZERO);
+        assert_eq!(duty_cycle.wait_for(Duration::from_secs(1)), Some(Duration::ZERO));
+        assert!(duty_cycle.sent.is_empty());
+    }
+
+    #[test]
+    fn plans_send_hold_back_or_drop() {
+        let mut duty_cycle = tight();
+        let now = tokio::time::Instant::now();
+        let in_two_hours = now + 2 * DUTY_CYCLE_WINDOW;
+        assert_eq!(duty_cycle.plan(Duration::from_secs(1), now, now), Plan::Send);
+
+        duty_cycle.record(Duration::from_secs(3));
+        let held = duty_cycle.plan(Duration::from_secs(1), now, in_two_hours);
+        assert!(matches!(held, Plan::HoldBack(wait) if wait > Duration::from_secs(3590)));
+        // A deadline before the airtime frees up drops the message
+        let dropped = duty_cycle.plan(Duration::from_secs(1), now, now + Duration::from_secs(60));
+        assert!(matches!(dropped, Plan::Drop(reason) if reason.contains("would have to wait")));
+        let dropped = duty_cycle.plan(Duration::from_secs(4), now, in_two_hours);
+        assert!(matches!(dropped, Plan::Drop(reason) if reason.contains("more than the hourly budget")));
+    }
+
+    #[test]
+    fn held_counts_messages_until_they_are_due() {
+        let mut sends = DeferredSends::default();
+        assert_eq!((sends.held(), sends.next_due()), (0, None));
+
+        let now = tokio::time::Instant::now();
+        sends.push(deferred(2, now + Duration::from_secs(3600)));
+        sends.push(deferred(4, now));
+        sends.push(deferred(6, now));
+        assert_eq!(sends.held(), 3);
+        assert_eq!(sends.next_due(), Some(now));
+
+        let due: Vec<u32> = sends.take_due().iter().map(|send| send.channel).collect();
+        assert_eq!(due, vec![4, 6]);
+        assert_eq!(sends.held(), 1);
+        assert_eq!(sends.next_due(), Some(now + Duration::from_secs(3600)));
+        assert!(sends.take_due().is_empty());
+    }
+}
@@ -0,0 +1,37 @@
+use std::time::Duration;
+use thiserror::Error;
+
+// Failures of the gateway, by class, so callers can react to each differently
+#[derive(Debug, Error)]
+pub enum RedAlertError {
+    // The alert API could not be reached or answered with an error status
+    #[error("alert API unreachable: {0}")]
+    ApiUnreachable(String),
+    // Alert or city data didn't have the expected shape
+    #[error("parse error: {0}")]
+    ParseError(String),
+    // The radio (or the mesh MQTT broker) could not be reached
+    #[error("radio unavailable: {0}")]
+    RadioUnavailable(String),
+    // The alert API asked us to slow down (HTTP 429); poll again no sooner than this
+    #[error("alert API rate limited; retry after {}s", .retry_after.as_secs())]
+    RateLimited { retry_after: Duration },
+    // A message could not be handed to the transport, even after retrying
+    #[error("failed to send message after {attempts} attempt(s): {reason}")]
+    SendFailed { attempts: u32, reason: String },
+    // Sending would exceed the regional duty cycle for longer than the gateway may wait
+    #[error("duty cycle limit: {0}")]
+    DutyCycle(String),
+    // Transmission is paused from a controller, so nothing went on air
+    #[error("transmission is paused")]
+    Paused,
+    // Invalid command line, config file or mapping
+    #[error("{0}")]
+    Config(String),
+}
+
+impl From<serde_json::Error> for RedAlertError {
+    fn from(e: serde_json::Error) -> Self {
+        RedAlertError::ParseError(e.to_string())
+    }
+}
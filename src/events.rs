@@ -0,0 +1,162 @@
+use crate::influx;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex, PoisonError};
+use tokio::sync::broadcast;
+
+// Whether lifecycle events are written to stdout as JSON lines
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+// Events kept for a slow stream subscriber before it starts missing some
+const STREAM_CAPACITY: usize = 256;
+
+// An event with its time in Unix milliseconds
+pub type StampedEvent = (i64, Event);
+
+// Live events, for streaming clients
+static STREAM: LazyLock<broadcast::Sender<StampedEvent>> = LazyLock::new(|| broadcast::channel(STREAM_CAPACITY).0);
+
+// How many of the latest events are kept to replay to a client that connects
+static REPLAY: AtomicUsize = AtomicUsize::new(0);
+
+// The latest events, oldest first
+static RECENT: Mutex<VecDeque<StampedEvent>> = Mutex::new(VecDeque::new());
+
+// Lifecycle events of the gateway, serialized with an "event" tag
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    // An alert came in from a source, before any routing
+    AlertFetched {
+        source: String,
+        alert_type: String,
+        cities: Vec<String>,
+        instructions: Option<String>,
+    },
+    // An alert was skipped without being routed
+    AlertSkipped {
+        alert_type: String,
+        reason: String,
+    },
+    // An alert was resolved to the zones it will be sent to
+    AlertParsed {
+        alert_type: String,
+        cities: Vec<String>,
+        zones: Vec<u32>,
+    },
+    // An alert was no longer seen on a channel and has ended there
+    AlertCleared {
+        alert_type: String,
+        channel: u32,
+        cities: Vec<String>,
+    },
+    // The alert source rate limited the gateway, which stops polling it for a while
+    ApiRateLimited {
+        source: String,
+        retry_after_secs: u64,
+    },
+    // A message was handed to the transport
+    SendSucceeded {
+        channel: u32,
+        category: String,
+        message: String,
+        attempts: u32,
+    },
+    // A message would have been sent, but the gateway is only observing
+    SendObserved {
+        channel: u32,
+        category: String,
+        message: String,
+    },
+    // A message could not be sent after all retries
+    SendFailed {
+        channel: u32,
+        category: String,
+        message: String,
+        attempts: u32,
+        error: String,
+    },
+    // A background task failed or panicked and will be restarted
+    TaskFailed {
+        task: String,
+        error: String,
+    },
+    // The route to a monitored node was traced; no hops if it couldn't be reached
+    MeshPathChecked {
+        node: String,
+        hops: Option<u32>,
+        min_snr_db: Option<f64>,
+        degraded: Option<String>,
+    },
+    // A canary probe went through the send path, or didn't within its timeout
+    CanaryChecked {
+        channel: u32,
+        ok: bool,
+        latency_ms: Option<u64>,
+        error: Option<String>,
+    },
+}
+
+// Enable or disable JSON event output on stdout
+pub fn set_json_output(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+// Set how many of the latest events are replayed to a client on connect
+pub fn set_replay(events: usize) {
+    REPLAY.store(events, Ordering::Relaxed);
+}
+
+// An event as a JSON object with its time, as written to stdout and streamed to clients
+pub fn to_json(time: DateTime<Utc>, event: &Event) -> Value {
+    let mut line = json!({ "time": time.to_rfc3339() });
+    if let (Some(line), Ok(Value::Object(fields))) = (line.as_object_mut(), serde_json::to_value(event)) {
+        line.extend(fields);
+    }
+    line
+}
+
+// Publish a lifecycle event
+pub fn emit(event: Event) {
+    influx::record_event(&event);
+    let now = Utc::now();
+
+    // Recorded and sent under the same lock, so a subscriber sees every event exactly once
+    {
+        let mut recent = RECENT.lock().unwrap_or_else(PoisonError::into_inner);
+        let replay = REPLAY.load(Ordering::Relaxed);
+        if replay > 0 {
+            if recent.len() >= replay {
+                recent.pop_front();
+            }
+            recent.push_back((now.timestamp_millis(), event.clone()));
+        }
+        if STREAM.receiver_count() > 0 {
+            let _ = STREAM.send((now.timestamp_millis(), event.clone()));
+        }
+    }
+
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        let line = to_json(now, &event);
+
+        // One object per line, flushed right away so pipelines see it immediately
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+    }
+}
+
+// Receive every event emitted from now on
+pub fn subscribe() -> broadcast::Receiver<StampedEvent> {
+    STREAM.subscribe()
+}
+
+// The latest events, oldest first, and every event emitted after them
+pub fn subscribe_with_replay() -> (Vec<StampedEvent>, broadcast::Receiver<StampedEvent>) {
+    let recent = RECENT.lock().unwrap_or_else(PoisonError::into_inner);
+    (recent.iter().cloned().collect(), STREAM.subscribe())
+}
@@ -0,0 +1,96 @@
+use crate::capout::escape;
+use crate::notify::{Notification, Notifier};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+pub type SharedFeed = Arc<Mutex<AlertFeed>>;
+
+// Lock the shared feed, still usable if a task panicked while holding it
+pub fn lock_feed(feed: &SharedFeed) -> MutexGuard<'_, AlertFeed> {
+    feed.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct FeedEntry {
+    id: String,
+    sent: DateTime<Utc>,
+    notification: Notification,
+}
+
+// The latest alerts the gateway sent, newest first, for the Atom feed of the HTTP server
+pub struct AlertFeed {
+    entries: VecDeque<FeedEntry>,
+    capacity: usize,
+    // Makes entry IDs unique across restarts
+    started: i64,
+    counter: u64,
+}
+
+impl AlertFeed {
+    pub fn new(capacity: usize) -> Self {
+        AlertFeed {
+            entries: VecDeque::new(),
+            capacity: capacity.max(1),
+            started: Utc::now().timestamp_millis(),
+            counter: 0,
+        }
+    }
+
+    pub fn record(&mut self, notification: &Notification, sent: DateTime<Utc>) {
+        self.counter += 1;
+        self.entries.push_front(FeedEntry {
+            id: format!("urn:red-alert-meshtastic:{}-{}", self.started, self.counter),
+            sent,
+            notification: notification.clone(),
+        });
+        self.entries.truncate(self.capacity);
+    }
+
+    // Atom 1.0 document of the entries
+    pub fn atom(&self) -> String {
+        let updated = self.entries.front().map_or(self.started_at(), |entry| entry.sent);
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        xml.push_str("  <title>red-alert-meshtastic: transmitted alerts</title>\n");
+        xml.push_str("  <id>urn:red-alert-meshtastic:feed</id>\n");
+        xml.push_str(&format!("  <updated>{}</updated>\n", timestamp(updated)));
+        xml.push_str("  <author><name>red-alert-meshtastic</name></author>\n");
+        xml.push_str("  <generator>red-alert-meshtastic</generator>\n");
+        for entry in &self.entries {
+            let notification = &entry.notification;
+            xml.push_str("  <entry>\n");
+            xml.push_str(&format!("    <id>{}</id>\n", entry.id));
+            xml.push_str(&format!("    <title>{}</title>\n", escape(&notification.headline())));
+            xml.push_str(&format!("    <updated>{}</updated>\n", timestamp(entry.sent)));
+            if let Some(alert_date) = notification.alert_date {
+                xml.push_str(&format!("    <published>{}</published>\n", timestamp(alert_date)));
+            }
+            xml.push_str(&format!("    <category term=\"{}\"/>\n", escape(&notification.category)));
+            for zone in &notification.zones {
+                xml.push_str(&format!("    <category term=\"{}\" label=\"zone\"/>\n", escape(&zone.name)));
+            }
+            xml.push_str(&format!("    <content type=\"text\">{}</content>\n", escape(&notification.text())));
+            xml.push_str("  </entry>\n");
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+
+    fn started_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.started).unwrap_or_else(Utc::now)
+    }
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// Adds every alert the gateway transmitted to the feed
+pub struct FeedRecorder(pub SharedFeed);
+
+impl Notifier for FeedRecorder {
+    fn notify(&self, notification: &Notification) {
+        lock_feed(&self.0).record(notification, Utc::now());
+    }
+}
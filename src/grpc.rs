@@ -0,0 +1,216 @@
+use crate::active::{lock_active, SharedActiveAlerts};
+use crate::api::AlertResult;
+use crate::debug::StateRequest;
+use crate::events::{self, Event};
+use crate::SharedPause;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("redalert.v1");
+}
+
+use proto::gateway_event::Event as ProtoEvent;
+use proto::gateway_server::{Gateway, GatewayServer};
+
+// State shared by all gRPC calls
+#[derive(Clone)]
+pub struct GrpcState {
+    pub alerts_tx: mpsc::Sender<(&'static str, AlertResult)>,
+    pub state_tx: mpsc::Sender<StateRequest>,
+    pub token: Option<String>,
+    pub active: SharedActiveAlerts,
+    pub paused: SharedPause,
+}
+
+impl From<Event> for ProtoEvent {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::AlertFetched { source, alert_type, cities, instructions } => {
+                ProtoEvent::AlertFetched(proto::AlertFetched { source, alert_type, cities, instructions })
+            }
+            Event::AlertSkipped { alert_type, reason } => {
+                ProtoEvent::AlertSkipped(proto::AlertSkipped { alert_type, reason })
+            }
+            Event::AlertParsed { alert_type, cities, zones } => {
+                ProtoEvent::AlertParsed(proto::AlertParsed { alert_type, cities, zones })
+            }
+            Event::AlertCleared { alert_type, channel, cities } => {
+                ProtoEvent::AlertCleared(proto::AlertCleared { alert_type, channel, cities })
+            }
+            Event::ApiRateLimited { source, retry_after_secs } => {
+                ProtoEvent::ApiRateLimited(proto::ApiRateLimited { source, retry_after_secs })
+            }
+            Event::SendSucceeded { channel, category, message, attempts } => {
+                ProtoEvent::SendSucceeded(proto::SendSucceeded { channel, category, message, attempts })
+            }
+            Event::SendObserved { channel, category, message } => {
+                ProtoEvent::SendObserved(proto::SendObserved { channel, category, message })
+            }
+            Event::SendFailed { channel, category, message, attempts, error } => {
+                ProtoEvent::SendFailed(proto::SendFailed { channel, category, message, attempts, error })
+            }
+            Event::TaskFailed { task, error } => ProtoEvent::TaskFailed(proto::TaskFailed { task, error }),
+            Event::MeshPathChecked { node, hops, min_snr_db, degraded } => {
+                ProtoEvent::MeshPathChecked(proto::MeshPathChecked { node, hops, min_snr_db, degraded })
+            }
+            Event::CanaryChecked { channel, ok, latency_ms, error } => {
+                ProtoEvent::CanaryChecked(proto::CanaryChecked { channel, ok, latency_ms, error })
+            }
+        }
+    }
+}
+
+struct GatewayService {
+    state: GrpcState,
+}
+
+impl GatewayService {
+    fn set_paused(&self, paused: bool) -> proto::PauseResponse {
+        if self.state.paused.swap(paused, Ordering::SeqCst) != paused {
+            if paused {
+                log::warn!("Transmission paused over gRPC; alerts are processed but not sent");
+            } else {
+                log::info!("Transmission resumed over gRPC");
+            }
+        }
+        proto::PauseResponse { paused }
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<proto::GatewayEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Gateway for GatewayService {
+    type StreamEventsStream = EventStream;
+
+    async fn stream_events(
+        &self,
+        _request: Request<proto::StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let stream = BroadcastStream::new(events::subscribe()).filter_map(|received| match received {
+            Ok((time_unix_ms, event)) => Some(Ok(proto::GatewayEvent {
+                time_unix_ms,
+                event: Some(event.into()),
+            })),
+            // A slow client misses events rather than holding the gateway up
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                log::warn!("gRPC event stream fell behind; {} event(s) skipped", missed);
+                None
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn get_status(&self, _request: Request<proto::GetStatusRequest>) -> Result<Response<proto::Status>, Status> {
+        let (reply, dump) = oneshot::channel();
+        self.state
+            .state_tx
+            .send(reply)
+            .await
+            .map_err(|_| Status::unavailable("alert pipeline is not running"))?;
+
+        // The alert loop answers between alerts; it may be busy sending
+        let state = match tokio::time::timeout(Duration::from_secs(10), dump).await {
+            Ok(Ok(state)) => state,
+            _ => return Err(Status::unavailable("alert loop is busy; try again")),
+        };
+
+        let mut active_cities: Vec<String> = lock_active(&self.state.active)
+            .snapshot()
+            .into_iter()
+            .map(|city| city.name)
+            .collect();
+        active_cities.sort();
+
+        Ok(Response::new(proto::Status {
+            uptime_secs: state["uptime_secs"].as_u64().unwrap_or_default(),
+            paused: self.state.paused.load(Ordering::SeqCst),
+            pending_alerts: state["pending_alerts"].as_u64().unwrap_or_default(),
+            last_transmission_secs_ago: state["last_transmission_secs_ago"].as_u64(),
+            active_cities,
+            state_json: state.to_string(),
+        }))
+    }
+
+    async fn inject_alert(
+        &self,
+        request: Request<proto::InjectAlertRequest>,
+    ) -> Result<Response<proto::InjectAlertResponse>, Status> {
+        let inject = request.into_inner();
+
+        if inject.alert_type.trim().is_empty() {
+            return Err(Status::invalid_argument("alert_type must not be empty"));
+        }
+        if inject.cities.is_empty() && inject.zones.is_empty() {
+            return Err(Status::invalid_argument("at least one city or zone is required"));
+        }
+
+        log::info!(
+            "Alert injected over gRPC: {} for cities {:?}, zones {:?}",
+            inject.alert_type,
+            inject.cities,
+            inject.zones
+        );
+        let alert = AlertResult {
+            alert_type: inject.alert_type,
+            cities: inject.cities,
+            instructions: inject.instructions,
+            zones: inject.zones,
+            alert_date: None,
+            areas: BTreeMap::new(),
+        };
+        self.state
+            .alerts_tx
+            .send(("grpc", alert))
+            .await
+            .map_err(|_| Status::unavailable("alert pipeline is not running"))?;
+
+        Ok(Response::new(proto::InjectAlertResponse {}))
+    }
+
+    async fn pause(&self, _request: Request<proto::PauseRequest>) -> Result<Response<proto::PauseResponse>, Status> {
+        Ok(Response::new(self.set_paused(true)))
+    }
+
+    async fn resume(&self, _request: Request<proto::ResumeRequest>) -> Result<Response<proto::PauseResponse>, Status> {
+        Ok(Response::new(self.set_paused(false)))
+    }
+}
+
+// Run the gRPC server until it fails. tonic::Status is large, but it is the error tonic expects
+#[allow(clippy::result_large_err)]
+pub async fn serve(addr: SocketAddr, state: GrpcState) -> Result<(), String> {
+    // Every call needs the bearer token; the service is disabled when no token is configured
+    let token = state.token.clone();
+    let authorize = move |request: Request<()>| {
+        let expected = token
+            .as_deref()
+            .ok_or_else(|| Status::permission_denied("no --http-token configured"))?;
+        let provided = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if provided == Some(expected) {
+            Ok(request)
+        } else {
+            Err(Status::unauthenticated("invalid or missing bearer token"))
+        }
+    };
+
+    log::info!("gRPC server listening on {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(GatewayServer::with_interceptor(GatewayService { state }, authorize))
+        .serve(addr)
+        .await
+        .map_err(|e| format!("gRPC server on {} failed: {}", addr, e))
+}
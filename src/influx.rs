@@ -0,0 +1,303 @@
+use crate::active::{lock_active, SharedActiveAlerts};
+use crate::events::Event;
+use crate::ratelimit::SharedChannelLoad;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::time::sleep;
+
+// Whether points are collected at all; off unless an exporter runs
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Points waiting for the next export, in line protocol
+static PENDING: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Points kept while the target is unreachable; the oldest are dropped beyond this
+const MAX_PENDING: usize = 10_000;
+
+// Largest UDP datagram sent, safely below a typical MTU
+const MAX_DATAGRAM: usize = 1400;
+
+// Where points are written
+#[derive(Debug, Clone)]
+pub enum InfluxTarget {
+    // InfluxDB UDP listener or Telegraf socket_listener, as host:port
+    Udp(String),
+    // InfluxDB write endpoint (e.g. http://influx:8086/api/v2/write?org=o&bucket=b), with an optional token
+    Http { url: String, token: Option<String> },
+}
+
+impl InfluxTarget {
+    pub fn parse(url: &str, token: Option<String>) -> Result<Self, String> {
+        if let Some(addr) = url.strip_prefix("udp://") {
+            return Ok(InfluxTarget::Udp(addr.trim_end_matches('/').to_string()));
+        }
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(InfluxTarget::Http { url: url.to_string(), token });
+        }
+        Err(format!("Influx target {} must start with udp://, http:// or https://", url))
+    }
+}
+
+// A field value in line protocol
+enum Field {
+    Int(i64),
+    Float(f64),
+}
+
+// Escape a measurement name, tag key or tag value
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+fn push(measurement: &str, tags: &[(&str, &str)], fields: &[(&str, Field)]) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut line = escape(measurement);
+    for (key, value) in tags {
+        // Line protocol has no empty tag values
+        if !value.is_empty() {
+            line.push_str(&format!(",{}={}", escape(key), escape(value)));
+        }
+    }
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| match value {
+            Field::Int(value) => format!("{}={}i", escape(key), value),
+            Field::Float(value) => format!("{}={}", escape(key), value),
+        })
+        .collect();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    line.push_str(&format!(" {} {}", fields.join(","), timestamp));
+
+    let mut pending = PENDING.lock().unwrap_or_else(PoisonError::into_inner);
+    if pending.len() >= MAX_PENDING {
+        pending.remove(0);
+    }
+    pending.push(line);
+}
+
+// Count a lifecycle event. Points about alerts carry the category as the
+// alert_type tag and, where one applies, the zone as the channel tag, the same
+// tags the first measurements had, so existing queries keep working.
+pub fn record_event(event: &Event) {
+    match event {
+        Event::AlertFetched { source, alert_type, cities, .. } => push(
+            "alerts_fetched",
+            &[("source", source), ("alert_type", alert_type)],
+            &[("cities", Field::Int(cities.len() as i64))],
+        ),
+        Event::AlertSkipped { alert_type, reason } => push(
+            "alerts_skipped",
+            &[("alert_type", alert_type), ("reason", reason)],
+            &[("count", Field::Int(1))],
+        ),
+        // One point per alert, with 0 zones for an alert that reached none, and one
+        // per zone it was routed to
+        Event::AlertParsed { alert_type, cities, zones } => {
+            push(
+                "alerts_routed",
+                &[("alert_type", alert_type)],
+                &[("cities", Field::Int(cities.len() as i64)), ("zones", Field::Int(zones.len() as i64))],
+            );
+            for zone in zones {
+                push(
+                    "alerts_routed_by_channel",
+                    &[("channel", &zone.to_string()), ("alert_type", alert_type)],
+                    &[("count", Field::Int(1)), ("cities", Field::Int(cities.len() as i64))],
+                );
+            }
+        }
+        Event::AlertCleared { alert_type, channel, cities } => push(
+            "alerts_cleared",
+            &[("channel", &channel.to_string()), ("alert_type", alert_type)],
+            &[("cities", Field::Int(cities.len() as i64))],
+        ),
+        Event::ApiRateLimited { source, retry_after_secs } => push(
+            "api_rate_limited",
+            &[("source", source)],
+            &[("retry_after_secs", Field::Int(*retry_after_secs as i64))],
+        ),
+        Event::SendSucceeded { channel, category, message, attempts } => push(
+            "sends",
+            &[("channel", &channel.to_string()), ("alert_type", category), ("result", "ok")],
+            &[("attempts", Field::Int(i64::from(*attempts))), ("bytes", Field::Int(message.len() as i64))],
+        ),
+        Event::SendObserved { channel, category, message } => push(
+            "sends",
+            &[("channel", &channel.to_string()), ("alert_type", category), ("result", "observed")],
+            &[("attempts", Field::Int(0)), ("bytes", Field::Int(message.len() as i64))],
+        ),
+        Event::SendFailed { channel, category, message, attempts, .. } => push(
+            "sends",
+            &[("channel", &channel.to_string()), ("alert_type", category), ("result", "failed")],
+            &[("attempts", Field::Int(i64::from(*attempts))), ("bytes", Field::Int(message.len() as i64))],
+        ),
+        Event::TaskFailed { task, .. } => push("task_failures", &[("task", task)], &[("count", Field::Int(1))]),
+        Event::MeshPathChecked { node, hops, min_snr_db, degraded } => {
+            let mut fields = vec![
+                ("reachable", Field::Int(hops.is_some() as i64)),
+                ("degraded", Field::Int(degraded.is_some() as i64)),
+            ];
+            if let Some(hops) = hops {
+                fields.push(("hops", Field::Int(i64::from(*hops))));
+            }
+            if let Some(snr) = min_snr_db {
+                fields.push(("min_snr_db", Field::Float(*snr)));
+            }
+            push("mesh_paths", &[("node", node)], &fields);
+        }
+        Event::CanaryChecked { channel, ok, latency_ms, .. } => {
+            let mut fields = vec![("ok", Field::Int(*ok as i64))];
+            if let Some(latency_ms) = latency_ms {
+                fields.push(("latency_ms", Field::Int(*latency_ms as i64)));
+            }
+            push("canary", &[("channel", &channel.to_string())], &fields);
+        }
+    }
+}
+
+// A packet handed to the transport, with its estimated time on air
+pub fn record_transmission(channel: u32, category: &str, bytes: usize, airtime: Duration) {
+    push(
+        "transmissions",
+        &[("channel", &channel.to_string()), ("alert_type", category)],
+        &[("bytes", Field::Int(bytes as i64)), ("airtime_ms", Field::Float(airtime.as_secs_f64() * 1000.0))],
+    );
+}
+
+// Part or all of an alert that was not sent, and why
+pub fn record_suppressed(reason: &str, category: &str, cities: usize) {
+    push(
+        "alerts_suppressed",
+        &[("reason", reason), ("alert_type", category)],
+        &[("count", Field::Int(1)), ("cities", Field::Int(cities as i64))],
+    );
+}
+
+// How long something took: "alert" from the official event time to routing,
+// "send" from handing a message to the sender until the transport took it, "alert_to_send"
+// from the official event time until it went out on a channel
+pub fn record_latency(kind: &str, channel: Option<u32>, category: &str, latency: Duration) {
+    let channel = channel.map(|channel| channel.to_string()).unwrap_or_default();
+    push(
+        "latency",
+        &[("kind", kind), ("channel", &channel), ("alert_type", category)],
+        &[("ms", Field::Float(latency.as_secs_f64() * 1000.0))],
+    );
+}
+
+async fn write(target: &InfluxTarget, client: &reqwest::Client, lines: &[String]) -> Result<(), String> {
+    match target {
+        InfluxTarget::Udp(addr) => {
+            let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+            let mut datagram = String::new();
+            for line in lines {
+                if !datagram.is_empty() && datagram.len() + line.len() + 1 > MAX_DATAGRAM {
+                    socket.send_to(datagram.as_bytes(), addr).await.map_err(|e| e.to_string())?;
+                    datagram.clear();
+                }
+                datagram.push_str(line);
+                datagram.push('\n');
+            }
+            socket.send_to(datagram.as_bytes(), addr).await.map_err(|e| e.to_string())?;
+            Ok(())
+        }
+        InfluxTarget::Http { url, token } => {
+            let mut request = client.post(url).body(lines.join("\n"));
+            if let Some(token) = token {
+                request = request.header(reqwest::header::AUTHORIZATION, format!("Token {}", token));
+            }
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(format!("{} answered {}", url, response.status()))
+            }
+        }
+    }
+}
+
+// Gauges of the cities under alert: per zone and category, and per zone (0 for quiet zones)
+fn record_active(active: &SharedActiveAlerts, channels: &[u32]) {
+    let mut per_category: BTreeMap<(u32, String), i64> = BTreeMap::new();
+    let mut per_zone: BTreeMap<u32, i64> = channels.iter().map(|channel| (*channel, 0)).collect();
+    for city in lock_active(active).cities() {
+        for zone in &city.zones {
+            *per_category.entry((*zone, city.alert_type.clone())).or_default() += 1;
+            *per_zone.entry(*zone).or_default() += 1;
+        }
+    }
+
+    for ((zone, category), cities) in per_category {
+        push(
+            "active_alerts",
+            &[("channel", &zone.to_string()), ("alert_type", &category)],
+            &[("cities", Field::Int(cities))],
+        );
+    }
+    for (zone, cities) in per_zone {
+        push(
+            "zone_state",
+            &[("channel", &zone.to_string())],
+            &[("active", Field::Int(i64::from(cities > 0))), ("cities", Field::Int(cities))],
+        );
+    }
+}
+
+// Write the collected points, the alert gauges and the radio's channel load if it
+// is watched, at a fixed interval
+pub async fn export(
+    target: InfluxTarget,
+    every: Duration,
+    load: Option<SharedChannelLoad>,
+    active: SharedActiveAlerts,
+    channels: Vec<u32>,
+) -> Result<(), String> {
+    ENABLED.store(true, Ordering::Relaxed);
+    let client = reqwest::Client::new();
+    log::info!("Exporting metrics to {:?} every {:?}", target, every);
+
+    loop {
+        sleep(every).await;
+
+        record_active(&active, &channels);
+        let reading = load
+            .as_ref()
+            .and_then(|load| *load.lock().unwrap_or_else(PoisonError::into_inner));
+        if let Some(reading) = reading {
+            push(
+                "radio",
+                &[],
+                &[
+                    ("channel_utilization", Field::Float(reading.channel_utilization)),
+                    ("air_util_tx", Field::Float(reading.air_util_tx)),
+                ],
+            );
+        }
+
+        let lines = std::mem::take(&mut *PENDING.lock().unwrap_or_else(PoisonError::into_inner));
+        if lines.is_empty() {
+            continue;
+        }
+        if let Err(e) = write(&target, &client, &lines).await {
+            log::warn!("Failed to export {} metric point(s): {}", lines.len(), e);
+            // Keep them for the next attempt, behind nothing newer than themselves
+            let mut pending = PENDING.lock().unwrap_or_else(PoisonError::into_inner);
+            let newer = std::mem::replace(&mut *pending, lines);
+            pending.extend(newer);
+            let excess = pending.len().saturating_sub(MAX_PENDING);
+            pending.drain(..excess);
+        }
+    }
+}
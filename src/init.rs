@@ -0,0 +1,304 @@
+use crate::channels::{ChannelNames, ChannelRef};
+use crate::config::ConfigFile;
+use crate::device::{self, Device};
+use crate::zones::ZoneScheme;
+use crate::City;
+use clap::{Args, CommandFactory};
+use rand::Rng;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::io::{BufRead, Write};
+use std::process::{Command, Stdio};
+use toml::{Table, Value};
+
+#[derive(Args, Debug)]
+pub struct InitArgs {
+    /// Config file to write
+    #[arg(default_value = "red-alert.toml")]
+    pub path: String,
+}
+
+// Ask a question on the terminal, returning the default for an empty answer
+fn ask(question: &str, default: &str) -> Result<String, String> {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    std::io::stdout().flush().map_err(|e| e.to_string())?;
+
+    let mut answer = String::new();
+    let read = std::io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|e| format!("Failed to read answer: {}", e))?;
+    if read == 0 {
+        return Err("Setup aborted (end of input)".to_string());
+    }
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default.to_string() } else { answer.to_string() })
+}
+
+fn ask_yes_no(question: &str, default: bool) -> Result<bool, String> {
+    let question = format!("{} [{}]", question, if default { "Y/n" } else { "y/N" });
+    loop {
+        match ask(&question, "")?.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Please answer y or n"),
+        }
+    }
+}
+
+fn ask_number(question: &str, default: u32) -> Result<u32, String> {
+    loop {
+        match ask(question, &default.to_string())?.parse() {
+            Ok(number) => return Ok(number),
+            Err(_) => println!("Please enter a number"),
+        }
+    }
+}
+
+// Step 1: how to reach the radio
+fn choose_device() -> Result<Device, String> {
+    println!("\nStep 1/5: Device");
+    let candidates = device::find_serial_candidates();
+    let mut options: Vec<(String, Option<String>)> = candidates
+        .iter()
+        .map(|c| {
+            let label = format!("{} ({} {:04x}:{:04x})", c.port, c.vendor, c.vendor_id, c.product_id);
+            (label, Some(c.port.clone()))
+        })
+        .collect();
+    options.push(("Network host (--host)".to_string(), None));
+    #[cfg(feature = "ble")]
+    options.push(("Bluetooth (--ble)".to_string(), None));
+    options.push(("Let the meshtastic CLI pick the device".to_string(), None));
+
+    for (index, (label, _)) in options.iter().enumerate() {
+        println!("  {}) {}", index + 1, label);
+    }
+
+    loop {
+        let choice = ask_number("Choose", 1)? as usize;
+        if choice == 0 || choice > options.len() {
+            println!("Please choose 1-{}", options.len());
+            continue;
+        }
+        if let Some(port) = &options[choice - 1].1 {
+            return Ok(Device::Port(port.clone()));
+        }
+        return match choice - candidates.len() {
+            1 => Ok(Device::Host(ask("Address of the node (host or host:port)", "")?)),
+            #[cfg(feature = "ble")]
+            2 => Ok(Device::Ble(ask("BLE name or address (see the discover command)", "")?)),
+            _ => Ok(Device::Default),
+        };
+    }
+}
+
+// Step 2: zones and the channels they are sent on
+fn choose_zones(cities: &[City], device: &Device) -> Result<Option<Vec<Value>>, String> {
+    println!("\nStep 2/5: Channels and zones");
+    println!("Channel 0 gets alerts covering every zone; each zone gets its own channel.");
+
+    let builtin = ZoneScheme::builtin();
+    let mut zones: Vec<(String, u32, Vec<String>)> = Vec::new();
+
+    if ask_yes_no("Use the built-in 7 zones on channels 1-7?", true)? {
+        for zone in builtin.zones() {
+            zones.push((zone.name.clone(), zone.channel, zone.districts.clone()));
+        }
+    } else {
+        let districts: BTreeSet<&str> = cities
+            .iter()
+            .map(|city| city.zone_en.as_str())
+            .filter(|district| !district.is_empty())
+            .collect();
+        println!("Known districts: {}", districts.iter().cloned().collect::<Vec<_>>().join(", "));
+
+        let count = ask_number("How many zones?", 3)?;
+        for index in 1..=count {
+            let name = ask(&format!("Name of zone {}", index), &format!("Zone{}", index))?;
+            let channel = loop {
+                let channel = ask_number(&format!("Channel index of {}", name), index)?;
+                if channel == 0 {
+                    println!("Channel 0 is reserved for alerts covering every zone");
+                } else if zones.iter().any(|(_, used, _)| *used == channel) {
+                    println!("Channel {} is already used", channel);
+                } else {
+                    break channel;
+                }
+            };
+            let chosen = loop {
+                let answer = ask(&format!("Districts of {} (comma separated)", name), "")?;
+                let mut chosen = Vec::new();
+                let mut unknown = Vec::new();
+                for district in answer.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+                    match districts.iter().find(|known| known.eq_ignore_ascii_case(district)) {
+                        Some(known) => chosen.push(known.to_string()),
+                        None => unknown.push(district.to_string()),
+                    }
+                }
+                if !unknown.is_empty() {
+                    println!("Unknown districts: {}", unknown.join(", "));
+                } else if chosen.is_empty() {
+                    println!("A zone needs at least one district");
+                } else {
+                    break chosen;
+                }
+            };
+            zones.push((name, channel, chosen));
+        }
+    }
+
+    // Channels created by name can be referenced by name, so the layout survives reordering on the radio
+    let by_name = ask_yes_no("Create a channel for every zone on the radio now?", false)?;
+    if by_name {
+        for (name, _, _) in &zones {
+            let mut cmd = Command::new("meshtastic");
+            device.apply(&mut cmd);
+            cmd.arg("--ch-add").arg(name);
+            cmd.stdout(Stdio::null());
+            let status = device::run_cli(&mut cmd)
+                .map_err(|e| format!("Failed to execute meshtastic --ch-add: {}", e))?
+                .status;
+            if !status.success() {
+                return Err(format!("meshtastic --ch-add {} failed", name));
+            }
+            println!("Created channel {}", name);
+        }
+    }
+
+    let uses_builtin = !by_name
+        && zones.len() == builtin.zones().len()
+        && zones
+            .iter()
+            .zip(builtin.zones())
+            .all(|((name, channel, districts), zone)| {
+                *name == zone.name && *channel == zone.channel && *districts == zone.districts
+            });
+    if uses_builtin {
+        return Ok(None);
+    }
+
+    let tables = zones
+        .into_iter()
+        .map(|(name, channel, districts)| {
+            let mut table = Table::new();
+            table.insert(
+                "channel".to_string(),
+                if by_name { Value::String(name.clone()) } else { Value::Integer(channel.into()) },
+            );
+            table.insert("name".to_string(), Value::String(name));
+            table.insert(
+                "districts".to_string(),
+                Value::Array(districts.into_iter().map(Value::String).collect()),
+            );
+            Value::Table(table)
+        })
+        .collect();
+    Ok(Some(tables))
+}
+
+// Step 3: where alerts are published besides the mesh
+fn choose_notifiers(config: &mut Table) -> Result<(), String> {
+    println!("\nStep 3/5: Notifiers");
+
+    let mqtt_host = ask("MQTT broker for alert events (blank to skip)", "")?;
+    if !mqtt_host.is_empty() {
+        config.insert("mqtt-host".to_string(), Value::String(mqtt_host));
+        let topic = ask("MQTT topic prefix", "red-alert")?;
+        config.insert("mqtt-prefix".to_string(), Value::String(topic));
+    }
+
+    let http_listen = ask("Address for the HTTP API, e.g. 0.0.0.0:8080 (blank to skip)", "")?;
+    if !http_listen.is_empty() {
+        let token: String = (0..24)
+            .map(|_| format!("{:x}", rand::thread_rng().gen_range(0..16u8)))
+            .collect();
+        println!("Generated HTTP API token: {}", token);
+        config.insert("http-listen".to_string(), Value::String(http_listen));
+        config.insert("http-token".to_string(), Value::String(token));
+    }
+    Ok(())
+}
+
+// Step 4: check that the radio transmits
+fn test_transmission(device: &Device) -> Result<(), String> {
+    println!("\nStep 4/5: Test transmission");
+    if !ask_yes_no("Send a test message on channel 0 now?", false)? {
+        return Ok(());
+    }
+
+    let mut cmd = Command::new("meshtastic");
+    device.apply(&mut cmd);
+    cmd.arg("--ch-index").arg("0").arg("--sendtext").arg("red-alert-meshtastic test message");
+    let status = device::run_cli(&mut cmd)
+        .map_err(|e| format!("Failed to execute meshtastic --sendtext: {}", e))?
+        .status;
+    if status.success() {
+        println!("Test message sent; check that it arrived on another node.");
+    } else {
+        println!("meshtastic exited with {}; check the device settings before going live.", status);
+    }
+    Ok(())
+}
+
+// Check a written config the same way startup would
+fn validate(path: &str) -> Result<(), String> {
+    let config = ConfigFile::load(path, None)?;
+
+    let command = crate::Args::command();
+    let bin: OsString = "red-alert-meshtastic".into();
+    let matches = command
+        .clone()
+        .try_get_matches_from([bin.clone()])
+        .map_err(|e| e.to_string())?;
+    let mut args = vec![bin];
+    args.extend(config.args(&command, &matches)?);
+    command.try_get_matches_from(args).map_err(|e| e.to_string())?;
+
+    // Channel names can only be resolved against the radio at startup
+    if let Some(zones) = config.zones()? {
+        if zones.iter().all(|zone| matches!(zone.channel, ChannelRef::Index(_))) {
+            ZoneScheme::from_config(zones, &mut ChannelNames::from_mesh_channels(&[]))?;
+        }
+    }
+    Ok(())
+}
+
+// Walk a new operator through writing a config file
+pub fn run(args: &InitArgs, cities: &[City]) -> Result<(), String> {
+    println!("red-alert-meshtastic setup; the answers are written to {}", args.path);
+    if std::path::Path::new(&args.path).exists() && !ask_yes_no(&format!("{} exists. Overwrite it?", args.path), false)? {
+        return Err("Setup aborted; the existing config was kept".to_string());
+    }
+
+    let mut config = Table::new();
+
+    let device = choose_device()?;
+    match &device {
+        Device::Host(host) => config.insert("host".to_string(), Value::String(host.clone())),
+        Device::Port(port) => config.insert("port".to_string(), Value::String(port.clone())),
+        #[cfg(feature = "ble")]
+        Device::Ble(ble) => config.insert("ble".to_string(), Value::String(ble.clone())),
+        Device::Default => None,
+    };
+
+    if let Some(zones) = choose_zones(cities, &device)? {
+        config.insert("zone".to_string(), Value::Array(zones));
+    }
+
+    choose_notifiers(&mut config)?;
+    test_transmission(&device)?;
+
+    println!("\nStep 5/5: Writing {}", args.path);
+    let contents = toml::to_string(&config).map_err(|e| format!("Failed to render the config: {}", e))?;
+    std::fs::write(&args.path, contents).map_err(|e| format!("Failed to write {}: {}", args.path, e))?;
+    validate(&args.path).map_err(|e| format!("The written config is invalid: {}", e))?;
+
+    println!("Done. Start the gateway with: red-alert-meshtastic --config {}", args.path);
+    Ok(())
+}
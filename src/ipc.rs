@@ -0,0 +1,341 @@
+use crate::api::{self, AlertResult};
+use crate::debug::StateRequest;
+use crate::events::{self, Event};
+use crate::SharedPause;
+use crate::ReloadRequest;
+use chrono::{DateTime, Utc};
+use clap::{Args, Subcommand};
+use serde_json::{json, Value};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot};
+
+// How long a command may wait for the alert loop, which answers between alerts
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// What a control connection can reach in the running gateway
+#[derive(Clone)]
+pub struct ControlState {
+    pub alerts_tx: mpsc::Sender<(&'static str, AlertResult)>,
+    pub state_tx: mpsc::Sender<StateRequest>,
+    pub reload_tx: mpsc::Sender<ReloadRequest>,
+    pub paused: SharedPause,
+}
+
+// A command written to the socket: a JSON object such as {"command": "pause"} or
+// {"command": "inject", "alert": {...}}, or the same as plain text ("pause", "inject {...}")
+enum ControlCommand {
+    Pause,
+    Resume,
+    Reload,
+    Status,
+    Inject(Value),
+}
+
+impl ControlCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            ControlCommand::Pause => "pause",
+            ControlCommand::Resume => "resume",
+            ControlCommand::Reload => "reload",
+            ControlCommand::Status => "status",
+            ControlCommand::Inject(_) => "inject",
+        }
+    }
+}
+
+fn parse_command(line: &str) -> Result<ControlCommand, String> {
+    let (name, alert) = match serde_json::from_str::<Value>(line) {
+        Ok(Value::Object(mut object)) => {
+            let name = object.get("command").and_then(Value::as_str).unwrap_or_default().to_string();
+            (name, object.remove("alert"))
+        }
+        _ => match line.split_once(char::is_whitespace) {
+            Some((name, alert)) => (
+                name.to_string(),
+                Some(serde_json::from_str(alert).map_err(|e| format!("Invalid alert JSON: {}", e))?),
+            ),
+            None => (line.to_string(), None),
+        },
+    };
+    match (name.to_lowercase().as_str(), alert) {
+        ("pause", _) => Ok(ControlCommand::Pause),
+        ("resume", _) => Ok(ControlCommand::Resume),
+        ("reload", _) => Ok(ControlCommand::Reload),
+        ("status", _) => Ok(ControlCommand::Status),
+        ("inject", Some(alert)) => Ok(ControlCommand::Inject(alert)),
+        ("inject", None) => Err("inject needs an alert".to_string()),
+        (name, _) => Err(format!("Unknown command {:?}; use pause, resume, reload, status or inject", name)),
+    }
+}
+
+fn set_paused(control: &ControlState, paused: bool) -> String {
+    if control.paused.swap(paused, Ordering::SeqCst) != paused {
+        if paused {
+            log::warn!("Transmission paused over the control socket; alerts are processed but not sent");
+        } else {
+            log::info!("Transmission resumed over the control socket");
+        }
+    }
+    if paused { "Transmission paused" } else { "Transmission resumed" }.to_string()
+}
+
+// Ask the alert loop for something, giving up if it stays busy; the request can
+// wait in a full queue as well as for the answer
+async fn ask<T, R>(requests: &mpsc::Sender<oneshot::Sender<R>>, answer: impl FnOnce(R) -> Result<T, String>) -> Result<T, String> {
+    let exchange = async {
+        let (reply, response) = oneshot::channel();
+        requests
+            .send(reply)
+            .await
+            .map_err(|_| "the alert pipeline is not running".to_string())?;
+        response.await.map_err(|_| "the alert pipeline is not running".to_string())
+    };
+    match tokio::time::timeout(CONTROL_TIMEOUT, exchange).await {
+        Ok(response) => answer(response?),
+        Err(_) => Err("the alert loop is busy; try again".to_string()),
+    }
+}
+
+// Carry out a command, answering with the reply object written back to the client
+async fn handle(control: &ControlState, line: &str) -> Value {
+    let command = match parse_command(line) {
+        Ok(command) => command,
+        Err(e) => return json!({ "reply": "error", "ok": false, "message": e }),
+    };
+    let name = command.name();
+    let result: Result<Value, String> = match command {
+        ControlCommand::Pause => Ok(json!({ "message": set_paused(control, true) })),
+        ControlCommand::Resume => Ok(json!({ "message": set_paused(control, false) })),
+        ControlCommand::Reload => ask(&control.reload_tx, |result| result)
+            .await
+            .map(|message| json!({ "message": message })),
+        ControlCommand::Status => ask(&control.state_tx, Ok).await.map(|state| {
+            json!({
+                "message": if control.paused.load(Ordering::SeqCst) { "paused" } else { "sending" },
+                "state": state,
+            })
+        }),
+        ControlCommand::Inject(alert) => match api::parse_alert_json(alert).await {
+            Ok(alerts) => {
+                let count = alerts.len();
+                for alert in alerts {
+                    log::info!("Alert injected over the control socket: {} for {:?}", alert.alert_type, alert.cities);
+                    if control.alerts_tx.send(("socket", alert)).await.is_err() {
+                        return json!({ "reply": name, "ok": false, "message": "the alert pipeline is not running" });
+                    }
+                }
+                Ok(json!({ "message": format!("{} alert(s) queued", count) }))
+            }
+            Err(e) => Err(format!("Invalid alert: {}", e)),
+        },
+    };
+
+    let mut reply = json!({ "reply": name, "ok": result.is_ok() });
+    match result {
+        Ok(Value::Object(fields)) => {
+            if let Some(reply) = reply.as_object_mut() {
+                reply.extend(fields);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => reply["message"] = json!(e),
+    }
+    reply
+}
+
+async fn write_line(stream: &mut OwnedWriteHalf, line: &Value) -> std::io::Result<()> {
+    stream.write_all(format!("{}\n", line).as_bytes()).await
+}
+
+async fn write_event(stream: &mut OwnedWriteHalf, time_unix_ms: i64, event: &Event) -> std::io::Result<()> {
+    let time = DateTime::from_timestamp_millis(time_unix_ms).unwrap_or_else(Utc::now);
+    write_line(stream, &events::to_json(time, event)).await
+}
+
+// Write the latest events and then every new one to a client, one JSON object per
+// line, and answer each command line it writes with a line carrying a "reply" field
+async fn serve_client(stream: UnixStream, control: ControlState) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let (recent, mut live) = events::subscribe_with_replay();
+    for (time, event) in &recent {
+        if write_event(&mut write, *time, event).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            received = live.recv() => match received {
+                Ok((time, event)) => {
+                    if write_event(&mut write, time, &event).await.is_err() {
+                        return;
+                    }
+                }
+                // A slow client misses events rather than holding the gateway up
+                Err(RecvError::Lagged(missed)) => {
+                    log::warn!("Event socket client fell behind; {} event(s) skipped", missed);
+                }
+                Err(RecvError::Closed) => return,
+            },
+            line = lines.next_line() => match line {
+                Ok(Some(line)) if line.trim().is_empty() => {}
+                Ok(Some(line)) => {
+                    let reply = handle(&control, line.trim()).await;
+                    if write_line(&mut write, &reply).await.is_err() {
+                        return;
+                    }
+                }
+                // The client hung up
+                Ok(None) | Err(_) => return,
+            },
+        }
+    }
+}
+
+// Removes the socket file when the server stops
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// Bind the socket in a directory only we can enter and move it into place once it is
+// restricted, so nobody else can connect before its permissions are set
+fn bind_private(path: &Path) -> Result<UnixListener, String> {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("socket");
+    let staging = path.with_file_name(format!(".{}.{}", name, std::process::id()));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .map_err(|e| format!("Failed to create {}: {}", staging.display(), e))?;
+    let staged = staging.join(name);
+    let bound = UnixListener::bind(&staged)
+        .map_err(|e| format!("Failed to bind {}: {}", path.display(), e))
+        .and_then(|listener| {
+            // Commands can pause the gateway, so only the owner and its group may connect
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o660))
+                .map_err(|e| format!("Failed to restrict access to {}: {}", path.display(), e))?;
+            std::fs::rename(&staged, path).map_err(|e| format!("Failed to move the socket to {}: {}", path.display(), e))?;
+            Ok(listener)
+        });
+    let _ = std::fs::remove_dir_all(&staging);
+    bound
+}
+
+// Serve the event stream and the control commands on a Unix domain socket, for
+// programs and scripts on the same host such as sirens and displays
+pub async fn serve(path: &Path, control: ControlState) -> Result<(), String> {
+    // A socket left behind by an earlier run would make the bind fail
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(format!("{} is in use by another process", path.display()));
+        }
+        std::fs::remove_file(path).map_err(|e| format!("Failed to remove the stale socket {}: {}", path.display(), e))?;
+    }
+    let listener = bind_private(path)?;
+    let _socket_file = SocketFile(path.to_path_buf());
+    log::info!("Streaming events and accepting commands on {}", path.display());
+
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .map_err(|e| format!("Failed to accept a connection on {}: {}", path.display(), e))?;
+        tokio::spawn(serve_client(stream, control.clone()));
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct CtlArgs {
+    /// Socket of the running gateway (default: its --event-socket)
+    #[arg(long)]
+    pub socket: Option<String>,
+
+    #[command(subcommand)]
+    pub command: CtlCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CtlCommand {
+    /// Stop transmitting; alerts are still processed
+    Pause,
+    /// Transmit again after a pause
+    Resume,
+    /// Re-read the config file and apply the settings used while handling alerts
+    Reload,
+    /// Print the gateway's state
+    Status,
+    /// Queue an alert as if it came from the feed
+    Inject {
+        /// File with the alert as JSON (an oref alert or the normalized form), or - for stdin
+        file: String,
+    },
+}
+
+// Send one command to a running gateway and print its reply
+pub async fn run(args: &CtlArgs, default_socket: Option<&str>) -> Result<(), String> {
+    let path = args
+        .socket
+        .as_deref()
+        .or(default_socket)
+        .ok_or("No socket given; pass --socket or set --event-socket")?;
+    let command = match &args.command {
+        CtlCommand::Pause => json!({ "command": "pause" }),
+        CtlCommand::Resume => json!({ "command": "resume" }),
+        CtlCommand::Reload => json!({ "command": "reload" }),
+        CtlCommand::Status => json!({ "command": "status" }),
+        CtlCommand::Inject { file } => {
+            let text = if file == "-" {
+                std::io::read_to_string(std::io::stdin()).map_err(|e| format!("Failed to read stdin: {}", e))?
+            } else {
+                std::fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file, e))?
+            };
+            let alert: Value = serde_json::from_str(&text).map_err(|e| format!("Invalid alert JSON: {}", e))?;
+            json!({ "command": "inject", "alert": alert })
+        }
+    };
+
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", path, e))?;
+    let (read, mut write) = stream.into_split();
+    write_line(&mut write, &command)
+        .await
+        .map_err(|e| format!("Failed to send the command: {}", e))?;
+
+    // Events stream in ahead of the reply; skip them
+    let mut lines = BufReader::new(read).lines();
+    let reply = tokio::time::timeout(CONTROL_TIMEOUT * 2, async {
+        while let Some(line) = lines.next_line().await.map_err(|e| e.to_string())? {
+            if let Ok(reply) = serde_json::from_str::<Value>(&line) {
+                if reply.get("reply").is_some() {
+                    return Ok(reply);
+                }
+            }
+        }
+        Err("the gateway closed the connection without replying".to_string())
+    })
+    .await
+    .map_err(|_| "No reply from the gateway".to_string())??;
+
+    if let Some(state) = reply.get("state") {
+        println!("{}", serde_json::to_string_pretty(state).map_err(|e| e.to_string())?);
+    }
+    let message = reply["message"].as_str().unwrap_or_default();
+    if reply["ok"].as_bool().unwrap_or(false) {
+        println!("{}", message);
+        Ok(())
+    } else {
+        Err(message.to_string())
+    }
+}
@@ -0,0 +1,288 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+// Stage of a tracked alert; cleared alerts are dropped from the tracker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AlertState {
+    // Sent once, no cities added since
+    New,
+    // Cities were added after the first transmission
+    Updated,
+}
+
+impl AlertState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AlertState::New => "new",
+            AlertState::Updated => "updated",
+        }
+    }
+}
+
+// What an incoming alert means for the alert already tracked on its channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transition {
+    // Nothing tracked yet, or a newer event for the same cities: send the full alert
+    New,
+    // The alert now covers these additional cities
+    Expanded(Vec<String>),
+    // Same alert as already sent
+    Unchanged,
+}
+
+// An alert of one category on one channel (zone or area group)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackedAlert {
+    pub category: String,
+    pub channel: u32,
+    pub state: AlertState,
+    pub cities: BTreeSet<String>,
+    // Official time of the newest event, for dated alerts
+    pub alert_date: Option<DateTime<Utc>>,
+    pub since: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+// Alerts currently in effect, keyed by category and channel, so repeated polls
+// of the same alert turn into "new", "expanded" and "all clear" messages
+#[derive(Debug, Default)]
+pub struct AlertLifecycle {
+    // How long an alert stays in effect after it was last seen
+    clear_after: Duration,
+    alerts: HashMap<(String, u32), TrackedAlert>,
+}
+
+impl AlertLifecycle {
+    pub fn new(clear_after: Duration) -> Self {
+        AlertLifecycle {
+            clear_after,
+            alerts: HashMap::new(),
+        }
+    }
+
+    // How an alert covering these cities on the channel relates to what was already sent
+    pub fn transition(
+        &self,
+        category: &str,
+        channel: u32,
+        cities: &[String],
+        alert_date: Option<DateTime<Utc>>,
+    ) -> Transition {
+        let Some(tracked) = self.alerts.get(&(category.to_string(), channel)) else {
+            return Transition::New;
+        };
+
+        // Alerts targeting the channel directly carry no cities to compare
+        if cities.is_empty() {
+            return Transition::New;
+        }
+
+        // A later siren for the same area is a new event, not a repeat of the old one
+        if let (Some(date), Some(tracked_date)) = (alert_date, tracked.alert_date) {
+            if date > tracked_date {
+                return Transition::New;
+            }
+        }
+
+        let added: Vec<String> = cities
+            .iter()
+            .filter(|city| !tracked.cities.contains(*city))
+            .cloned()
+            .collect();
+        if added.is_empty() {
+            Transition::Unchanged
+        } else {
+            Transition::Expanded(added)
+        }
+    }
+
+    // Remember that the alert is in effect on the channel
+    pub fn record(
+        &mut self,
+        category: &str,
+        channel: u32,
+        cities: &[String],
+        alert_date: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) {
+        let tracked = self
+            .alerts
+            .entry((category.to_string(), channel))
+            .or_insert_with(|| TrackedAlert {
+                category: category.to_string(),
+                channel,
+                state: AlertState::New,
+                cities: BTreeSet::new(),
+                alert_date,
+                since: now,
+                last_seen: now,
+            });
+
+        // A later event starts the alert over
+        if let (Some(date), Some(tracked_date)) = (alert_date, tracked.alert_date) {
+            if date > tracked_date {
+                tracked.state = AlertState::New;
+                tracked.cities.clear();
+                tracked.since = now;
+            }
+        }
+
+        if cities.iter().any(|city| !tracked.cities.contains(city)) && !tracked.cities.is_empty() {
+            tracked.state = AlertState::Updated;
+        }
+        tracked.cities.extend(cities.iter().cloned());
+        tracked.alert_date = tracked.alert_date.max(alert_date);
+        tracked.last_seen = now;
+    }
+
+    // Whether any alert is still in effect on the channel
+    pub fn is_active(&self, channel: u32) -> bool {
+        self.alerts.values().any(|tracked| tracked.channel == channel)
+    }
+
+    // Drop and return the alerts that have not been seen for longer than the clear time
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<TrackedAlert> {
+        let clear_after = chrono::Duration::from_std(self.clear_after).unwrap_or_default();
+        let expired: Vec<(String, u32)> = self
+            .alerts
+            .iter()
+            .filter(|(_, tracked)| now - tracked.last_seen > clear_after)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut cleared: Vec<TrackedAlert> = expired
+            .into_iter()
+            .filter_map(|key| self.alerts.remove(&key))
+            .collect();
+        cleared.sort_by(|a, b| (a.channel, &a.category).cmp(&(b.channel, &b.category)));
+        cleared
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.alerts.is_empty()
+    }
+
+    // Alerts in effect, to hand over to another gateway
+    pub fn snapshot(&self) -> Vec<TrackedAlert> {
+        self.alerts.values().cloned().collect()
+    }
+
+    // Take over alerts tracked by another gateway, replacing our own
+    #[cfg_attr(not(feature = "cluster"), allow(dead_code))]
+    pub fn restore(&mut self, alerts: Vec<TrackedAlert>) {
+        self.alerts = alerts
+            .into_iter()
+            .map(|tracked| ((tracked.category.clone(), tracked.channel), tracked))
+            .collect();
+    }
+
+    // Tracked alerts with their state, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let mut alerts: Vec<&TrackedAlert> = self.alerts.values().collect();
+        alerts.sort_by(|a, b| (a.channel, &a.category).cmp(&(b.channel, &b.category)));
+        let alerts: Vec<Value> = alerts
+            .into_iter()
+            .map(|tracked| {
+                json!({
+                    "category": tracked.category,
+                    "channel": tracked.channel,
+                    "state": tracked.state.as_str(),
+                    "cities": tracked.cities,
+                    "alert_date": tracked.alert_date.map(|date| date.to_rfc3339()),
+                    "since": tracked.since.to_rfc3339(),
+                    "last_seen": tracked.last_seen.to_rfc3339(),
+                })
+            })
+            .collect();
+        json!({ "clear_after_secs": self.clear_after.as_secs(), "alerts": alerts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_760_000_000 + secs, 0).unwrap()
+    }
+
+    fn cities(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn transitions_from_new_to_expanded_and_unchanged() {
+        let mut lifecycle = AlertLifecycle::new(Duration::from_secs(600));
+        assert_eq!(lifecycle.transition("missiles", 2, &cities(&["Sderot"]), Some(at(0))), Transition::New);
+        lifecycle.record("missiles", 2, &cities(&["Sderot"]), Some(at(0)), at(0));
+        assert!(lifecycle.is_active(2));
+        assert!(!lifecycle.is_active(3));
+
+        assert_eq!(lifecycle.transition("missiles", 2, &cities(&["Sderot"]), Some(at(0))), Transition::Unchanged);
+        assert_eq!(
+            lifecycle.transition("missiles", 2, &cities(&["Sderot", "Netivot"]), Some(at(0))),
+            Transition::Expanded(cities(&["Netivot"]))
+        );
+        // Other categories and channels are tracked apart
+        assert_eq!(lifecycle.transition("hostileAircraftIntrusion", 2, &cities(&["Sderot"]), None), Transition::New);
+        assert_eq!(lifecycle.transition("missiles", 4, &cities(&["Sderot"]), None), Transition::New);
+        // Channel-wide alerts have no cities to compare
+        assert_eq!(lifecycle.transition("missiles", 2, &[], None), Transition::New);
+
+        lifecycle.record("missiles", 2, &cities(&["Netivot"]), Some(at(0)), at(10));
+        assert_eq!(lifecycle.snapshot()[0].state, AlertState::Updated);
+        assert_eq!(lifecycle.snapshot()[0].cities.len(), 2);
+    }
+
+    #[test]
+    fn later_event_starts_the_alert_over() {
+        let mut lifecycle = AlertLifecycle::new(Duration::from_secs(600));
+        lifecycle.record("missiles", 2, &cities(&["Sderot", "Netivot"]), Some(at(0)), at(0));
+        assert_eq!(lifecycle.transition("missiles", 2, &cities(&["Sderot"]), Some(at(120))), Transition::New);
+
+        lifecycle.record("missiles", 2, &cities(&["Sderot"]), Some(at(120)), at(120));
+        let tracked = &lifecycle.snapshot()[0];
+        assert_eq!(tracked.state, AlertState::New);
+        assert_eq!(tracked.cities, cities(&["Sderot"]).into_iter().collect());
+        assert_eq!((tracked.since, tracked.alert_date), (at(120), Some(at(120))));
+    }
+
+    #[test]
+    fn expires_after_the_clear_time() {
+        let mut lifecycle = AlertLifecycle::new(Duration::from_secs(600));
+        lifecycle.record("missiles", 2, &cities(&["Sderot"]), None, at(0));
+        lifecycle.record("missiles", 1, &cities(&["Metula"]), None, at(300));
+
+        assert!(lifecycle.expire(at(600)).is_empty());
+        let cleared = lifecycle.expire(at(601));
+        assert_eq!(cleared.len(), 1);
+        assert_eq!(cleared[0].channel, 2);
+        assert!(!lifecycle.is_active(2));
+        assert!(lifecycle.is_active(1));
+
+        let cleared = lifecycle.expire(at(1000));
+        assert_eq!(cleared.len(), 1);
+        assert!(lifecycle.is_empty());
+    }
+
+    #[test]
+    fn restores_from_a_snapshot() {
+        let mut old = AlertLifecycle::new(Duration::from_secs(600));
+        old.record("missiles", 2, &cities(&["Sderot"]), Some(at(0)), at(0));
+        old.record("earthQuake", 0, &[], None, at(0));
+        let snapshot: Vec<TrackedAlert> =
+            serde_json::from_str(&serde_json::to_string(&old.snapshot()).unwrap()).unwrap();
+
+        let mut lifecycle = AlertLifecycle::new(Duration::from_secs(600));
+        lifecycle.record("missiles", 7, &cities(&["Tel Aviv"]), None, at(0));
+        lifecycle.restore(snapshot);
+        assert!(!lifecycle.is_active(7));
+        assert!(lifecycle.is_active(0));
+        assert_eq!(lifecycle.transition("missiles", 2, &cities(&["Sderot"]), Some(at(0))), Transition::Unchanged);
+        assert_eq!(lifecycle.expire(at(601)).len(), 2);
+    }
+}
@@ -0,0 +1,83 @@
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+use std::sync::RwLock;
+
+// Timezone of log timestamps, digests and times in messages; official siren times are
+// published in Israel time, so that is the default until --timezone is applied
+static TIMEZONE: RwLock<Tz> = RwLock::new(chrono_tz::Asia::Jerusalem);
+
+pub fn set_timezone(tz: Tz) {
+    *TIMEZONE.write().unwrap_or_else(|e| e.into_inner()) = tz;
+}
+
+pub fn timezone() -> Tz {
+    *TIMEZONE.read().unwrap_or_else(|e| e.into_inner())
+}
+
+// The current time in the configured timezone
+pub fn now() -> DateTime<Tz> {
+    Utc::now().with_timezone(&timezone())
+}
+
+// Convert a time to the configured timezone
+pub fn to_local(time: DateTime<Utc>) -> DateTime<Tz> {
+    time.with_timezone(&timezone())
+}
+
+// The hour a daily schedule fires at on a date: the configured hour, or the next
+// one when a DST change skips it (the repeated hour of the fall change fires once)
+pub fn scheduled_hour(date: NaiveDate, hour: u32) -> u32 {
+    let skipped = date
+        .and_hms_opt(hour, 0, 0)
+        .is_some_and(|time| timezone().from_local_datetime(&time).earliest().is_none());
+    if skipped {
+        hour + 1
+    } else {
+        hour
+    }
+}
+
+// Writes log lines to stderr with timestamps in the configured timezone, with offset
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {:<5} [{}] {}\n",
+            now().format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        let _ = std::io::stderr().lock().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+// Install the logger; call once at startup
+pub fn init_logging(level: LevelFilter) -> Result<(), String> {
+    log::set_logger(&LOGGER).map_err(|e| e.to_string())?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+// Parse an IANA timezone name such as "Asia/Jerusalem" or "UTC"
+pub fn parse_timezone(value: &str) -> Result<Tz, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Unknown timezone {} (expected an IANA name such as Asia/Jerusalem)", value))
+}
@@ -0,0 +1,102 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::time::sleep;
+
+// How often a standby checks whether the lock holder has gone away
+const RETRY_EVERY: Duration = Duration::from_secs(2);
+
+// Keeps two gateways on one host from transmitting at once: the instance holding
+// an exclusive lock on the lock file transmits, others stand by. The OS releases
+// the lock when its holder exits or dies, so a standby takes over without cleanup.
+#[derive(Clone, Debug)]
+pub struct InstanceLock {
+    path: PathBuf,
+    // The locked file, kept open for as long as the process runs
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl InstanceLock {
+    // Try to take the lock right away, so a lone instance transmits from the first poll
+    pub fn acquire(path: &str) -> Result<Self, String> {
+        let lock = InstanceLock {
+            path: PathBuf::from(path),
+            file: Arc::new(Mutex::new(None)),
+        };
+        if !lock.try_lock()? {
+            log::warn!(
+                "Another instance holds the lock on {}; standing by until it exits",
+                lock.path.display()
+            );
+        }
+        Ok(lock)
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.file.lock().unwrap_or_else(PoisonError::into_inner).is_some()
+    }
+
+    // Take the lock if it's free, writing our PID into the file for the operator
+    fn try_lock(&self) -> Result<bool, String> {
+        let mut held = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        if held.is_some() {
+            return Ok(true);
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open lock file {}: {}", self.path.display(), e))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(false),
+            Err(TryLockError::Error(e)) => {
+                return Err(format!("Failed to lock {}: {}", self.path.display(), e));
+            }
+        }
+
+        let pid = std::process::id();
+        if let Err(e) = file.set_len(0).and_then(|()| writeln!(file, "{}", pid)) {
+            log::warn!("Failed to write the PID into {}: {}", self.path.display(), e);
+        }
+        *held = Some(file);
+        Ok(true)
+    }
+
+    // Wait for the lock holder to go away and take over
+    pub async fn wait_for_lock(self) -> Result<(), String> {
+        while !self.try_lock()? {
+            sleep(RETRY_EVERY).await;
+        }
+        log::info!("Holding the lock on {}; transmitting alerts", self.path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_instance_stands_by_until_the_first_exits() {
+        let path = std::env::temp_dir().join(format!("red-alert-lock-{}.pid", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let first = InstanceLock::acquire(path).unwrap();
+        assert!(first.is_held());
+        assert_eq!(std::fs::read_to_string(path).unwrap().trim(), std::process::id().to_string());
+
+        let second = InstanceLock::acquire(path).unwrap();
+        assert!(!second.is_held());
+        assert_eq!(second.try_lock(), Ok(false));
+
+        drop(first);
+        assert_eq!(second.try_lock(), Ok(true));
+        assert!(second.is_held());
+        let _ = std::fs::remove_file(path);
+    }
+}
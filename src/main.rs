@@ -1,16 +1,182 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use std::ffi::OsString;
 use log::LevelFilter;
 use rust_embed::RustEmbed;
 use serde::Deserialize;
-use simple_logger::SimpleLogger;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::process::{Command, Stdio};
 use std::time::Duration;
 use tokio::time::sleep;
-use crate::api::fetch_alert;
+use crate::active::{lock_active, ActiveAlerts, ActiveCity, SharedActiveAlerts};
+use crate::admin::{AdminArgs, AdminAuth, AdminCommand};
+use crate::api::{AlertResult, CityArea, Oref};
+use crate::debug::StateRequest;
+#[cfg(feature = "cap")]
+use crate::cap::CapSource;
+use crate::capout::{CapArea, CapPublisher};
+use crate::chirpstack::{parse_chirpstack_target, ChirpstackTarget, ChirpstackTransport};
+use crate::cityindex::CityIndex;
+#[cfg(feature = "cluster")]
+use crate::cluster::Cluster;
+use crate::dedup::AlertDedup;
+use crate::device::Device;
+use crate::discover::DiscoverArgs;
+use crate::influx::InfluxTarget;
+use crate::init::InitArgs;
+use crate::meshcheck::MeshCheckArgs;
+use crate::nodes::NodesArgs;
+use crate::watch::WatchArgs;
+use crate::lifecycle::{AlertLifecycle, Transition};
+use crate::lockfile::InstanceLock;
+#[cfg(feature = "web")]
+use crate::map::AlertMap;
+use crate::error::RedAlertError;
+use crate::areas::AreaMap;
+#[cfg(feature = "sqlite")]
+use crate::backfill::BackfillArgs;
+use crate::canary::Canary;
+use crate::replay::ReplayAlertArgs;
+use crate::reconcile::OrefCombined;
+use crate::retention::{PruneArgs, Retention, Stored};
+use crate::channels::ChannelNames;
+use crate::channelstats::ChannelStats;
+use crate::config::ConfigFile;
+use crate::configgen::ConfigArgs;
+use crate::country::{CountryProfile, Language, Regions};
+use crate::validity::{parse_validity, Validity};
+use crate::shelter::{parse_shelter_time, ThreatPassed};
+#[cfg(feature = "sqlite")]
+use crate::subscribers::{lock_subscribers, SharedSubscribers, SubscriberStore};
+use crate::zones::ZoneScheme;
+use crate::zoneof::ZoneOfArgs;
+use crate::events::Event;
+#[cfg(feature = "web")]
+use crate::feed::{AlertFeed, FeedRecorder};
+use crate::meshmqtt::{
+    format_node_id, parse_mesh_channel, parse_node_num, InboundText, MeshChannel, BROADCAST_ADDR, DETECTION_SENSOR_APP,
+    TEXT_MESSAGE_APP,
+};
+#[cfg(feature = "mqtt")]
+use crate::meshmqtt::MeshMqttTransport;
+use crate::nodedb::{parse_position, FixedPosition, NodeIdentity};
+use crate::peers::{PeerGateways, SharedPeers};
+use crate::resend::{RecentMessages, ReplayRequest};
+use crate::storeforward::{lock_store_forward, SharedStoreForward};
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttPublisher;
+use crate::mastodon::{MastodonNotifier, Visibility};
+use crate::matrix::{parse_matrix_level, MatrixNotifier};
+use crate::notify::{NotifiedZone, Notification, Notifier};
+use crate::signalcli::SignalNotifier;
+use crate::slack::SlackNotifier;
+use crate::xmpp::{XmppApi, XmppNotifier};
+use crate::outbox::OutboxSettings;
+use crate::multipart::{PartStore, ResendRequest};
+use crate::sequence::SequenceCounters;
+use crate::signing::VerifyArgs;
+use crate::protective::{parse_protective_action, ProtectiveActions};
+use crate::abbrev::{parse_abbreviation, Abbreviations};
+use crate::repeat::{parse_repeat, RepeatPolicy, Repeats};
+use crate::ratelimit::{parse_category_gap, AirtimeThrottle, CategoryGaps, SharedChannelLoad, ZoneCooldown};
+use crate::dedicated::CategoryChannels;
+use crate::digest::AlertLog;
+use crate::suppressed::{Reason, Suppressed};
+use crate::dutycycle::{DeferredSend, DeferredSends, DutyCycle, ModemPreset, Plan, Region};
+use crate::ukraine::UkraineSource;
+use chrono::{Timelike, Utc};
+use chrono_tz::Tz;
+use std::time::Instant;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
 
+mod active;
+mod abbrev;
+mod admin;
 mod api;
+mod areas;
+#[cfg(feature = "sqlite")]
+mod backfill;
+mod canary;
+#[cfg(feature = "cap")]
+mod cap;
+mod capout;
+mod channels;
+mod channelstats;
+mod chirpstack;
+mod cityindex;
+#[cfg(feature = "cluster")]
+mod cluster;
+mod config;
+mod configgen;
+mod country;
+#[cfg(all(target_os = "linux", feature = "dbus"))]
+mod dbus;
+mod debug;
+mod dedicated;
+mod dedup;
+mod device;
+mod discover;
+mod error;
+mod digest;
+mod dutycycle;
+mod events;
+#[cfg(feature = "web")]
+mod feed;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod influx;
+mod localtime;
+mod lockfile;
+#[cfg(feature = "web")]
+mod map;
+mod mastodon;
+mod matrix;
+mod meshcheck;
+mod init;
+#[cfg(unix)]
+mod ipc;
+mod lifecycle;
+mod meshmqtt;
+mod multipart;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod nodedb;
+mod nodes;
+mod notify;
+mod outbox;
+mod peers;
+mod protective;
+mod ratelimit;
+mod reconcile;
+mod repeat;
+mod replay;
+mod resend;
+mod retention;
+mod sequence;
+mod shelter;
+mod signalcli;
+mod signing;
+mod slack;
+mod stdin;
+#[cfg(feature = "sqlite")]
+mod store;
+mod storeforward;
+#[cfg(feature = "sqlite")]
+mod subscribers;
+mod supervisor;
+mod suppressed;
+mod ukraine;
+#[cfg(feature = "web")]
+mod web;
+mod validity;
+mod watch;
+mod xmpp;
+mod zoneof;
+mod zones;
 
 #[derive(RustEmbed)]
 #[folder = "src"]
@@ -18,345 +184,3756 @@ struct Asset;
 
 #[derive(Debug, Deserialize)]
 struct City {
+    #[serde(default)]
+    id: u32,
     name: String,
     name_en: String,
+    // District in Hebrew, as the feed may report it
+    #[serde(default)]
+    zone: String,
     zone_en: String,
+    #[serde(default)]
+    lat: f64,
+    #[serde(default)]
+    lng: f64,
+    // Seconds to reach a shelter (migun time), 0 if unknown
+    #[serde(default)]
+    countdown: u32,
 }
 
-async fn check_node_connection(args: &Args) -> Result<(), String> {
+impl City {
+    // Latitude and longitude; the data has 0, 0 for entries without a location
+    fn coordinates(&self) -> Option<(f64, f64)> {
+        (self.lat != 0.0 || self.lng != 0.0).then_some((self.lat, self.lng))
+    }
+
+    // Time to reach a shelter, if the data has one
+    fn shelter_time(&self) -> Option<Duration> {
+        (self.countdown > 0).then(|| Duration::from_secs(self.countdown.into()))
+    }
+}
+
+async fn check_node_connection(device: &Device) -> Result<(), RedAlertError> {
     // Construct the command to run `meshtastic --info`
     let mut cmd = Command::new("meshtastic");
 
-    // Conditionally add the "--host" argument if the host is provided
-    if let Some(host) = &args.host {
-        cmd.arg("--host");
-        cmd.arg(host);
-    }
+    // Add the --host or --port argument for the device
+    device.apply(&mut cmd);
+
+    // Add the --info argument
+    cmd.arg("--info");
+
+    // Ensure the command doesn't output to the console
+    cmd.stdout(Stdio::piped());
+
+    // Run the command and capture the output
+    let output = device::run_cli(&mut cmd);
+
+    match output {
+        Ok(output) => {
+            // Convert the stdout to a string (output is captured as bytes)
+            let stdout = String::from_utf8_lossy(&output.stdout);
+
+            // Check if the output contains "Error"
+            if stdout.contains("Error") {
+                return Err(RedAlertError::RadioUnavailable(format!("Received error output: {}", stdout)));
+            }
+
+            // Check the first line of the output for connection confirmation
+            if let Some(first_line) = stdout.lines().next() {
+                if first_line == "Connected to radio" {
+                    log::info!("Successfully connected to the node.");
+                    Ok(())
+                } else {
+                    Err(RedAlertError::RadioUnavailable(format!(
+                        "Failed to connect to the radio. First line: {}",
+                        first_line
+                    )))
+                }
+            } else {
+                Err(RedAlertError::RadioUnavailable(
+                    "Output from meshtastic --info was empty.".to_string(),
+                ))
+            }
+        }
+        Err(e) => Err(RedAlertError::RadioUnavailable(format!(
+            "Failed to execute meshtastic --info: {}",
+            e
+        ))),
+    }
+}
+
+
+#[derive(Parser, Debug)]
+#[command(long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// TOML config file with default options and named profiles
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Profile of the config file to use (see [profile.<name>] tables)
+    #[arg(long, requires = "config")]
+    profile: Option<String>,
+
+    /// Directory for the state kept between runs, such as the admin command counters
+    #[arg(long, default_value_t = default_data_dir())]
+    data_dir: String,
+
+    /// Network address with port of device to connect to in the form of target.address:port
+    #[arg(long)]
+    host: Option<String>,
+
+    /// Serial port of the device (e.g. /dev/ttyUSB0); detected automatically when neither --host nor --port is given
+    #[arg(long, conflicts_with = "host")]
+    port: Option<String>,
+
+    /// BLE name or address of the device (see the discover command)
+    #[arg(long, conflicts_with_all = ["host", "port"])]
+    ble: Option<String>,
+
+    /// Zones to ignore when sending alerts
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    ignore: Option<Vec<u32>>,
+
+    /// Zones every tsunami alert is sent to, whichever cities it lists; give no zones to turn this off
+    /// [default: the zones covering built-in zones 2, 4 and 6 in the zone map]
+    #[arg(long, num_args = 0.., value_delimiter = ' ')]
+    tsunami_zone: Option<Vec<u32>>,
+
+    /// Districts whose zones every tsunami alert is sent to
+    #[arg(long, num_args = 0.., value_delimiter = ' ', default_values_t = ["Eilat".to_string()])]
+    tsunami_district: Vec<String>,
+
+    /// Node IDs of critical repeaters to monitor (e.g. !a1b2c3d4)
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    repeater: Option<Vec<String>>,
+
+    /// Hours a repeater may go unheard before the operator is warned
+    #[arg(long, default_value_t = 6)]
+    repeater_timeout: u64,
+
+    /// Node IDs of key nodes to trace the route to (e.g. !a1b2c3d4), one per region
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    mesh_check_node: Vec<String>,
+
+    /// Minutes between traceroutes to the --mesh-check-node nodes in the background
+    #[arg(long)]
+    mesh_check_every: Option<u64>,
+
+    /// SNR in dB below which a link on the path to a key node counts as degraded
+    #[arg(long, default_value_t = -10.0, allow_hyphen_values = true)]
+    mesh_check_min_snr: f64,
+
+    /// Channel to send a small canary probe on regularly, checking the whole send path works
+    /// before a real alert needs it; best a dedicated monitoring channel. With --transport cli the
+    /// mesh has to acknowledge the probe, with --transport mqtt another node has to uplink it back.
+    #[arg(long)]
+    canary_channel: Option<u32>,
+
+    /// Minutes between canary probes
+    #[arg(long, default_value_t = 60)]
+    canary_every: u64,
+
+    /// Seconds a canary probe may take to be acknowledged or heard back before it counts as failed
+    #[arg(long, default_value_t = 120)]
+    canary_timeout: u64,
+
+    /// Node that has to hear canary probes and uplink them (e.g. !a1b2c3d4); any other node if unset
+    #[arg(long)]
+    canary_listener: Option<String>,
+
+    /// File to append snapshots of the node DB to, one JSON line each
+    #[arg(long)]
+    node_snapshots: Option<String>,
+
+    /// Minutes between node DB snapshots
+    #[arg(long, default_value_t = 60)]
+    node_snapshot_every: u64,
+
+    /// Address for the embedded HTTP server to listen on (e.g. 0.0.0.0:8080)
+    #[arg(long)]
+    http_listen: Option<SocketAddr>,
+
+    /// Bearer token required by protected HTTP endpoints such as POST /alerts/manual
+    #[arg(long)]
+    http_token: Option<String>,
+
+    /// SQLite file of the nodes subscribed to alerts by direct message, with their zones, categories,
+    /// language and quiet hours; set over the mesh ("sub", "zones", "cats", "lang", "quiet",
+    /// "prefs", "unsub") or the /subscribers HTTP endpoints
+    #[arg(long)]
+    subscriber_db: Option<String>,
+
+    /// Number of the latest events replayed to a client connecting to the /events WebSocket or --event-socket
+    #[arg(long, default_value_t = 50)]
+    events_replay: usize,
+
+    /// Number of the latest sent alerts in the /feed.atom Atom feed of the HTTP server
+    #[arg(long, default_value_t = 50)]
+    feed_entries: usize,
+
+    /// Unix domain socket to stream events on as newline-delimited JSON, for programs on the same
+    /// host such as sirens and displays, which also takes commands from the ctl subcommand
+    /// (e.g. /run/red-alert/events.sock)
+    #[arg(long)]
+    event_socket: Option<String>,
+
+    /// Offer status methods and AlertReceived/AlertSent signals on this D-Bus (Linux only), for
+    /// desktop applets and system daemons; the system bus needs a policy allowing the bus name
+    #[arg(long, value_enum)]
+    dbus: Option<DbusBus>,
+
+    /// Address for the gRPC control and streaming API to listen on (e.g. 0.0.0.0:50051); uses the --http-token
+    #[arg(long)]
+    grpc_listen: Option<SocketAddr>,
+
+    /// MQTT broker host to publish alert events and zone state to
+    #[arg(long)]
+    mqtt_host: Option<String>,
+
+    /// MQTT broker port
+    #[arg(long, default_value_t = 1883)]
+    mqtt_port: u16,
+
+    /// MQTT username
+    #[arg(long)]
+    mqtt_username: Option<String>,
+
+    /// MQTT password
+    #[arg(long)]
+    mqtt_password: Option<String>,
+
+    /// Prefix for all published MQTT topics
+    #[arg(long, default_value = "red-alert")]
+    mqtt_prefix: String,
+
+    /// Directory where notifications for MQTT and the CAP endpoint wait until delivered,
+    /// so they survive a restart; failed ones end up in its *.dead.jsonl files [default: outbox in --data-dir]
+    #[arg(long)]
+    outbox_dir: Option<String>,
+
+    /// Delivery attempts, with exponential backoff up to 5 minutes, before a notification is dead-lettered
+    #[arg(long, default_value_t = 15)]
+    outbox_max_attempts: u32,
+
+    /// Delete stored data older than this many days: --node-snapshots lines, outbox dead letters,
+    /// --cap-output-dir documents and the alert store of backfill and prune
+    #[arg(long)]
+    retention_days: Option<u64>,
+
+    /// Keep each of those files and directories under this many megabytes, dropping the oldest data first
+    #[arg(long)]
+    retention_mb: Option<u64>,
+
+    /// Minutes between prunings of stored data while running, with --retention-days or --retention-mb
+    #[arg(long, default_value_t = 60)]
+    prune_every: u64,
+
+    /// Minimum seconds since the previous transmission for a category, as CATEGORY=SECONDS.
+    /// missiles and terroristInfiltration default to 0, general to 30, drills to 60, others to --min-send-gap
+    #[arg(long, value_parser = parse_category_gap)]
+    category_gap: Vec<(String, u64)>,
+
+    /// Broadcast every message of a category this many more times, this many seconds apart, as
+    /// CATEGORY=COUNT:SECONDS (e.g. missiles=2:15); repeats carry the same sequence number and signature
+    #[arg(long, value_parser = parse_repeat)]
+    repeat: Vec<(String, RepeatPolicy)>,
+
+    /// Minimum seconds between transmissions for categories without a --category-gap
+    #[arg(long, default_value_t = ratelimit::DEFAULT_SEND_GAP.as_secs())]
+    min_send_gap: u64,
+
+    /// Times a failed transmission is retried
+    #[arg(long, default_value_t = 3)]
+    send_retries: u32,
+
+    /// Seconds to wait before retrying a failed transmission
+    #[arg(long, default_value_t = 5)]
+    send_retry_delay: u64,
+
+    /// Seconds during which a zone isn't re-alerted unless the alert adds new cities (0 disables)
+    #[arg(long, default_value_t = 0)]
+    zone_cooldown: u64,
+
+    /// Seconds between pushes of the host's time to the attached radio, starting at startup
+    #[arg(long)]
+    time_sync: Option<u64>,
+
+    /// Long name of the gateway node shown in the mesh apps, e.g. "RedAlert GW North"
+    #[arg(long)]
+    node_long_name: Option<String>,
+
+    /// Short name of the gateway node (up to 4 characters), e.g. "RAGW"
+    #[arg(long)]
+    node_short_name: Option<String>,
+
+    /// Fixed position of the gateway node as LAT,LON or LAT,LON,ALT (meters)
+    #[arg(long, value_parser = parse_position)]
+    node_position: Option<FixedPosition>,
+
+    /// Forward only the cities of an alert within this many km of the gateway (see --location);
+    /// alerts with none of their cities that close are dropped. Earthquakes and tsunamis are exempt,
+    /// and cities without a known location are kept
+    #[arg(long)]
+    radius_km: Option<f64>,
+
+    /// Center of --radius-km as LAT,LON; defaults to --node-position
+    #[arg(long, value_parser = parse_position, requires = "radius_km")]
+    location: Option<FixedPosition>,
+
+    /// Seconds between re-applying the node's names and position, so edits and resets are undone
+    #[arg(long, default_value_t = 3600)]
+    node_info_refresh: u64,
+
+    /// Seconds between reads of the attached node's channel utilization; gaps are stretched while the channel is congested
+    #[arg(long)]
+    channel_util_poll: Option<u64>,
+
+    /// Channel utilization (%) above which the gap between transmissions is stretched
+    #[arg(long, default_value_t = 25.0)]
+    channel_util_threshold: f64,
+
+    /// Seconds added to the gap of the least urgent categories at full congestion (40% utilization);
+    /// more urgent categories get a smaller share
+    #[arg(long, default_value_t = 60)]
+    congestion_gap: u64,
+
+    /// LoRa region of the radio; regions with a legal duty cycle (e.g. EU_868 10%, UA_868 1%) are enforced
+    #[arg(long, value_enum)]
+    region: Option<Region>,
+
+    /// Duty cycle in percent per hour to stay within, overriding the region's limit
+    #[arg(long)]
+    duty_cycle: Option<f64>,
+
+    /// Modem preset of the mesh, used to estimate the airtime of each message
+    #[arg(long, value_enum, default_value_t = ModemPreset::LongFast)]
+    modem_preset: ModemPreset,
+
+    /// Longest a message is held back to stay within the duty cycle before it is dropped
+    #[arg(long, default_value_t = 300)]
+    duty_cycle_max_wait: u64,
+
+    /// JSON file keeping per-channel message counters; when given, every text message starts with "#N"
+    /// so receivers can detect missed messages
+    #[arg(long)]
+    sequence_file: Option<String>,
+
+    /// Shared secret to sign text messages with a truncated HMAC (" ~1a2b3c4d"), checked with the verify command
+    #[arg(long)]
+    hmac_key: Option<String>,
+
+    /// Shared secret of admin commands sent to the gateway by direct message, signed with the admin command
+    #[arg(long)]
+    admin_key: Option<String>,
+
+    /// Node IDs (e.g. !a1b2c3d4) allowed to send admin commands: pause, resume, poll, test and stats;
+    /// needs --admin-key and the mesh MQTT transport
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    admin_node: Option<Vec<String>>,
+
+    /// Node IDs of other gateways serving overlapping meshes (e.g. !a1b2c3d4); alerts one of them already
+    /// broadcast are not sent again. Needs --transport mqtt and the same settings on every gateway
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    peer_gateway: Option<Vec<String>>,
+
+    /// Seconds each gateway waits per lower-numbered peer before sending, giving that peer the chance to go first
+    #[arg(long, default_value_t = 5)]
+    peer_holdoff: u64,
+
+    /// Answer "resend", "last N" and "missed" direct messages from mesh nodes with the recent
+    /// alerts of their zone. Needs --transport mqtt to hear them
+    #[arg(long)]
+    serve_resend: bool,
+
+    /// Redis URL shared by the gateways of a cluster (e.g. redis://10.0.0.5/); only the leader transmits,
+    /// and dedup and alert state are kept in Redis so a standby takes over without repeats
+    #[arg(long)]
+    redis_url: Option<String>,
+
+    /// Prefix of the Redis keys, one per cluster
+    #[arg(long, default_value = "red-alert")]
+    redis_prefix: String,
+
+    /// Seconds the leader lease lasts without renewal; a standby takes over this long after the leader fails
+    #[arg(long, default_value_t = 15)]
+    leader_lease: u64,
+
+    /// Lock file guarding against a second instance on this host (e.g. /run/red-alert.lock); an instance
+    /// that can't take the lock stands by and takes over when the holder exits
+    #[arg(long)]
+    lock_file: Option<String>,
+
+    /// Add the bell character to siren-category alerts, sounding the buzzers and strobes of nodes
+    /// whose external notification module has "alert bell" enabled
+    #[arg(long)]
+    bell: bool,
+
+    /// Alert categories that ring the bell
+    #[arg(long, num_args = 1.., value_delimiter = ' ', default_values_t = ["missiles".to_string(), "hostileAircraftIntrusion".to_string(), "terroristInfiltration".to_string()])]
+    bell_category: Vec<String>,
+
+    /// Send the Home Front Command protective actions for hazardousMaterials and radiologicalEvent
+    /// as a follow-up after the first alert of those categories
+    #[arg(long)]
+    builtin_protective_actions: bool,
+
+    /// Follow-up guidance sent after the first alert of a category, as CATEGORY=TEXT; replaces the
+    /// built-in protective actions, an empty TEXT turns them off
+    #[arg(long, value_parser = parse_protective_action)]
+    protective_action: Vec<(String, String)>,
+
+    /// Replace the instruction texts of the alert feed with short forms before sending, e.g.
+    /// "היכנסו למרחב המוגן ושהו בו 10 דקות" with "Shelter 10min"
+    #[arg(long)]
+    abbreviate: bool,
+
+    /// Short form of an instruction text as FULL=SHORT, applied before sending; adds to or replaces
+    /// the built-in ones of --abbreviate, an empty SHORT keeps the text in full
+    #[arg(long, value_parser = parse_abbreviation)]
+    abbreviation: Vec<(String, String)>,
+
+    /// Minutes after an earthquake alert to send aftershock guidance on the channels it went out on
+    #[arg(long)]
+    aftershock_guidance: Option<u64>,
+
+    /// Tell each zone when the threat has passed and people may leave the shelter, once the
+    /// category's shelter time has gone by without a newer alert there
+    #[arg(long)]
+    threat_passed: bool,
+
+    /// Minutes to stay sheltered after the last alert of a category, as CATEGORY=MINUTES, for
+    /// --threat-passed; replaces the built-in times (missiles and hostileAircraftIntrusion 10), 0 turns a category off
+    #[arg(long, value_parser = parse_shelter_time)]
+    shelter_time: Vec<(String, u64)>,
+
+    /// Node IDs (e.g. !a1b2c3d4) whose external notification module is switched on for the bell at
+    /// startup over remote admin; needs --transport cli and admin access to the nodes
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    bell_node: Option<Vec<String>>,
+
+    /// Send an "all clear" message once an alert has not been seen in the feed for this many seconds
+    #[arg(long)]
+    all_clear_after: Option<u64>,
+
+    /// Append "valid until HH:MM" to alert messages, so late receivers can tell stale alerts apart
+    #[arg(long)]
+    valid_until: bool,
+
+    /// Minutes an alert of a category stays valid, as CATEGORY=MINUTES; replaces the built-in
+    /// durations (missiles 10, radiologicalEvent 180, ...)
+    #[arg(long, value_parser = parse_validity)]
+    validity: Vec<(String, u64)>,
+
+    /// Minutes alerts of categories without a built-in duration or --validity stay valid
+    #[arg(long, default_value_t = 30)]
+    valid_for: u64,
+
+    /// How alerts are worded on the mesh
+    #[arg(long, value_enum, default_value_t = MessageStyle::Text)]
+    message_style: MessageStyle,
+
+    /// Timezone of log timestamps, the digest hour and times in messages (IANA name)
+    #[arg(long, default_value = "Asia/Jerusalem", value_parser = localtime::parse_timezone)]
+    timezone: Tz,
+
+    /// Hour (0-23, in --timezone) at which to transmit a digest of the last 24 hours
+    #[arg(long, value_parser = clap::value_parser!(u32).range(0..24))]
+    digest_hour: Option<u32>,
+
+    /// Channel index the daily digest is sent on
+    #[arg(long, default_value_t = 0)]
+    digest_channel: u32,
+
+    /// Where alerts come from; selects the country's categories, region model and message language
+    #[arg(long, value_enum, default_value_t = Source::Oref)]
+    source: Source,
+
+    /// Language of place names in messages [default: english for Israel, local otherwise]
+    #[arg(long, value_enum)]
+    language: Option<Language>,
+
+    /// With --source history, read the recent alerts from the alert archive, whose rows can give
+    /// locality IDs and districts, instead of alertsHistory.json; the archive holds the whole
+    /// day, so it is downloaded at most every 30 seconds
+    #[arg(long)]
+    history_archive: bool,
+
+    /// Seconds between polls of the alert feed
+    #[arg(long, default_value_t = 5)]
+    poll_interval: u64,
+
+    /// Up to this many milliseconds of random delay added to every poll, so gateways don't poll in lockstep
+    #[arg(long, default_value_t = 0)]
+    poll_jitter: u64,
+
+    /// Poll every --poll-active seconds while an alert is in effect or was seen in the last
+    /// --active-window minutes, and every --poll-quiet seconds once none has been for --quiet-after seconds
+    #[arg(long)]
+    adaptive_poll: bool,
+
+    /// Seconds between polls while an alert is in effect (with --adaptive-poll)
+    #[arg(long, default_value_t = 2)]
+    poll_active: u64,
+
+    /// Seconds between polls during quiet periods (with --adaptive-poll)
+    #[arg(long, default_value_t = 15)]
+    poll_quiet: u64,
+
+    /// Minutes after the last alert was seen that polling stays at --poll-active, to catch follow-up waves
+    #[arg(long, default_value_t = 10)]
+    active_window: u64,
+
+    /// Seconds without an alert in effect before polling slows to --poll-quiet
+    #[arg(long, default_value_t = 900)]
+    quiet_after: u64,
+
+    /// API token for alerts.in.ua (for --source ukraine)
+    #[arg(long)]
+    ua_token: Option<String>,
+
+    /// URL of the CAP document or ATOM feed of CAP alerts (for --source cap)
+    #[arg(long)]
+    cap_url: Option<String>,
+
+    /// Seconds between polls of the CAP feed
+    #[arg(long, default_value_t = 60)]
+    cap_poll: u64,
+
+    /// Directory to write every processed alert to as a CAP 1.2 XML document
+    #[arg(long)]
+    cap_output_dir: Option<String>,
+
+    /// URL to POST every processed alert to as a CAP 1.2 XML document
+    #[arg(long)]
+    cap_output_url: Option<String>,
+
+    /// Sender of the published CAP documents, also the prefix of their identifiers
+    #[arg(long, default_value = "red-alert-meshtastic")]
+    cap_sender: String,
+
+    /// URL of the XMPP server's HTTP message API to send alerts through, e.g.
+    /// https://xmpp.example.org:5281/rest (Prosody) or https://xmpp.example.org:5443/api/send_message (ejabberd)
+    #[arg(long)]
+    xmpp_url: Option<String>,
+
+    /// Which HTTP message API --xmpp-url is
+    #[arg(long, value_enum, default_value_t = XmppApi::Prosody)]
+    xmpp_api: XmppApi,
+
+    /// User to authenticate to the XMPP message API as
+    #[arg(long)]
+    xmpp_user: Option<String>,
+
+    /// Password of --xmpp-user
+    #[arg(long)]
+    xmpp_password: Option<String>,
+
+    /// Address (JID) alerts are sent from; needed with ejabberd
+    #[arg(long)]
+    xmpp_from: Option<String>,
+
+    /// Addresses (JIDs) to send alerts to directly
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    xmpp_to: Vec<String>,
+
+    /// Multi-user chat rooms to post alerts in; the sending address must be allowed to post there
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    xmpp_room: Vec<String>,
+
+    /// Matrix homeserver to post alerts through, e.g. https://matrix.example.org
+    #[arg(long)]
+    matrix_homeserver: Option<String>,
+
+    /// Access token of the Matrix account that posts
+    #[arg(long)]
+    matrix_token: Option<String>,
+
+    /// IDs of the Matrix rooms to post in (e.g. !abcdef:example.org); the account must have joined them
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    matrix_room: Vec<String>,
+
+    /// How loudly alerts of a category are posted, as CATEGORY=LEVEL: room (mention @room), text or
+    /// notice (no notification). Missiles, infiltration and aircraft default to room, unknown categories to notice
+    #[arg(long, value_parser = parse_matrix_level)]
+    matrix_level: Vec<(String, matrix::Level)>,
+
+    /// Also post failed sends, failed tasks and failing canaries to the Matrix rooms
+    #[arg(long)]
+    matrix_health: bool,
+
+    /// URL of a signal-cli REST API to send alerts to Signal through, e.g. http://localhost:8080
+    #[arg(long)]
+    signal_url: Option<String>,
+
+    /// Phone number of the Signal account registered with signal-cli, e.g. +972501234567
+    #[arg(long)]
+    signal_number: Option<String>,
+
+    /// Signal groups (as the REST API lists them, e.g. group.dGVzdA==) and phone numbers to send alerts to
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    signal_recipient: Vec<String>,
+
+    /// Slack or Mattermost incoming webhook URLs to post alerts to, colored by category with
+    /// the zones, cities and instructions as fields
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    slack_webhook: Vec<String>,
+
+    /// Name the webhook posts appear under, where the workspace allows overriding it
+    #[arg(long, requires = "slack_webhook")]
+    slack_username: Option<String>,
+
+    /// Mastodon server to post a public notice of every transmitted alert to, e.g. https://mastodon.social;
+    /// notices name the category and zones, not the cities
+    #[arg(long)]
+    mastodon_url: Option<String>,
+
+    /// Access token of the Mastodon account, with the write:statuses scope
+    #[arg(long)]
+    mastodon_token: Option<String>,
+
+    /// Who sees the posted notices
+    #[arg(long, value_enum, default_value_t = Visibility::Public)]
+    mastodon_visibility: Visibility,
+
+    /// Where to export metrics in InfluxDB line protocol: udp://host:port, or an InfluxDB write URL
+    /// such as http://influx:8086/api/v2/write?org=ORG&bucket=BUCKET
+    #[arg(long)]
+    influx_url: Option<String>,
+
+    /// InfluxDB API token for an HTTP --influx-url
+    #[arg(long)]
+    influx_token: Option<String>,
+
+    /// Seconds between metric exports; the radio's channel load is included when --channel-util-poll is set
+    #[arg(long, default_value_t = 10)]
+    influx_interval: u64,
+
+    /// Format of stdout: human-readable logs only, or one JSON object per lifecycle event
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// URL of a city database in the cities.json format (e.g. a community-maintained targets.json),
+    /// downloaded at startup instead of using the built-in copy
+    #[arg(long)]
+    cities_url: Option<String>,
+
+    /// Where the last good download of --cities-url is kept, used when the URL can't be reached
+    #[arg(long, default_value = "cities-cache.json")]
+    cities_cache: String,
+
+    /// Exit at startup if cities.json has unmapped districts or duplicate names instead of warning
+    #[arg(long)]
+    strict_cities: bool,
+
+    /// YAML file of zones (channel, name, districts) replacing the built-in zones and any [[zone]] in the config
+    #[arg(long)]
+    zone_map: Option<String>,
+
+    /// TOML file mapping official alert areas (or whole districts) to channels, replacing zone routing
+    #[arg(long)]
+    area_map: Option<String>,
+
+    /// Fetch, parse, route and log alerts without ever transmitting
+    #[arg(long)]
+    observe: bool,
+
+    /// How alert messages reach the mesh
+    #[arg(long, value_enum, default_value_t = TransportKind::Cli)]
+    transport: TransportKind,
+
+    /// Meshtastic MQTT broker host (for --transport mqtt)
+    #[arg(long)]
+    mesh_mqtt_host: Option<String>,
+
+    /// Meshtastic MQTT broker port
+    #[arg(long, default_value_t = 1883)]
+    mesh_mqtt_port: u16,
+
+    /// Meshtastic MQTT username
+    #[arg(long)]
+    mesh_mqtt_username: Option<String>,
+
+    /// Meshtastic MQTT password
+    #[arg(long)]
+    mesh_mqtt_password: Option<String>,
+
+    /// Meshtastic MQTT root topic, including the region
+    #[arg(long, default_value = "msh/EU_868")]
+    mesh_mqtt_root: String,
+
+    /// Node ID the gateway publishes as on the Meshtastic MQTT broker (e.g. !a1b2c3d4)
+    #[arg(long)]
+    mesh_gateway_id: Option<String>,
+
+    /// Channel for a channel index, as INDEX=NAME[:PSK] with a base64 PSK (defaults to AQ==)
+    #[arg(long, value_parser = parse_mesh_channel)]
+    mesh_channel: Vec<(u32, MeshChannel)>,
+
+    /// Base URL of the ChirpStack REST API (for --transport chirpstack), e.g. http://chirpstack:8090
+    #[arg(long)]
+    chirpstack_url: Option<String>,
+
+    /// ChirpStack API token
+    #[arg(long)]
+    chirpstack_token: Option<String>,
+
+    /// Where a channel's alerts go, as CHANNEL=multicast:ID or CHANNEL=device:DEVEUI;
+    /// channel 0 alerts go to every target unless channel 0 has its own
+    #[arg(long, value_parser = parse_chirpstack_target)]
+    chirpstack_target: Vec<(u32, ChirpstackTarget)>,
+
+    /// LoRaWAN port of the alert downlinks
+    #[arg(long, default_value_t = 10)]
+    chirpstack_fport: u8,
+
+    /// Bytes of message text in each downlink, after the 7-byte header; keep it within the network's smallest data rate
+    #[arg(long, default_value_t = 40)]
+    chirpstack_max_text: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// List Meshtastic nodes reachable over BLE and the LAN, with the option to reach each
+    Discover(DiscoverArgs),
+    /// Interactively create a config file: device, zones and channels, notifiers and a test message
+    Init(InitArgs),
+    /// Write a commented default config file or a JSON Schema of the config file
+    Config(ConfigArgs),
+    /// Check the signature of messages received from a gateway run with the same --hmac-key
+    Verify(VerifyArgs),
+    /// Sign an admin command for an --admin-node to send to the gateway by direct message
+    Admin(AdminArgs),
+    /// Trace the route to key nodes and report their hop counts and weakest links
+    MeshCheck(MeshCheckArgs),
+    /// List the attached radio's node DB: names, last heard, SNR, battery and position
+    Nodes(NodesArgs),
+    /// Import past alerts between two dates from the alert archive into a local SQLite store, without transmitting
+    #[cfg(feature = "sqlite")]
+    Backfill(BackfillArgs),
+    /// Find the city or area nearest to a point and the zones its alerts go out on
+    ZoneOf(ZoneOfArgs),
+    /// Retransmit a past alert from the alert archive, marked as a drill, to rehearse the response to it
+    ReplayAlert(ReplayAlertArgs),
+    /// Poll for alerts and print them in color with their zones and the messages that would be sent, without transmitting
+    Watch(WatchArgs),
+    /// Delete stored data past --retention-days or --retention-mb now
+    Prune(PruneArgs),
+    /// Pause, resume, reload, query or inject an alert into a gateway running with --event-socket
+    #[cfg(unix)]
+    Ctl(ipc::CtlArgs),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Source {
+    /// Poll the Home Front Command (oref) live alerts endpoint
+    Oref,
+    /// Poll the oref alert history endpoint
+    History,
+    /// Poll the oref live and history endpoints together, sending each alert once from whichever lists it first
+    Combined,
+    /// Read newline-delimited alert JSON from stdin
+    Stdin,
+    /// Poll the alerts.in.ua API for Ukrainian alerts; needs --ua-token and an --area-map of oblasts
+    Ukraine,
+    /// Poll a CAP document or ATOM feed of CAP alerts (--cap-url); needs an --area-map of CAP area codes
+    Cap,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DbusBus {
+    /// The system bus, for system daemons
+    System,
+    /// The session bus of the logged-in user, for desktop applets
+    Session,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Logs only (written to stderr)
+    Text,
+    /// Newline-delimited JSON events on stdout, logs on stderr
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum MessageStyle {
+    /// Free text: "🚨missiles", "🚨missiles expanded to ...", "✅missiles all clear"
+    Text,
+    /// Detection sensor convention, one sensor per zone: "<zone> state: 1" while alerted, "<zone> state: 0" when clear.
+    /// Sent on the detection sensor port with --transport mqtt, as text with the CLI
+    Sensor,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum TransportKind {
+    /// Run the meshtastic CLI against a locally attached radio
+    Cli,
+    /// Publish encrypted packets to a Meshtastic MQTT broker
+    Mqtt,
+    /// Queue compact downlinks on a ChirpStack LoRaWAN server
+    Chirpstack,
+}
+
+// Command line with the unset options of the selected config profile filled in
+fn with_config_file(cli_args: &[OsString], matches: &clap::ArgMatches) -> Result<Vec<OsString>, String> {
+    let Some(path) = matches.get_one::<String>("config") else {
+        return Ok(cli_args.to_vec());
+    };
+    let profile = matches.get_one::<String>("profile");
+
+    let config = ConfigFile::load(path, profile.map(String::as_str))?;
+    if let Some(profile) = profile {
+        log::info!("Using profile {} from {}", profile, path);
+    }
+
+    let mut merged: Vec<OsString> = cli_args.iter().take(1).cloned().collect();
+    merged.extend(config.args(&Args::command(), matches)?);
+    merged.extend(cli_args.iter().skip(1).cloned());
+    Ok(merged)
+}
+
+// Parse the command line, filling in unset options from the selected config profile
+fn load_args() -> Result<Args, RedAlertError> {
+    let cli_args: Vec<OsString> = std::env::args_os().collect();
+    let matches = Args::command().get_matches_from(&cli_args);
+    let merged = with_config_file(&cli_args, &matches).map_err(RedAlertError::Config)?;
+    Ok(Args::parse_from(merged))
+}
+
+// Parse the command line and config file again for a reload, reporting errors
+// instead of exiting so a typo doesn't take the running gateway down
+fn reload_args() -> Result<Args, String> {
+    // Only the first line of clap's error says what is wrong
+    let first_line = |e: clap::Error| e.to_string().lines().next().unwrap_or_default().trim_start_matches("error: ").to_string();
+    let cli_args: Vec<OsString> = std::env::args_os().collect();
+    let matches = Args::command().try_get_matches_from(&cli_args).map_err(first_line)?;
+    let merged = with_config_file(&cli_args, &matches)?;
+    Args::try_parse_from(merged).map_err(first_line)
+}
+
+enum Transport {
+    Cli(Device),
+    #[cfg(feature = "mqtt")]
+    MeshMqtt(MeshMqttTransport),
+    Chirpstack(ChirpstackTransport),
+    // Observation mode: log what would be sent, never transmit
+    Observe,
+}
+
+impl Transport {
+    // Build the transport selected on the command line
+    fn from_args(args: &Args, device: &Device) -> Result<Self, RedAlertError> {
+        if args.observe {
+            log::info!("Observation mode: alerts are processed and logged but never transmitted");
+            return Ok(Transport::Observe);
+        }
+
+        match args.transport {
+            TransportKind::Cli => Ok(Transport::Cli(device.clone())),
+            #[cfg(not(feature = "mqtt"))]
+            TransportKind::Mqtt => Err(RedAlertError::Config(
+                "--transport mqtt needs a build with the \"mqtt\" feature".to_string(),
+            )),
+            #[cfg(feature = "mqtt")]
+            TransportKind::Mqtt => {
+                let host = args
+                    .mesh_mqtt_host
+                    .as_deref()
+                    .ok_or_else(|| RedAlertError::Config("--transport mqtt requires --mesh-mqtt-host".to_string()))?;
+                let gateway_id = args
+                    .mesh_gateway_id
+                    .as_deref()
+                    .ok_or_else(|| RedAlertError::Config("--transport mqtt requires --mesh-gateway-id".to_string()))?;
+                let gateway_id = parse_node_num(gateway_id).map_err(RedAlertError::Config)?;
+                if args.mesh_channel.is_empty() {
+                    return Err(RedAlertError::Config(
+                        "--transport mqtt requires at least one --mesh-channel".to_string(),
+                    ));
+                }
+
+                Ok(Transport::MeshMqtt(MeshMqttTransport::connect(
+                    host,
+                    args.mesh_mqtt_port,
+                    args.mesh_mqtt_username
+                        .as_deref()
+                        .map(|username| (username, args.mesh_mqtt_password.as_deref().unwrap_or_default())),
+                    &args.mesh_mqtt_root,
+                    gateway_id,
+                    args.mesh_channel.iter().cloned().collect(),
+                    args.canary_channel.is_some(),
+                )))
+            }
+            TransportKind::Chirpstack => {
+                let url = args.chirpstack_url.as_deref().ok_or_else(|| {
+                    RedAlertError::Config("--transport chirpstack requires --chirpstack-url".to_string())
+                })?;
+                let token = args.chirpstack_token.as_deref().ok_or_else(|| {
+                    RedAlertError::Config("--transport chirpstack requires --chirpstack-token".to_string())
+                })?;
+                if args.chirpstack_target.is_empty() {
+                    return Err(RedAlertError::Config(
+                        "--transport chirpstack requires at least one --chirpstack-target".to_string(),
+                    ));
+                }
+
+                Ok(Transport::Chirpstack(ChirpstackTransport::new(
+                    url,
+                    token,
+                    args.chirpstack_target.iter().cloned().collect(),
+                    args.chirpstack_fport,
+                    args.chirpstack_max_text,
+                )))
+            }
+        }
+    }
+}
+
+struct MessageSender {
+    last_message_time: Option<std::time::Instant>,
+    transport: Transport,
+    gaps: CategoryGaps,
+    throttle: Option<AirtimeThrottle>,
+    duty_cycle: Option<DutyCycle>,
+    duty_cycle_max_wait: Duration,
+    // Messages held back for airtime
+    deferred: DeferredSends,
+    parts: PartStore,
+    sequence: Option<SequenceCounters>,
+    hmac_key: Option<String>,
+    peers: Option<SharedPeers>,
+    // Set while transmission is paused from a controller
+    paused: SharedPause,
+    // Broadcasts kept for nodes asking for what they missed
+    recent: RecentMessages,
+    zone_cooldown: ZoneCooldown,
+    // Broadcasts still to be sent again
+    repeats: Repeats,
+    stats: ChannelStats,
+    // Alerts and cities not sent, by reason
+    suppressed: Suppressed,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl MessageSender {
+    fn new(transport: Transport, gaps: CategoryGaps, zone_cooldown: ZoneCooldown, retries: u32, retry_delay: Duration) -> Self {
+        MessageSender {
+            last_message_time: None,
+            transport,
+            gaps,
+            throttle: None,
+            duty_cycle: None,
+            duty_cycle_max_wait: Duration::ZERO,
+            deferred: DeferredSends::default(),
+            parts: PartStore::new(),
+            sequence: None,
+            hmac_key: None,
+            peers: None,
+            paused: SharedPause::default(),
+            recent: RecentMessages::new(),
+            zone_cooldown,
+            repeats: Repeats::default(),
+            stats: ChannelStats::new(ModemPreset::LongFast),
+            suppressed: Suppressed::default(),
+            retries,
+            retry_delay,
+        }
+    }
+
+    // Node number of the gateway on the mesh, if the transport has its own
+    fn node_id(&self) -> Option<u32> {
+        match &self.transport {
+            #[cfg(feature = "mqtt")]
+            Transport::MeshMqtt(mqtt) => Some(mqtt.gateway_id()),
+            _ => None,
+        }
+    }
+
+    // Texts heard on the mesh, for transports that can receive
+    fn take_inbound(&mut self) -> Option<mpsc::Receiver<InboundText>> {
+        match &mut self.transport {
+            #[cfg(feature = "mqtt")]
+            Transport::MeshMqtt(mqtt) => mqtt.take_inbound(),
+            _ => None,
+        }
+    }
+
+    // Store-and-forward servers heard on the mesh, for transports that can receive
+    fn store_forward(&self) -> Option<SharedStoreForward> {
+        match &self.transport {
+            #[cfg(feature = "mqtt")]
+            Transport::MeshMqtt(mqtt) => Some(mqtt.store_forward()),
+            _ => None,
+        }
+    }
+
+    // Stretch gaps while the channel is congested
+    fn with_throttle(mut self, throttle: Option<AirtimeThrottle>) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    // Keep transmissions within a duty cycle, holding messages back at most max_wait
+    fn with_duty_cycle(mut self, duty_cycle: Option<DutyCycle>, max_wait: Duration) -> Self {
+        self.duty_cycle = duty_cycle;
+        self.duty_cycle_max_wait = max_wait;
+        self
+    }
+
+    // Repeat the broadcasts of some categories
+    fn with_repeats(mut self, repeats: Repeats) -> Self {
+        self.repeats = repeats;
+        self
+    }
+
+    // Count transmissions per channel, with airtime estimated for the channel's modem preset
+    fn with_stats(mut self, stats: ChannelStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    // Number text messages per channel
+    fn with_sequence(mut self, sequence: Option<SequenceCounters>) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    // Sign text messages with a shared secret
+    fn with_hmac_key(mut self, hmac_key: Option<String>) -> Self {
+        self.hmac_key = hmac_key;
+        self
+    }
+
+    // Share the pause switch with the control APIs
+    fn with_pause(mut self, paused: SharedPause) -> Self {
+        self.paused = paused;
+        self
+    }
+
+    // Leave alerts to peer gateways that already sent them
+    fn with_peers(mut self, peers: Option<SharedPeers>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    // Hand a single message for a node (or BROADCAST_ADDR) to the transport; the meshtastic CLI can only send text messages
+    #[cfg_attr(not(feature = "mqtt"), allow(unused_variables))]
+    async fn send_once(
+        &self,
+        chan: u32,
+        to: u32,
+        category: &str,
+        portnum: u64,
+        message: &str,
+    ) -> Result<(), RedAlertError> {
+        match &self.transport {
+            Transport::Cli(device) => {
+                let mut command = Command::new("meshtastic");
+                command.arg("--ch-index");
+                command.arg(chan.to_string());
+                if to != BROADCAST_ADDR {
+                    command.arg("--dest").arg(format_node_id(to));
+                }
+                command.arg("--sendtext");
+                command.arg(message);
+                device.apply(&mut command);
+                command.stdout(Stdio::null()).stderr(Stdio::piped());
+                // Waits for any other meshtastic run to let go of the radio, then for this one to finish
+                let output = tokio::task::spawn_blocking(move || device::run_cli(&mut command))
+                    .await
+                    .map_err(|e| RedAlertError::RadioUnavailable(format!("The meshtastic run failed: {}", e)))?
+                    .map_err(|e| RedAlertError::RadioUnavailable(format!("Failed to run meshtastic: {}", e)))?;
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    Err(RedAlertError::RadioUnavailable(format!(
+                        "meshtastic exited with {}: {}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )))
+                }
+            }
+            #[cfg(feature = "mqtt")]
+            Transport::MeshMqtt(mqtt) => mqtt
+                .send_text(chan, to, portnum, message)
+                .await
+                .map_err(RedAlertError::RadioUnavailable),
+            // LoRaWAN devices are addressed by target, not by node
+            Transport::Chirpstack(chirpstack) => chirpstack
+                .send(chan, category, message)
+                .await
+                .map_err(RedAlertError::RadioUnavailable),
+            Transport::Observe => Ok(()),
+        }
+    }
+
+    // Give lower-numbered peer gateways the chance to send an alert first; once per
+    // alert, so an alert to several zones is held back only once
+    async fn hold_off_for_peers(&self) {
+        if let Some(peers) = &self.peers {
+            let holdoff = peers.holdoff();
+            if !holdoff.is_zero() {
+                sleep(holdoff).await;
+            }
+        }
+    }
+
+    async fn send_message_with_retry(
+        &mut self,
+        chan: u32,
+        category: &str,
+        message: &str,
+    ) -> Result<(), RedAlertError> {
+        // Long messages go out as numbered parts so receivers can reassemble them
+        for part in self.parts.split(chan, category, message) {
+            if self.peers.as_ref().is_some_and(|peers| peers.already_sent(chan, &part)) {
+                log::info!("A peer gateway already sent this on channel {}; not sending: {}", chan, part);
+                continue;
+            }
+            self.send_with_retry(chan, BROADCAST_ADDR, category, TEXT_MESSAGE_APP, &part).await?;
+            // Only what this gateway sent is offered to nodes asking for a resend
+            self.recent.record(chan, category, &part);
+        }
+        Ok(())
+    }
+
+    // Send the repeats that are due, exactly as the original went out. A repeat is
+    // redundancy on top of a successful send, so it is dropped rather than retried
+    // or held back for airtime.
+    async fn send_due_repeats(&mut self) {
+        for repeat in self.repeats.take_due() {
+            let number = repeat.count - repeat.remaining;
+            if let Transport::Observe = self.transport {
+                log::info!(
+                    "Observation mode, not repeating on channel {} ({}/{}): {}",
+                    repeat.channel,
+                    number,
+                    repeat.count,
+                    repeat.message
+                );
+                continue;
+            }
+            if self.paused.load(Ordering::SeqCst) {
+                continue;
+            }
+
+            let airtime = match &mut self.duty_cycle {
+                Some(duty_cycle) => {
+                    let airtime = duty_cycle.airtime(&repeat.message);
+                    if duty_cycle.wait_for(airtime) != Some(Duration::ZERO) {
+                        log::warn!("Skipping a repeat on channel {} to stay within the duty cycle", repeat.channel);
+                        continue;
+                    }
+                    airtime
+                }
+                None => Duration::ZERO,
+            };
+
+            log::info!(
+                "Repeating on channel {} ({}/{}): {}",
+                repeat.channel,
+                number,
+                repeat.count,
+                repeat.message
+            );
+            match self
+                .send_once(repeat.channel, BROADCAST_ADDR, &repeat.category, repeat.portnum, &repeat.message)
+                .await
+            {
+                Ok(_) => {
+                    self.last_message_time = Some(std::time::Instant::now());
+                    if let Some(duty_cycle) = &mut self.duty_cycle {
+                        duty_cycle.record(airtime);
+                    }
+                    self.stats.record_sent(repeat.channel, &repeat.category, &repeat.message);
+                }
+                Err(e) => {
+                    log::warn!("Failed to repeat a message on channel {}: {}", repeat.channel, e);
+                    self.stats.record_failed(repeat.channel);
+                }
+            }
+        }
+    }
+
+    // Send a text to a single node
+    async fn send_direct(&mut self, chan: u32, to: u32, category: &str, message: &str) -> Result<(), RedAlertError> {
+        for part in self.parts.split(chan, category, message) {
+            self.send_with_retry(chan, to, category, TEXT_MESSAGE_APP, &part).await?;
+        }
+        Ok(())
+    }
+
+    // Retransmit parts of a recent split message, e.g. after a receiver missed one
+    async fn resend_parts(&mut self, chan: u32, category: &str, parts: &[String]) -> Result<(), RedAlertError> {
+        for part in parts {
+            self.send_with_retry(chan, BROADCAST_ADDR, category, TEXT_MESSAGE_APP, part).await?;
+        }
+        Ok(())
+    }
+
+    // Report a zone as a detection sensor named after it
+    async fn send_sensor_state(
+        &mut self,
+        chan: u32,
+        category: &str,
+        name: &str,
+        alerted: bool,
+    ) -> Result<(), RedAlertError> {
+        let message = format!("{} state: {}", name, alerted as u8);
+        self.send_with_retry(chan, BROADCAST_ADDR, category, DETECTION_SENSOR_APP, &message).await
+    }
+
+    async fn send_with_retry(
+        &mut self,
+        chan: u32,
+        to: u32,
+        category: &str,
+        portnum: u64,
+        message: &str,
+    ) -> Result<(), RedAlertError> {
+        self.transmit(chan, to, category, portnum, message, None).await
+    }
+
+    // Send the messages held back for airtime that are due, or hold them back again
+    async fn send_due_deferred(&mut self) {
+        for send in self.deferred.take_due() {
+            let result = self
+                .transmit(send.channel, send.to, &send.category, send.portnum, &send.message, Some(send.deadline))
+                .await;
+            if let Err(e) = ignore_paused(result) {
+                log::error!("Failed to send a held back message to channel {}: {}", send.channel, e);
+            }
+        }
+    }
+
+    // Send a message, holding it back while it doesn't fit the duty cycle; a message
+    // already held back is dropped once it misses its deadline
+    async fn transmit(
+        &mut self,
+        chan: u32,
+        to: u32,
+        category: &str,
+        portnum: u64,
+        message: &str,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<(), RedAlertError> {
+        let (retries, delay) = (self.retries, self.retry_delay);
+        let started = Instant::now();
+        // Broadcasts go out so a store-and-forward server on the channel keeps them
+        let portnum = match self.store_forward() {
+            Some(store_forward) if to == BROADCAST_ADDR => {
+                let mut store_forward = lock_store_forward(&store_forward);
+                store_forward.check_channel(chan);
+                store_forward.stored_portnum(chan, portnum)
+            }
+            _ => portnum,
+        };
+        if let Transport::Observe = self.transport {
+            log::info!("Observation mode, not sending to channel {}: {}", chan, message);
+            events::emit(Event::SendObserved {
+                channel: chan,
+                category: category.to_string(),
+                message: message.to_string(),
+            });
+            if to == BROADCAST_ADDR {
+                self.repeats.schedule(chan, category, portnum, message);
+            }
+            return Ok(());
+        }
+        if self.paused.load(Ordering::SeqCst) {
+            log::warn!("Transmission is paused, not sending to channel {}: {}", chan, message);
+            events::emit(Event::SendObserved {
+                channel: chan,
+                category: category.to_string(),
+                message: message.to_string(),
+            });
+            return Err(RedAlertError::Paused);
+        }
+
+        // Space out transmissions according to the category's priority
+        // and stretch the gap while the channel is congested
+        let extra = self.throttle.as_ref().map(|throttle| throttle.extra_gap(category)).unwrap_or_default();
+        let gap = self.gaps.gap_for(category) + extra;
+        if let Some(last_time) = self.last_message_time {
+            let elapsed = last_time.elapsed();
+            if elapsed < gap {
+                if !extra.is_zero() {
+                    log::info!("Channel is congested; holding {} messages back an extra {:?}", category, extra);
+                }
+                sleep(gap - elapsed).await;
+            }
+        }
+
+        let original = message;
+
+        // Number text messages per channel so receivers can spot missed ones
+        let numbered = match &self.sequence {
+            Some(sequence) if portnum == TEXT_MESSAGE_APP => Some(format!("#{} {}", sequence.next(chan), message)),
+            _ => None,
+        };
+        let message = numbered.as_deref().unwrap_or(message);
+
+        // Sign last, so the signature covers the sequence number too
+        let signed = match &self.hmac_key {
+            Some(key) if portnum == TEXT_MESSAGE_APP => Some(signing::sign(key, message)),
+            _ => None,
+        };
+        let message = signed.as_deref().unwrap_or(message);
+
+        // Hold the message back until it fits the regional duty cycle, or drop it if that takes too long
+        let held_back = deadline.is_some();
+        let now = tokio::time::Instant::now();
+        let deadline = deadline.unwrap_or(now + self.duty_cycle_max_wait);
+        let airtime = match &mut self.duty_cycle {
+            Some(duty_cycle) => {
+                let airtime = duty_cycle.airtime(message);
+                match duty_cycle.plan(airtime, now, deadline) {
+                    Plan::Send => {}
+                    Plan::HoldBack(wait) => {
+                        log::warn!(
+                            "Duty cycle limit reached ({:?} of airtime used this hour); holding the message to channel {} back {:?}",
+                            duty_cycle.used(),
+                            chan,
+                            wait
+                        );
+                        self.deferred.push(DeferredSend {
+                            due: now + wait,
+                            deadline,
+                            channel: chan,
+                            to,
+                            category: category.to_string(),
+                            portnum,
+                            message: original.to_string(),
+                        });
+                        return Ok(());
+                    }
+                    Plan::Drop(reason) => {
+                        log::error!("Not sending to channel {} to stay within the duty cycle: {}", chan, reason);
+                        let suppressed = if held_back { Reason::ExpiredInQueue } else { Reason::DutyCycle };
+                        self.suppressed.record(suppressed, category, 0);
+                        return Err(RedAlertError::DutyCycle(reason));
+                    }
+                }
+                airtime
+            }
+            None => Duration::ZERO,
+        };
+
+        for attempt in 0..=retries {
+            log::info!("Sending an alert with content: {}", message);
+            let result = self.send_once(chan, to, category, portnum, message).await;
+            match result {
+                Ok(_) => {
+                    self.last_message_time = Some(std::time::Instant::now());
+                    if let Some(duty_cycle) = &mut self.duty_cycle {
+                        duty_cycle.record(airtime);
+                    }
+                    if let (Some(sequence), Some(_)) = (&mut self.sequence, &numbered) {
+                        sequence.advance(chan);
+                    }
+                    if to == BROADCAST_ADDR {
+                        self.repeats.schedule(chan, category, portnum, message);
+                    }
+                    self.stats.record_sent(chan, category, message);
+                    influx::record_latency("send", Some(chan), category, started.elapsed());
+                    events::emit(Event::SendSucceeded {
+                        channel: chan,
+                        category: category.to_string(),
+                        message: message.to_string(),
+                        attempts: attempt + 1,
+                    });
+                    return Ok(());
+                }
+                Err(e) => {
+                    if attempt < retries {
+                        log::warn!("Error sending message: {}. Retrying in {:?}...", e, delay);
+                        sleep(delay).await;
+                    } else {
+                        log::error!("Error sending message after {} attempts: {}", attempt + 1, e);
+                        self.stats.record_failed(chan);
+                        events::emit(Event::SendFailed {
+                            channel: chan,
+                            category: category.to_string(),
+                            message: message.to_string(),
+                            attempts: attempt + 1,
+                            error: e.to_string(),
+                        });
+                        return Err(RedAlertError::SendFailed {
+                            attempts: attempt + 1,
+                            reason: e.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// How long the city database download may take before falling back
+const CITIES_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(20);
+
+// Parse city data and check it is usable: a list of cities that map to districts
+fn parse_cities(data: &[u8]) -> Result<Vec<City>, String> {
+    let cities: Vec<City> = serde_json::from_slice(data).map_err(|e| format!("Not valid city data: {}", e))?;
+    if !cities.iter().any(|city| !city.name.is_empty() && !city.zone_en.is_empty()) {
+        return Err("The city data lists no cities with a district".to_string());
+    }
+    Ok(cities)
+}
+
+// Fetch and check the city database from a URL, returning its raw contents and the parsed cities
+async fn download_cities(url: &str) -> Result<(Vec<u8>, Vec<City>), String> {
+    let client = reqwest::Client::builder()
+        .timeout(CITIES_DOWNLOAD_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let response = client.get(url).send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status().as_u16()));
+    }
+    let data = response.bytes().await.map_err(|e| e.to_string())?.to_vec();
+    let cities = parse_cities(&data)?;
+    Ok((data, cities))
+}
+
+// Name of a source as given on the command line
+fn source_name(source: Source) -> String {
+    source
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+// The country profile of the selected source; alerts read from stdin are oref alerts
+fn country_profile(args: &Args) -> Result<Box<dyn CountryProfile>, RedAlertError> {
+    match args.source {
+        Source::Oref | Source::Stdin => Ok(Box::new(Oref::new(false, false))),
+        Source::History => Ok(Box::new(Oref::new(true, args.history_archive))),
+        Source::Combined => Ok(Box::new(OrefCombined::new())),
+        // Ukrainian alerts come from alerts.in.ua, which needs a token
+        Source::Ukraine => match &args.ua_token {
+            Some(token) => Ok(Box::new(UkraineSource::new(token.clone()))),
+            None => Err(RedAlertError::Config("--source ukraine needs an alerts.in.ua --ua-token".to_string())),
+        },
+        // CAP alerts come from any CAP or ATOM feed
+        #[cfg(feature = "cap")]
+        Source::Cap => match &args.cap_url {
+            Some(url) => Ok(Box::new(CapSource::new(url.clone(), Duration::from_secs(args.cap_poll.max(5))))),
+            None => Err(RedAlertError::Config("--source cap needs a --cap-url".to_string())),
+        },
+        #[cfg(not(feature = "cap"))]
+        Source::Cap => Err(RedAlertError::Config("--source cap needs a build with the \"cap\" feature".to_string())),
+    }
+}
+
+// Load the city database: from --cities-url if given, falling back to the last
+// good download and then to the built-in cities.json
+async fn load_cities(args: &Args) -> Result<Vec<City>, RedAlertError> {
+    if let Some(url) = &args.cities_url {
+        match download_cities(url).await {
+            Ok((data, cities)) => {
+                log::info!("Downloaded {} cities from {}", cities.len(), url);
+                if let Err(e) = std::fs::write(&args.cities_cache, data) {
+                    log::warn!("Failed to cache the city database in {}: {}", args.cities_cache, e);
+                }
+                return Ok(cities);
+            }
+            Err(e) => log::warn!("Failed to download the city database from {}: {}", url, e),
+        }
+
+        match std::fs::read(&args.cities_cache).map_err(|e| e.to_string()).and_then(|data| parse_cities(&data)) {
+            Ok(cities) => {
+                log::warn!("Using the cached city database from {}", args.cities_cache);
+                return Ok(cities);
+            }
+            Err(e) => log::warn!("No usable cached city database in {} ({}); using the built-in one", args.cities_cache, e),
+        }
+    }
+
+    let cities_json = Asset::get("cities.json").ok_or_else(|| RedAlertError::Config("Failed to load cities.json".to_string()))?;
+    let cities: Vec<City> = serde_json::from_slice(&cities_json.data)?;
+    Ok(cities)
+}
+
+// Cross-check the city data against the zone mapping, returning every problem found
+fn validate_cities(cities: &[City], zones: &ZoneScheme) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    // Districts that no zone contains, with how many cities they hold
+    let mut unmapped: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut duplicates: BTreeSet<&str> = BTreeSet::new();
+
+    for city in cities {
+        // The "select all" placeholder entry has no district
+        if city.zone_en.is_empty() {
+            continue;
+        }
+        if zones.zones_for_city(city).is_empty() {
+            *unmapped.entry(city.zone_en.as_str()).or_default() += 1;
+        }
+        if !seen.insert(city.name.as_str()) {
+            duplicates.insert(city.name.as_str());
+        }
+    }
+
+    for (district, count) in unmapped {
+        problems.push(format!(
+            "District \"{}\" ({} cities) maps to no zone; alerts for it would be dropped",
+            district, count
+        ));
+    }
+    for name in duplicates {
+        problems.push(format!("City name \"{}\" appears more than once", name));
+    }
+    for unmatched in zones.unmatched_routes(cities) {
+        problems.push(format!("A [[route]] names {}, which is not in the city data", unmatched));
+    }
+    problems
+}
+
+// Name of a city in the language of messages; English names fall back to the Hebrew one
+fn place_name(city: &City, language: Language) -> &str {
+    match language {
+        Language::English if !city.name_en.is_empty() => &city.name_en,
+        _ => &city.name,
+    }
+}
+
+// Shelter time of the most urgent alerted city, e.g. "Sderot +3 – 15s to shelter"
+fn shelter_note(cities: &CityIndex, alerted: &[String], language: Language) -> Option<String> {
+    let (most_urgent, shelter_time) = alerted
+        .iter()
+        .filter_map(|name| cities.get(name))
+        .filter_map(|city| city.shelter_time().map(|time| (city, time)))
+        .min_by_key(|(_, time)| *time)?;
+
+    let name = if alerted.len() > 1 {
+        format!("{} +{}", place_name(most_urgent, language), alerted.len() - 1)
+    } else {
+        place_name(most_urgent, language).to_string()
+    };
+    Some(format!("{} – {}s to shelter", name, shelter_time.as_secs()))
+}
+
+// Append the shelter time of the alerted cities to a message
+fn with_shelter_note(message: &str, cities: &CityIndex, alerted: &[String], language: Language) -> String {
+    match shelter_note(cities, alerted, language) {
+        Some(note) => format!("{} | {}", message, note),
+        None => message.to_string(),
+    }
+}
+
+// What to do during an earthquake, unless the alert carries its own instructions
+const EARTHQUAKE_GUIDANCE: &str =
+    "Get out to open ground away from buildings; if you can't, go to a safe room or stairwell";
+
+// Follow-up after an earthquake, sent with --aftershock-guidance
+const AFTERSHOCK_GUIDANCE: &str =
+    "Aftershocks may follow: stay out of damaged buildings and keep to open ground; if one hits, act as in the earthquake";
+
+// Tsunami alerts always reach the coast: evacuation needs lead time and the city list often lags
+fn is_tsunami(alert_type: &str) -> bool {
+    alert_type.to_lowercase().contains("tsunami")
+}
+
+// Earthquakes are felt far beyond any zone, so their alerts go out on every channel
+fn is_earthquake(alert_type: &str) -> bool {
+    alert_type.to_lowercase().contains("earthquake")
+}
+
+// Drills and tests are announced by other means and never go out on the mesh
+fn is_drill(alert_type: &str) -> bool {
+    let alert_type = alert_type.to_lowercase();
+    alert_type.contains("drill") || alert_type.contains("test")
+}
+
+// Source of the alerts coordinators post to /alerts/manual
+const MANUAL_SOURCE: &str = "http";
+
+// Seconds an alert stays in effect after it was last seen in the feed, unless
+// --all-clear-after says otherwise; later polls of it are not sent again
+const CLEAR_AFTER: u64 = 600;
+
+// How long polling stays at half rate after the alert source rate limited the gateway
+const RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(600);
+
+// Most cities named in an "expanded" message; the rest are counted
+const MAX_LISTED_CITIES: usize = 5;
+
+// Names of alerted cities for a message, e.g. "Ashdod, Yavne +3"
+fn city_list(cities: &CityIndex, alerted: &[String], language: Language) -> String {
+    let names: Vec<&str> = alerted
+        .iter()
+        .take(MAX_LISTED_CITIES)
+        .map(|name| cities.get(name).map(|city| place_name(city, language)).unwrap_or(name))
+        .collect();
+    let mut list = names.join(", ");
+    if alerted.len() > MAX_LISTED_CITIES {
+        list.push_str(&format!(" +{}", alerted.len() - MAX_LISTED_CITIES));
+    }
+    list
+}
+
+// Channels of an alerted city: its area groups' with an area map, otherwise its zones,
+// by the locality ID or district the feed gives when the name isn't known
+fn city_channels(cities: &CityIndex, area_map: Option<&AreaMap>, city: &str, area: Option<&CityArea>) -> Vec<u32> {
+    match area_map {
+        Some(area_map) => area_map.channels_for(city),
+        None => match (cities.zones(city), area) {
+            ([], Some(area)) => cities.zones_of_area(area).to_vec(),
+            (zones, _) => zones.to_vec(),
+        },
+    }
+}
+
+// Send past alerts as drills on the zones of their cities, or only on `only_zones`
+async fn replay_alerts(
+    sender: &mut MessageSender,
+    alerts: &[AlertResult],
+    only_zones: &[u32],
+    cities: &CityIndex,
+    area_map: Option<&AreaMap>,
+    language: Language,
+) -> Result<(), RedAlertError> {
+    for alert in alerts {
+        let mut zone_cities: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+        for city in &alert.cities {
+            for zone in city_channels(cities, area_map, city, alert.areas.get(city)) {
+                if only_zones.is_empty() || only_zones.contains(&zone) {
+                    zone_cities.entry(zone).or_default().push(city.clone());
+                }
+            }
+        }
+        if zone_cities.is_empty() {
+            log::warn!("The {} alert of {:?} has no city in the zones to replay on", alert.alert_type, alert.alert_date);
+            continue;
+        }
+
+        let opening = replay::drill_message(&alert.alert_type, alert.alert_date);
+        for (channel, alerted) in zone_cities {
+            let message = with_shelter_note(&opening, cities, &alerted, language);
+            log::info!("Replaying on channel {}: {}", channel, message);
+            sender.send_message_with_retry(channel, "drill", &message).await?;
+        }
+    }
+    Ok(())
+}
+
+// Remember the alerted cities so they can be served as active alerts
+fn record_active_cities(active: &SharedActiveAlerts, cities: &CityIndex, alert_result: &AlertResult) {
+    let now = Utc::now();
+    let mut active = lock_active(active);
+
+    for name in &alert_result.cities {
+        let city = cities.get(name);
+        let (lat, lng) = city.and_then(City::coordinates).unwrap_or_default();
+        active.record(ActiveCity {
+            name: name.clone(),
+            name_en: city.map(|c| c.name_en.clone()).unwrap_or_default(),
+            zones: cities.zones(name).to_vec(),
+            alert_type: alert_result.alert_type.clone(),
+            lat,
+            lng,
+            since: now,
+            last_seen: now,
+        });
+    }
+}
+
+// A send skipped while transmission is paused isn't a failure for messages that
+// only matter at the time, such as all clears, the digest and replies to nodes
+fn ignore_paused(result: Result<(), RedAlertError>) -> Result<(), RedAlertError> {
+    match result {
+        Err(RedAlertError::Paused) => Ok(()),
+        result => result,
+    }
+}
+
+// Detection sensor name of a channel: its area group or zone
+fn sensor_name(zones: &ZoneScheme, area_map: Option<&AreaMap>, channel: u32) -> String {
+    if channel == 0 {
+        return "All zones".to_string();
+    }
+    area_map
+        .map_or_else(|| zones.name_for(channel), |area_map| area_map.name_for(channel))
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("Channel {}", channel))
+}
+
+// Report an alert that came in from a source, ignoring empty polls
+fn emit_fetched(source: &str, alert_result: &AlertResult) {
+    if !alert_result.alert_type.contains("none") {
+        events::emit(Event::AlertFetched {
+            source: source.to_string(),
+            alert_type: alert_result.alert_type.clone(),
+            cities: alert_result.cities.clone(),
+            instructions: alert_result.instructions.clone(),
+        });
+    }
+}
+
+// Request to re-read the config file, answered with what was applied
+pub type ReloadRequest = tokio::sync::oneshot::Sender<Result<String, String>>;
+
+// Whether transmission is paused, shared by the sender and the control APIs
+pub type SharedPause = Arc<std::sync::atomic::AtomicBool>;
+
+// Where state is kept without --data-dir: the directory systemd sets up for a
+// unit with StateDirectory=, else the user's XDG state directory
+fn default_data_dir() -> String {
+    if let Some(dir) = std::env::var("STATE_DIRECTORY").ok().and_then(|dirs| dirs.split(':').next().map(str::to_string)) {
+        return dir;
+    }
+    let base = std::env::var("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| std::env::var("HOME").map(|home| Path::new(&home).join(".local/state")));
+    match base {
+        Ok(base) => base.join("red-alert-meshtastic").display().to_string(),
+        Err(_) => ".".to_string(),
+    }
+}
+
+// File in the data directory with the last admin command counter per node
+const ADMIN_COUNTERS: &str = "admin-counters.json";
+
+// Directory of the outbox queues
+fn outbox_dir(args: &Args) -> PathBuf {
+    match &args.outbox_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(&args.data_dir).join("outbox"),
+    }
+}
+
+// Data the gateway keeps on disk that grows while it runs
+fn stored_data(args: &Args) -> Vec<Stored> {
+    let mut stored = vec![Stored::DeadLetters(outbox_dir(args))];
+    if let Some(path) = &args.node_snapshots {
+        stored.push(Stored::JsonLines(PathBuf::from(path), "time"));
+    }
+    if let Some(dir) = &args.cap_output_dir {
+        stored.push(Stored::Directory(PathBuf::from(dir), "xml"));
+    }
+    stored
+}
+
+// Requests the alert loop serves besides polling the feed
+struct Inbox {
+    // Alerts injected from outside the oref feed, with their source
+    alerts_rx: mpsc::Receiver<(&'static str, AlertResult)>,
+    state_rx: mpsc::Receiver<StateRequest>,
+    resend_rx: mpsc::Receiver<ResendRequest>,
+    reload_rx: mpsc::Receiver<ReloadRequest>,
+    // Texts heard on the mesh; closed right away for transports that can't receive
+    mesh_rx: mpsc::Receiver<InboundText>,
+}
+
+// Everything the alert pipeline needs while running
+struct Gateway {
+    args: Args,
+    cities: Arc<CityIndex>,
+    zones: ZoneScheme,
+    sender: MessageSender,
+    active: SharedActiveAlerts,
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<MqttPublisher>,
+    cap_publisher: Option<CapPublisher>,
+    // Chat and social outputs told about every alert
+    notifiers: Vec<Box<dyn Notifier>>,
+    area_map: Option<AreaMap>,
+    alert_log: AlertLog,
+    dedup: AlertDedup,
+    lifecycle: AlertLifecycle,
+    // Where alerts come from and how they are named, routed and worded
+    country: Box<dyn CountryProfile>,
+    // Language of place names in messages
+    language: Language,
+    #[cfg(feature = "cluster")]
+    cluster: Option<Cluster>,
+    // Set with --lock-file; only the holder of the lock transmits
+    instance_lock: Option<InstanceLock>,
+    // Whether this gateway transmitted on the previous tick
+    #[cfg(feature = "cluster")]
+    leading: bool,
+    // Set when admin commands are accepted from the mesh
+    admin: Option<AdminAuth>,
+    // How often the feed is polled; operators can change it over the mesh
+    poll_every: Duration,
+    // When the last alert in effect was cleared, for adaptive polling
+    quiet_since: Option<Instant>,
+    // When an alert was last seen on any source, sent or not, for adaptive polling
+    alert_seen_at: Option<Instant>,
+    // When the alert source last rate limited the gateway
+    rate_limited_at: Option<Instant>,
+    // Follow-up guidance per alert category
+    protective: ProtectiveActions,
+    // Short forms of instruction texts, to save payload
+    abbreviations: Abbreviations,
+    // Extra channels for alert categories, with their own templates and gaps
+    category_channels: CategoryChannels,
+    // How long alerts of each category stay valid
+    validity: Validity,
+    // When to send aftershock guidance after an earthquake, and on which channels
+    aftershock_due: Option<(Instant, Vec<u32>)>,
+    // Zones waiting to be told the threat has passed, with --threat-passed
+    threat_passed: Option<ThreatPassed>,
+    // Probes checking the send path end to end, with the mesh MQTT transport
+    canary: Option<Canary>,
+    // Nodes that get alerts by direct message, with --subscriber-db
+    #[cfg(feature = "sqlite")]
+    subscribers: Option<SharedSubscribers>,
+    started: Instant,
+}
+
+impl Gateway {
+    // Main logic to send alerts to appropriate zones
+    async fn process_alert(&mut self) -> Result<(), RedAlertError> {
+        // Fetch the current alerts from the configured country's API
+        let source = self.country.source();
+        let mut alerts = self.country.fetch_alerts().await?;
+
+        // While the channel is congested every send waits longer, so the most severe events go first
+        let congested = self.sender.throttle.as_ref().is_some_and(|throttle| throttle.congestion() > 0.0);
+        if congested {
+            alerts.sort_by_key(|alert| std::cmp::Reverse(ratelimit::severity(&alert.alert_type)));
+        }
+        // One alert failing to go out doesn't hold back the others
+        for alert_result in alerts {
+            emit_fetched(source, &alert_result);
+            if let Err(e) = self.dispatch_alert(alert_result, false).await {
+                log::error!("Error processing alert: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // Whether this gateway transmits; a gateway taking over the leader lease
+    // picks up the alerts its predecessor had in effect
+    async fn lead(&mut self) -> bool {
+        // Another instance on this host is transmitting
+        if self.instance_lock.as_ref().is_some_and(|lock| !lock.is_held()) {
+            return false;
+        }
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &mut self.cluster {
+            let leader = cluster.is_leader();
+            if leader && !self.leading {
+                let alerts = cluster.load_state().await;
+                log::info!("Taking over {} alert(s) in effect", alerts.len());
+                self.lifecycle.restore(alerts);
+            }
+            self.leading = leader;
+            return leader;
+        }
+        true
+    }
+
+    // Store the alerts in effect for the gateway that takes over next
+    async fn share_state(&mut self) {
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &mut self.cluster {
+            cluster.save_state(&self.lifecycle.snapshot()).await;
+        }
+    }
+
+    // Send the alerts that standbys received (HTTP, stdin) on their behalf
+    #[cfg(feature = "cluster")]
+    async fn dispatch_forwarded(&mut self) {
+        let Some(cluster) = &mut self.cluster else {
+            return;
+        };
+        for (source, alert) in cluster.take_forwarded().await {
+            emit_fetched(&source, &alert);
+            if let Err(e) = self.dispatch_alert(alert, source == MANUAL_SOURCE).await {
+                log::error!("Error processing forwarded alert: {}", e);
+            }
+        }
+    }
+
+    // Expire finished alerts and publish zones that became active or clear
+    async fn refresh_active_state(&mut self) {
+        lock_active(&self.active).expire(Utc::now());
+
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &mut self.mqtt {
+            let snapshot = lock_active(&self.active).snapshot();
+            mqtt.sync_zone_states(&snapshot, &self.zones.channels());
+        }
+    }
+
+    // Clear alerts that are no longer in the feed and announce it on their channels
+    async fn send_all_clears(&mut self) -> Result<(), RedAlertError> {
+        let mut cleared_sensors = HashSet::new();
+        let all_cleared = self.lifecycle.expire(Utc::now());
+        if !all_cleared.is_empty() {
+            self.sender.hold_off_for_peers().await;
+        }
+        for cleared in all_cleared {
+            events::emit(Event::AlertCleared {
+                alert_type: cleared.category.clone(),
+                channel: cleared.channel,
+                cities: cleared.cities.iter().cloned().collect(),
+            });
+            match self.args.message_style {
+                MessageStyle::Text if self.args.all_clear_after.is_none() => {
+                    log::info!("{} alert on channel {} cleared", cleared.category, cleared.channel);
+                }
+                MessageStyle::Text => {
+                    let message = format!("✅{} all clear", cleared.category);
+                    ignore_paused(
+                        self.sender
+                            .send_message_with_retry(cleared.channel, &cleared.category, &message)
+                            .await,
+                    )?;
+                }
+                // The zone's sensor stays on while another category is still in effect there
+                MessageStyle::Sensor
+                    if self.lifecycle.is_active(cleared.channel) || !cleared_sensors.insert(cleared.channel) => {}
+                MessageStyle::Sensor => {
+                    let name = sensor_name(&self.zones, self.area_map.as_ref(), cleared.channel);
+                    ignore_paused(
+                        self.sender
+                            .send_sensor_state(cleared.channel, &cleared.category, &name, false)
+                            .await,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Follow an earthquake alert with aftershock guidance once its delay has passed
+    async fn send_aftershock_guidance_if_due(&mut self) -> Result<(), RedAlertError> {
+        if self.aftershock_due.as_ref().is_none_or(|(due, _)| Instant::now() < *due) {
+            return Ok(());
+        }
+        let Some((_, channels)) = self.aftershock_due.take() else {
+            return Ok(());
+        };
+
+        let message = format!("🌍{}", AFTERSHOCK_GUIDANCE);
+        log::info!("Sending aftershock guidance on channels {:?}", channels);
+        for channel in channels {
+            ignore_paused(self.sender.send_message_with_retry(channel, "aftershock", &message).await)?;
+        }
+        Ok(())
+    }
+
+    // Tell the zones whose shelter time ran out without a newer alert that they may leave the shelter
+    async fn send_threat_passed(&mut self) -> Result<(), RedAlertError> {
+        let Some(threat_passed) = &mut self.threat_passed else {
+            return Ok(());
+        };
+        for passed in threat_passed.due(Utc::now()) {
+            let minutes = passed.shelter_time.as_secs() / 60;
+            if self.args.message_style != MessageStyle::Text {
+                log::info!("Shelter time of the {} alert on channel {} is over", passed.category, passed.channel);
+                continue;
+            }
+            let message = format!(
+                "✅{}: threat passed, you may leave the shelter ({} min since the last alert at {})",
+                passed.category,
+                minutes,
+                localtime::to_local(passed.last_alert).format("%H:%M")
+            );
+            log::info!("Threat of the {} alert on channel {} has passed", passed.category, passed.channel);
+            ignore_paused(
+                self.sender
+                    .send_message_with_retry(passed.channel, &passed.category, &message)
+                    .await,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Transmit the daily digest once the configured local hour is reached
+    async fn send_digest_if_due(&mut self) -> Result<(), RedAlertError> {
+        let Some(digest_hour) = self.args.digest_hour else {
+            return Ok(());
+        };
+
+        let local = localtime::now();
+        let digest_hour = localtime::scheduled_hour(local.date_naive(), digest_hour);
+        if !self.alert_log.digest_due(local.date_naive(), local.hour(), digest_hour) {
+            return Ok(());
+        }
+        self.alert_log.mark_digest_sent(local.date_naive());
+
+        let digest = self.alert_log.digest(Utc::now(), self.started.elapsed());
+        log::info!("Sending daily digest: {}", digest);
+        ignore_paused(
+            self.sender
+                .send_message_with_retry(self.args.digest_channel, "digest", &digest)
+                .await,
+        )
+    }
+
+    // Everything useful for debugging missed or duplicated alerts
+    fn debug_state(&self, pending_alerts: usize) -> serde_json::Value {
+        #[cfg(feature = "cluster")]
+        let cluster = self.cluster.as_ref().map(Cluster::debug_state);
+        #[cfg(not(feature = "cluster"))]
+        let cluster: Option<serde_json::Value> = None;
+        serde_json::json!({
+            "time": Utc::now().to_rfc3339(),
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "paused": self.sender.paused.load(Ordering::SeqCst),
+            "active": lock_active(&self.active).debug_state(),
+            "dedup": self.dedup.debug_state(),
+            "lifecycle": self.lifecycle.debug_state(),
+            "threat_passed": self.threat_passed.as_ref().map(ThreatPassed::debug_state),
+            "canary": self.canary.as_ref().map(Canary::debug_state),
+            "pending_alerts": pending_alerts,
+            "last_transmission_secs_ago": self.sender.last_message_time.map(|time| time.elapsed().as_secs()),
+            "zone_cooldown": self.sender.zone_cooldown.debug_state(),
+            "airtime": self.sender.throttle.as_ref().map(AirtimeThrottle::debug_state),
+            "duty_cycle": self.sender.duty_cycle.as_ref().map(DutyCycle::debug_state),
+            "held_back": self.sender.deferred.debug_state(),
+            "split_messages": self.sender.parts.debug_state(),
+            "sequence": self.sender.sequence.as_ref().map(SequenceCounters::debug_state),
+            "peers": self.sender.peers.as_ref().map(|peers| peers.debug_state()),
+            "channel_stats": self.sender.stats.debug_state(),
+            "suppressed": self.sender.suppressed.debug_state(),
+            "store_forward": self.sender.store_forward().map(|store_forward| lock_store_forward(&store_forward).debug_state()),
+            "cluster": cluster,
+            "rate_limited_secs_ago": self.rate_limited_at.map(|at| at.elapsed().as_secs()),
+            "api_responses": api::recent_responses(),
+        })
+    }
+
+    // Apply the command line and config file as they are now. Only the settings read
+    // while handling alerts change; the transport, listeners, zones and templates keep
+    // their startup values until a restart.
+    fn reload(&mut self) -> Result<String, String> {
+        let mut args = reload_args()?;
+        if args.radius_km.is_some() && args.location.or(args.node_position).is_none() {
+            return Err("--radius-km needs --location or --node-position".to_string());
+        }
+        // Set by the subcommand rather than the config file
+        args.observe = self.args.observe;
+        args.output = self.args.output;
+
+        self.poll_every = Duration::from_secs(args.poll_interval.max(1));
+        self.protective = ProtectiveActions::new(args.builtin_protective_actions, &args.protective_action);
+        self.abbreviations = Abbreviations::new(args.abbreviate, &args.abbreviation);
+        self.validity = Validity::new(args.valid_for, &args.validity);
+        self.args = args;
+        Ok(match &self.args.config {
+            Some(path) => format!("Reloaded {}; restart to apply transport, listener, zone and template changes", path),
+            None => "Reloaded the command line; restart to apply transport, listener, zone and template changes".to_string(),
+        })
+    }
+
+    // Time until the next poll: faster while alerts are in effect or were just seen and
+    // slower after a long quiet period in adaptive mode, plus random jitter
+    fn next_poll_delay(&mut self) -> Duration {
+        let window = Duration::from_secs(self.args.active_window * 60);
+        let recent = self.alert_seen_at.is_some_and(|seen| seen.elapsed() < window);
+        let every = if !self.args.adaptive_poll {
+            self.poll_every
+        } else if !self.lifecycle.is_empty() || recent {
+            self.quiet_since = None;
+            Duration::from_secs(self.args.poll_active)
+        } else if self.quiet_since.get_or_insert_with(Instant::now).elapsed() >= Duration::from_secs(self.args.quiet_after) {
+            Duration::from_secs(self.args.poll_quiet)
+        } else {
+            self.poll_every
+        };
+        // Back off for a while after being rate limited
+        let every = if self.rate_limited_at.is_some_and(|at| at.elapsed() < RATE_LIMIT_COOLDOWN) {
+            every * 2
+        } else {
+            every
+        };
+        let jitter = match self.args.poll_jitter {
+            0 => 0,
+            max => rand::random::<u64>() % (max + 1),
+        };
+        every.max(Duration::from_secs(1)) + Duration::from_millis(jitter)
+    }
+
+    // Send a canary probe on its channel when one is due; it has to be heard back before the next
+    async fn send_canary_if_due(&mut self) {
+        let Some(canary) = &mut self.canary else {
+            return;
+        };
+        if self.sender.paused.load(Ordering::SeqCst) {
+            return;
+        }
+        let now = Instant::now();
+        let Some(probe) = canary.due(now) else {
+            return;
+        };
+        let channel = canary.channel();
+        match self
+            .sender
+            .send_with_retry(channel, BROADCAST_ADDR, "canary", TEXT_MESSAGE_APP, &probe)
+            .await
+        {
+            Ok(()) => canary.sent(probe, now),
+            Err(e) => canary.send_failed(e.to_string(), now),
+        }
+    }
+
+    // Tell the operator the alert source is rate limiting us, once per episode
+    fn rate_limited(&mut self, retry_after: Duration) {
+        let source = self.country.source().to_string();
+        if self.rate_limited_at.is_some_and(|at| at.elapsed() < RATE_LIMIT_COOLDOWN) {
+            log::info!("Still rate limited by the {} source; retrying in {}s", source, retry_after.as_secs());
+        } else {
+            log::warn!(
+                "The {} source is rate limiting the gateway; retrying in {}s and polling at half rate for the next {} minutes",
+                source,
+                retry_after.as_secs(),
+                RATE_LIMIT_COOLDOWN.as_secs() / 60
+            );
+        }
+        self.rate_limited_at = Some(Instant::now());
+        events::emit(Event::ApiRateLimited {
+            source,
+            retry_after_secs: retry_after.as_secs(),
+        });
+    }
+
+    // Poll the feed on the poll schedule and handle alerts injected from other sources
+    async fn run(&mut self, inbox: &mut Inbox) {
+        let mut next_poll = tokio::time::Instant::now();
+
+        loop {
+            // Repeats and messages held back for airtime go out between polls
+            let next_send = self.sender.repeats.next_due().into_iter().chain(self.sender.deferred.next_due()).min();
+            tokio::select! {
+                _ = tokio::time::sleep_until(next_send.unwrap_or(next_poll)), if next_send.is_some() => {
+                    self.sender.send_due_deferred().await;
+                    self.sender.send_due_repeats().await;
+                }
+                _ = tokio::time::sleep_until(next_poll) => {
+                    next_poll = tokio::time::Instant::now() + self.next_poll_delay();
+                    // A standby leaves the feed to the leader
+                    if !self.lead().await {
+                        continue;
+                    }
+                    #[cfg(feature = "cluster")]
+                    self.dispatch_forwarded().await;
+
+                    // Handle process_alert errors without exiting the loop
+                    if self.args.source != Source::Stdin {
+                        match self.process_alert().await {
+                            Err(RedAlertError::RateLimited { retry_after }) => {
+                                self.rate_limited(retry_after);
+                                next_poll = next_poll.max(tokio::time::Instant::now() + retry_after);
+                            }
+                            Err(e) => log::error!("Error processing alert: {}", e),
+                            Ok(()) => {}
+                        }
+                    }
+                    self.refresh_active_state().await;
+
+                    if let Err(e) = self.send_all_clears().await {
+                        log::error!("Error sending all clear: {}", e);
+                    }
+
+                    if let Err(e) = self.send_aftershock_guidance_if_due().await {
+                        log::error!("Error sending aftershock guidance: {}", e);
+                    }
+
+                    if let Err(e) = self.send_threat_passed().await {
+                        log::error!("Error sending threat passed follow-up: {}", e);
+                    }
+
+                    if let Err(e) = self.send_digest_if_due().await {
+                        log::error!("Error sending daily digest: {}", e);
+                    }
+
+                    self.send_canary_if_due().await;
+                    self.share_state().await;
+                }
+                Some((source, alert)) = inbox.alerts_rx.recv() => {
+                    if !self.lead().await {
+                        #[cfg(feature = "cluster")]
+                        if let Some(cluster) = &mut self.cluster {
+                            log::info!("Standing by; forwarding the {} alert from {} to the leader", alert.alert_type, source);
+                            if let Err(e) = cluster.forward(source, &alert).await {
+                                log::error!("{}", e);
+                            }
+                            continue;
+                        }
+                        log::warn!("Standing by; dropping the {} alert from {}", alert.alert_type, source);
+                        continue;
+                    }
+                    emit_fetched(source, &alert);
+                    if let Err(e) = self.dispatch_alert(alert, source == MANUAL_SOURCE).await {
+                        log::error!("Error processing injected alert: {}", e);
+                    }
+                    self.share_state().await;
+                }
+                Some(reply) = inbox.state_rx.recv() => {
+                    let state = self.debug_state(inbox.alerts_rx.len());
+                    log::info!("State dump: {}", state);
+                    let _ = reply.send(state);
+                }
+                Some(reply) = inbox.reload_rx.recv() => {
+                    let result = self.reload();
+                    match &result {
+                        Ok(applied) => log::info!("{}", applied),
+                        Err(e) => log::error!("Reload failed, keeping the current settings: {}", e),
+                    }
+                    let _ = reply.send(result);
+                    next_poll = tokio::time::Instant::now() + self.next_poll_delay();
+                }
+                Some(text) = inbox.mesh_rx.recv() => {
+                    // Only the leader answers the mesh
+                    if !self.lead().await {
+                        continue;
+                    }
+                    let poll_every = self.poll_every;
+                    if let Err(e) = ignore_paused(self.handle_mesh_text(text).await) {
+                        log::error!("Error answering a request from the mesh: {}", e);
+                    }
+                    // An operator changed the poll interval; apply it right away
+                    if self.poll_every != poll_every {
+                        next_poll = tokio::time::Instant::now() + self.next_poll_delay();
+                    }
+                }
+                Some(request) = inbox.resend_rx.recv() => {
+                    match self.sender.parts.lookup(&request.id, &request.parts) {
+                        Ok((chan, category, parts)) => {
+                            log::info!("Resending {} part(s) of message {} on channel {}", parts.len(), request.id, chan);
+                            let _ = request.reply.send(Ok(parts.len()));
+                            if let Err(e) = ignore_paused(self.sender.resend_parts(chan, &category, &parts).await) {
+                                log::error!("Error resending message {}: {}", request.id, e);
+                            }
+                        }
+                        Err(e) => {
+                            let _ = request.reply.send(Err(e));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Answer "resend" / "last N" direct messages (with --serve-resend) with the recent
+    // alerts of the requester's zone, taken to be the channel the request came in on
+    async fn handle_mesh_text(&mut self, text: InboundText) -> Result<(), RedAlertError> {
+        // Our own canary probe, heard over the air by another node
+        if let Some(canary) = &mut self.canary {
+            if self.sender.node_id() == Some(text.from) && canary.heard(&text.text, text.gateway, Instant::now()) {
+                return Ok(());
+            }
+        }
+        if self.sender.node_id() != Some(text.to) {
+            return Ok(());
+        }
+        if admin::is_admin_text(&text.text) {
+            return self.handle_admin_text(text).await;
+        }
+        #[cfg(feature = "sqlite")]
+        if let (Some(subscribers), Some(command)) = (&self.subscribers, subscribers::parse_command(&text.text)) {
+            let reply = lock_subscribers(subscribers)
+                .handle(text.from, text.channel, command)
+                .unwrap_or_else(|e| {
+                    log::warn!("Subscription request from {} failed: {}", format_node_id(text.from), e);
+                    e
+                });
+            log::info!("Subscription of {}: {}", format_node_id(text.from), reply);
+            return self.sender.send_direct(text.channel, text.from, "subscription", &reply).await;
+        }
+        let Some(request) = resend::parse_request(&text.text).filter(|_| self.args.serve_resend) else {
+            log::debug!("Ignoring direct message from {}: {}", format_node_id(text.from), text.text);
+            return Ok(());
+        };
+        if !self.sender.recent.allow_request(text.from) {
+            log::info!("{} asked for a resend again too soon; ignoring", format_node_id(text.from));
+            return Ok(());
+        }
+
+        let messages = match request {
+            ReplayRequest::Last(count) => {
+                let messages = self.sender.recent.for_channel(text.channel, count);
+                log::info!(
+                    "{} asked for the last {} message(s) on channel {}; resending {}",
+                    format_node_id(text.from),
+                    count,
+                    text.channel,
+                    messages.len()
+                );
+                messages
+            }
+            ReplayRequest::Missed(window) => {
+                // A store-and-forward server on the channel replays its whole history,
+                // so point the node to it rather than spending airtime twice
+                let server = self
+                    .sender
+                    .store_forward()
+                    .and_then(|store_forward| lock_store_forward(&store_forward).server_for(text.channel));
+                if let Some(server) = server {
+                    log::info!(
+                        "{} asked for missed alerts on channel {}; referring it to store-and-forward server {}",
+                        format_node_id(text.from),
+                        text.channel,
+                        format_node_id(server)
+                    );
+                    let reply = format!("Send SF to {} to replay missed messages", format_node_id(server));
+                    return self.sender.send_direct(text.channel, text.from, "resend", &reply).await;
+                }
+                let messages = self.sender.recent.missed_on_channel(text.channel, window);
+                log::info!(
+                    "{} asked for alerts missed on channel {} in the last {} minutes; resending {}",
+                    format_node_id(text.from),
+                    text.channel,
+                    window.as_secs() / 60,
+                    messages.len()
+                );
+                messages
+            }
+        };
+        if messages.is_empty() {
+            return self
+                .sender
+                .send_direct(text.channel, text.from, "resend", "No recent alerts for your zone")
+                .await;
+        }
+        for message in messages {
+            self.sender
+                .send_direct(text.channel, text.from, &message.category, &resend::resent_text(&message))
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Carry out a signed admin command from an operator's node and report back to it
+    async fn handle_admin_text(&mut self, text: InboundText) -> Result<(), RedAlertError> {
+        let from = format_node_id(text.from);
+        let Some(admin) = &mut self.admin else {
+            log::debug!("Ignoring admin command from {}; no --admin-node is configured", from);
+            return Ok(());
+        };
+        let command = match admin.authorize(text.from, &text.text) {
+            Ok(command) => command,
+            Err(e) => {
+                log::warn!("Rejected admin command from {}: {}", from, e);
+                return Ok(());
+            }
+        };
+        log::info!("Admin command from {}: {:?}", from, command);
+
+        let reply = match command {
+            AdminCommand::Pause => {
+                self.sender.paused.store(true, Ordering::SeqCst);
+                "Transmission paused".to_string()
+            }
+            AdminCommand::Resume => {
+                self.sender.paused.store(false, Ordering::SeqCst);
+                "Transmission resumed".to_string()
+            }
+            AdminCommand::Poll(seconds) => {
+                self.poll_every = Duration::from_secs(seconds);
+                format!("Polling every {}s", seconds)
+            }
+            AdminCommand::Test => {
+                self.sender
+                    .send_message_with_retry(text.channel, "test", "red-alert-meshtastic test message")
+                    .await?;
+                format!("Test message sent on channel {}", text.channel)
+            }
+            AdminCommand::Stats => {
+                let uptime = self.started.elapsed().as_secs();
+                format!(
+                    "Up {}h{:02}m, {}, polling every {}s, {} alert(s) in effect, last sent {}; {}; {}",
+                    uptime / 3600,
+                    uptime % 3600 / 60,
+                    if self.sender.paused.load(Ordering::SeqCst) { "paused" } else { "sending" },
+                    self.poll_every.as_secs(),
+                    self.lifecycle.snapshot().len(),
+                    match self.sender.last_message_time {
+                        Some(time) => format!("{}s ago", time.elapsed().as_secs()),
+                        None => "never".to_string(),
+                    },
+                    self.sender.stats.summary(),
+                    self.sender.suppressed.summary()
+                )
+            }
+        };
+        self.sender.send_direct(text.channel, text.from, "admin", &reply).await
+    }
+
+    // Tell the subscribed nodes about the cities and zones of an alert that just went
+    // out on the mesh, by direct message, at most once a minute per node
+    #[cfg(feature = "sqlite")]
+    async fn notify_subscribers(&mut self, alert_result: &AlertResult, cities: &HashSet<String>, channels: &HashSet<u32>) {
+        let Some(subscribers) = &self.subscribers else {
+            return;
+        };
+        let subscribers = subscribers.clone();
+        let all = match lock_subscribers(&subscribers).all() {
+            Ok(all) => all,
+            Err(e) => {
+                log::error!("{}", e);
+                return;
+            }
+        };
+        let hour = localtime::now().hour() as u8;
+        for (node, preferences) in all {
+            if !preferences.wants(&alert_result.alert_type) || preferences.is_quiet(hour) {
+                continue;
+            }
+            let alerted: Vec<String> = alert_result
+                .cities
+                .iter()
+                .filter(|city| cities.contains(*city))
+                .filter(|city| {
+                    let zones = city_channels(&self.cities, self.area_map.as_ref(), city, alert_result.areas.get(*city));
+                    preferences.wants_zone(&zones)
+                })
+                .cloned()
+                .collect();
+            let zones: Vec<String> = alert_result
+                .zones
+                .iter()
+                .filter(|zone| channels.contains(*zone) && preferences.wants_zone(&[**zone]))
+                .map(u32::to_string)
+                .collect();
+            let places = match (alerted.is_empty(), zones.is_empty()) {
+                (false, _) => city_list(&self.cities, &alerted, preferences.language().unwrap_or(self.language)),
+                (true, false) => format!("zone {}", zones.join(", ")),
+                (true, true) => continue,
+            };
+            if !lock_subscribers(&subscribers).may_notify(node, Instant::now()) {
+                log::info!("Subscriber {} was notified less than a minute ago; not notifying", format_node_id(node));
+                continue;
+            }
+            let message = format!("🚨{} | {}", alert_result.alert_type, places);
+            if let Err(e) = self
+                .sender
+                .send_direct(preferences.channel, node, &alert_result.alert_type, &message)
+                .await
+            {
+                log::warn!("Failed to notify subscriber {}: {}", format_node_id(node), e);
+            }
+        }
+    }
+
+    // Route an alert to its zones and send it, whatever its source. A coordinator's
+    // manual alert goes out in full every time, whatever is already in effect.
+    async fn dispatch_alert(&mut self, mut alert_result: AlertResult, manual: bool) -> Result<(), RedAlertError> {
+        // Follow-up waves come soon after, even when this alert isn't sent
+        let any_place = !alert_result.cities.is_empty() || !alert_result.zones.is_empty();
+        if any_place && !alert_result.alert_type.contains("none") {
+            self.alert_seen_at = Some(Instant::now());
+        }
+        // Events from the history feed stay listed for a while, and the combined feeds list
+        // them twice; send each one once
+        let deduped = self.dedup.retain_new(&mut alert_result, Utc::now());
+        if deduped.first_repeats > 0 {
+            self.sender
+                .suppressed
+                .record(Reason::Dedup, &alert_result.alert_type, deduped.first_repeats);
+        }
+        if !deduped.new {
+            log::debug!("Skipping already sent {} alert from {:?}", alert_result.alert_type, alert_result.alert_date);
+            return Ok(());
+        }
+        // The same check across the cluster, so a new leader doesn't repeat its predecessor
+        #[cfg(feature = "cluster")]
+        if let Some(cluster) = &mut self.cluster {
+            let listed = alert_result.cities.len();
+            let unsent = cluster.retain_unsent(&mut alert_result).await;
+            if listed > alert_result.cities.len() {
+                self.sender
+                    .suppressed
+                    .record(Reason::ClusterDedup, &alert_result.alert_type, listed - alert_result.cities.len());
+            }
+            if !unsent {
+                log::debug!("Skipping {} alert already sent by the cluster", alert_result.alert_type);
+                return Ok(());
+            }
+        }
+
+        let args = &self.args;
+        let cities = &self.cities;
+        let language = self.language;
+        let sender = &mut self.sender;
 
-    // Add the --info argument
-    cmd.arg("--info");
+        // Only proceed if there is an actual alert
+        if !alert_result.alert_type.contains("none") {
+            // Check if the alert contains "drill" or "test" (case insensitive)
+            if is_drill(&alert_result.alert_type) {
+                log::info!("Received a drill or test alert: {}", alert_result.alert_type);
+                events::emit(Event::AlertSkipped {
+                    alert_type: alert_result.alert_type.clone(),
+                    reason: "drill or test".to_string(),
+                });
+                sender.suppressed.record(Reason::Drill, &alert_result.alert_type, alert_result.cities.len());
+                return Ok(());  // Skip sending the message
+            }
 
-    // Ensure the command doesn't output to the console
-    cmd.stdout(Stdio::piped());
+            record_active_cities(&self.active, cities, &alert_result);
 
-    // Run the command and capture the output
-    let output = cmd.output();
+            // A small community mesh only cares about the cities around it
+            let far_reaching = is_earthquake(&alert_result.alert_type) || is_tsunami(&alert_result.alert_type);
+            if let (Some(radius_km), Some(location), false) = (args.radius_km, args.location.or(args.node_position), far_reaching) {
+                let center = (location.latitude, location.longitude);
+                let listed = alert_result.cities.len();
+                // A city without a known location is kept: better an alert too many than a missed one
+                alert_result.cities.retain(|city| match cities.distance_from(city, center) {
+                    Some(distance) => distance <= radius_km,
+                    None => {
+                        log::warn!("{} has no known location; keeping it in the {} alert", city, alert_result.alert_type);
+                        true
+                    }
+                });
+                if listed > alert_result.cities.len() {
+                    sender
+                        .suppressed
+                        .record(Reason::Radius, &alert_result.alert_type, listed - alert_result.cities.len());
+                    log::info!(
+                        "{} of {} cities of the {} alert are more than {} km away",
+                        listed - alert_result.cities.len(),
+                        listed,
+                        alert_result.alert_type,
+                        radius_km
+                    );
+                }
+                if listed > 0 && alert_result.cities.is_empty() && alert_result.zones.is_empty() {
+                    events::emit(Event::AlertSkipped {
+                        alert_type: alert_result.alert_type.clone(),
+                        reason: format!("no city within {} km", radius_km),
+                    });
+                    return Ok(());
+                }
+            }
 
-    match output {
-        Ok(output) => {
-            // Convert the stdout to a string (output is captured as bytes)
-            let stdout = String::from_utf8_lossy(&output.stdout);
+            // Prepare a vector to store valid zones (for maintaining order)
+            let mut valid_zones = Vec::new();
+            // Cities of the alert per zone, used by the zone cooldown
+            let mut zone_cities: HashMap<u32, Vec<String>> = HashMap::new();
+            // Extract ignored zones if any
+            let ignored_zones: HashSet<u32> = match &args.ignore {
+                Some(ignored) => ignored.iter().cloned().collect(),
+                None => HashSet::new(),
+            };
 
-            // Check if the output contains "Error"
-            if stdout.contains("Error") {
-                log::error!("Received error output: {}", stdout);
-                std::process::exit(1);
+            let mut ignored_cities = 0;
+            for city in &alert_result.cities {
+                let zones = city_channels(cities, self.area_map.as_ref(), city, alert_result.areas.get(city));
+                if self.area_map.is_none() && cities.get(city).is_none() && !zones.is_empty() {
+                    log::info!("{} is not in the city data; routed by district or settlement to {:?}", city, zones);
+                }
+                if zones.is_empty() {
+                    log::warn!("{} of the {} alert is not in any zone", city, alert_result.alert_type);
+                    sender.suppressed.record_unresolved(city);
+                } else if zones.iter().all(|zone| ignored_zones.contains(zone)) {
+                    ignored_cities += 1;
+                }
+                for zone in zones {
+                    // Add the zone to the vector if it's not already there and not ignored
+                    if !valid_zones.contains(&zone) && !ignored_zones.contains(&zone) {
+                        valid_zones.push(zone);
+                    }
+                    zone_cities.entry(zone).or_default().push(city.clone());
+                }
             }
 
-            // Check the first line of the output for connection confirmation
-            if let Some(first_line) = stdout.lines().next() {
-                if first_line == "Connected to radio" {
-                    log::info!("Successfully connected to the node.");
-                    return Ok(());
-                } else {
-                    log::error!("Failed to connect to the radio. First line: {}", first_line);
-                    std::process::exit(1);
+            if ignored_cities > 0 {
+                sender.suppressed.record(Reason::IgnoredZone, &alert_result.alert_type, ignored_cities);
+            }
+
+            // Add zones that were targeted directly (e.g. manual alerts)
+            for zone in &alert_result.zones {
+                if !valid_zones.contains(zone) && !ignored_zones.contains(zone) {
+                    valid_zones.push(*zone);
                 }
-            } else {
-                log::error!("Output from meshtastic --info was empty.");
-                std::process::exit(1);
             }
-        }
-        Err(e) => {
-            // Log error if the command failed to run
-            log::error!("Failed to execute meshtastic --info: {}", e);
-            std::process::exit(1);
-        }
-    }
-}
 
+            // Coastal zones get every tsunami alert; zone numbers don't apply to an area map
+            if is_tsunami(&alert_result.alert_type) && self.area_map.is_none() {
+                let district_zones = args
+                    .tsunami_district
+                    .iter()
+                    .flat_map(|district| self.zones.zones_for_area(district));
+                let zones = args.tsunami_zone.clone().unwrap_or_else(|| self.zones.tsunami_zones());
+                for zone in zones.into_iter().chain(district_zones) {
+                    if !valid_zones.contains(&zone) && !ignored_zones.contains(&zone) {
+                        valid_zones.push(zone);
+                    }
+                }
+            }
 
-#[derive(Parser, Debug)]
-#[command(long_about = None)]
-struct Args {
-    /// Network address with port of device to connect to in the form of target.address:port
-    #[arg(long)]
-    host: Option<String>,
+            // An earthquake goes out everywhere, whichever cities it was reported for
+            let earthquake = is_earthquake(&alert_result.alert_type);
+            if earthquake {
+                let all_channels = match &self.area_map {
+                    Some(area_map) => area_map.channels(),
+                    None => self.zones.channels(),
+                };
+                valid_zones = all_channels
+                    .into_iter()
+                    .filter(|zone| !ignored_zones.contains(zone))
+                    .collect();
+            }
 
-    /// Zones to ignore when sending alerts
-    #[arg(long, num_args = 1.., value_delimiter = ' ')]
-    ignore: Option<HashSet<u32>>,
-}
+            // Sort the zones to send messages in the correct order
+            valid_zones.sort();
+            self.alert_log.record(&alert_result.alert_type, &valid_zones, Utc::now());
 
-struct MessageSender {
-    last_message_time: Option<std::time::Instant>,
-}
+            events::emit(Event::AlertParsed {
+                alert_type: alert_result.alert_type.clone(),
+                cities: alert_result.cities.clone(),
+                zones: valid_zones.clone(),
+            });
+            if let Some(alert_date) = alert_result.alert_date {
+                influx::record_latency("alert", None, &alert_result.alert_type, (Utc::now() - alert_date).to_std().unwrap_or_default());
+            }
 
-impl MessageSender {
-    fn new() -> Self {
-        MessageSender {
-            last_message_time: None,
-        }
-    }
 
-    async fn send_message_with_retry(
-        &mut self,
-        chan: u32,
-        message: &str,
-        retries: u32,
-        delay: Duration,
-        args: &Args,
-    ) -> Result<(), String> {
-        if let Some(last_time) = self.last_message_time {
-            let elapsed = last_time.elapsed();
-            if elapsed < Duration::from_secs(10) {
-                sleep(Duration::from_secs(10) - elapsed).await;
+            // Official time of the event in local time, so receivers can judge freshness
+            let alert_type = match alert_result.alert_date {
+                Some(alert_date) => format!("{} {}", alert_result.alert_type, localtime::to_local(alert_date).format("%H:%M:%S")),
+                None => alert_result.alert_type.clone(),
+            };
+
+            // Create the formatted message based on the reason and instructions
+            let instructions = alert_result
+                .instructions
+                .as_deref()
+                .map(|instructions| self.abbreviations.apply(instructions));
+            let message = if earthquake {
+                let guidance = instructions.as_deref().unwrap_or(EARTHQUAKE_GUIDANCE);
+                format!("🌍{} - {}", alert_type, guidance)
+            } else if let Some(instructions) = &instructions {
+                format!("🚨{} - {:?}", alert_type, instructions)
+            } else {
+                format!("🚨{}", alert_type)
+            };
+
+
+            // Determine which channels to send the alert to
+            let dedicated = self.category_channels.channels_for(&alert_result.alert_type);
+            if valid_zones.is_empty() && !earthquake && dedicated.is_empty() {
+                log::info!("No valid zones to send the alert to after ignoring specified zones.");
+                sender.suppressed.record(Reason::NoZones, &alert_result.alert_type, alert_result.cities.len());
+                return Ok(());  // No zones left to send an alert to
             }
-        }
 
-        for attempt in 0..=retries {
-            let mut command = Command::new("meshtastic");
-            command.arg("--ch-index");
-            command.arg(chan.to_string());
-            command.arg("--sendtext");
-            command.arg(message.to_string());
+            let valid_until = self
+                .validity
+                .valid_until(&alert_result.alert_type, alert_result.alert_date.unwrap_or_else(Utc::now));
+
+            // Every zone that isn't ignored is alerted
+            let all_zones_alerted = self.zones.all_zones_in(&valid_zones, &ignored_zones);
 
-            if let Some(host) = &args.host {
-                command.arg("--host").arg(host);
+            // Channels to send on, with the cities of the alert each one covers
+            let mut targets: Vec<(u32, Vec<String>)> = if earthquake {
+                // Channel 0 and every zone or area channel, right away
+                std::iter::once(0)
+                    .chain(valid_zones.iter().copied())
+                    .map(|channel| (channel, alert_result.cities.clone()))
+                    .collect()
+            } else if self.area_map.is_none() && all_zones_alerted {
+                // If all non-ignored zones are valid, send to channel 0, and still
+                // to the [[route]] channels the alert reached, which aren't zones
+                std::iter::once((0, alert_result.cities.clone()))
+                    .chain(
+                        valid_zones
+                            .iter()
+                            .filter(|channel| !self.zones.zones().iter().any(|zone| zone.channel == **channel))
+                            .map(|channel| (*channel, zone_cities.get(channel).cloned().unwrap_or_default())),
+                    )
+                    .collect()
+            } else {
+                // Send to each valid zone in the sorted order
+                valid_zones
+                    .iter()
+                    .map(|zone| (*zone, zone_cities.get(zone).cloned().unwrap_or_default()))
+                    .collect()
+            };
+
+            // Categories with a dedicated channel also go out there, with every city of the alert
+            for channel in dedicated {
+                if !targets.iter().any(|(target, _)| *target == channel) {
+                    targets.push((channel, alert_result.cities.clone()));
+                }
             }
-            log::info!("Sending an alert with content: {}", message);
-            let result = command.spawn();
-            match result {
-                Ok(_) => {
-                    self.last_message_time = Some(std::time::Instant::now());
-                    return Ok(());
+
+            // The first poll of an alert sends it in full, later polls only report added cities
+            let transitions: Vec<(u32, Vec<String>, Transition)> = targets
+                .into_iter()
+                .map(|(channel, cities_in_zone)| {
+                    let transition = match self.lifecycle.transition(
+                        &alert_result.alert_type,
+                        channel,
+                        &cities_in_zone,
+                        alert_result.alert_date,
+                    ) {
+                        _ if manual => Transition::New,
+                        transition => transition,
+                    };
+                    (channel, cities_in_zone, transition)
+                })
+                .collect();
+            // Whether the alert started or grew anywhere, rather than being polled again
+            let changed = transitions.iter().any(|(_, _, transition)| *transition != Transition::Unchanged);
+
+            // Publish the alert event before transmitting, which can take a while
+            #[cfg(feature = "mqtt")]
+            if let (Some(mqtt), true) = (&self.mqtt, changed) {
+                mqtt.publish_alert(&alert_result, &valid_zones, valid_until);
+            }
+            if let (Some(cap_publisher), true) = (&self.cap_publisher, changed) {
+                let areas: Vec<CapArea> = valid_zones
+                    .iter()
+                    .map(|zone| CapArea {
+                        channel: *zone,
+                        name: sensor_name(&self.zones, self.area_map.as_ref(), *zone),
+                        cities: zone_cities.get(zone).cloned().unwrap_or_default(),
+                    })
+                    .collect();
+                cap_publisher.publish(&alert_result, &areas, valid_until);
+            }
+            if changed {
+                sender.hold_off_for_peers().await;
+            }
+
+            let now = Utc::now();
+            let mut quake_channels = Vec::new();
+            let mut failed = None;
+            let mut failed_cities = HashSet::new();
+            // Cities and channels the alert started or grew on and that got it, for notifiers and subscribers
+            let mut announced_cities = HashSet::new();
+            let mut announced_channels = HashSet::new();
+            // Of those, the channels it went on air on right away rather than being held back for airtime
+            let mut transmitted_channels = HashSet::new();
+            for (channel, cities_in_zone, transition) in transitions {
+                let first = transition == Transition::New;
+                let new_cities = match &transition {
+                    Transition::New => Some(cities_in_zone.clone()),
+                    Transition::Expanded(added) => Some(added.clone()),
+                    Transition::Unchanged => None,
+                };
+                // Every new siren in the zone restarts its shelter time
+                if let Some(threat_passed) = &mut self.threat_passed {
+                    if transition != Transition::Unchanged || alert_result.alert_date.is_some() {
+                        threat_passed.record(&alert_result.alert_type, channel, alert_result.alert_date.unwrap_or(now));
+                    }
                 }
-                Err(e) => {
-                    if attempt < retries {
-                        log::warn!("Error sending message: {}. Retrying in {:?}...", e, delay);
-                        sleep(delay).await;
-                    } else {
-                        log::error!("Error sending message after {} attempts: {}", retries, e);
-                        return Err(format!("Failed to send message: {}", e));
+                let template = self.category_channels.template_for(channel, &alert_result.alert_type);
+                let message = match transition {
+                    // Further reports of the same earthquake add nothing to act on
+                    Transition::Expanded(_) if earthquake => {
+                        self.lifecycle
+                            .record(&alert_result.alert_type, channel, &cities_in_zone, alert_result.alert_date, now);
+                        continue;
+                    }
+                    // A sensor only changes state when the alert starts
+                    Transition::Expanded(_) if args.message_style == MessageStyle::Sensor => {
+                        log::info!("{} alert on channel {} expanded; sensor state unchanged", alert_result.alert_type, channel);
+                        self.lifecycle
+                            .record(&alert_result.alert_type, channel, &cities_in_zone, alert_result.alert_date, now);
+                        continue;
+                    }
+                    Transition::New => match template {
+                        Some(template) => dedicated::render(
+                            template,
+                            &alert_type,
+                            &localtime::now().format("%H:%M").to_string(),
+                            &city_list(cities, &cities_in_zone, language),
+                            cities_in_zone.len(),
+                        ),
+                        None => with_shelter_note(&message, cities, &cities_in_zone, language),
+                    },
+                    Transition::Expanded(added) => match template {
+                        Some(template) => dedicated::render(
+                            template,
+                            &alert_type,
+                            &localtime::now().format("%H:%M").to_string(),
+                            &city_list(cities, &added, language),
+                            added.len(),
+                        ),
+                        None => {
+                            let expanded = format!(
+                                "🚨{} expanded to {}",
+                                alert_result.alert_type,
+                                city_list(cities, &added, language)
+                            );
+                            with_shelter_note(&expanded, cities, &added, language)
+                        }
+                    },
+                    Transition::Unchanged => {
+                        log::debug!("{} alert on channel {} is unchanged; not re-sending", alert_result.alert_type, channel);
+                        self.lifecycle
+                            .record(&alert_result.alert_type, channel, &cities_in_zone, alert_result.alert_date, now);
+                        continue;
+                    }
+                };
+
+                // Receivers that get the message late can tell whether it still applies
+                let message = if args.valid_until && args.message_style == MessageStyle::Text {
+                    format!("{} | valid until {}", message, localtime::to_local(valid_until).format("%H:%M"))
+                } else {
+                    message
+                };
+
+                // Nodes with an alert bell sound their buzzer or strobe for siren categories
+                let message = if args.bell && args.bell_category.contains(&alert_result.alert_type) {
+                    format!("{}\u{7}", message)
+                } else {
+                    message
+                };
+
+                if earthquake {
+                    quake_channels.push(channel);
+                }
+                if !manual && !self.category_channels.allows(channel, &alert_result.alert_type) {
+                    log::info!(
+                        "Channel {} carried a {} alert too recently; not sending",
+                        channel,
+                        alert_result.alert_type
+                    );
+                    sender.suppressed.record(Reason::CategoryGap, &alert_result.alert_type, cities_in_zone.len());
+                } else if !earthquake && !manual && !sender.zone_cooldown.allows(channel, &cities_in_zone) {
+                    log::info!("Channel {} is cooling down and the alert adds no new cities; not re-sending", channel);
+                    sender.suppressed.record(Reason::Cooldown, &alert_result.alert_type, cities_in_zone.len());
+                } else {
+                    let held = sender.deferred.held();
+                    let (sent, on_air) = match args.message_style {
+                        MessageStyle::Text => {
+                            let sent = sender
+                                .send_message_with_retry(channel, &alert_result.alert_type, &message)
+                                .await;
+                            let on_air = sender.deferred.held() == held;
+                            // The full guidance follows the alert, in as many parts as it takes
+                            if let (Ok(()), Some(actions)) =
+                                (&sent, self.protective.for_category(&alert_result.alert_type).filter(|_| first))
+                            {
+                                let follow_up = format!("ℹ️{}: {}", alert_result.alert_type, actions);
+                                if let Err(e) = sender
+                                    .send_message_with_retry(channel, &alert_result.alert_type, &follow_up)
+                                    .await
+                                {
+                                    log::error!("Failed to send the {} guidance to channel {}: {}", alert_result.alert_type, channel, e);
+                                }
+                            }
+                            (sent, on_air)
+                        }
+                        MessageStyle::Sensor => {
+                            let name = sensor_name(&self.zones, self.area_map.as_ref(), channel);
+                            let sent = sender
+                                .send_sensor_state(channel, &alert_result.alert_type, &name, true)
+                                .await;
+                            (sent, sender.deferred.held() == held)
+                        }
+                    };
+                    // Nothing went out while paused: the alert isn't tracked, cooled down or
+                    // announced to subscribers, so it goes out in full once transmission resumes
+                    if let Err(RedAlertError::Paused) = sent {
+                        failed_cities.extend(cities_in_zone);
+                        continue;
+                    }
+                    // The other zones still get the alert; this one isn't tracked, so the next poll tries it again
+                    if let Err(e) = sent {
+                        log::error!("Failed to send the {} alert to channel {}: {}", alert_result.alert_type, channel, e);
+                        failed.get_or_insert(e);
+                        failed_cities.extend(cities_in_zone);
+                        continue;
+                    }
+                    sender.zone_cooldown.record(channel, &cities_in_zone);
+                    self.category_channels.record(channel, &alert_result.alert_type);
+                    if let Some(new_cities) = new_cities {
+                        announced_channels.insert(channel);
+                        announced_cities.extend(new_cities);
+                        if on_air {
+                            transmitted_channels.insert(channel);
+                        }
                     }
+                    if let Some(alert_date) = alert_result.alert_date {
+                        let latency = (Utc::now() - alert_date).to_std().unwrap_or_default();
+                        sender.stats.record_alert_latency(channel, &alert_result.alert_type, latency);
+                    }
+                }
+                self.lifecycle
+                    .record(&alert_result.alert_type, channel, &cities_in_zone, alert_result.alert_date, now);
+            }
+
+            if let (Some(minutes), false) = (args.aftershock_guidance, quake_channels.is_empty()) {
+                log::info!("Aftershock guidance will follow in {} minute(s)", minutes);
+                self.aftershock_due = Some((Instant::now() + Duration::from_secs(minutes * 60), quake_channels));
+            }
+            #[cfg(feature = "cluster")]
+            if let Some(cluster) = &mut self.cluster {
+                let sent: Vec<String> = alert_result
+                    .cities
+                    .iter()
+                    .filter(|city| !failed_cities.contains(*city))
+                    .cloned()
+                    .collect();
+                cluster.mark_sent(&alert_result.alert_type, &sent, alert_result.alert_date).await;
+            }
+            // Notifiers, the Atom feed among them, hear of the zones the alert started or
+            // grew on once it went on air there; nothing went out in observation mode
+            let observing = matches!(self.sender.transport, Transport::Observe);
+            if !self.notifiers.is_empty() && !transmitted_channels.is_empty() && !observing {
+                let notification = Notification {
+                    category: alert_result.alert_type.clone(),
+                    alert_date: alert_result.alert_date,
+                    zones: valid_zones
+                        .iter()
+                        .filter(|zone| transmitted_channels.contains(*zone))
+                        .map(|zone| NotifiedZone {
+                            name: sensor_name(&self.zones, self.area_map.as_ref(), *zone),
+                            cities: zone_cities
+                                .get(zone)
+                                .into_iter()
+                                .flatten()
+                                .map(|name| cities.get(name).map(|city| place_name(city, self.language)).unwrap_or(name).to_string())
+                                .collect(),
+                        })
+                        .collect(),
+                    instructions: alert_result.instructions.clone(),
+                    valid_until: Some(valid_until),
+                };
+                for notifier in &self.notifiers {
+                    notifier.notify(&notification);
                 }
             }
+
+            #[cfg(feature = "sqlite")]
+            if !announced_channels.is_empty() {
+                self.notify_subscribers(&alert_result, &announced_cities, &announced_channels).await;
+            }
+            if let Some(e) = failed {
+                return Err(e);
+            }
         }
+
         Ok(())
     }
 }
 
-// Load Cities.json
-async fn load_cities() -> Result<Vec<City>, String> {
-    let cities_json = Asset::get("cities.json").ok_or("Failed to load cities.json")?;
-    let cities: Vec<City> = serde_json::from_slice(&cities_json.data).map_err(|e| e.to_string())?;
-    Ok(cities)
-}
 
-// Get the zone number based on zone_en (translated from Hebrew city name)
-fn get_zone_number(zone_en: &str) -> Option<u32> {
-    // Zone 1: Northern (average time: 24.65 seconds)
-    if [
-        "Upper Galilee",
-        "Confrontation Line",
-        "North Golan",
-        "South Golan",
-        "Center Galilee",
-    ]
-        .contains(&zone_en)
-    {
-        return Some(1); // Northern
+#[tokio::main]
+async fn main() -> Result<(), RedAlertError> {
+    // Initialize logging; timestamps are in Israel time until --timezone is read
+    localtime::init_logging(LevelFilter::Info).map_err(RedAlertError::Config)?;
+
+    // Parse command-line arguments
+    let mut args = load_args()?;
+    localtime::set_timezone(args.timezone);
+
+    // Watching is observation mode with a console view of the alerts in place of the log
+    if let Some(Commands::Watch(watch)) = &args.command {
+        let no_color = watch.no_color;
+        args.observe = true;
+        args.output = OutputFormat::Text;
+        log::set_max_level(LevelFilter::Warn);
+        tokio::spawn(watch::print_events(no_color));
     }
 
-    // Zone 2: SouthCoast (average time: 51.23 seconds)
-    if [
-        "Gaza Envelope",
-        "West Lachish",
-        "Lachish",
-        "HaShfela",
-    ]
-        .contains(&zone_en)
-    {
-        return Some(2); // SouthCoast
+    events::set_json_output(args.output == OutputFormat::Json);
+    if args.http_listen.is_some() || args.event_socket.is_some() {
+        events::set_replay(args.events_replay);
     }
 
-    // Zone 3: InterNorth (average time: 65.07 seconds)
-    if [
-        "Lower Galilee",
-        "Beit She'an Valley",
-        "HaAmakim",
-        "Wadi Ara",
-    ]
-        .contains(&zone_en)
-    {
-        return Some(3); // InterNorth
-    }
-
-    // Zone 4: DesertRegion (average time: 67.70 seconds)
-    if [
-        "West Negev",
-        "Center Negev",
-        "South Negev",
-        "Dead Sea",
-        "Arava",
-        "Eilat",
-    ]
-        .contains(&zone_en)
-    {
-        return Some(4); // DesertRegion
+    if let Some(Commands::Config(config)) = &args.command {
+        return configgen::run(config).map_err(RedAlertError::Config);
     }
 
-    // Zone 5: NorthCoast (average time: 72.81 seconds)
-    if ["HaMifratz", "HaCarmel", "Menashe"].contains(&zone_en) {
-        return Some(5); // NorthCoast
+    if let Some(Commands::Discover(discover)) = &args.command {
+        return discover::run(discover).await.map_err(RedAlertError::Config);
     }
 
-    // Zone 6: CentralInter (average time: 86.87 seconds)
-    if [
-        "Shomron",
-        "Jerusalem",
-        "Yehuda",
-        "Shfelat Yehuda",
-        "Bika'a",
-    ]
-        .contains(&zone_en)
-    {
-        return Some(6); // CentralInter
+    let retention = Retention::new(args.retention_days, args.retention_mb);
+    #[cfg(feature = "sqlite")]
+    if let Some(Commands::Backfill(backfill)) = &args.command {
+        return backfill::run(backfill, retention).await.map_err(RedAlertError::Config);
     }
 
-    // Zone 7: CentralCoast (average time: 90.00 seconds)
-    if ["Sharon", "Yarkon", "Dan"].contains(&zone_en) {
-        return Some(7); // CentralCoast
+    if let Some(Commands::Prune(prune)) = &args.command {
+        return retention::run(prune, stored_data(&args), retention).map_err(RedAlertError::Config);
     }
 
-    None // Return None if the zone_en does not match any known zones
-}
+    if let Some(Commands::Verify(verify)) = &args.command {
+        return signing::run(verify, args.hmac_key.as_deref()).map_err(RedAlertError::Config);
+    }
+
+    if let Some(Commands::Admin(admin)) = &args.command {
+        return admin::run(admin, args.admin_key.as_deref()).map_err(RedAlertError::Config);
+    }
 
+    #[cfg(unix)]
+    if let Some(Commands::Ctl(ctl)) = &args.command {
+        return ipc::run(ctl, args.event_socket.as_deref()).await.map_err(RedAlertError::Config);
+    }
 
+    let cities = load_cities(&args).await?;
 
-// Find zone for a city in Hebrew
-async fn find_zone_for_city(cities: &Vec<City>, city_name_he: &str) -> Option<u32> {
-    for city in cities {
-        if city.name == city_name_he {
-            return get_zone_number(&city.zone_en);
-        }
+    if let Some(Commands::Init(init)) = &args.command {
+        return init::run(init, &cities).map_err(RedAlertError::Config);
     }
-    None
-}
 
-// Main logic to send alerts to appropriate zones
-async fn process_alert(sender: &mut MessageSender, args: &Args, cities: &Vec<City>) -> Result<(), String> {
-    // Load city data
+    // Reach the radio through --host, --port, --ble or the first responding USB serial device
+    let device = match (&args.host, &args.port, &args.ble) {
+        (Some(host), _, _) => Device::Host(host.clone()),
+        (None, Some(port), _) => Device::Port(port.clone()),
+        #[cfg(feature = "ble")]
+        (None, None, Some(ble)) => Device::Ble(ble.clone()),
+        #[cfg(not(feature = "ble"))]
+        (None, None, Some(_)) => {
+            return Err(RedAlertError::Config("--ble needs a build with the \"ble\" feature".to_string()));
+        }
+        // Looking up a zone only talks to the radio for channel names, if at all
+        (None, None, None) if args.transport == TransportKind::Cli && !matches!(args.command, Some(Commands::ZoneOf(_))) => {
+            match tokio::task::spawn_blocking(device::detect_serial_port).await {
+                Ok(Some(port)) => Device::Port(port),
+                _ => Device::Default,
+            }
+        }
+        (None, None, None) => Device::Default,
+    };
+
+    if let Some(Commands::MeshCheck(check)) = &args.command {
+        return meshcheck::run(check, &device, &args.mesh_check_node, args.mesh_check_min_snr).map_err(RedAlertError::Config);
+    }
 
+    if let Some(Commands::Nodes(nodes)) = &args.command {
+        return nodes::run(nodes, &device).map_err(RedAlertError::Config);
+    }
 
-    // Fetch the current alert (from the API)
-    let alert_result = fetch_alert(false).await.unwrap();
+    // Zone and area maps may name channels; resolve them with the transport's channel list
+    let mut channel_names = match args.transport {
+        TransportKind::Cli => ChannelNames::from_device(device.clone()),
+        TransportKind::Mqtt => ChannelNames::from_mesh_channels(&args.mesh_channel),
+        // LoRaWAN targets are configured by channel index
+        TransportKind::Chirpstack => ChannelNames::from_mesh_channels(&[]),
+    };
 
-    // Only proceed if there is an actual alert
-    if (!alert_result.alert_type.contains("none")) {
-        // Check if the alert contains "drill" or "test" (case insensitive)
-        if alert_result.alert_type.to_lowercase().contains("drill") || alert_result.alert_type.to_lowercase().contains("test") {
-            log::info!("Received a drill or test alert: {}", alert_result.alert_type);
-            return Ok(());  // Skip sending the message
+    // Use the zones from the zone map or the config file if either defines any
+    let zones = match (&args.zone_map, &args.config) {
+        (Some(path), _) => Some(zones::load_zone_map(path).map_err(RedAlertError::Config)?),
+        (None, Some(path)) => ConfigFile::load(path, args.profile.as_deref())
+            .and_then(|config| config.zones())
+            .map_err(RedAlertError::Config)?,
+        (None, None) => None,
+    };
+    let zones = match zones {
+        Some(zones) => {
+            let zones = ZoneScheme::from_config(zones, &mut channel_names).map_err(RedAlertError::Config)?;
+            for zone in zones.zones() {
+                log::info!("{} (channel {}): {}", zone.name, zone.channel, zone.districts.join(", "));
+            }
+            zones
         }
+        None => ZoneScheme::builtin(),
+    };
+
+    // Apply the config's [[route]] overrides on top of the zones
+    let routes = match &args.config {
+        Some(path) => ConfigFile::load(path, args.profile.as_deref())
+            .and_then(|config| config.routes())
+            .map_err(RedAlertError::Config)?,
+        None => Vec::new(),
+    };
+    let zones = zones.with_routes(routes, &mut channel_names).map_err(RedAlertError::Config)?;
+    for route in zones.routes() {
+        log::info!(
+            "Route {}{}: {} -> channel(s) {:?}",
+            route.name.as_deref().unwrap_or("override"),
+            if route.replace { " (replacing zones)" } else { "" },
+            route.cities.iter().chain(&route.districts).cloned().collect::<Vec<_>>().join(", "),
+            route.channels
+        );
+    }
+
+    if let Some(Commands::ZoneOf(zone_of)) = &args.command {
+        return zoneof::run(zone_of, &CityIndex::new(cities, &zones), &zones).map_err(RedAlertError::Config);
+    }
 
-        // Prepare a vector to store valid zones (for maintaining order)
-        let mut valid_zones = Vec::new();
-        // Extract ignored zones if any
-        let ignored_zones: HashSet<u32> = match &args.ignore {
-            Some(ignored) => ignored.iter().cloned().collect(),
-            None => HashSet::new(),
+    // Extra channels for alert categories from the config's [[category]] tables
+    let categories = match &args.config {
+        Some(path) => ConfigFile::load(path, args.profile.as_deref())
+            .and_then(|config| config.categories())
+            .map_err(RedAlertError::Config)?,
+        None => Vec::new(),
+    };
+    let category_channels = CategoryChannels::new(categories, &mut channel_names).map_err(RedAlertError::Config)?;
+    for dedicated in category_channels.channels() {
+        log::info!(
+            "{} alerts also go to channel {} (at most every {}s)",
+            dedicated.category,
+            dedicated.channel,
+            dedicated.min_gap.as_secs()
+        );
+    }
+
+    // Report districts that would be silently dropped at alert time
+    let problems = validate_cities(&cities, &zones);
+    for problem in &problems {
+        log::warn!("cities.json: {}", problem);
+    }
+    if problems.is_empty() {
+        log::info!("cities.json: {} cities loaded, every district maps to a zone", cities.len());
+    } else if args.strict_cities {
+        return Err(RedAlertError::Config(format!(
+            "cities.json failed validation with {} problem(s)",
+            problems.len()
+        )));
+    }
+
+    // --radius-km is measured from --location or the node's fixed position
+    if let Some(radius_km) = args.radius_km {
+        let Some(center) = args.location.or(args.node_position) else {
+            return Err(RedAlertError::Config("--radius-km needs --location or --node-position".to_string()));
         };
+        log::info!(
+            "Forwarding only cities within {} km of {:.4},{:.4}",
+            radius_km,
+            center.latitude,
+            center.longitude
+        );
+    }
 
-        for city in alert_result.cities {
-            if let Some(zone) = find_zone_for_city(&cities, &city).await {
-                // Add the zone to the vector if it's not already there and not ignored
-                if !valid_zones.contains(&zone) && !ignored_zones.contains(&zone) {
-                    valid_zones.push(zone);
-                }
+    let country = country_profile(&args)?;
+    let language = args.language.unwrap_or_else(|| country.language());
+
+    // Route by alert area instead of zone if an area map was given
+    let area_map = match (&args.area_map, country.regions()) {
+        (Some(path), Regions::Areas(_)) => {
+            Some(AreaMap::load_regions(path, &mut channel_names).map_err(RedAlertError::Config)?)
+        }
+        (Some(path), Regions::Cities) => {
+            Some(AreaMap::load(path, &cities, &mut channel_names).map_err(RedAlertError::Config)?)
+        }
+        (None, Regions::Areas(areas)) => {
+            return Err(RedAlertError::Config(format!(
+                "--source {} needs an --area-map listing {} of each channel",
+                source_name(args.source),
+                areas
+            )))
+        }
+        (None, Regions::Cities) => None,
+    };
+
+    // Check node connection before starting the loop
+    if args.transport == TransportKind::Cli && !args.observe {
+        if let Err(e) = check_node_connection(&device).await {
+            log::error!("Failed to connect to the node: {}", e);
+        } else {
+            log::info!("Node connection successful. All systems operational.");
+        }
+    }
+
+    // Start monitoring critical repeaters if any were configured
+    if let Some(repeaters) = args.repeater.clone() {
+        let device = device.clone();
+        let timeout = Duration::from_secs(args.repeater_timeout * 3600);
+        supervisor::supervise("repeater monitor", move || {
+            let monitor = nodedb::monitor_repeaters(device.clone(), repeaters.clone(), timeout, Duration::from_secs(600));
+            async move {
+                monitor.await;
+                Ok(())
+            }
+        });
+    }
+
+    // Keep stored data within the retention limits
+    if let Some(retention) = retention {
+        let (stored, every) = (stored_data(&args), Duration::from_secs(args.prune_every.max(1) * 60));
+        supervisor::supervise("pruning", move || {
+            let pruning = retention::prune_periodically(stored.clone(), retention, every);
+            async move {
+                pruning.await;
+                Ok(())
             }
+        });
+    }
+
+    // Keep a history of the node DB for coverage analysis
+    if let Some(path) = args.node_snapshots.clone() {
+        if args.transport == TransportKind::Cli {
+            let device = device.clone();
+            let every = Duration::from_secs(args.node_snapshot_every.max(1) * 60);
+            supervisor::supervise("node snapshots", move || {
+                let snapshots = nodes::record_snapshots(device.clone(), path.clone(), every);
+                async move {
+                    snapshots.await;
+                    Ok(())
+                }
+            });
+        } else {
+            log::warn!("--node-snapshots needs --transport cli to read the node DB; not taking snapshots");
         }
+    }
 
-        // Find the zones for each city in the alert
-        for city in alert_result.cities {
-            if let Some(zone) = find_zone_for_city(&cities, &city).await {
-                // Add the zone to the vector if it's not already there
-                if !valid_zones.contains(&zone) {
-                    valid_zones.push(zone);
+    // Trace the route to key nodes on a schedule; traceroutes go out over the radio
+    if let Some(minutes) = args.mesh_check_every {
+        if args.mesh_check_node.is_empty() {
+            log::warn!("--mesh-check-every needs at least one --mesh-check-node; not tracing any routes");
+        } else if args.observe {
+            log::info!("Observation mode: not tracing the route to {} node(s)", args.mesh_check_node.len());
+        } else if args.transport != TransportKind::Cli {
+            log::warn!("--mesh-check-every needs --transport cli to send traceroutes; not tracing any routes");
+        } else {
+            let device = device.clone();
+            let nodes = args.mesh_check_node.clone();
+            let min_snr = args.mesh_check_min_snr;
+            let every = Duration::from_secs(minutes.max(1) * 60);
+            supervisor::supervise("mesh check", move || {
+                let monitor = meshcheck::monitor_paths(device.clone(), nodes.clone(), min_snr, every);
+                async move {
+                    monitor.await;
+                    Ok(())
                 }
-            }
+            });
         }
+    }
 
-        // Sort the zones to send messages in the correct order
-        valid_zones.sort();
+    // Switch on the alert bell of the given nodes
+    if let Some(nodes) = args.bell_node.clone() {
+        if args.observe {
+            log::info!("Observation mode: not changing the alert bell settings of {} node(s)", nodes.len());
+        } else if args.transport == TransportKind::Cli {
+            let device = device.clone();
+            tokio::task::spawn_blocking(move || {
+                for node in nodes {
+                    match nodedb::enable_alert_bell(&device, &node) {
+                        Ok(()) => log::info!("Enabled the alert bell of {}", node),
+                        Err(e) => log::warn!("Could not enable the alert bell of {}: {}", node, e),
+                    }
+                }
+            });
+        } else {
+            log::warn!("--bell-node needs a locally attached radio (--transport cli); ignoring it");
+        }
+    }
 
+    // Keep the attached radio's clock in step with the host
+    match args.time_sync {
+        Some(every) if args.transport == TransportKind::Cli && !args.observe => {
+            let device = device.clone();
+            let every = Duration::from_secs(every.max(60));
+            supervisor::supervise("time sync", move || {
+                let sync = nodedb::sync_time(device.clone(), every);
+                async move {
+                    sync.await;
+                    Ok(())
+                }
+            });
+        }
+        Some(_) if args.observe => {}
+        Some(_) => log::warn!("--time-sync needs a locally attached radio (--transport cli); ignoring it"),
+        None => {}
+    }
 
-        // Create the formatted message based on the reason and instructions
-        let message = if let Some(instructions) = &alert_result.instructions {
-            format!("🚨{} - {:?}", alert_result.alert_type, instructions)
+    // Name and place the gateway node so mesh users can tell where alerts come from
+    let identity = NodeIdentity {
+        long_name: args.node_long_name.clone(),
+        short_name: args.node_short_name.clone(),
+        position: args.node_position,
+    };
+    if let Some(name) = &identity.long_name {
+        if name.is_empty() || name.len() > 39 {
+            return Err(RedAlertError::Config("--node-long-name must be 1-39 bytes long".to_string()));
+        }
+    }
+    if let Some(name) = &identity.short_name {
+        if name.is_empty() || name.chars().count() > 4 {
+            return Err(RedAlertError::Config("--node-short-name must be 1-4 characters long".to_string()));
+        }
+    }
+    if !identity.is_empty() {
+        if args.observe {
+            log::info!("Observation mode: not changing the node's names and position");
+        } else if args.transport == TransportKind::Cli {
+            let device = device.clone();
+            let every = Duration::from_secs(args.node_info_refresh.max(60));
+            supervisor::supervise("node identity", move || {
+                let refresh = nodedb::refresh_identity(device.clone(), identity.clone(), every);
+                async move {
+                    refresh.await;
+                    Ok(())
+                }
+            });
         } else {
-            format!("🚨{}", alert_result.alert_type)
+            log::warn!("--node-long-name, --node-short-name and --node-position need a locally attached radio (--transport cli); ignoring them");
+        }
+    }
+
+    // Watch the channel load of the attached radio if requested
+    let channel_load = match args.channel_util_poll {
+        Some(poll) if args.transport == TransportKind::Cli => {
+            let load: SharedChannelLoad = Arc::new(Mutex::new(None));
+            let every = Duration::from_secs(poll.max(1));
+            let (device, monitor_load) = (device.clone(), load.clone());
+            supervisor::supervise("channel load monitor", move || {
+                let monitor = nodedb::monitor_channel_load(device.clone(), monitor_load.clone(), every);
+                async move {
+                    monitor.await;
+                    Ok(())
+                }
+            });
+            Some((load, every))
+        }
+        Some(_) => {
+            log::warn!("--channel-util-poll needs a locally attached radio (--transport cli); ignoring it");
+            None
+        }
+        None => None,
+    };
+    // A reading is trusted until two more polls have been missed
+    let throttle = channel_load.clone().map(|(load, every)| {
+        AirtimeThrottle::new(
+            load,
+            args.channel_util_threshold,
+            Duration::from_secs(args.congestion_gap),
+            every * 3,
+        )
+    });
+
+    // Cities currently under alert, shared with the HTTP server and the metrics export
+    let active: SharedActiveAlerts = Arc::new(Mutex::new(ActiveAlerts::new()));
+
+    // Export metrics to InfluxDB if requested
+    if let Some(url) = &args.influx_url {
+        let target = InfluxTarget::parse(url, args.influx_token.clone()).map_err(RedAlertError::Config)?;
+        let every = Duration::from_secs(args.influx_interval.max(1));
+        let load = channel_load.map(|(load, _)| load);
+        let (active, channels) = (active.clone(), zones.channels());
+        supervisor::supervise("metrics export", move || {
+            influx::export(target.clone(), every, load.clone(), active.clone(), channels.clone())
+        });
+    }
+
+    // Account airtime against the region's duty cycle unless it allows continuous transmission
+    let duty_cycle = args
+        .duty_cycle
+        .or(args.region.map(|region| region.duty_cycle_percent()))
+        .filter(|percent| *percent < 100.0)
+        .map(|percent| {
+            log::info!("Keeping transmissions within a {}% duty cycle ({:?})", percent, args.modem_preset);
+            DutyCycle::new(percent, args.modem_preset)
+        });
+
+    // Continue message numbering where the previous run stopped
+    let sequence = match &args.sequence_file {
+        Some(path) => Some(SequenceCounters::load(path).map_err(RedAlertError::Config)?),
+        None => None,
+    };
+
+    // Create the message sender
+    let mut sender = MessageSender::new(
+        Transport::from_args(&args, &device)?,
+        CategoryGaps::new(Duration::from_secs(args.min_send_gap), &args.category_gap),
+        ZoneCooldown::new(Duration::from_secs(args.zone_cooldown)),
+        args.send_retries,
+        Duration::from_secs(args.send_retry_delay),
+    )
+    .with_throttle(throttle)
+    .with_duty_cycle(duty_cycle, Duration::from_secs(args.duty_cycle_max_wait))
+    .with_sequence(sequence)
+    .with_hmac_key(args.hmac_key.clone())
+    .with_repeats(Repeats::new(&args.repeat))
+    .with_stats(ChannelStats::new(args.modem_preset));
+
+    // Replaying a past alert only needs the zones and the sender
+    if let Some(Commands::ReplayAlert(replay)) = &args.command {
+        let alerts = replay::find(replay).await.map_err(RedAlertError::Config)?;
+        let city_index = CityIndex::new(cities, &zones);
+        return replay_alerts(&mut sender, &alerts, &replay.zone, &city_index, area_map.as_ref(), language).await;
+    }
+
+    // Notifications off the mesh are queued and retried on their own
+    let outbox = OutboxSettings {
+        dir: outbox_dir(&args),
+        max_attempts: args.outbox_max_attempts,
+    };
+
+    // Connect to the MQTT broker if configured
+    #[cfg(not(feature = "mqtt"))]
+    if let Some(host) = &args.mqtt_host {
+        log::warn!("--mqtt-host {} needs a build with the \"mqtt\" feature; not publishing to MQTT", host);
+    }
+    #[cfg(feature = "mqtt")]
+    let mqtt = args
+        .mqtt_host
+        .as_deref()
+        .map(|host| {
+            MqttPublisher::connect(
+                host,
+                args.mqtt_port,
+                args.mqtt_username.as_deref(),
+                args.mqtt_password.as_deref(),
+                &args.mqtt_prefix,
+                &outbox,
+            )
+        })
+        .transpose()
+        .map_err(RedAlertError::Config)?;
+
+    // Publish processed alerts as CAP documents if requested
+    let cap_publisher = if args.cap_output_dir.is_some() || args.cap_output_url.is_some() {
+        Some(
+            CapPublisher::new(args.cap_output_dir.clone(), args.cap_output_url.clone(), args.cap_sender.clone(), &outbox)
+                .map_err(RedAlertError::Config)?,
+        )
+    } else {
+        None
+    };
+
+    // Tell chat and social outputs about alerts if configured
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+    if let Some(url) = &args.xmpp_url {
+        let auth = args.xmpp_user.clone().map(|user| (user, args.xmpp_password.clone()));
+        let xmpp = XmppNotifier::new(url, args.xmpp_api, auth, args.xmpp_from.clone(), &args.xmpp_to, &args.xmpp_room, &outbox)
+            .map_err(RedAlertError::Config)?;
+        notifiers.push(Box::new(xmpp));
+    }
+    if let Some(homeserver) = &args.matrix_homeserver {
+        let Some(token) = args.matrix_token.clone() else {
+            return Err(RedAlertError::Config("--matrix-homeserver needs --matrix-token".to_string()));
+        };
+        let matrix = MatrixNotifier::new(homeserver, token, &args.matrix_room, &args.matrix_level, args.matrix_health, &outbox)
+            .map_err(RedAlertError::Config)?;
+        notifiers.push(Box::new(matrix));
+    }
+    if let Some(url) = &args.signal_url {
+        let Some(number) = args.signal_number.clone() else {
+            return Err(RedAlertError::Config("--signal-url needs --signal-number".to_string()));
+        };
+        let signal = SignalNotifier::new(url, number, &args.signal_recipient, &outbox).map_err(RedAlertError::Config)?;
+        notifiers.push(Box::new(signal));
+    }
+    if !args.slack_webhook.is_empty() {
+        let slack = SlackNotifier::new(&args.slack_webhook, args.slack_username.clone(), &outbox).map_err(RedAlertError::Config)?;
+        notifiers.push(Box::new(slack));
+    }
+    if let Some(url) = &args.mastodon_url {
+        let Some(token) = args.mastodon_token.clone() else {
+            return Err(RedAlertError::Config("--mastodon-url needs --mastodon-token".to_string()));
         };
+        let mastodon = MastodonNotifier::new(url, token, args.mastodon_visibility, &outbox).map_err(RedAlertError::Config)?;
+        notifiers.push(Box::new(mastodon));
+    }
 
+    // Alerts injected from outside the oref feed (e.g. the HTTP API)
+    let (alerts_tx, alerts_rx) = mpsc::channel::<(&'static str, AlertResult)>(16);
 
-        // Determine which channels to send the alert to
-        if valid_zones.is_empty() {
-            log::info!("No valid zones to send the alert to after ignoring specified zones.");
-            return Ok(());  // No zones left to send an alert to
-        } else if valid_zones.len() + ignored_zones.len() > 6 {
-            // If all non-ignored zones are valid, send to channel 0
-            sender
-                .send_message_with_retry(0, &message, 3, Duration::from_secs(5), args)
-                .await?;
-        } else {
-            // Send to each valid zone in the sorted order
-            for zone in valid_zones {
-                sender
-                    .send_message_with_retry(zone, &message, 3, Duration::from_secs(5), args)
-                    .await?;
+    // Read alerts from stdin instead of polling oref if requested
+    if args.source == Source::Stdin {
+        let alerts_tx = alerts_tx.clone();
+        supervisor::supervise("stdin reader", move || {
+            let reader = stdin::read_alerts(alerts_tx.clone());
+            async move {
+                reader.await;
+                Ok(())
             }
+        });
+    }
+
+    // State dumps requested on SIGUSR1 or over HTTP
+    let (state_tx, state_rx) = mpsc::channel::<StateRequest>(4);
+    #[cfg(unix)]
+    {
+        let state_tx = state_tx.clone();
+        supervisor::supervise("SIGUSR1 handler", move || debug::dump_on_sigusr1(state_tx.clone()));
+    }
+
+    // Retransmissions of parts of split messages
+    #[cfg_attr(not(feature = "web"), allow(unused_variables))]
+    let (resend_tx, resend_rx) = mpsc::channel::<ResendRequest>(4);
+
+    // Reloads of the config file asked for over the control socket
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    let (reload_tx, reload_rx) = mpsc::channel::<ReloadRequest>(1);
+
+    // Preferences of the nodes subscribed to direct-message alerts
+    #[cfg(not(feature = "sqlite"))]
+    if let Some(path) = &args.subscriber_db {
+        log::warn!("--subscriber-db {} needs a build with the \"sqlite\" feature; not taking subscriptions", path);
+    }
+    #[cfg(feature = "sqlite")]
+    let subscribers: Option<SharedSubscribers> = match &args.subscriber_db {
+        Some(path) => {
+            let store = SubscriberStore::open(path).map_err(RedAlertError::Config)?;
+            log::info!("Subscriber preferences in {}", path);
+            Some(Arc::new(Mutex::new(store)))
         }
+        None => None,
+    };
 
+    // Start the embedded HTTP server if requested
+    let city_index = Arc::new(CityIndex::new(cities, &zones));
+    if let Some(addr) = args.http_listen {
+        #[cfg(feature = "web")]
+        {
+            let feed = Arc::new(Mutex::new(AlertFeed::new(args.feed_entries)));
+            notifiers.push(Box::new(FeedRecorder(feed.clone())));
+            let state = web::WebState {
+                alerts_tx: alerts_tx.clone(),
+                state_tx: state_tx.clone(),
+                resend_tx: resend_tx.clone(),
+                token: args.http_token.clone(),
+                active: active.clone(),
+                map: Arc::new(AlertMap::new(city_index.cities(), &zones)),
+                cities: city_index.clone(),
+                #[cfg(feature = "sqlite")]
+                subscribers: subscribers.clone(),
+                feed,
+            };
+            supervisor::supervise("HTTP server", move || web::serve(addr, state.clone()));
+        }
+        #[cfg(not(feature = "web"))]
+        log::warn!("--http-listen {} needs a build with the \"web\" feature; not serving HTTP", addr);
     }
 
-        Ok(())
+    // Start the gRPC control API if requested
+    let paused = SharedPause::default();
+    if let Some(addr) = args.grpc_listen {
+        #[cfg(feature = "grpc")]
+        {
+            let state = grpc::GrpcState {
+                alerts_tx: alerts_tx.clone(),
+                state_tx: state_tx.clone(),
+                token: args.http_token.clone(),
+                active: active.clone(),
+                paused: paused.clone(),
+            };
+            supervisor::supervise("gRPC server", move || grpc::serve(addr, state.clone()));
+        }
+        #[cfg(not(feature = "grpc"))]
+        log::warn!("--grpc-listen {} needs a build with the \"grpc\" feature; not serving gRPC", addr);
+    }
 
-}
+    // Stream events to local programs and take control commands from them if requested
+    if let Some(path) = args.event_socket.clone() {
+        #[cfg(unix)]
+        {
+            let control = ipc::ControlState {
+                alerts_tx: alerts_tx.clone(),
+                state_tx: state_tx.clone(),
+                reload_tx: reload_tx.clone(),
+                paused: paused.clone(),
+            };
+            supervisor::supervise("event socket", move || {
+                let (path, control) = (PathBuf::from(&path), control.clone());
+                async move { ipc::serve(&path, control).await }
+            });
+        }
+        #[cfg(not(unix))]
+        log::warn!("--event-socket {} needs Unix domain sockets, which this platform lacks; not streaming events", path);
+    }
 
+    // Offer status and alert signals on D-Bus if requested
+    if let Some(bus) = args.dbus {
+        #[cfg(all(target_os = "linux", feature = "dbus"))]
+        {
+            let state = dbus::DbusState {
+                state_tx: state_tx.clone(),
+                active: active.clone(),
+                paused: paused.clone(),
+            };
+            supervisor::supervise("D-Bus service", move || dbus::serve(bus, state.clone()));
+        }
+        #[cfg(all(target_os = "linux", not(feature = "dbus")))]
+        log::warn!("--dbus {:?} needs a build with the \"dbus\" feature; not offering the D-Bus service", bus);
+        #[cfg(not(target_os = "linux"))]
+        log::warn!("--dbus {:?} is only supported on Linux; not offering the D-Bus service", bus);
+    }
+    sender = sender.with_pause(paused.clone());
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-        // Initialize logging
-    SimpleLogger::new()
-        .with_level(LevelFilter::Info) // Set to Debug to capture more logs
-        .init()
-        .unwrap();
+    // Check the send path end to end with canary probes: the radio's acknowledgment
+    // with a local radio, the probe coming back through another node with mesh MQTT
+    let canary = match args.canary_channel {
+        Some(_) if args.observe => {
+            log::info!("Observation mode: not sending canary probes");
+            None
+        }
+        Some(channel) => {
+            let every = Duration::from_secs(args.canary_every.max(1) * 60);
+            let timeout = Duration::from_secs(args.canary_timeout.max(1));
+            match args.transport {
+                TransportKind::Cli => {
+                    let device = device.clone();
+                    supervisor::supervise("canary", move || {
+                        let monitor = canary::monitor_ack(device.clone(), channel, every, timeout, paused.clone());
+                        async move {
+                            monitor.await;
+                            Ok(())
+                        }
+                    });
+                    None
+                }
+                TransportKind::Mqtt => {
+                    let listener = args
+                        .canary_listener
+                        .as_deref()
+                        .map(parse_node_num)
+                        .transpose()
+                        .map_err(RedAlertError::Config)?;
+                    log::info!("Sending a canary probe on channel {} every {:?}", channel, every);
+                    Some(Canary::new(channel, every, timeout, listener))
+                }
+                TransportKind::Chirpstack => {
+                    log::warn!("--canary-channel needs --transport cli or mqtt to check delivery; not sending canary probes");
+                    None
+                }
+            }
+        }
+        None => None,
+    };
 
-    // Parse command-line arguments
-    let args = Args::parse();
+    // Peer gateways are heard through the mesh MQTT broker
+    let peers: Option<SharedPeers> = match (&args.peer_gateway, sender.node_id()) {
+        (Some(peer_ids), Some(own)) => {
+            let mut peer_nums = Vec::new();
+            for id in peer_ids {
+                peer_nums.push(parse_node_num(id).map_err(RedAlertError::Config)?);
+            }
+            let peers = Arc::new(PeerGateways::new(own, peer_nums, Duration::from_secs(args.peer_holdoff)));
+            log::info!("Coordinating with {} peer gateway(s); holding alerts back {:?}", peer_ids.len(), peers.holdoff());
+            Some(peers)
+        }
+        (Some(_), None) => {
+            log::warn!("--peer-gateway needs --transport mqtt to hear the other gateways; ignoring it");
+            None
+        }
+        (None, _) => None,
+    };
+    sender = sender.with_peers(peers.clone());
 
-    let cities = load_cities().await?;
+    // Direct messages asking for missed alerts, heard through the mesh MQTT broker
+    let mesh_rx = match sender.take_inbound() {
+        Some(mesh_rx) => match peers {
+            Some(peers) => peers::watch_peers(peers, mesh_rx),
+            None => mesh_rx,
+        },
+        None if args.serve_resend => {
+            return Err(RedAlertError::Config(
+                "--serve-resend needs --transport mqtt to hear direct messages from the mesh".to_string(),
+            ));
+        }
+        None => mpsc::channel(1).1,
+    };
 
-    // Check node connection before starting the loop
-    if let Err(e) = check_node_connection(&args).await {
-        log::error!("Failed to connect to the node: {}", e);
-    } else {
-        log::info!("Node connection successful. All systems operational.");
+    // Share dedup, alert state and leadership with the other gateways of the cluster
+    #[cfg(not(feature = "cluster"))]
+    if args.redis_url.is_some() {
+        // Every gateway of the cluster would transmit
+        return Err(RedAlertError::Config("--redis-url needs a build with the \"cluster\" feature".to_string()));
     }
+    #[cfg(feature = "cluster")]
+    let cluster = match &args.redis_url {
+        Some(url) => {
+            let cluster = Cluster::connect(url, &args.redis_prefix, Duration::from_secs(args.leader_lease))
+                .await
+                .map_err(RedAlertError::Config)?;
+            let lease = cluster.clone();
+            supervisor::supervise("leader lease", move || {
+                let hold = lease.clone().hold_lease();
+                async move {
+                    hold.await;
+                    Ok(())
+                }
+            });
+            Some(cluster)
+        }
+        None => None,
+    };
 
-    // Create the message sender
-    let mut sender = MessageSender::new();
+    // Stand by while another instance on this host holds the lock
+    let instance_lock = match &args.lock_file {
+        Some(path) => {
+            let lock = InstanceLock::acquire(path).map_err(RedAlertError::Config)?;
+            let waiting = lock.clone();
+            supervisor::supervise("instance lock", move || waiting.clone().wait_for_lock());
+            Some(lock)
+        }
+        None => None,
+    };
 
-    // Create an interval to trigger every 5 seconds
-    let mut interval = tokio::time::interval(Duration::from_secs(5));
+    // Operators in the field can manage the gateway from their nodes
+    let admin = match (&args.admin_node, &args.admin_key) {
+        (Some(nodes), Some(key)) => {
+            if args.transport != TransportKind::Mqtt {
+                log::warn!("Admin commands are only heard through the mesh MQTT transport; --admin-node has no effect");
+            }
+            let counters = Path::new(&args.data_dir).join(ADMIN_COUNTERS);
+            Some(AdminAuth::new(key.clone(), nodes, &counters).map_err(RedAlertError::Config)?)
+        }
+        (Some(_), None) => {
+            return Err(RedAlertError::Config("--admin-node needs --admin-key".to_string()));
+        }
+        (None, _) => None,
+    };
 
-    // Enter the main processing loop
-    loop {
-        interval.tick().await;
+    let lifecycle = AlertLifecycle::new(Duration::from_secs(args.all_clear_after.unwrap_or(CLEAR_AFTER)));
+    let poll_every = Duration::from_secs(args.poll_interval.max(1));
+    let protective = ProtectiveActions::new(args.builtin_protective_actions, &args.protective_action);
+    let abbreviations = Abbreviations::new(args.abbreviate, &args.abbreviation);
+    let validity = Validity::new(args.valid_for, &args.validity);
+    let threat_passed = args.threat_passed.then(|| ThreatPassed::new(&args.shelter_time));
+    let gateway = Gateway {
+        args,
+        cities: city_index,
+        zones,
+        sender,
+        active,
+        #[cfg(feature = "mqtt")]
+        mqtt,
+        cap_publisher,
+        notifiers,
+        area_map,
+        alert_log: AlertLog::new(),
+        dedup: AlertDedup::new(),
+        lifecycle,
+        country,
+        language,
+        #[cfg(feature = "cluster")]
+        cluster,
+        instance_lock,
+        #[cfg(feature = "cluster")]
+        leading: false,
+        admin,
+        poll_every,
+        quiet_since: None,
+        alert_seen_at: None,
+        rate_limited_at: None,
+        protective,
+        abbreviations,
+        category_channels,
+        validity,
+        aftershock_due: None,
+        threat_passed,
+        canary,
+        #[cfg(feature = "sqlite")]
+        subscribers,
+        started: Instant::now(),
+    };
 
-        // Handle process_alert errors without exiting the loop
-        if let Err(e) = process_alert(&mut sender, &args, &cities).await {
-            log::error!("Error processing alert: {}", e);
+    // Run the alert loop under supervision; a panic while handling one alert restarts
+    // the loop with its state intact instead of killing the daemon
+    let gateway = Arc::new(tokio::sync::Mutex::new(gateway));
+    let inbox = Arc::new(tokio::sync::Mutex::new(Inbox {
+        alerts_rx,
+        state_rx,
+        resend_rx,
+        reload_rx,
+        mesh_rx,
+    }));
+    let alert_loop = supervisor::supervise("alert loop", move || {
+        let (gateway, inbox) = (gateway.clone(), inbox.clone());
+        async move {
+            let mut gateway = gateway.lock().await;
+            let mut inbox = inbox.lock().await;
+            gateway.run(&mut inbox).await;
+            Ok(())
         }
-    }
+    });
+
+    let _ = alert_loop.await;
+    Ok(())
 }
@@ -0,0 +1,135 @@
+use crate::active::ActiveCity;
+use crate::localtime;
+use crate::zones::ZoneScheme;
+use crate::City;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+// Width of the rendered map in pixels; the height follows from the area covered
+const WIDTH: f64 = 360.0;
+
+// Margin around the outermost cities, in degrees
+const PADDING: f64 = 0.05;
+
+// Room at the top for the caption
+const CAPTION_HEIGHT: f64 = 24.0;
+
+// A city drawn on the map
+#[derive(Debug)]
+struct MapPoint {
+    name: String,
+    lat: f64,
+    lng: f64,
+    zones: Vec<u32>,
+}
+
+// Map of every known city with the alerted ones highlighted, drawn as SVG from
+// the city coordinates alone, so no map tiles or shapes need to be shipped
+#[derive(Debug)]
+pub struct AlertMap {
+    points: Vec<MapPoint>,
+    max_lat: f64,
+    min_lng: f64,
+    // Pixels per degree of longitude and of latitude
+    x_scale: f64,
+    y_scale: f64,
+    height: f64,
+}
+
+impl AlertMap {
+    pub fn new(cities: &[City], zones: &ZoneScheme) -> Self {
+        let points: Vec<MapPoint> = cities
+            .iter()
+            // Entries without coordinates would all land on (0, 0)
+            .filter_map(|city| {
+                let (lat, lng) = city.coordinates()?;
+                Some(MapPoint {
+                    name: city.name.clone(),
+                    lat,
+                    lng,
+                    zones: zones.zones_for_city(city),
+                })
+            })
+            .collect();
+
+        let (mut min_lat, mut max_lat, mut min_lng, mut max_lng) = (f64::MAX, f64::MIN, f64::MAX, f64::MIN);
+        for point in &points {
+            min_lat = min_lat.min(point.lat);
+            max_lat = max_lat.max(point.lat);
+            min_lng = min_lng.min(point.lng);
+            max_lng = max_lng.max(point.lng);
+        }
+        if points.is_empty() {
+            (min_lat, max_lat, min_lng, max_lng) = (0.0, 1.0, 0.0, 1.0);
+        }
+        let (min_lat, max_lat) = (min_lat - PADDING, max_lat + PADDING);
+        let (min_lng, max_lng) = (min_lng - PADDING, max_lng + PADDING);
+
+        // A degree of longitude is shorter than one of latitude away from the equator
+        let x_scale = WIDTH / (max_lng - min_lng);
+        let y_scale = x_scale / ((min_lat + max_lat) / 2.0).to_radians().cos();
+        let height = (max_lat - min_lat) * y_scale;
+
+        AlertMap {
+            points,
+            max_lat,
+            min_lng,
+            x_scale,
+            y_scale,
+            height,
+        }
+    }
+
+    fn position(&self, lat: f64, lng: f64) -> (f64, f64) {
+        let x = (lng - self.min_lng) * self.x_scale;
+        let y = CAPTION_HEIGHT + (self.max_lat - lat) * self.y_scale;
+        (x, y)
+    }
+
+    // SVG with alerted cities in red, the rest of their zones in orange and quiet cities in grey
+    pub fn render_svg(&self, active: &[ActiveCity]) -> String {
+        let alerted: HashSet<&str> = active.iter().map(|city| city.name.as_str()).collect();
+        let alerted_zones: HashSet<u32> = active.iter().flat_map(|city| city.zones.iter().copied()).collect();
+
+        let height = CAPTION_HEIGHT + self.height;
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.0}" height="{h:.0}" viewBox="0 0 {w:.0} {h:.0}"><rect width="100%" height="100%" fill="#f4f4f4"/>"##,
+            w = WIDTH,
+            h = height
+        );
+
+        // Alerted cities are drawn last so they stay on top
+        let mut quiet = String::new();
+        let mut in_zone = String::new();
+        let mut hit = String::new();
+        for point in &self.points {
+            let (x, y) = self.position(point.lat, point.lng);
+            let (layer, radius, fill) = if alerted.contains(point.name.as_str()) {
+                (&mut hit, 3.0, "#d0021b")
+            } else if point.zones.iter().any(|zone| alerted_zones.contains(zone)) {
+                (&mut in_zone, 1.5, "#f5a623")
+            } else {
+                (&mut quiet, 1.2, "#bbbbbb")
+            };
+            let _ = write!(layer, r#"<circle cx="{:.1}" cy="{:.1}" r="{}" fill="{}"/>"#, x, y, radius, fill);
+        }
+        svg.push_str(&quiet);
+        svg.push_str(&in_zone);
+        svg.push_str(&hit);
+
+        let caption = match active.len() {
+            0 => "No active alerts".to_string(),
+            1 => "1 city under alert".to_string(),
+            count => format!("{} cities under alert", count),
+        };
+        let _ = write!(
+            svg,
+            r##"<text x="6" y="16" font-family="sans-serif" font-size="12" fill="#333333">{} - {}</text></svg>"##,
+            caption,
+            localtime::now().format("%H:%M")
+        );
+        svg
+    }
+}
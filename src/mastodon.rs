@@ -0,0 +1,93 @@
+use crate::notify::{Auth, JsonPost, Notification, Notifier};
+use crate::outbox::{Outbox, OutboxSettings};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+// Longest status a Mastodon server accepts by default
+const MAX_STATUS_CHARS: usize = 500;
+
+// Who sees the posted statuses
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Visibility {
+    /// On the profile and the public timelines
+    Public,
+    /// On the profile, left out of the public timelines
+    Unlisted,
+}
+
+impl Visibility {
+    fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+        }
+    }
+}
+
+// A status counts as posted only once the server answers with it; a proxy in front
+// of the server may answer 2xx with something else
+fn posted(answer: &str) -> Result<(), String> {
+    let status: Value = serde_json::from_str(answer).map_err(|_| "the server's answer is not a status".to_string())?;
+    match status.get("id") {
+        Some(_) => {
+            log::info!("Mastodon: posted {}", status["url"].as_str().unwrap_or("a status"));
+            Ok(())
+        }
+        None => Err("the server's answer is not a status".to_string()),
+    }
+}
+
+// The public notice of an alert: the category, its zones with how many cities each, and
+// the instructions, without listing the cities
+fn status(notification: &Notification) -> String {
+    let mut status = notification.headline();
+    if !notification.zones.is_empty() {
+        let zones: Vec<String> = notification
+            .zones
+            .iter()
+            .map(|zone| match zone.cities.len() {
+                0 => zone.name.clone(),
+                1 => format!("{} (1 city)", zone.name),
+                count => format!("{} ({} cities)", zone.name, count),
+            })
+            .collect();
+        status.push_str(&format!("\nZones: {}", zones.join(", ")));
+    }
+    if let Some(instructions) = &notification.instructions {
+        status.push_str(&format!("\n{}", instructions));
+    }
+    if status.chars().count() > MAX_STATUS_CHARS {
+        status = status.chars().take(MAX_STATUS_CHARS - 1).collect();
+        status.push('…');
+    }
+    status
+}
+
+// Posts a public notice of every transmitted alert to a Mastodon account, as a record
+// of what the gateway sent
+pub struct MastodonNotifier {
+    statuses_url: String,
+    visibility: Visibility,
+    outbox: Outbox,
+}
+
+impl MastodonNotifier {
+    pub fn new(instance: &str, token: String, visibility: Visibility, outbox: &OutboxSettings) -> Result<Self, String> {
+        // A retry of a status that did get posted isn't posted twice
+        let delivery = JsonPost::new("the server", Some(Auth::Bearer(token)))?
+            .with_idempotency_key()
+            .with_check(posted);
+        Ok(MastodonNotifier {
+            statuses_url: format!("{}/api/v1/statuses", instance.trim_end_matches('/')),
+            visibility,
+            outbox: Outbox::start("mastodon", outbox, delivery)?,
+        })
+    }
+}
+
+impl Notifier for MastodonNotifier {
+    fn notify(&self, notification: &Notification) {
+        let body = json!({ "status": status(notification), "visibility": self.visibility.as_str() });
+        self.outbox.push(&self.statuses_url, body.to_string(), false, notification.valid_until);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::capout::escape;
+use crate::events::{self, Event};
+use crate::notify::{Auth, JsonPost, Notification, Notifier};
+use crate::outbox::{Outbox, OutboxSettings};
+use crate::ratelimit;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+// How loudly a message is posted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    // Mentions @room, so everyone in the room is notified
+    Room,
+    // A normal message, notified as the members' settings say
+    Text,
+    // A notice, which clients show without notifying
+    Notice,
+}
+
+pub fn parse_matrix_level(value: &str) -> Result<(String, Level), String> {
+    let (category, level) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected CATEGORY=LEVEL, got {}", value))?;
+    let level = match level.trim() {
+        "room" => Level::Room,
+        "text" => Level::Text,
+        "notice" => Level::Notice,
+        other => return Err(format!("Unknown level {}; use room, text or notice", other)),
+    };
+    Ok((category.trim().to_string(), level))
+}
+
+// Level of a category without a --matrix-level: the most severe alerts mention the room
+fn default_level(category: &str) -> Level {
+    match ratelimit::severity(category) {
+        3 => Level::Room,
+        0 => Level::Notice,
+        _ => Level::Text,
+    }
+}
+
+// Posts to Matrix rooms through one account
+struct Rooms {
+    homeserver: reqwest::Url,
+    rooms: Vec<String>,
+    outbox: Outbox,
+    // Tells apart the transactions of this run
+    started: i64,
+    counter: AtomicU32,
+}
+
+impl Rooms {
+    fn send_url(&self, room: &str) -> Result<reqwest::Url, String> {
+        let txn = format!("red-alert-{}-{}", self.started, self.counter.fetch_add(1, Ordering::Relaxed));
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .map_err(|_| format!("{} can't be a homeserver URL", self.homeserver))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3", "rooms", room, "send", "m.room.message", &txn]);
+        Ok(url)
+    }
+
+    fn post(&self, content: &Value, valid_until: Option<DateTime<Utc>>) {
+        for room in &self.rooms {
+            match self.send_url(room) {
+                Ok(url) => self.outbox.push(url.as_str(), content.to_string(), false, valid_until),
+                Err(e) => log::error!("Matrix: {}", e),
+            }
+        }
+    }
+}
+
+// A formatted message with its plain-text fallback
+fn message(level: Level, body: String, html: String) -> Value {
+    let (msgtype, body, html) = match level {
+        Level::Room => ("m.text", format!("@room {}", body), format!("@room {}", html)),
+        Level::Text => ("m.text", body, html),
+        Level::Notice => ("m.notice", body, html),
+    };
+    let mut content = json!({
+        "msgtype": msgtype,
+        "body": body,
+        "format": "org.matrix.custom.html",
+        "formatted_body": html,
+    });
+    if level == Level::Room {
+        content["m.mentions"] = json!({ "room": true });
+    }
+    content
+}
+
+// The alert as HTML: the headline in bold, a list of zones with their cities, the instructions
+fn alert_html(notification: &Notification) -> String {
+    let mut html = format!("<strong>{}</strong>", escape(&notification.headline()));
+    if !notification.zones.is_empty() {
+        html.push_str("<ul>");
+        for zone in &notification.zones {
+            if zone.cities.is_empty() {
+                html.push_str(&format!("<li>{}</li>", escape(&zone.name)));
+            } else {
+                html.push_str(&format!("<li><b>{}</b>: {}</li>", escape(&zone.name), escape(&zone.cities.join(", "))));
+            }
+        }
+        html.push_str("</ul>");
+    }
+    if let Some(instructions) = &notification.instructions {
+        html.push_str(&format!("<p><em>{}</em></p>", escape(instructions)));
+    }
+    html
+}
+
+// Posts alerts to Matrix rooms, louder for more severe categories, and optionally
+// the gateway's health: failed sends and tasks, and canaries that stop getting through
+pub struct MatrixNotifier {
+    rooms: Arc<Rooms>,
+    levels: Vec<(String, Level)>,
+}
+
+impl MatrixNotifier {
+    pub fn new(
+        homeserver: &str,
+        token: String,
+        rooms: &[String],
+        levels: &[(String, Level)],
+        health: bool,
+        outbox: &OutboxSettings,
+    ) -> Result<Self, String> {
+        if rooms.is_empty() {
+            return Err("--matrix-homeserver needs --matrix-room".to_string());
+        }
+        let homeserver = reqwest::Url::parse(homeserver).map_err(|e| format!("Invalid --matrix-homeserver {}: {}", homeserver, e))?;
+        // Messages are put at their full send URL, whose transaction ID makes a retry
+        // of a message that did arrive harmless
+        let delivery = JsonPost::new("the homeserver", Some(Auth::Bearer(token)))?.put();
+        let rooms = Arc::new(Rooms {
+            homeserver,
+            rooms: rooms.to_vec(),
+            outbox: Outbox::start("matrix", outbox, delivery)?,
+            started: chrono::Utc::now().timestamp_millis(),
+            counter: AtomicU32::new(0),
+        });
+        if health {
+            tokio::spawn(post_health(rooms.clone()));
+        }
+        Ok(MatrixNotifier {
+            rooms,
+            levels: levels.to_vec(),
+        })
+    }
+
+    fn level(&self, category: &str) -> Level {
+        self.levels
+            .iter()
+            .rev()
+            .find(|(configured, _)| configured == category)
+            .map_or_else(|| default_level(category), |(_, level)| *level)
+    }
+}
+
+impl Notifier for MatrixNotifier {
+    fn notify(&self, notification: &Notification) {
+        let level = self.level(&notification.category);
+        self.rooms.post(&message(level, notification.text(), alert_html(notification)), notification.valid_until);
+    }
+}
+
+// Post a notice when the gateway fails to send, a task fails, or a canary starts or
+// stops failing
+async fn post_health(rooms: Arc<Rooms>) {
+    let mut failing_canaries: HashSet<u32> = HashSet::new();
+    let mut live = events::subscribe();
+    loop {
+        let text = match live.recv().await {
+            Ok((_, Event::SendFailed { channel, category, attempts, error, .. })) => format!(
+                "⚠️ Failed to send a {} message on channel {} after {} attempt(s): {}",
+                category, channel, attempts, error
+            ),
+            Ok((_, Event::TaskFailed { task, error })) => format!("⚠️ {} failed and is restarting: {}", task, error),
+            Ok((_, Event::CanaryChecked { channel, ok: false, error, .. })) => {
+                if !failing_canaries.insert(channel) {
+                    continue;
+                }
+                format!(
+                    "⚠️ Canary on channel {} failed: {}; alerts may not be reaching the mesh",
+                    channel,
+                    error.unwrap_or_default()
+                )
+            }
+            Ok((_, Event::CanaryChecked { channel, ok: true, .. })) => {
+                if !failing_canaries.remove(&channel) {
+                    continue;
+                }
+                format!("✅ Canary on channel {} got through again", channel)
+            }
+            Ok(_) => continue,
+            Err(RecvError::Lagged(missed)) => {
+                log::warn!("Matrix health notices fell behind; {} event(s) skipped", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        rooms.post(&message(Level::Notice, text.clone(), escape(&text)), None);
+    }
+}
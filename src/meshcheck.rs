@@ -0,0 +1,183 @@
+use crate::device::{run_cli, Device};
+use crate::events::{self, Event};
+use crate::nodedb::normalize_node_id;
+use clap::Args;
+use std::collections::{HashMap, VecDeque};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tokio::time::sleep;
+
+// Seconds the meshtastic CLI waits for a traceroute reply
+const TRACEROUTE_TIMEOUT_SECS: u64 = 60;
+
+// Checks of a node kept to judge its usual path by
+const HISTORY: usize = 48;
+
+// Hops a path may grow beyond the shortest one seen recently before it counts as degraded
+const EXTRA_HOPS: u32 = 2;
+
+#[derive(Args, Debug)]
+pub struct MeshCheckArgs {
+    /// Node IDs to trace (e.g. !a1b2c3d4); defaults to the --mesh-check-node list
+    pub nodes: Vec<String>,
+}
+
+// The route to a node and back as reported by one traceroute
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracedPath {
+    // Links on the way to the node
+    pub hops: u32,
+    // Weakest link in either direction, where the nodes reported one
+    pub min_snr: Option<f64>,
+    pub route: String,
+}
+
+// Parse `meshtastic --traceroute` output such as
+//   Route traced towards destination:
+//   !0a1b2c3d --> !11223344 (6.25dB) --> !ba4bf9d0 (-2.0dB)
+//   Route traced back to us:
+//   !ba4bf9d0 --> !11223344 (5.0dB) --> !0a1b2c3d (7.75dB)
+fn parse_traceroute(output: &str) -> Option<TracedPath> {
+    let mut lines = output.lines().map(str::trim);
+    let towards = lines
+        .by_ref()
+        .skip_while(|line| !line.starts_with("Route traced towards destination"))
+        .nth(1)?;
+    let back = lines
+        .skip_while(|line| !line.starts_with("Route traced back to us"))
+        .nth(1)
+        .unwrap_or_default();
+
+    let hops = towards.split(" --> ").count().checked_sub(1)? as u32;
+    let min_snr = [towards, back]
+        .iter()
+        .flat_map(|route| route.split(" --> "))
+        .filter_map(|node| {
+            let snr = node.split_once(" (")?.1.strip_suffix("dB)")?;
+            snr.parse::<f64>().ok()
+        })
+        .min_by(f64::total_cmp);
+    Some(TracedPath {
+        hops,
+        min_snr,
+        route: towards.to_string(),
+    })
+}
+
+// Trace the route to a node through the attached radio
+pub fn traceroute(device: &Device, node: &str) -> Result<TracedPath, String> {
+    let mut cmd = Command::new("meshtastic");
+    device.apply(&mut cmd);
+    cmd.arg("--traceroute").arg(node);
+    cmd.arg("--timeout").arg(TRACEROUTE_TIMEOUT_SECS.to_string());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::null());
+
+    let output = run_cli(&mut cmd).map_err(|e| format!("Failed to execute meshtastic --traceroute: {}", e))?;
+    parse_traceroute(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| "no route came back".to_string())
+}
+
+// Recent paths to each node, to tell a degraded path from its usual one
+#[derive(Debug, Default)]
+struct PathHistory {
+    paths: HashMap<String, VecDeque<Option<TracedPath>>>,
+}
+
+impl PathHistory {
+    // Record a check, returning why the path counts as degraded, if it does
+    fn record(&mut self, node: &str, path: Option<TracedPath>, min_snr: f64) -> Option<String> {
+        let history = self.paths.entry(node.to_string()).or_default();
+        let shortest = history.iter().flatten().map(|path| path.hops).min();
+        if history.len() == HISTORY {
+            history.pop_front();
+        }
+        history.push_back(path.clone());
+
+        let Some(path) = path else {
+            return Some("no route".to_string());
+        };
+        if let Some(shortest) = shortest.filter(|shortest| path.hops >= shortest + EXTRA_HOPS) {
+            return Some(format!("path grew from {} to {} hops", shortest, path.hops));
+        }
+        match path.min_snr {
+            Some(snr) if snr < min_snr => Some(format!("weakest link at {:.2} dB", snr)),
+            _ => None,
+        }
+    }
+}
+
+// Trace the route to each node every so often, recording hop counts and SNR and
+// warning when a path degrades: alerts may no longer be reaching that part of the mesh
+pub async fn monitor_paths(device: Device, nodes: Vec<String>, min_snr: f64, check_every: Duration) {
+    let nodes: Vec<String> = nodes.iter().map(|id| normalize_node_id(id)).collect();
+    let mut history = PathHistory::default();
+    // Nodes we already warned about, so the warning isn't repeated every check
+    let mut degraded: Vec<String> = Vec::new();
+
+    log::info!("Tracing the route to {} node(s) every {:?}", nodes.len(), check_every);
+
+    loop {
+        for node in &nodes {
+            let (device, target) = (device.clone(), node.clone());
+            let path = tokio::task::spawn_blocking(move || traceroute(&device, &target))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|r| r);
+            if let Err(e) = &path {
+                log::debug!("Traceroute to {} failed: {}", node, e);
+            }
+
+            let path = path.ok();
+            let reason = history.record(node, path.clone(), min_snr);
+            events::emit(Event::MeshPathChecked {
+                node: node.clone(),
+                hops: path.as_ref().map(|path| path.hops),
+                min_snr_db: path.as_ref().and_then(|path| path.min_snr),
+                degraded: reason.clone(),
+            });
+
+            match reason {
+                Some(reason) if !degraded.contains(node) => {
+                    log::warn!("Path to {} degraded ({}); alerts may not be reaching its region", node, reason);
+                    degraded.push(node.clone());
+                }
+                None if degraded.contains(node) => {
+                    log::info!("Path to {} is healthy again", node);
+                    degraded.retain(|id| id != node);
+                }
+                _ => {}
+            }
+        }
+
+        sleep(check_every).await;
+    }
+}
+
+// Trace the route to each node once and print what came back
+pub fn run(args: &MeshCheckArgs, device: &Device, default_nodes: &[String], min_snr: f64) -> Result<(), String> {
+    let nodes = if args.nodes.is_empty() { default_nodes } else { &args.nodes };
+    if nodes.is_empty() {
+        return Err("Give the nodes to trace, or configure --mesh-check-node".to_string());
+    }
+
+    for node in nodes {
+        let node = normalize_node_id(node);
+        log::info!("Tracing the route to {}...", node);
+        match traceroute(device, &node) {
+            Ok(path) => {
+                let snr = path
+                    .min_snr
+                    .map(|snr| format!("{:.2} dB", snr))
+                    .unwrap_or_else(|| "unknown".to_string());
+                let warning = match path.min_snr {
+                    Some(snr) if snr < min_snr => " (below --mesh-check-min-snr)",
+                    _ => "",
+                };
+                println!("{}: {} hop(s), weakest link {}{}", node, path.hops, snr, warning);
+                println!("  {}", path.route);
+            }
+            Err(e) => println!("{}: unreachable, {}", node, e),
+        }
+    }
+    Ok(())
+}
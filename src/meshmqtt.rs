@@ -0,0 +1,569 @@
+// Without the "mqtt" feature only the node IDs and channel settings are used, not the packet codec
+#![cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+
+#[cfg(feature = "mqtt")]
+use crate::storeforward::{lock_store_forward, SharedStoreForward};
+use aes::cipher::{KeyIvInit, StreamCipher};
+use base64::Engine;
+#[cfg(feature = "mqtt")]
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+#[cfg(feature = "mqtt")]
+use std::collections::HashMap;
+#[cfg(feature = "mqtt")]
+use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "mqtt")]
+use tokio::sync::mpsc;
+
+// Well-known Meshtastic default channel key, selected by a one-byte PSK of 1
+const DEFAULT_PSK: [u8; 16] = [
+    0xd4, 0xf1, 0xbb, 0x3a, 0x20, 0x29, 0x07, 0x59, 0xf0, 0xbc, 0xff, 0xab, 0xcf, 0x4e, 0x69, 0x01,
+];
+
+// PortNum.TEXT_MESSAGE_APP
+pub const TEXT_MESSAGE_APP: u64 = 1;
+
+// PortNum.DETECTION_SENSOR_APP, used by the detection sensor module for "<name> state: <0|1>"
+pub const DETECTION_SENSOR_APP: u64 = 10;
+
+// PortNum.STORE_FORWARD_APP, used by store-and-forward servers and their clients
+pub const STORE_FORWARD_APP: u64 = 65;
+
+// StoreAndForward.RequestResponse values below this come from a server, the rest from clients
+const STORE_FORWARD_CLIENT_FIRST: u64 = 64;
+
+// Address used by Meshtastic for broadcast packets
+pub const BROADCAST_ADDR: u32 = 0xffff_ffff;
+
+// Received texts waiting for the alert loop; more are dropped
+const INBOUND_QUEUE: usize = 32;
+
+const HOP_LIMIT: u64 = 3;
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
+
+// A Meshtastic channel as seen on the MQTT broker
+#[derive(Debug, Clone)]
+pub struct MeshChannel {
+    pub name: String,
+    // Expanded AES key, empty for unencrypted channels
+    pub key: Vec<u8>,
+}
+
+impl MeshChannel {
+    // Expand a base64 channel PSK the same way the firmware does
+    pub fn new(name: &str, psk_base64: &str) -> Result<Self, String> {
+        let psk = base64::engine::general_purpose::STANDARD
+            .decode(psk_base64)
+            .map_err(|e| format!("Invalid base64 PSK for channel {}: {}", name, e))?;
+
+        let key = match psk.len() {
+            0 => vec![],
+            // Index into the well-known keys: 0 disables encryption, 1 is the default key
+            1 if psk[0] == 0 => vec![],
+            1 => {
+                let mut key = DEFAULT_PSK.to_vec();
+                key[15] = key[15].wrapping_add(psk[0] - 1);
+                key
+            }
+            16 | 32 => psk,
+            len => return Err(format!("PSK for channel {} must be 0, 1, 16 or 32 bytes, got {}", name, len)),
+        };
+
+        Ok(MeshChannel {
+            name: name.to_string(),
+            key,
+        })
+    }
+
+    // Channel hash carried in MeshPacket.channel so receivers can pick the right key
+    pub fn hash(&self) -> u32 {
+        let xor = |bytes: &[u8]| bytes.iter().fold(0u8, |acc, b| acc ^ b);
+        (xor(self.name.as_bytes()) ^ xor(&self.key)) as u32
+    }
+}
+
+// Parse a `--mesh-channel` value of the form INDEX=NAME[:PSK]
+pub fn parse_mesh_channel(value: &str) -> Result<(u32, MeshChannel), String> {
+    let (index, rest) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected INDEX=NAME[:PSK], got {}", value))?;
+    let index: u32 = index
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid channel index in {}", value))?;
+    let (name, psk) = rest.split_once(':').unwrap_or((rest, "AQ=="));
+    Ok((index, MeshChannel::new(name.trim(), psk.trim())?))
+}
+
+// Minimal protobuf writer for the few Meshtastic messages we need
+#[derive(Default)]
+struct ProtoWriter {
+    buf: Vec<u8>,
+}
+
+impl ProtoWriter {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn tag(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn uint(&mut self, field: u32, value: u64) {
+        self.tag(field, 0);
+        self.varint(value);
+    }
+
+    fn fixed32(&mut self, field: u32, value: u32) {
+        self.tag(field, 5);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.tag(field, 2);
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+}
+
+// Value of a protobuf field
+enum ProtoValue<'a> {
+    Varint(u64),
+    Fixed32(u32),
+    Bytes(&'a [u8]),
+}
+
+// Minimal protobuf reader, the counterpart of ProtoWriter
+struct ProtoReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> ProtoReader<'a> {
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = self.buf.split_first()?;
+            self.buf = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some(bytes)
+    }
+
+    // Next field number and value, or None at the end (or on malformed input)
+    fn next_field(&mut self) -> Option<(u32, ProtoValue<'a>)> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        let key = self.varint()?;
+        let field = (key >> 3) as u32;
+        let value = match key & 7 {
+            0 => ProtoValue::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                return self.next_field();
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                ProtoValue::Bytes(self.take(len)?)
+            }
+            5 => ProtoValue::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().ok()?)),
+            _ => return None,
+        };
+        Some((field, value))
+    }
+}
+
+// A text message heard on the mesh, as forwarded by an MQTT-enabled node
+#[derive(Debug, Clone)]
+pub struct InboundText {
+    pub from: u32,
+    pub to: u32,
+    // Index of the channel (--mesh-channel) it was sent on
+    pub channel: u32,
+    pub text: String,
+    // Gateway node that uplinked it to the broker
+    pub gateway: u32,
+}
+
+// Gateway that uplinked a packet, its sender, recipient, port and payload
+type Uplinked = (u32, u32, u32, u64, Vec<u8>);
+
+// Decode a ServiceEnvelope published on a channel's topic; a packet without a
+// gateway ID is taken to be uplinked by its sender
+fn decode_envelope(payload: &[u8], channel: &MeshChannel) -> Option<Uplinked> {
+    let mut envelope = ProtoReader { buf: payload };
+    let (mut packet, mut gateway) = (None, None);
+    while let Some((field, value)) = envelope.next_field() {
+        match (field, value) {
+            (1, ProtoValue::Bytes(bytes)) => packet = Some(bytes),
+            (3, ProtoValue::Bytes(bytes)) => {
+                gateway = std::str::from_utf8(bytes).ok().and_then(|id| parse_node_num(id).ok());
+            }
+            _ => {}
+        }
+    }
+
+    let (mut from, mut to, mut id) = (0, BROADCAST_ADDR, 0);
+    let (mut decoded, mut encrypted) = (None, None);
+    let mut reader = ProtoReader { buf: packet? };
+    while let Some((field, value)) = reader.next_field() {
+        match (field, value) {
+            (1, ProtoValue::Fixed32(value)) => from = value,
+            (2, ProtoValue::Fixed32(value)) => to = value,
+            (4, ProtoValue::Bytes(bytes)) => decoded = Some(bytes.to_vec()),
+            (5, ProtoValue::Bytes(bytes)) => encrypted = Some(bytes.to_vec()),
+            (6, ProtoValue::Fixed32(value)) => id = value,
+            _ => {}
+        }
+    }
+
+    let data = match (decoded, encrypted) {
+        (Some(data), _) => data,
+        (None, Some(mut data)) if !channel.key.is_empty() => {
+            apply_channel_cipher(&channel.key, id, from, &mut data);
+            data
+        }
+        _ => return None,
+    };
+
+    let (mut portnum, mut payload) = (0, None);
+    let mut reader = ProtoReader { buf: &data };
+    while let Some((field, value)) = reader.next_field() {
+        match (field, value) {
+            (1, ProtoValue::Varint(value)) => portnum = value,
+            (2, ProtoValue::Bytes(bytes)) => payload = Some(bytes.to_vec()),
+            _ => {}
+        }
+    }
+    Some((gateway.unwrap_or(from), from, to, portnum, payload?))
+}
+
+// Whether a StoreAndForward payload was sent by a server, e.g. its heartbeat
+fn is_store_forward_server(payload: &[u8]) -> bool {
+    let mut reader = ProtoReader { buf: payload };
+    while let Some((field, value)) = reader.next_field() {
+        if let (1, ProtoValue::Varint(rr)) = (field, value) {
+            return rr > 0 && rr < STORE_FORWARD_CLIENT_FIRST;
+        }
+    }
+    false
+}
+
+// Encode a Data message carrying a text payload for the given port
+fn encode_text_data(portnum: u64, text: &str) -> Vec<u8> {
+    let mut data = ProtoWriter::default();
+    data.uint(1, portnum);
+    data.bytes(2, text.as_bytes());
+    data.buf
+}
+
+// Encrypt (or decrypt) a payload in place with the channel key
+fn apply_channel_cipher(key: &[u8], packet_id: u32, from: u32, payload: &mut [u8]) {
+    let mut nonce = [0u8; 16];
+    nonce[..8].copy_from_slice(&(packet_id as u64).to_le_bytes());
+    nonce[8..12].copy_from_slice(&from.to_le_bytes());
+
+    // Key lengths are validated in MeshChannel::new, so construction can't fail here
+    match key.len() {
+        16 => {
+            if let Ok(mut cipher) = Aes128Ctr::new_from_slices(key, &nonce) {
+                cipher.apply_keystream(payload);
+            }
+        }
+        32 => {
+            if let Ok(mut cipher) = Aes256Ctr::new_from_slices(key, &nonce) {
+                cipher.apply_keystream(payload);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Encode a ServiceEnvelope wrapping a text MeshPacket
+fn encode_envelope(channel: &MeshChannel, gateway_id: u32, to: u32, packet_id: u32, portnum: u64, text: &str) -> Vec<u8> {
+    let rx_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as u32;
+
+    let mut packet = ProtoWriter::default();
+    packet.fixed32(1, gateway_id);
+    packet.fixed32(2, to);
+    packet.uint(3, channel.hash() as u64);
+
+    let mut data = encode_text_data(portnum, text);
+    if channel.key.is_empty() {
+        packet.bytes(4, &data);
+    } else {
+        apply_channel_cipher(&channel.key, packet_id, gateway_id, &mut data);
+        packet.bytes(5, &data);
+    }
+
+    packet.fixed32(6, packet_id);
+    packet.fixed32(7, rx_time);
+    packet.uint(9, HOP_LIMIT);
+    packet.uint(15, HOP_LIMIT);
+
+    let mut envelope = ProtoWriter::default();
+    envelope.bytes(1, &packet.buf);
+    envelope.bytes(2, channel.name.as_bytes());
+    envelope.bytes(3, format_node_id(gateway_id).as_bytes());
+    envelope.buf
+}
+
+pub fn format_node_id(id: u32) -> String {
+    format!("!{:08x}", id)
+}
+
+// Parse a node ID such as "!a1b2c3d4" into its numeric form
+pub fn parse_node_num(id: &str) -> Result<u32, String> {
+    u32::from_str_radix(id.trim().trim_start_matches('!'), 16).map_err(|_| format!("Invalid node ID: {}", id))
+}
+
+// Sends text messages by publishing them to a Meshtastic MQTT broker, letting
+// MQTT-enabled gateway nodes downlink them onto their meshes, and hears the
+// texts those nodes uplink
+#[cfg(feature = "mqtt")]
+pub struct MeshMqttTransport {
+    client: AsyncClient,
+    root_topic: String,
+    gateway_id: u32,
+    channels: HashMap<u32, MeshChannel>,
+    inbound: Option<mpsc::Receiver<InboundText>>,
+    store_forward: SharedStoreForward,
+}
+
+#[cfg(feature = "mqtt")]
+impl MeshMqttTransport {
+    pub fn connect(
+        host: &str,
+        port: u16,
+        credentials: Option<(&str, &str)>,
+        root_topic: &str,
+        gateway_id: u32,
+        channels: HashMap<u32, MeshChannel>,
+        forward_own: bool,
+    ) -> Self {
+        let mut options = MqttOptions::new(format_node_id(gateway_id), host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some((username, password)) = credentials {
+            options.set_credentials(username, password);
+        }
+
+        let (client, mut eventloop) = AsyncClient::new(options, 64);
+        let root_topic = root_topic.trim_end_matches('/').to_string();
+        let (inbound_tx, inbound) = mpsc::channel(INBOUND_QUEUE);
+
+        // Channel name in the topic -> index and key, for decoding uplinked packets
+        let by_name: HashMap<String, (u32, MeshChannel)> = channels
+            .iter()
+            .map(|(index, channel)| (channel.name.clone(), (*index, channel.clone())))
+            .collect();
+        let subscriber = client.clone();
+        let store_forward = SharedStoreForward::default();
+        let servers = store_forward.clone();
+        let prefix = format!("{}/2/e/", root_topic);
+        tokio::spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    // Subscriptions don't survive a reconnect, so renew them on every connection
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        for name in by_name.keys() {
+                            let topic = format!("{}{}/+", prefix, name);
+                            if let Err(e) = subscriber.try_subscribe(&topic, QoS::AtMostOnce) {
+                                log::warn!("Failed to subscribe to {}: {}", topic, e);
+                            }
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        let name = publish
+                            .topic
+                            .strip_prefix(&prefix)
+                            .and_then(|rest| rest.split('/').next())
+                            .unwrap_or_default();
+                        let Some((index, channel)) = by_name.get(name) else {
+                            continue;
+                        };
+                        let Some((gateway, from, to, portnum, payload)) = decode_envelope(&publish.payload, channel) else {
+                            continue;
+                        };
+                        // Our own packets come back from the broker too; only keep
+                        // those another gateway heard over the air
+                        if gateway == gateway_id || (from == gateway_id && portnum != TEXT_MESSAGE_APP) {
+                            continue;
+                        }
+                        if portnum == STORE_FORWARD_APP && is_store_forward_server(&payload) {
+                            lock_store_forward(&servers).heard(from, *index);
+                            continue;
+                        }
+                        if portnum != TEXT_MESSAGE_APP {
+                            continue;
+                        }
+                        if let Ok(text) = String::from_utf8(payload) {
+                            // Only the canary listens for our own texts coming back
+                            if from == gateway_id && !forward_own {
+                                continue;
+                            }
+                            let text = InboundText {
+                                from,
+                                to,
+                                channel: *index,
+                                text,
+                                gateway,
+                            };
+                            if inbound_tx.try_send(text).is_err() {
+                                log::warn!("Dropping a text from {} heard on the mesh; the alert loop is busy", format_node_id(from));
+                            }
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::warn!("Meshtastic MQTT connection error: {}. Reconnecting...", e);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        });
+
+        MeshMqttTransport {
+            client,
+            root_topic,
+            gateway_id,
+            channels,
+            inbound: Some(inbound),
+            store_forward,
+        }
+    }
+
+    // Node number the gateway sends as
+    pub fn gateway_id(&self) -> u32 {
+        self.gateway_id
+    }
+
+    // Texts heard on the configured channels; can be taken once
+    pub fn take_inbound(&mut self) -> Option<mpsc::Receiver<InboundText>> {
+        self.inbound.take()
+    }
+
+    // Store-and-forward servers heard on the configured channels
+    pub fn store_forward(&self) -> SharedStoreForward {
+        self.store_forward.clone()
+    }
+
+    // Publish a text payload on the given port to a node (or BROADCAST_ADDR) on the channel mapped to the given index
+    pub async fn send_text(&self, chan: u32, to: u32, portnum: u64, text: &str) -> Result<(), String> {
+        let channel = self
+            .channels
+            .get(&chan)
+            .ok_or_else(|| format!("No --mesh-channel configured for channel index {}", chan))?;
+
+        let packet_id: u32 = rand::random();
+        let envelope = encode_envelope(channel, self.gateway_id, to, packet_id, portnum, text);
+        let topic = format!(
+            "{}/2/e/{}/{}",
+            self.root_topic,
+            channel.name,
+            format_node_id(self.gateway_id)
+        );
+
+        self.client
+            .publish(&topic, QoS::AtLeastOnce, false, envelope)
+            .await
+            .map_err(|e| format!("Failed to publish to {}: {}", topic, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A LongFast ServiceEnvelope from node !1a2b3c4d with the default key,
+    // assembled and encrypted (AES-128-CTR) outside this codebase
+    const LONGFAST_ENVELOPE: [u8; 65] = [
+        0x0a, 0x2a, 0x0d, 0x4d, 0x3c, 0x2b, 0x1a, 0x15, 0xff, 0xff, 0xff, 0xff, 0x18, 0x08, 0x2a, 0x0e, 0x42, 0xe5, 0xd7,
+        0xcd, 0x69, 0x37, 0xfc, 0x79, 0x59, 0xa8, 0xba, 0x44, 0xf2, 0xc3, 0x35, 0xfe, 0xca, 0xad, 0x0b, 0x3d, 0x00, 0x78,
+        0xe7, 0x68, 0x48, 0x03, 0x78, 0x03, 0x12, 0x08, 0x4c, 0x6f, 0x6e, 0x67, 0x46, 0x61, 0x73, 0x74, 0x1a, 0x09, 0x21,
+        0x31, 0x61, 0x32, 0x62, 0x33, 0x63, 0x34, 0x64,
+    ];
+
+    fn long_fast() -> MeshChannel {
+        MeshChannel::new("LongFast", "AQ==").unwrap()
+    }
+
+    #[test]
+    fn default_psk_expands_to_the_well_known_key() {
+        let channel = long_fast();
+        assert_eq!(channel.key, DEFAULT_PSK.to_vec());
+        // Firmware shows hash 8 for LongFast on the default key
+        assert_eq!(channel.hash(), 8);
+        assert!(MeshChannel::new("Open", "AA==").unwrap().key.is_empty());
+        assert!(MeshChannel::new("Bad", "AQI=").is_err());
+    }
+
+    #[test]
+    fn cipher_matches_known_keystream() {
+        let mut data = encode_text_data(TEXT_MESSAGE_APP, "hello mesh");
+        apply_channel_cipher(&DEFAULT_PSK, 0x0bad_cafe, 0x1a2b_3c4d, &mut data);
+        assert_eq!(
+            data,
+            [0x42, 0xe5, 0xd7, 0xcd, 0x69, 0x37, 0xfc, 0x79, 0x59, 0xa8, 0xba, 0x44, 0xf2, 0xc3]
+        );
+    }
+
+    #[test]
+    fn decodes_known_envelope() {
+        let (gateway, from, to, portnum, payload) = decode_envelope(&LONGFAST_ENVELOPE, &long_fast()).unwrap();
+        assert_eq!(gateway, 0x1a2b_3c4d);
+        assert_eq!(from, 0x1a2b_3c4d);
+        assert_eq!(to, BROADCAST_ADDR);
+        assert_eq!(portnum, TEXT_MESSAGE_APP);
+        assert_eq!(payload, b"hello mesh");
+    }
+
+    #[test]
+    fn envelope_round_trips() {
+        for channel in [long_fast(), MeshChannel::new("Open", "").unwrap()] {
+            let text = "צבע אדום: שדרות";
+            let envelope = encode_envelope(&channel, 0xa1b2_c3d4, 0x42, 7, TEXT_MESSAGE_APP, text);
+            let (gateway, from, to, portnum, payload) = decode_envelope(&envelope, &channel).unwrap();
+            assert_eq!((gateway, from, to, portnum), (0xa1b2_c3d4, 0xa1b2_c3d4, 0x42, TEXT_MESSAGE_APP));
+            assert_eq!(String::from_utf8(payload).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn wrong_key_does_not_yield_the_text() {
+        let envelope = encode_envelope(&long_fast(), 1, BROADCAST_ADDR, 7, TEXT_MESSAGE_APP, "hello mesh");
+        let other = MeshChannel::new("LongFast", "Ag==").unwrap();
+        let decoded = decode_envelope(&envelope, &other);
+        assert!(decoded.is_none_or(|(.., payload)| payload != b"hello mesh"));
+    }
+
+    #[test]
+    fn node_ids_round_trip() {
+        assert_eq!(format_node_id(0x1a2b_3c4d), "!1a2b3c4d");
+        assert_eq!(parse_node_num("!1a2b3c4d"), Ok(0x1a2b_3c4d));
+        assert!(parse_node_num("!zz").is_err());
+        let (index, channel) = parse_mesh_channel("2=Alerts").unwrap();
+        assert_eq!((index, channel.name.as_str(), channel.key.len()), (2, "Alerts", 16));
+    }
+}
@@ -0,0 +1,152 @@
+use crate::active::ActiveCity;
+use crate::api::AlertResult;
+use crate::outbox::{Delivery, DeliveryFuture, Outbox, OutboxItem, OutboxSettings};
+use chrono::{DateTime, Utc};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+// How long the broker has to acknowledge a message before it is published again
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Publishes queued messages one at a time, each only delivered once the broker acknowledged it
+struct MqttDelivery {
+    client: AsyncClient,
+    connected: Arc<AtomicBool>,
+    acks: mpsc::UnboundedReceiver<()>,
+}
+
+impl Delivery for MqttDelivery {
+    fn deliver<'a>(&'a mut self, item: &'a OutboxItem) -> DeliveryFuture<'a> {
+        Box::pin(async move {
+            if !self.connected.load(Ordering::SeqCst) {
+                return Err("not connected to the broker".to_string());
+            }
+            // Late acknowledgements of earlier attempts
+            while self.acks.try_recv().is_ok() {}
+            self.client
+                .publish(&item.target, QoS::AtLeastOnce, item.retain, item.body.clone())
+                .await
+                .map_err(|e| e.to_string())?;
+            match tokio::time::timeout(ACK_TIMEOUT, self.acks.recv()).await {
+                Ok(Some(())) => Ok(()),
+                _ => Err("the broker did not acknowledge it".to_string()),
+            }
+        })
+    }
+}
+
+// Publishes alert events and per-zone retained state to an MQTT broker
+pub struct MqttPublisher {
+    outbox: Outbox,
+    prefix: String,
+    // Cities last published per zone, empty while the zone is clear
+    zone_states: BTreeMap<u32, Vec<String>>,
+}
+
+impl MqttPublisher {
+    // Connect to the broker and keep the connection alive in a background task
+    pub fn connect(
+        host: &str,
+        port: u16,
+        username: Option<&str>,
+        password: Option<&str>,
+        prefix: &str,
+        outbox: &OutboxSettings,
+    ) -> Result<Self, String> {
+        let mut options = MqttOptions::new(format!("red-alert-{}", std::process::id()), host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = username {
+            options.set_credentials(username, password.unwrap_or_default());
+        }
+
+        let (client, mut eventloop) = AsyncClient::new(options, 64);
+        let connected = Arc::new(AtomicBool::new(false));
+        let (acks_tx, acks) = mpsc::unbounded_channel();
+        let connection = connected.clone();
+        tokio::spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => connection.store(true, Ordering::SeqCst),
+                    Ok(Event::Incoming(Packet::PubAck(_))) => {
+                        let _ = acks_tx.send(());
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        connection.store(false, Ordering::SeqCst);
+                        log::warn!("MQTT connection error: {}. Reconnecting...", e);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        });
+
+        let delivery = MqttDelivery { client, connected, acks };
+        Ok(MqttPublisher {
+            outbox: Outbox::start("mqtt", outbox, delivery)?,
+            prefix: prefix.trim_end_matches('/').to_string(),
+            zone_states: BTreeMap::new(),
+        })
+    }
+
+    fn publish(&self, topic: String, retain: bool, payload: serde_json::Value, valid_until: Option<DateTime<Utc>>) {
+        self.outbox.push(&topic, payload.to_string(), retain, valid_until);
+    }
+
+    // Publish an event message for an alert that is being sent to the given zones
+    pub fn publish_alert(&self, alert: &AlertResult, zones: &[u32], valid_until: DateTime<Utc>) {
+        let payload = json!({
+            "alert_type": alert.alert_type,
+            "cities": alert.cities,
+            "instructions": alert.instructions,
+            "zones": zones,
+            "time": Utc::now().to_rfc3339(),
+            "valid_until": valid_until.to_rfc3339(),
+        });
+        self.publish(format!("{}/alert", self.prefix), false, payload, Some(valid_until));
+    }
+
+    // Publish retained state for every zone whose alerted cities changed
+    pub fn sync_zone_states(&mut self, active: &[ActiveCity], known_zones: &[u32]) {
+        let mut cities_by_zone: BTreeMap<u32, Vec<&ActiveCity>> = BTreeMap::new();
+        for city in active {
+            for zone in &city.zones {
+                cities_by_zone.entry(*zone).or_default().push(city);
+            }
+        }
+
+        for zone in known_zones {
+            let cities = cities_by_zone.remove(zone).unwrap_or_default();
+            let names: Vec<String> = cities.iter().map(|city| city.name.clone()).collect();
+            if self.zone_states.get(zone) == Some(&names) {
+                continue;
+            }
+
+            let payload = match cities.iter().map(|city| city.since).min() {
+                Some(since) => {
+                    let mut alert_types: Vec<&str> = cities.iter().map(|city| city.alert_type.as_str()).collect();
+                    alert_types.sort();
+                    alert_types.dedup();
+                    json!({
+                        "state": "active",
+                        "alert_types": alert_types,
+                        "cities": names,
+                        "since": since.to_rfc3339(),
+                    })
+                }
+                None => json!({
+                    "state": "clear",
+                    "since": Utc::now().to_rfc3339(),
+                }),
+            };
+
+            log::info!("Zone {} state: {}", zone, payload["state"]);
+            self.publish(format!("{}/zone/{}/state", self.prefix, zone), true, payload, None);
+            self.zone_states.insert(*zone, names);
+        }
+    }
+}
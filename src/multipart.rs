@@ -0,0 +1,224 @@
+use crate::signing::SIGNATURE_BYTES;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use tokio::sync::oneshot;
+
+// Longest text the Meshtastic apps accept in one message, in bytes
+pub const MAX_TEXT_BYTES: usize = 200;
+
+// Room kept for a sequence number, e.g. "#12345 "
+const SEQUENCE_BYTES: usize = 7;
+
+// Room kept for the part tag, e.g. "[a3 10/12] "
+const TAG_BYTES: usize = 11;
+
+// Number of split messages kept for retransmission
+const RECENT_MESSAGES: usize = 32;
+
+// Short IDs are two base-36 characters
+const ID_SPACE: u16 = 36 * 36;
+
+// A request to retransmit parts of a split message, answered with the number of parts queued
+pub struct ResendRequest {
+    pub id: String,
+    // 1-based part numbers; all parts if empty
+    pub parts: Vec<usize>,
+    pub reply: oneshot::Sender<Result<usize, String>>,
+}
+
+// A message that went out in several tagged parts
+#[derive(Debug, Clone)]
+pub struct SplitMessage {
+    pub id: String,
+    pub channel: u32,
+    pub category: String,
+    // Parts with their tags, in order
+    pub parts: Vec<String>,
+}
+
+// Split text into chunks of at most max_bytes, preferring to break at whitespace
+fn split_text(text: &str, max_bytes: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text.trim();
+    while rest.len() > max_bytes {
+        let mut cut = max_bytes;
+        while !rest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        // Break at the last space unless that leaves a very short chunk
+        if let Some(space) = rest[..cut].rfind(char::is_whitespace) {
+            if space > max_bytes / 2 {
+                cut = space;
+            }
+        }
+        chunks.push(rest[..cut].trim_end().to_string());
+        rest = rest[cut..].trim_start();
+    }
+    if !rest.is_empty() {
+        chunks.push(rest.to_string());
+    }
+    chunks
+}
+
+// Two base-36 characters, e.g. "a3"
+fn short_id(number: u16) -> String {
+    let digit = |n: u16| char::from_digit(u32::from(n), 36).unwrap_or('0');
+    format!("{}{}", digit(number / 36 % 36), digit(number % 36))
+}
+
+// Splits long messages into tagged parts ("[a3 2/3] ...") and keeps recent ones
+// so missing parts can be retransmitted
+#[derive(Debug)]
+pub struct PartStore {
+    next_id: u16,
+    recent: VecDeque<SplitMessage>,
+}
+
+impl PartStore {
+    pub fn new() -> Self {
+        PartStore {
+            // Start at a random ID so restarts don't reuse the IDs of the previous run
+            next_id: rand::random::<u16>() % ID_SPACE,
+            recent: VecDeque::new(),
+        }
+    }
+
+    // The texts to transmit for a message: the message itself if it fits, otherwise tagged parts
+    pub fn split(&mut self, channel: u32, category: &str, message: &str) -> Vec<String> {
+        if message.len() + SEQUENCE_BYTES + SIGNATURE_BYTES <= MAX_TEXT_BYTES {
+            return vec![message.to_string()];
+        }
+
+        let id = short_id(self.next_id);
+        self.next_id = (self.next_id + 1) % ID_SPACE;
+
+        let chunks = split_text(message, MAX_TEXT_BYTES - SEQUENCE_BYTES - SIGNATURE_BYTES - TAG_BYTES);
+        let total = chunks.len();
+        let parts: Vec<String> = chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| format!("[{} {}/{}] {}", id, index + 1, total, chunk))
+            .collect();
+
+        if self.recent.len() == RECENT_MESSAGES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(SplitMessage {
+            id,
+            channel,
+            category: category.to_string(),
+            parts: parts.clone(),
+        });
+        parts
+    }
+
+    // Parts of a recent split message by 1-based number (all parts if none are given)
+    pub fn lookup(&self, id: &str, numbers: &[usize]) -> Result<(u32, String, Vec<String>), String> {
+        let message = self
+            .recent
+            .iter()
+            .rev()
+            .find(|message| message.id.eq_ignore_ascii_case(id))
+            .ok_or_else(|| format!("No recent message with ID {}", id))?;
+
+        let parts = if numbers.is_empty() {
+            message.parts.clone()
+        } else {
+            numbers
+                .iter()
+                .map(|number| {
+                    number
+                        .checked_sub(1)
+                        .and_then(|index| message.parts.get(index))
+                        .cloned()
+                        .ok_or_else(|| format!("Message {} has no part {}", id, number))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        Ok((message.channel, message.category.clone(), parts))
+    }
+
+    // IDs of the split messages kept for retransmission, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let messages: Vec<Value> = self
+            .recent
+            .iter()
+            .map(|message| json!({ "id": message.id, "channel": message.channel, "parts": message.parts.len() }))
+            .collect();
+        Value::Array(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Room for a message sent whole
+    const WHOLE_BYTES: usize = MAX_TEXT_BYTES - SEQUENCE_BYTES - SIGNATURE_BYTES;
+
+    #[test]
+    fn splits_at_the_byte_limit() {
+        assert_eq!(split_text(&"a".repeat(10), 10), vec!["a".repeat(10)]);
+        assert_eq!(split_text(&"a".repeat(11), 10), vec!["a".repeat(10), "a".to_string()]);
+        // Prefer the last space, but not one that leaves a very short chunk
+        assert_eq!(split_text("aaaaaaa bbbbbb", 10), vec!["aaaaaaa", "bbbbbb"]);
+        assert_eq!(split_text("aa bbbbbbbbbb", 10), vec!["aa bbbbbbb", "bbb"]);
+        assert_eq!(split_text("  ", 10), Vec::<String>::new());
+    }
+
+    #[test]
+    fn never_splits_inside_a_hebrew_letter() {
+        // Hebrew letters are two bytes, so an odd limit falls inside one
+        let text = "צבעאדוםשדרותנתיבות";
+        let chunks = split_text(text, 5);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 5 && chunk.chars().count() == 2));
+        assert_eq!(chunks.concat(), text);
+
+        let long = "צבע אדום ".repeat(40);
+        let parts = PartStore::new().split(2, "missiles", &long);
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|part| part.len() <= WHOLE_BYTES));
+    }
+
+    #[test]
+    fn tags_parts_with_id_and_count() {
+        assert_eq!(short_id(0), "00");
+        assert_eq!(short_id(37), "11");
+        assert_eq!(short_id(ID_SPACE - 1), "zz");
+
+        let mut store = PartStore::new();
+        let parts = store.split(2, "missiles", &"word ".repeat(80));
+        let id = &parts[0][1..3];
+        assert_eq!(parts.len(), 3);
+        for (index, part) in parts.iter().enumerate() {
+            assert!(part.starts_with(&format!("[{} {}/3] word", id, index + 1)));
+            assert!(part.len() <= WHOLE_BYTES);
+        }
+        // The next split message gets the next ID
+        let next = store.split(2, "missiles", &"word ".repeat(80));
+        assert_ne!(&next[0][1..3], id);
+    }
+
+    #[test]
+    fn short_messages_pass_through() {
+        let mut store = PartStore::new();
+        let fits = "a".repeat(WHOLE_BYTES);
+        assert_eq!(store.split(2, "missiles", &fits), vec![fits]);
+        assert!(store.recent.is_empty());
+        assert_eq!(store.split(2, "missiles", &"a".repeat(WHOLE_BYTES + 1)).len(), 2);
+    }
+
+    #[test]
+    fn looks_up_parts_for_retransmission() {
+        let mut store = PartStore::new();
+        let parts = store.split(5, "missiles", &"word ".repeat(80));
+        let id = parts[0][1..3].to_uppercase();
+
+        let (channel, category, all) = store.lookup(&id, &[]).unwrap();
+        assert_eq!((channel, category.as_str(), all), (5, "missiles", parts.clone()));
+        assert_eq!(store.lookup(&id, &[3, 1]).unwrap().2, vec![parts[2].clone(), parts[0].clone()]);
+        assert!(store.lookup(&id, &[0]).is_err());
+        assert!(store.lookup(&id, &[4]).is_err());
+        assert!(store.lookup("!!", &[]).is_err());
+    }
+}
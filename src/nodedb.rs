@@ -0,0 +1,413 @@
+use crate::device::{run_cli, Device};
+use crate::ratelimit::{ChannelLoad, SharedChannelLoad};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::PoisonError;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::sleep;
+
+// Marker printed by `meshtastic --info` right before the node DB JSON
+const NODES_MARKER: &str = "Nodes in mesh:";
+
+// Marker printed by `meshtastic --info` right before the local node's info JSON
+const MY_INFO_MARKER: &str = "My info:";
+
+// User section of a node DB entry
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeUser {
+    #[serde(default)]
+    pub long_name: String,
+    #[serde(default)]
+    pub short_name: String,
+}
+
+// Telemetry a node reports about itself
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceMetrics {
+    pub channel_utilization: Option<f64>,
+    pub air_util_tx: Option<f64>,
+    // Percent, over 100 when powered externally
+    pub battery_level: Option<u32>,
+    pub voltage: Option<f64>,
+}
+
+// Last position a node reported, as decoded by the meshtastic CLI
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodePosition {
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<i32>,
+}
+
+// Single node DB entry as printed by the meshtastic CLI
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfo {
+    #[serde(skip_deserializing)]
+    pub id: String,
+    pub num: Option<u32>,
+    #[serde(default)]
+    pub user: NodeUser,
+    pub last_heard: Option<u64>,
+    // SNR of the last packet heard directly from the node
+    pub snr: Option<f64>,
+    pub hops_away: Option<u32>,
+    #[serde(default)]
+    pub device_metrics: DeviceMetrics,
+    #[serde(default)]
+    pub position: NodePosition,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MyInfo {
+    my_node_num: u32,
+}
+
+// Normalize a node ID so "!A1B2C3D4", "a1b2c3d4" and "!a1b2c3d4" compare equal
+pub fn normalize_node_id(id: &str) -> String {
+    format!("!{}", id.trim().trim_start_matches('!').to_lowercase())
+}
+
+// Run `meshtastic --info` and return its stdout
+pub fn run_info(device: &Device) -> Result<String, String> {
+    let mut cmd = Command::new("meshtastic");
+    device.apply(&mut cmd);
+    cmd.arg("--info");
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::null());
+
+    let output = run_cli(&mut cmd).map_err(|e| format!("Failed to execute meshtastic --info: {}", e))?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// Turn on a remote node's external notification module (buzzer, strobe) for
+// messages carrying the bell character, over remote admin
+pub fn enable_alert_bell(device: &Device, node: &str) -> Result<(), String> {
+    let node = normalize_node_id(node);
+    let mut cmd = Command::new("meshtastic");
+    device.apply(&mut cmd);
+    cmd.arg("--dest").arg(&node);
+    for setting in [
+        "external_notification.enabled",
+        "external_notification.alert_bell",
+        "external_notification.alert_bell_buzzer",
+    ] {
+        cmd.arg("--set").arg(setting).arg("true");
+    }
+    cmd.stdout(Stdio::null());
+    cmd.stderr(Stdio::piped());
+
+    let output = run_cli(&mut cmd).map_err(|e| format!("Failed to execute meshtastic --set for {}: {}", node, e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "meshtastic --set for {} failed: {}",
+            node,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+// Set the attached node's clock to the host's time
+pub fn set_time(device: &Device) -> Result<(), String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut cmd = Command::new("meshtastic");
+    device.apply(&mut cmd);
+    cmd.arg("--set-time").arg(now.to_string());
+    cmd.stdout(Stdio::null());
+    cmd.stderr(Stdio::piped());
+
+    let output = run_cli(&mut cmd).map_err(|e| format!("Failed to execute meshtastic --set-time: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "meshtastic --set-time failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+// Push the host's time to the attached node now and then at a fixed interval,
+// so alerts aren't stamped or ordered by a drifting or unset clock
+pub async fn sync_time(device: Device, every: Duration) {
+    loop {
+        let result = {
+            let device = device.clone();
+            tokio::task::spawn_blocking(move || set_time(&device))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|r| r)
+        };
+
+        match result {
+            Ok(()) => log::debug!("Set the node's clock to the host's time"),
+            Err(e) => log::warn!("Failed to set the node's clock: {}", e),
+        }
+
+        sleep(every).await;
+    }
+}
+
+// Fixed position of the gateway node: latitude, longitude and optional altitude in meters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<i32>,
+}
+
+// Parse "LAT,LON" or "LAT,LON,ALT", e.g. "32.0853,34.7818,40"
+pub fn parse_position(value: &str) -> Result<FixedPosition, String> {
+    let fields: Vec<&str> = value.split(',').map(str::trim).collect();
+    if !(2..=3).contains(&fields.len()) {
+        return Err(format!("Expected LAT,LON or LAT,LON,ALT, got {}", value));
+    }
+    let latitude: f64 = fields[0]
+        .parse()
+        .map_err(|_| format!("Invalid latitude: {}", fields[0]))?;
+    let longitude: f64 = fields[1]
+        .parse()
+        .map_err(|_| format!("Invalid longitude: {}", fields[1]))?;
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return Err(format!("Position out of range: {}", value));
+    }
+    let altitude = fields
+        .get(2)
+        .map(|alt| alt.parse().map_err(|_| format!("Invalid altitude: {}", alt)))
+        .transpose()?;
+    Ok(FixedPosition {
+        latitude,
+        longitude,
+        altitude,
+    })
+}
+
+// What the gateway node announces about itself to the mesh
+#[derive(Debug, Clone, Default)]
+pub struct NodeIdentity {
+    pub long_name: Option<String>,
+    pub short_name: Option<String>,
+    pub position: Option<FixedPosition>,
+}
+
+impl NodeIdentity {
+    pub fn is_empty(&self) -> bool {
+        self.long_name.is_none() && self.short_name.is_none() && self.position.is_none()
+    }
+}
+
+// Set the attached node's owner names and fixed position; the node broadcasts
+// its node info and position when they change
+pub fn set_identity(device: &Device, identity: &NodeIdentity) -> Result<(), String> {
+    let mut cmd = Command::new("meshtastic");
+    device.apply(&mut cmd);
+    if let Some(name) = &identity.long_name {
+        cmd.arg("--set-owner").arg(name);
+    }
+    if let Some(name) = &identity.short_name {
+        cmd.arg("--set-owner-short").arg(name);
+    }
+    if let Some(position) = &identity.position {
+        cmd.arg("--setlat").arg(position.latitude.to_string());
+        cmd.arg("--setlon").arg(position.longitude.to_string());
+        if let Some(altitude) = position.altitude {
+            cmd.arg("--setalt").arg(altitude.to_string());
+        }
+    }
+    cmd.stdout(Stdio::null());
+    cmd.stderr(Stdio::piped());
+
+    let output = run_cli(&mut cmd).map_err(|e| format!("Failed to execute meshtastic --set-owner: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "meshtastic --set-owner failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+// Apply the gateway node's names and position now and then at a fixed interval,
+// so they survive a factory reset or an edit from a phone app
+pub async fn refresh_identity(device: Device, identity: NodeIdentity, every: Duration) {
+    loop {
+        let result = {
+            let (device, identity) = (device.clone(), identity.clone());
+            tokio::task::spawn_blocking(move || set_identity(&device, &identity))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|r| r)
+        };
+
+        match result {
+            Ok(()) => log::debug!("Refreshed the node's names and position"),
+            Err(e) => log::warn!("Failed to set the node's names and position: {}", e),
+        }
+
+        sleep(every).await;
+    }
+}
+
+// Extract the node DB from `meshtastic --info` output
+pub fn parse_node_db(info: &str) -> Result<Vec<NodeInfo>, String> {
+    let start = info
+        .find(NODES_MARKER)
+        .ok_or("Node DB not found in meshtastic --info output")?;
+    let rest = &info[start + NODES_MARKER.len()..];
+
+    // Only the first JSON value after the marker belongs to the node DB
+    let mut stream = serde_json::Deserializer::from_str(rest).into_iter::<HashMap<String, NodeInfo>>();
+    let nodes = stream
+        .next()
+        .ok_or("Node DB in meshtastic --info output was empty")?
+        .map_err(|e| format!("Failed to parse node DB: {}", e))?;
+
+    let mut nodes: Vec<NodeInfo> = nodes
+        .into_iter()
+        .map(|(id, mut node)| {
+            node.id = normalize_node_id(&id);
+            node
+        })
+        .collect();
+    nodes.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(nodes)
+}
+
+// Read the node DB of the attached radio
+pub fn read_node_db(device: &Device) -> Result<Vec<NodeInfo>, String> {
+    parse_node_db(&run_info(device)?)
+}
+
+// Node number of the attached radio from `meshtastic --info` output
+fn parse_my_node_num(info: &str) -> Result<u32, String> {
+    let start = info
+        .find(MY_INFO_MARKER)
+        .ok_or("Local node info not found in meshtastic --info output")?;
+    let mut stream = serde_json::Deserializer::from_str(&info[start + MY_INFO_MARKER.len()..]).into_iter::<MyInfo>();
+    stream
+        .next()
+        .ok_or("Local node info in meshtastic --info output was empty")?
+        .map(|my_info| my_info.my_node_num)
+        .map_err(|e| format!("Failed to parse local node info: {}", e))
+}
+
+// Channel utilization and own airtime the attached radio reports for itself
+pub fn read_channel_load(device: &Device) -> Result<ChannelLoad, String> {
+    let info = run_info(device)?;
+    let my_node_num = parse_my_node_num(&info)?;
+    let nodes = parse_node_db(&info)?;
+    let metrics = nodes
+        .iter()
+        .find(|node| node.num == Some(my_node_num) || node.id == format!("!{:08x}", my_node_num))
+        .map(|node| &node.device_metrics)
+        .ok_or("The attached node is missing from its own node DB")?;
+
+    Ok(ChannelLoad {
+        channel_utilization: metrics
+            .channel_utilization
+            .ok_or("The attached node does not report channel utilization")?,
+        air_util_tx: metrics.air_util_tx.unwrap_or_default(),
+        measured_at: Instant::now(),
+    })
+}
+
+// Periodically read the channel load of the attached radio for the airtime throttle
+pub async fn monitor_channel_load(device: Device, load: SharedChannelLoad, check_every: Duration) {
+    loop {
+        let reading = {
+            let device = device.clone();
+            tokio::task::spawn_blocking(move || read_channel_load(&device))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|r| r)
+        };
+
+        match reading {
+            Ok(reading) => {
+                log::debug!(
+                    "Channel utilization {:.1}%, own airtime {:.1}%",
+                    reading.channel_utilization,
+                    reading.air_util_tx
+                );
+                *load.lock().unwrap_or_else(PoisonError::into_inner) = Some(reading);
+            }
+            Err(e) => log::warn!("Failed to read channel utilization: {}", e),
+        }
+
+        sleep(check_every).await;
+    }
+}
+
+// Periodically check that every critical repeater has been heard recently
+pub async fn monitor_repeaters(device: Device, repeaters: Vec<String>, timeout: Duration, check_every: Duration) {
+    let repeaters: Vec<String> = repeaters.iter().map(|id| normalize_node_id(id)).collect();
+    // Repeaters we already warned about, so the warning isn't repeated every check
+    let mut silent: Vec<String> = Vec::new();
+
+    log::info!(
+        "Monitoring {} repeater(s), warning after {:?} without contact",
+        repeaters.len(),
+        timeout
+    );
+
+    loop {
+        let device = device.clone();
+        let nodes = tokio::task::spawn_blocking(move || read_node_db(&device))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r);
+
+        match nodes {
+            Ok(nodes) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+
+                for repeater in &repeaters {
+                    let node = nodes.iter().find(|n| &n.id == repeater);
+                    let last_heard = node.and_then(|n| n.last_heard);
+                    let overdue = match last_heard {
+                        Some(heard) => now.saturating_sub(heard) > timeout.as_secs(),
+                        None => true,
+                    };
+                    let name = node.map(|n| n.user.long_name.as_str()).unwrap_or("unknown");
+
+                    if overdue && !silent.contains(repeater) {
+                        match last_heard {
+                            Some(heard) => log::warn!(
+                                "Repeater {} ({}) has not been heard for {} minutes; its zone may not be receiving alerts",
+                                repeater,
+                                name,
+                                now.saturating_sub(heard) / 60
+                            ),
+                            None => log::warn!(
+                                "Repeater {} ({}) has never been heard by the gateway node; its zone may not be receiving alerts",
+                                repeater,
+                                name
+                            ),
+                        }
+                        silent.push(repeater.clone());
+                    } else if !overdue && silent.contains(repeater) {
+                        log::info!("Repeater {} ({}) is being heard again", repeater, name);
+                        silent.retain(|id| id != repeater);
+                    }
+                }
+            }
+            Err(e) => log::error!("Failed to read node DB for repeater monitoring: {}", e),
+        }
+
+        sleep(check_every).await;
+    }
+}
@@ -0,0 +1,123 @@
+use crate::device::Device;
+use crate::nodedb::{read_node_db, NodeInfo};
+use chrono::Utc;
+use clap::Args;
+use serde_json::json;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::sleep;
+
+#[derive(Args, Debug)]
+pub struct NodesArgs {
+    /// Print the node DB as JSON instead of a table
+    #[arg(long)]
+    pub json: bool,
+}
+
+// How long ago a node was last heard, e.g. "5m", "3h" or "2d"
+fn heard_ago(now: u64, last_heard: Option<u64>) -> String {
+    let Some(heard) = last_heard.filter(|heard| *heard > 0) else {
+        return "never".to_string();
+    };
+    match now.saturating_sub(heard) {
+        secs if secs < 3600 => format!("{}m", secs / 60),
+        secs if secs < 86400 => format!("{}h", secs / 3600),
+        secs => format!("{}d", secs / 86400),
+    }
+}
+
+fn node_row(node: &NodeInfo, now: u64) -> [String; 7] {
+    let snr = node.snr.map(|snr| format!("{:.2}", snr)).unwrap_or_else(|| "-".to_string());
+    let hops = node.hops_away.map(|hops| hops.to_string()).unwrap_or_else(|| "-".to_string());
+    let battery = match node.device_metrics.battery_level {
+        Some(level) if level > 100 => "powered".to_string(),
+        Some(level) => format!("{}%", level),
+        None => "-".to_string(),
+    };
+    let position = match (node.position.latitude, node.position.longitude) {
+        (Some(latitude), Some(longitude)) => format!("{:.4},{:.4}", latitude, longitude),
+        _ => "-".to_string(),
+    };
+    [
+        node.id.clone(),
+        node.user.long_name.clone(),
+        heard_ago(now, node.last_heard),
+        snr,
+        hops,
+        battery,
+        position,
+    ]
+}
+
+// Print the attached radio's node DB, most recently heard first
+pub fn run(args: &NodesArgs, device: &Device) -> Result<(), String> {
+    let mut nodes = read_node_db(device)?;
+    nodes.sort_by_key(|node| std::cmp::Reverse(node.last_heard.unwrap_or_default()));
+
+    if args.json {
+        let json = serde_json::to_string_pretty(&nodes).map_err(|e| e.to_string())?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let header = ["ID", "NAME", "HEARD", "SNR", "HOPS", "BATTERY", "POSITION"].map(String::from);
+    let rows: Vec<[String; 7]> = std::iter::once(header)
+        .chain(nodes.iter().map(|node| node_row(node, now)))
+        .collect();
+    let mut widths = [0; 7];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in &rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+    Ok(())
+}
+
+// Append the node DB to a JSON lines file, one snapshot per line
+fn append_snapshot(path: &str, nodes: &[NodeInfo]) -> Result<(), String> {
+    let line = json!({ "time": Utc::now().to_rfc3339(), "nodes": nodes });
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    writeln!(file, "{}", line).map_err(|e| format!("Failed to write to {}: {}", path, e))
+}
+
+// Snapshot the attached radio's node DB at a fixed interval, so changes in
+// coverage (nodes lost, weaker signals, flat batteries) can be analyzed later
+pub async fn record_snapshots(device: Device, path: String, every: Duration) {
+    log::info!("Writing a node DB snapshot to {} every {:?}", path, every);
+    loop {
+        let result = {
+            let (device, path) = (device.clone(), path.clone());
+            tokio::task::spawn_blocking(move || {
+                let nodes = read_node_db(&device)?;
+                append_snapshot(&path, &nodes).map(|_| nodes.len())
+            })
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r)
+        };
+
+        match result {
+            Ok(count) => log::debug!("Wrote a snapshot of {} node(s) to {}", count, path),
+            Err(e) => log::warn!("Failed to snapshot the node DB: {}", e),
+        }
+
+        sleep(every).await;
+    }
+}
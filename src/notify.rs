@@ -0,0 +1,151 @@
+use crate::localtime;
+use crate::outbox::{Delivery, DeliveryFuture, OutboxItem};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+// A zone an alert went to, by name, with the alerted cities in it
+#[derive(Debug, Clone)]
+pub struct NotifiedZone {
+    pub name: String,
+    pub cities: Vec<String>,
+}
+
+// An alert as the chat and social outputs see it, once it went out on the mesh
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub category: String,
+    // Official time of the alert, if the source gave one
+    pub alert_date: Option<DateTime<Utc>>,
+    pub zones: Vec<NotifiedZone>,
+    pub instructions: Option<String>,
+    // When the alert stops being in effect; a notification not delivered by then is dropped
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+impl Notification {
+    // e.g. "🚨missiles 2026-10-16 14:02:33"
+    pub fn headline(&self) -> String {
+        let icon = if self.category.to_lowercase().contains("earthquake") { "🌍" } else { "🚨" };
+        match self.alert_date {
+            Some(date) => format!("{}{} {}", icon, self.category, localtime::to_local(date).format("%Y-%m-%d %H:%M:%S")),
+            None => format!("{}{}", icon, self.category),
+        }
+    }
+
+    // Plain text for outputs without formatting: the headline, one line per zone with
+    // its cities, and the instructions
+    pub fn text(&self) -> String {
+        let mut text = self.headline();
+        for zone in &self.zones {
+            if zone.cities.is_empty() {
+                text.push_str(&format!("\n{}", zone.name));
+            } else {
+                text.push_str(&format!("\n{}: {}", zone.name, zone.cities.join(", ")));
+            }
+        }
+        if let Some(instructions) = &self.instructions {
+            text.push_str(&format!("\n{}", instructions));
+        }
+        text
+    }
+}
+
+// An output that tells people off the mesh about alerts. notify is called from the
+// alert loop, so it must never wait: network outputs queue on an outbox.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, notification: &Notification);
+}
+
+// Checks the body of a 2xx answer, for APIs whose answer says what was done
+pub type AnswerCheck = fn(&str) -> Result<(), String>;
+
+// How an endpoint authenticates requests
+#[derive(Debug, Clone)]
+pub enum Auth {
+    // User and password
+    Basic(String, Option<String>),
+    Bearer(String),
+}
+
+// Sends queued JSON bodies to the URL each was queued for; the delivery of all the
+// chat and social outputs, which differ only in how they build the URL and body.
+// Anything but a 2xx answer means the notification is tried again.
+pub struct JsonPost {
+    client: reqwest::Client,
+    // Who answers, as errors name it, e.g. "the homeserver"
+    service: &'static str,
+    method: reqwest::Method,
+    auth: Option<Auth>,
+    // Whether requests carry a key the server uses to ignore a retry of one it already took
+    idempotency_key: bool,
+    check: Option<AnswerCheck>,
+}
+
+impl JsonPost {
+    pub fn new(service: &'static str, auth: Option<Auth>) -> Result<Self, String> {
+        Ok(JsonPost {
+            client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(30))
+                .build()
+                .map_err(|e| e.to_string())?,
+            service,
+            method: reqwest::Method::POST,
+            auth,
+            idempotency_key: false,
+            check: None,
+        })
+    }
+
+    // PUT instead of POST, for APIs whose URL names the message
+    pub fn put(mut self) -> Self {
+        self.method = reqwest::Method::PUT;
+        self
+    }
+
+    pub fn with_idempotency_key(mut self) -> Self {
+        self.idempotency_key = true;
+        self
+    }
+
+    pub fn with_check(mut self, check: AnswerCheck) -> Self {
+        self.check = Some(check);
+        self
+    }
+}
+
+impl Delivery for JsonPost {
+    fn deliver<'a>(&'a mut self, item: &'a OutboxItem) -> DeliveryFuture<'a> {
+        Box::pin(async move {
+            let mut request = self
+                .client
+                .request(self.method.clone(), &item.target)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(item.body.clone());
+            request = match &self.auth {
+                Some(Auth::Basic(user, password)) => request.basic_auth(user, password.as_deref()),
+                Some(Auth::Bearer(token)) => request.bearer_auth(token),
+                None => request,
+            };
+            if self.idempotency_key {
+                // The same for every attempt at an item
+                let key = Sha256::digest(format!("{}{}", item.queued_at.to_rfc3339(), item.body));
+                let key: String = key.iter().map(|byte| format!("{:02x}", byte)).collect();
+                request = request.header("Idempotency-Key", key);
+            }
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            let status = response.status();
+            if status.is_success() {
+                return match self.check {
+                    Some(check) => check(&response.text().await.map_err(|e| e.to_string())?),
+                    None => Ok(()),
+                };
+            }
+            let error = response.text().await.unwrap_or_default();
+            if error.trim().is_empty() {
+                Err(format!("{} answered {}", self.service, status))
+            } else {
+                Err(format!("{} answered {}: {}", self.service, status, error.trim()))
+            }
+        })
+    }
+}
@@ -0,0 +1,425 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::Write;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+
+// Wait after the first failed delivery, doubled after every further failure
+const BASE_BACKOFF: Duration = Duration::from_secs(2);
+
+// Longest wait between two delivery attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+// Lines appended for delivered or retried notifications before the queue file is
+// rewritten with only the pending ones
+const COMPACT_AFTER: usize = 256;
+
+pub type DeliveryFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+// A notification waiting to be delivered to an output off the mesh
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxItem {
+    // Where it goes, e.g. an MQTT topic or an endpoint URL
+    pub target: String,
+    pub body: String,
+    #[serde(default)]
+    pub retain: bool,
+    pub queued_at: DateTime<Utc>,
+    #[serde(default)]
+    pub attempts: u32,
+    // After this it is dropped instead of delivered, e.g. an alert no longer in effect
+    #[serde(default)]
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+impl OutboxItem {
+    fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.valid_until.is_some_and(|valid_until| valid_until < now)
+    }
+}
+
+// What became of the oldest notification in a queue
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FrontChange {
+    Failed,
+    Removed,
+}
+
+// A line of a queue file: a notification queued, or a change to the oldest one.
+// Only these are appended, so queuing and delivering never rewrite the file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum QueueLine {
+    Queued(OutboxItem),
+    Front { front: FrontChange },
+}
+
+// How an output hands over one notification; an error means it should be tried again later
+pub trait Delivery: Send + 'static {
+    fn deliver<'a>(&'a mut self, item: &'a OutboxItem) -> DeliveryFuture<'a>;
+}
+
+// Where outboxes keep their queues and when they give up on a notification
+#[derive(Debug, Clone)]
+pub struct OutboxSettings {
+    // Directory for the queue and dead-letter files
+    pub dir: PathBuf,
+    pub max_attempts: u32,
+}
+
+// A queue of notifications for one output, delivered in order by a background task
+// with exponential backoff, so an unreachable endpoint neither loses notifications
+// nor holds up the mesh. Pending notifications survive a restart; those that still
+// fail after the last attempt are written to a dead-letter file and dropped.
+pub struct Outbox {
+    tx: mpsc::UnboundedSender<OutboxItem>,
+}
+
+impl Outbox {
+    pub fn start(name: &'static str, settings: &OutboxSettings, delivery: impl Delivery) -> Result<Self, String> {
+        let dir = &settings.dir;
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create outbox directory {}: {}", dir.display(), e))?;
+        let files = QueueFiles {
+            queue: dir.join(format!("{}.jsonl", name)),
+            dead: dir.join(format!("{}.dead.jsonl", name)),
+        };
+        let mut queue = files.load()?;
+        let left = queue.len();
+        let now = Utc::now();
+        queue.retain(|item| !item.expired(now));
+        if left > queue.len() {
+            log::info!("{} outbox: dropped {} notification(s) from the last run that are no longer valid", name, left - queue.len());
+        }
+        if !queue.is_empty() {
+            log::info!("{} outbox: {} notification(s) left from the last run", name, queue.len());
+        }
+        files.save(&queue)?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let worker = Worker {
+            name,
+            files,
+            queue,
+            appended: 0,
+            max_attempts: settings.max_attempts.max(1),
+        };
+        tokio::spawn(worker.run(rx, delivery));
+        Ok(Outbox { tx })
+    }
+
+    // Queue a notification, to be dropped if still undelivered after valid_until;
+    // never waits for the output
+    pub fn push(&self, target: &str, body: String, retain: bool, valid_until: Option<DateTime<Utc>>) {
+        let item = OutboxItem {
+            target: target.to_string(),
+            body,
+            retain,
+            queued_at: Utc::now(),
+            attempts: 0,
+            valid_until,
+        };
+        if self.tx.send(item).is_err() {
+            log::error!("Outbox for {} has stopped; dropping a notification", target);
+        }
+    }
+}
+
+// The pending queue of an outbox and its dead letters
+struct QueueFiles {
+    queue: PathBuf,
+    dead: PathBuf,
+}
+
+impl QueueFiles {
+    // Replay the queue file into the pending notifications
+    fn load(&self) -> Result<VecDeque<OutboxItem>, String> {
+        let text = match std::fs::read_to_string(&self.queue) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(VecDeque::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.queue.display(), e)),
+        };
+        let mut queue = VecDeque::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let line = serde_json::from_str(line).map_err(|e| format!("Invalid entry in {}: {}", self.queue.display(), e))?;
+            match line {
+                QueueLine::Queued(item) => queue.push_back(item),
+                QueueLine::Front { front: FrontChange::Failed } => {
+                    if let Some(item) = queue.front_mut() {
+                        item.attempts += 1;
+                    }
+                }
+                QueueLine::Front { front: FrontChange::Removed } => {
+                    queue.pop_front();
+                }
+            }
+        }
+        Ok(queue)
+    }
+
+    fn append(&self, line: &QueueLine) -> Result<(), String> {
+        let line = serde_json::to_string(line).map_err(|e| e.to_string())?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.queue)
+            .map_err(|e| format!("Failed to open {}: {}", self.queue.display(), e))?;
+        writeln!(file, "{}", line).map_err(|e| format!("Failed to write to {}: {}", self.queue.display(), e))
+    }
+
+    // Rewrite the queue file with only the pending notifications, under a temporary
+    // name first so a crash never leaves half of it
+    fn save(&self, queue: &VecDeque<OutboxItem>) -> Result<(), String> {
+        let mut text = String::new();
+        for item in queue {
+            text.push_str(&serde_json::to_string(item).map_err(|e| e.to_string())?);
+            text.push('\n');
+        }
+        let tmp = self.queue.with_extension("jsonl.tmp");
+        std::fs::write(&tmp, text)
+            .and_then(|_| std::fs::rename(&tmp, &self.queue))
+            .map_err(|e| format!("Failed to write {}: {}", self.queue.display(), e))
+    }
+
+    fn dead_letter(&self, item: &OutboxItem, error: &str) -> Result<(), String> {
+        let line = serde_json::json!({ "failed_at": Utc::now().to_rfc3339(), "error": error, "item": item });
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.dead)
+            .map_err(|e| format!("Failed to open {}: {}", self.dead.display(), e))?;
+        writeln!(file, "{}", line).map_err(|e| format!("Failed to write to {}: {}", self.dead.display(), e))
+    }
+}
+
+struct Worker {
+    name: &'static str,
+    files: QueueFiles,
+    queue: VecDeque<OutboxItem>,
+    // Lines appended since the queue file was last rewritten
+    appended: usize,
+    max_attempts: u32,
+}
+
+impl Worker {
+    // Append a line to the queue file, rewriting it instead once it has grown long
+    // or nothing is pending
+    fn record(&mut self, line: QueueLine) {
+        self.appended += 1;
+        let result = if self.queue.is_empty() || self.appended >= COMPACT_AFTER {
+            self.appended = 0;
+            self.files.save(&self.queue)
+        } else {
+            self.files.append(&line)
+        };
+        if let Err(e) = result {
+            log::error!("{} outbox: {}", self.name, e);
+        }
+    }
+
+    fn enqueue(&mut self, item: OutboxItem) {
+        let line = QueueLine::Queued(item.clone());
+        self.queue.push_back(item);
+        self.record(line);
+    }
+
+    fn remove_front(&mut self) -> Option<OutboxItem> {
+        let item = self.queue.pop_front();
+        self.record(QueueLine::Front { front: FrontChange::Removed });
+        item
+    }
+
+    async fn run(mut self, mut rx: mpsc::UnboundedReceiver<OutboxItem>, mut delivery: impl Delivery) {
+        let mut open = true;
+        loop {
+            let Some(item) = self.queue.front() else {
+                match rx.recv().await {
+                    Some(item) => self.enqueue(item),
+                    None => return,
+                }
+                continue;
+            };
+
+            if item.expired(Utc::now()) {
+                log::warn!("{} outbox: dropping a notification for {} that is no longer valid", self.name, item.target);
+                self.remove_front();
+                continue;
+            }
+
+            let error = match delivery.deliver(item).await {
+                Ok(()) => {
+                    self.remove_front();
+                    continue;
+                }
+                Err(e) => e,
+            };
+
+            let attempts = self.queue.front().map_or(0, |item| item.attempts + 1);
+            if attempts >= self.max_attempts {
+                let Some(mut item) = self.remove_front() else {
+                    continue;
+                };
+                item.attempts = attempts;
+                log::error!(
+                    "{} outbox: giving up on a notification for {} after {} attempts: {}",
+                    self.name,
+                    item.target,
+                    attempts,
+                    error
+                );
+                if let Err(e) = self.files.dead_letter(&item, &error) {
+                    log::error!("{} outbox: {}", self.name, e);
+                }
+                continue;
+            }
+
+            let backoff = BASE_BACKOFF
+                .saturating_mul(2u32.saturating_pow(attempts - 1))
+                .min(MAX_BACKOFF);
+            let waiting = self.queue.len();
+            if let Some(item) = self.queue.front_mut() {
+                item.attempts = attempts;
+                log::warn!(
+                    "{} outbox: delivery to {} failed ({}); retrying in {}s, {} notification(s) waiting",
+                    self.name,
+                    item.target,
+                    error,
+                    backoff.as_secs(),
+                    waiting
+                );
+            }
+            self.record(QueueLine::Front { front: FrontChange::Failed });
+
+            // Keep taking new notifications while waiting to retry
+            let retry_at = Instant::now() + backoff;
+            loop {
+                tokio::select! {
+                    _ = sleep_until(retry_at) => break,
+                    item = rx.recv(), if open => match item {
+                        Some(item) => self.enqueue(item),
+                        None => open = false,
+                    },
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Delivers to a shared list, failing for targets starting with "down"
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Delivery for Recorder {
+        fn deliver<'a>(&'a mut self, item: &'a OutboxItem) -> DeliveryFuture<'a> {
+            Box::pin(async move {
+                if item.target.starts_with("down") {
+                    return Err("unreachable".to_string());
+                }
+                self.0.lock().unwrap().push(item.body.clone());
+                Ok(())
+            })
+        }
+    }
+
+    fn files(name: &str) -> QueueFiles {
+        let dir = std::env::temp_dir().join(format!("outbox-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = QueueFiles {
+            queue: dir.join("test.jsonl"),
+            dead: dir.join("test.dead.jsonl"),
+        };
+        let _ = std::fs::remove_file(&files.queue);
+        let _ = std::fs::remove_file(&files.dead);
+        files
+    }
+
+    fn item(target: &str, body: &str, valid_until: Option<DateTime<Utc>>) -> OutboxItem {
+        OutboxItem {
+            target: target.to_string(),
+            body: body.to_string(),
+            retain: false,
+            queued_at: Utc::now(),
+            attempts: 0,
+            valid_until,
+        }
+    }
+
+    fn worker(files: QueueFiles, max_attempts: u32) -> Worker {
+        Worker {
+            name: "test",
+            files,
+            queue: VecDeque::new(),
+            appended: 0,
+            max_attempts,
+        }
+    }
+
+    #[test]
+    fn appended_lines_replay_into_the_queue() {
+        let mut worker = worker(files("replay"), 5);
+        worker.enqueue(item("a", "1", None));
+        worker.enqueue(item("a", "2", None));
+        worker.enqueue(item("a", "3", None));
+        worker.remove_front();
+        worker.record(QueueLine::Front { front: FrontChange::Failed });
+        assert_eq!(std::fs::read_to_string(&worker.files.queue).unwrap().lines().count(), 5);
+
+        let queue = worker.files.load().unwrap();
+        let bodies: Vec<(&str, u32)> = queue.iter().map(|item| (item.body.as_str(), item.attempts)).collect();
+        assert_eq!(bodies, vec![("2", 1), ("3", 0)]);
+    }
+
+    #[test]
+    fn file_is_rewritten_when_empty_or_long() {
+        let mut worker = worker(files("compact"), 5);
+        worker.enqueue(item("a", "1", None));
+        worker.remove_front();
+        assert_eq!(std::fs::read_to_string(&worker.files.queue).unwrap(), "");
+
+        for n in 0..10 {
+            worker.enqueue(item("a", &n.to_string(), None));
+        }
+        for _ in 0..5 {
+            worker.remove_front();
+        }
+        assert_eq!(std::fs::read_to_string(&worker.files.queue).unwrap().lines().count(), 15);
+
+        // The line reaching the limit rewrites the file with only the pending ones
+        worker.appended = COMPACT_AFTER - 1;
+        worker.enqueue(item("a", "10", None));
+        assert_eq!(std::fs::read_to_string(&worker.files.queue).unwrap().lines().count(), 6);
+        assert_eq!(worker.files.load().unwrap().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn drains_in_order_dropping_expired_and_dead_letters() {
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let files = files("drain");
+        let (queue, dead) = (files.queue.clone(), files.dead.clone());
+        let worker = worker(files, 1);
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        tx.send(item("up", "first", None)).unwrap();
+        tx.send(item("up", "stale", Some(Utc::now() - chrono::Duration::seconds(1)))).unwrap();
+        tx.send(item("down", "lost", None)).unwrap();
+        tx.send(item("up", "last", Some(Utc::now() + chrono::Duration::minutes(10)))).unwrap();
+        drop(tx);
+        worker.run(rx, Recorder(delivered.clone())).await;
+
+        assert_eq!(*delivered.lock().unwrap(), vec!["first", "last"]);
+        assert_eq!(std::fs::read_to_string(&queue).unwrap(), "");
+        let dead = std::fs::read_to_string(&dead).unwrap();
+        assert_eq!(dead.lines().count(), 1);
+        assert!(dead.contains("\"lost\"") && dead.contains("unreachable"));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::meshmqtt::{format_node_id, InboundText, BROADCAST_ADDR};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+// How long a message heard from a peer suppresses our own copy
+const HEARD_WINDOW: Duration = Duration::from_secs(120);
+
+// Most peer messages remembered
+const MAX_HEARD: usize = 200;
+
+// Alert text without what differs between gateways: the "#N " sequence number,
+// the "[a3 1/2] " part tag and the " ~1a2b3c4d" signature
+fn normalize(text: &str) -> &str {
+    let mut text = text.trim();
+    if let Some((number, rest)) = text.strip_prefix('#').and_then(|rest| rest.split_once(' ')) {
+        if number.chars().all(|c| c.is_ascii_digit()) {
+            text = rest;
+        }
+    }
+    if let Some((tag, rest)) = text.strip_prefix('[').and_then(|rest| rest.split_once("] ")) {
+        if tag.contains('/') && tag.len() <= 10 {
+            text = rest;
+        }
+    }
+    if let Some((message, signature)) = text.rsplit_once(" ~") {
+        if signature.len() == 8 && signature.chars().all(|c| c.is_ascii_hexdigit()) {
+            text = message;
+        }
+    }
+    text
+}
+
+// Other gateways serving overlapping meshes; an alert one of them already
+// broadcast on a channel isn't sent again. The gateway with the lowest node
+// number sends right away, the others hold back in node number order.
+#[derive(Debug)]
+pub struct PeerGateways {
+    own: u32,
+    peers: Vec<u32>,
+    holdoff: Duration,
+    // Broadcasts heard from peers: when, channel, normalized text
+    heard: Mutex<VecDeque<(Instant, u32, String)>>,
+}
+
+pub type SharedPeers = Arc<PeerGateways>;
+
+impl PeerGateways {
+    pub fn new(own: u32, peers: Vec<u32>, holdoff: Duration) -> Self {
+        PeerGateways {
+            own,
+            peers,
+            holdoff,
+            heard: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // How long to wait before sending, so lower-numbered peers go first
+    pub fn holdoff(&self) -> Duration {
+        let rank = self.peers.iter().filter(|peer| **peer < self.own).count() as u32;
+        self.holdoff * rank
+    }
+
+    fn record(&self, channel: u32, text: &str) {
+        let mut heard = self.heard.lock().unwrap_or_else(PoisonError::into_inner);
+        if heard.len() == MAX_HEARD {
+            heard.pop_front();
+        }
+        heard.push_back((Instant::now(), channel, normalize(text).to_string()));
+    }
+
+    // Whether a peer already broadcast this text on the channel recently
+    pub fn already_sent(&self, channel: u32, text: &str) -> bool {
+        let text = normalize(text);
+        let mut heard = self.heard.lock().unwrap_or_else(PoisonError::into_inner);
+        heard.retain(|(at, _, _)| at.elapsed() < HEARD_WINDOW);
+        heard
+            .iter()
+            .any(|(_, heard_channel, heard_text)| *heard_channel == channel && heard_text == text)
+    }
+
+    // Peers and what was heard from them, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let heard = self.heard.lock().unwrap_or_else(PoisonError::into_inner);
+        json!({
+            "peers": self.peers.iter().map(|peer| format_node_id(*peer)).collect::<Vec<_>>(),
+            "holdoff_secs": self.holdoff().as_secs_f64(),
+            "heard": heard
+                .iter()
+                .map(|(at, channel, text)| json!({ "secs_ago": at.elapsed().as_secs(), "channel": channel, "text": text }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+// Take the peers' broadcasts out of the texts heard on the mesh, passing everything else on
+pub fn watch_peers(peers: SharedPeers, mut inbound: mpsc::Receiver<InboundText>) -> mpsc::Receiver<InboundText> {
+    let (tx, rx) = mpsc::channel(inbound.max_capacity());
+    tokio::spawn(async move {
+        while let Some(text) = inbound.recv().await {
+            if text.to == BROADCAST_ADDR && peers.peers.contains(&text.from) {
+                log::debug!("Heard {} on channel {}: {}", format_node_id(text.from), text.channel, text.text);
+                peers.record(text.channel, &text.text);
+                continue;
+            }
+            if tx.send(text).await.is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+// Home Front Command protective-action guidance for events where the one-line alert
+// leaves out what people must do, sent as a follow-up when turned on
+const BUILTIN_ACTIONS: [(&str, &str); 2] = [
+    (
+        "hazardousMaterials",
+        "Home Front Command: enter the nearest building, close the doors and windows, turn off air \
+         conditioning and ventilation, and stay tuned to the media for further instructions.",
+    ),
+    (
+        "radiologicalEvent",
+        "Home Front Command: enter the nearest building, close the doors and windows, turn off \
+         ventilation, and stay inside until instructed otherwise by the authorities.",
+    ),
+];
+
+// Parse a `--protective-action` value of the form CATEGORY=TEXT; an empty text turns it off
+pub fn parse_protective_action(value: &str) -> Result<(String, String), String> {
+    let (category, text) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected CATEGORY=TEXT, got {}", value))?;
+    Ok((category.trim().to_string(), text.trim().to_string()))
+}
+
+// Follow-up guidance per alert category: the built-in texts if turned on, with any overrides
+#[derive(Debug)]
+pub struct ProtectiveActions {
+    actions: HashMap<String, String>,
+}
+
+impl ProtectiveActions {
+    pub fn new(builtin: bool, overrides: &[(String, String)]) -> Self {
+        let mut actions: HashMap<String, String> = BUILTIN_ACTIONS
+            .iter()
+            .filter(|_| builtin)
+            .map(|(category, text)| (category.to_string(), text.to_string()))
+            .collect();
+        for (category, text) in overrides {
+            actions.insert(category.clone(), text.clone());
+        }
+        actions.retain(|_, text| !text.is_empty());
+        ProtectiveActions { actions }
+    }
+
+    // Guidance to follow an alert of the category with, if any
+    pub fn for_category(&self, category: &str) -> Option<&str> {
+        self.actions.get(category).map(String::as_str)
+    }
+}
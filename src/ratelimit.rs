@@ -0,0 +1,254 @@
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+// Default gap for categories without a specific setting (--min-send-gap)
+pub const DEFAULT_SEND_GAP: Duration = Duration::from_secs(10);
+
+// Built-in gaps: life-threatening categories go out immediately, low-priority ones are spaced out
+const BUILTIN_GAPS: [(&str, u64); 3] = [("missiles", 0), ("terroristInfiltration", 0), ("general", 30)];
+
+// Gap applied to every drill category unless configured otherwise
+const DRILL_GAP: Duration = Duration::from_secs(60);
+
+// Minimum time between consecutive transmissions, per alert category
+#[derive(Debug, Clone)]
+pub struct CategoryGaps {
+    default: Duration,
+    per_category: HashMap<String, Duration>,
+}
+
+impl CategoryGaps {
+    // Built-in gaps overridden by CATEGORY=SECONDS settings
+    pub fn new(default: Duration, overrides: &[(String, u64)]) -> Self {
+        let mut per_category: HashMap<String, Duration> = BUILTIN_GAPS
+            .iter()
+            .map(|(category, secs)| (category.to_string(), Duration::from_secs(*secs)))
+            .collect();
+        for (category, secs) in overrides {
+            per_category.insert(category.clone(), Duration::from_secs(*secs));
+        }
+
+        CategoryGaps {
+            default,
+            per_category,
+        }
+    }
+
+    // How long to wait after the previous transmission before sending this category
+    pub fn gap_for(&self, category: &str) -> Duration {
+        if let Some(gap) = self.per_category.get(category) {
+            return *gap;
+        }
+        if category.ends_with("Drill") {
+            return DRILL_GAP;
+        }
+        self.default
+    }
+}
+
+// Parse a `--category-gap` value of the form CATEGORY=SECONDS
+pub fn parse_category_gap(value: &str) -> Result<(String, u64), String> {
+    let (category, secs) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected CATEGORY=SECONDS, got {}", value))?;
+    let secs = secs
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid number of seconds in {}", value))?;
+    Ok((category.trim().to_string(), secs))
+}
+
+// Per-zone cooldown: a zone isn't re-alerted within the cooldown unless the
+// alert adds cities that the previous transmission didn't cover
+#[derive(Debug, Default)]
+pub struct ZoneCooldown {
+    cooldown: Duration,
+    // Last transmission per channel and the cities it covered
+    last_sent: HashMap<u32, (Instant, HashSet<String>)>,
+}
+
+impl ZoneCooldown {
+    pub fn new(cooldown: Duration) -> Self {
+        ZoneCooldown {
+            cooldown,
+            last_sent: HashMap::new(),
+        }
+    }
+
+    // Whether an alert covering these cities should be sent to the channel now. An
+    // alert targeting the channel directly names no cities to compare, so it is sent.
+    pub fn allows(&self, channel: u32, cities: &[String]) -> bool {
+        if cities.is_empty() {
+            return true;
+        }
+        match self.last_sent.get(&channel) {
+            Some((sent_at, covered)) if sent_at.elapsed() < self.cooldown => {
+                cities.iter().any(|city| !covered.contains(city))
+            }
+            _ => true,
+        }
+    }
+
+    // Remember a transmission; cities accumulate while the cooldown is running
+    pub fn record(&mut self, channel: u32, cities: &[String]) {
+        let cooldown = self.cooldown;
+        let entry = self
+            .last_sent
+            .entry(channel)
+            .or_insert_with(|| (Instant::now(), HashSet::new()));
+        if entry.0.elapsed() >= cooldown {
+            entry.1.clear();
+        }
+        entry.0 = Instant::now();
+        entry.1.extend(cities.iter().cloned());
+    }
+
+    // Running cooldowns per channel, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let mut channels: Vec<(&u32, &(Instant, HashSet<String>))> = self.last_sent.iter().collect();
+        channels.sort_by_key(|(channel, _)| **channel);
+        let channels: Vec<Value> = channels
+            .into_iter()
+            .map(|(channel, (sent_at, covered))| {
+                let mut cities: Vec<&String> = covered.iter().collect();
+                cities.sort();
+                json!({
+                    "channel": channel,
+                    "sent_secs_ago": sent_at.elapsed().as_secs(),
+                    "cooldown_remaining_secs": self.cooldown.saturating_sub(sent_at.elapsed()).as_secs(),
+                    "cities": cities,
+                })
+            })
+            .collect();
+        json!({ "cooldown_secs": self.cooldown.as_secs(), "channels": channels })
+    }
+}
+
+// Channel utilization (%) at which the firmware itself stops sending non-essential packets
+const FULL_CONGESTION_UTIL: f64 = 40.0;
+
+// Highest severity returned by `severity`
+const MAX_SEVERITY: u8 = 3;
+
+// How urgent a category is: life-threatening alerts first, drills and housekeeping last
+pub fn severity(category: &str) -> u8 {
+    match category {
+        "missiles" | "terroristInfiltration" | "hostileAircraftIntrusion" => 3,
+        "earthQuake" | "tsunami" | "radiologicalEvent" | "hazardousMaterials" => 2,
+        "general" => 1,
+        _ => 0,
+    }
+}
+
+// Load of the LoRa channel as last reported by the attached node
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelLoad {
+    // Percentage of time the channel was busy, all nodes included
+    pub channel_utilization: f64,
+    // Percentage of airtime used by the node's own transmissions
+    pub air_util_tx: f64,
+    pub measured_at: Instant,
+}
+
+pub type SharedChannelLoad = Arc<Mutex<Option<ChannelLoad>>>;
+
+// Stretches the gap between transmissions while the channel is congested; the
+// more severe the category, the less it is held back
+#[derive(Debug, Clone)]
+pub struct AirtimeThrottle {
+    load: SharedChannelLoad,
+    // Utilization above which gaps are stretched
+    threshold: f64,
+    // Extra gap for the least severe categories at full congestion
+    max_extra: Duration,
+    // Readings older than this are ignored
+    max_age: Duration,
+}
+
+impl AirtimeThrottle {
+    pub fn new(load: SharedChannelLoad, threshold: f64, max_extra: Duration, max_age: Duration) -> Self {
+        AirtimeThrottle {
+            load,
+            threshold,
+            max_extra,
+            max_age,
+        }
+    }
+
+    fn current_load(&self) -> Option<ChannelLoad> {
+        let load = (*self.load.lock().unwrap_or_else(PoisonError::into_inner))?;
+        (load.measured_at.elapsed() <= self.max_age).then_some(load)
+    }
+
+    // How congested the channel is, from 0 (at or below the threshold) to 1
+    pub fn congestion(&self) -> f64 {
+        let Some(load) = self.current_load() else {
+            return 0.0;
+        };
+        if load.channel_utilization <= self.threshold {
+            return 0.0;
+        }
+        if self.threshold >= FULL_CONGESTION_UTIL {
+            return 1.0;
+        }
+        ((load.channel_utilization - self.threshold) / (FULL_CONGESTION_UTIL - self.threshold)).clamp(0.0, 1.0)
+    }
+
+    // Time added to the category's gap because of congestion
+    pub fn extra_gap(&self, category: &str) -> Duration {
+        let holdback = f64::from(MAX_SEVERITY + 1 - severity(category)) / f64::from(MAX_SEVERITY + 1);
+        self.max_extra.mul_f64(self.congestion() * holdback)
+    }
+
+    // Last reading and the resulting congestion, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let load = self.current_load();
+        json!({
+            "threshold": self.threshold,
+            "channel_utilization": load.map(|load| load.channel_utilization),
+            "air_util_tx": load.map(|load| load.air_util_tx),
+            "measured_secs_ago": load.map(|load| load.measured_at.elapsed().as_secs()),
+            "congestion": self.congestion(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cities(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn zone_cooldown_allows_alerts_without_cities() {
+        let mut cooldown = ZoneCooldown::new(Duration::from_secs(60));
+        cooldown.record(2, &cities(&["Sderot"]));
+        assert!(cooldown.allows(2, &[]));
+    }
+
+    #[test]
+    fn zone_cooldown_holds_back_cities_already_covered() {
+        let mut cooldown = ZoneCooldown::new(Duration::from_secs(60));
+        assert!(cooldown.allows(2, &cities(&["Sderot"])));
+        cooldown.record(2, &cities(&["Sderot"]));
+        assert!(!cooldown.allows(2, &cities(&["Sderot"])));
+        assert!(cooldown.allows(2, &cities(&["Sderot", "Nir Am"])));
+        assert!(cooldown.allows(4, &cities(&["Sderot"])));
+    }
+
+    #[test]
+    fn zone_cooldown_accumulates_cities_and_ends() {
+        let mut cooldown = ZoneCooldown::new(Duration::from_secs(60));
+        cooldown.record(2, &cities(&["Sderot"]));
+        cooldown.record(2, &cities(&["Nir Am"]));
+        assert!(!cooldown.allows(2, &cities(&["Sderot", "Nir Am"])));
+
+        let mut expired = ZoneCooldown::new(Duration::ZERO);
+        expired.record(2, &cities(&["Sderot"]));
+        assert!(expired.allows(2, &cities(&["Sderot"])));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::api::{fetch_alerts, server_now, AlertResult};
+use crate::country::{AlertsFuture, CountryProfile, Language, Regions};
+use crate::error::RedAlertError;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use std::collections::HashMap;
+
+// How far apart a live listing and a history row of a category at a city may be to
+// count as the same event. The live feed shows an alert within seconds of its
+// official time, and keeps listing it while it is in effect.
+const RECONCILE_WINDOW: ChronoDuration = ChronoDuration::minutes(2);
+
+// How long a sighting is kept after it was last seen; the history feed lists
+// events for about two minutes
+const SIGHTING_TTL: ChronoDuration = ChronoDuration::minutes(5);
+
+// One event of a category at a city, as first seen on either feed. A city can have
+// more than one: a new siren there while the live feed still lists the last one shows
+// up in the history feed with its own official time.
+#[derive(Debug, Clone, Copy)]
+struct Sighting {
+    // Time the event is sent with: its official time when the history feed had it
+    // first, otherwise when the live feed first listed it
+    stamp: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+// Polls the oref live and history feeds together. The live feed is the earliest
+// source of an alert but lists it only briefly, so an alert can fall between two
+// polls; the history feed keeps it listed for a while. Both are matched into one
+// event per category, city and time, dated so the dedup sends each one once, by
+// whichever feed lists it first.
+#[derive(Default)]
+pub struct OrefCombined {
+    sightings: HashMap<(String, String), Vec<Sighting>>,
+}
+
+impl OrefCombined {
+    pub fn new() -> Self {
+        OrefCombined::default()
+    }
+
+    // Date of the event a category at a city belongs to, remembered as a new event of
+    // that date if there is none. A live listing at `seen` continues the event listed
+    // last; a history row of official time `seen` is the event dated close to it.
+    fn sight(&mut self, alert_type: &str, city: &str, seen: DateTime<Utc>, live: bool) -> DateTime<Utc> {
+        let sightings = self.sightings.entry((alert_type.to_string(), city.to_string())).or_default();
+        let found = if live {
+            sightings
+                .iter_mut()
+                .filter(|sighting| seen >= sighting.stamp - RECONCILE_WINDOW && seen <= sighting.last_seen + RECONCILE_WINDOW)
+                .max_by_key(|sighting| sighting.last_seen)
+        } else {
+            sightings
+                .iter_mut()
+                .find(|sighting| (seen - sighting.stamp).abs() <= RECONCILE_WINDOW)
+        };
+        match found {
+            Some(sighting) => {
+                sighting.last_seen = sighting.last_seen.max(seen);
+                sighting.stamp
+            }
+            None => {
+                sightings.push(Sighting { stamp: seen, last_seen: seen });
+                seen
+            }
+        }
+    }
+
+    // One alert seen at `seen` per event its cities belong to
+    fn split(&mut self, mut alert: AlertResult, seen: DateTime<Utc>, live: bool) -> Vec<AlertResult> {
+        if alert.cities.is_empty() {
+            return vec![alert];
+        }
+        let mut by_stamp: Vec<(DateTime<Utc>, Vec<String>)> = Vec::new();
+        for city in std::mem::take(&mut alert.cities) {
+            let stamp = self.sight(&alert.alert_type, &city, seen, live);
+            match by_stamp.iter_mut().find(|(date, _)| *date == stamp) {
+                Some((_, cities)) => cities.push(city),
+                None => by_stamp.push((stamp, vec![city])),
+            }
+        }
+        by_stamp
+            .into_iter()
+            .map(|(stamp, cities)| AlertResult {
+                alert_type: alert.alert_type.clone(),
+                areas: cities
+                    .iter()
+                    .filter_map(|city| Some((city.clone(), alert.areas.get(city)?.clone())))
+                    .collect(),
+                cities,
+                instructions: alert.instructions.clone(),
+                zones: alert.zones.clone(),
+                alert_date: Some(stamp),
+            })
+            .collect()
+    }
+
+    // Date the alerts of both feeds by the events they belong to. Live alerts keep
+    // their cities together; history alerts are split when some of their cities were
+    // already listed live, so those carry the live event's date and are dropped by
+    // the dedup, while cities the live feed missed go out with their official time.
+    fn reconcile(&mut self, live: Vec<AlertResult>, history: Vec<AlertResult>, now: DateTime<Utc>) -> Vec<AlertResult> {
+        self.sightings.retain(|_, sightings| {
+            sightings.retain(|sighting| now - sighting.last_seen <= SIGHTING_TTL);
+            !sightings.is_empty()
+        });
+
+        let mut alerts = Vec::new();
+        for alert in live {
+            alerts.extend(self.split(alert, now, true));
+        }
+        for alert in history {
+            match alert.alert_date {
+                Some(alert_date) => alerts.extend(self.split(alert, alert_date, false)),
+                None => alerts.push(alert),
+            }
+        }
+        alerts
+    }
+}
+
+// Both feeds are asked at once. A feed that fails leaves the other one to go on
+// with, unless the API asks to back off; only when both fail is the poll a failure.
+async fn fetch_both(combined: &mut OrefCombined) -> Result<Vec<AlertResult>, RedAlertError> {
+    let (live, history) = tokio::join!(fetch_alerts(false), fetch_alerts(true));
+    let (live, history) = match (live, history) {
+        (Err(e @ RedAlertError::RateLimited { .. }), _) | (_, Err(e @ RedAlertError::RateLimited { .. })) => return Err(e),
+        (Err(e), Err(_)) => return Err(e),
+        (Ok(live), Err(e)) => {
+            log::warn!("Polling the live feed only: {}", e);
+            (live, Vec::new())
+        }
+        (Err(e), Ok(history)) => {
+            log::warn!("Polling the history feed only: {}", e);
+            (Vec::new(), history)
+        }
+        (Ok(live), Ok(history)) => (live, history),
+    };
+    Ok(combined.reconcile(live, history, server_now()))
+}
+
+impl CountryProfile for OrefCombined {
+    fn source(&self) -> &'static str {
+        "oref_combined"
+    }
+
+    fn fetch_alerts(&mut self) -> AlertsFuture<'_> {
+        Box::pin(fetch_both(self))
+    }
+
+    fn regions(&self) -> Regions {
+        Regions::Cities
+    }
+
+    fn language(&self) -> Language {
+        Language::English
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn alert(cities: &[&str], alert_date: Option<DateTime<Utc>>) -> AlertResult {
+        AlertResult {
+            alert_type: "missiles".to_string(),
+            cities: cities.iter().map(|city| city.to_string()).collect(),
+            instructions: None,
+            zones: Vec::new(),
+            alert_date,
+            areas: Default::default(),
+        }
+    }
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap() + ChronoDuration::seconds(seconds)
+    }
+
+    fn dates(alerts: Vec<AlertResult>) -> Vec<(Vec<String>, Option<DateTime<Utc>>)> {
+        alerts.into_iter().map(|alert| (alert.cities, alert.alert_date)).collect()
+    }
+
+    #[test]
+    fn live_alert_keeps_its_date_while_listed() {
+        let mut combined = OrefCombined::new();
+        let first = combined.reconcile(vec![alert(&["Sderot"], None)], Vec::new(), at(0));
+        let again = combined.reconcile(vec![alert(&["Sderot"], None)], Vec::new(), at(5));
+        assert_eq!(first[0].alert_date, Some(at(0)));
+        assert_eq!(again[0].alert_date, Some(at(0)));
+    }
+
+    #[test]
+    fn history_of_a_live_alert_carries_the_live_date() {
+        let mut combined = OrefCombined::new();
+        combined.reconcile(vec![alert(&["Sderot"], None)], Vec::new(), at(0));
+        let alerts = combined.reconcile(Vec::new(), vec![alert(&["Sderot", "Nir Am"], Some(at(-3)))], at(5));
+        assert_eq!(
+            dates(alerts),
+            vec![(vec!["Sderot".to_string()], Some(at(0))), (vec!["Nir Am".to_string()], Some(at(-3)))]
+        );
+    }
+
+    #[test]
+    fn live_alert_listed_again_later_is_a_new_event() {
+        let mut combined = OrefCombined::new();
+        combined.reconcile(vec![alert(&["Sderot"], None)], Vec::new(), at(0));
+        let again = combined.reconcile(vec![alert(&["Sderot"], None)], Vec::new(), at(600));
+        assert_eq!(again[0].alert_date, Some(at(600)));
+    }
+
+    #[test]
+    fn history_alert_keeps_its_official_date_while_listed() {
+        let mut combined = OrefCombined::new();
+        let first = combined.reconcile(Vec::new(), vec![alert(&["Sderot"], Some(at(-20)))], at(0));
+        let again = combined.reconcile(Vec::new(), vec![alert(&["Sderot"], Some(at(-20)))], at(90));
+        assert_eq!(dates(first), vec![(vec!["Sderot".to_string()], Some(at(-20)))]);
+        assert_eq!(dates(again), vec![(vec!["Sderot".to_string()], Some(at(-20)))]);
+    }
+
+    #[test]
+    fn both_feeds_in_one_poll_carry_the_live_date() {
+        let mut combined = OrefCombined::new();
+        let alerts = combined.reconcile(vec![alert(&["Sderot"], None)], vec![alert(&["Sderot"], Some(at(-4)))], at(0));
+        assert_eq!(
+            dates(alerts),
+            vec![(vec!["Sderot".to_string()], Some(at(0))), (vec!["Sderot".to_string()], Some(at(0)))]
+        );
+    }
+
+    #[test]
+    fn second_history_event_of_a_city_still_listed_live_gets_its_own_date() {
+        let mut combined = OrefCombined::new();
+        for seconds in [0, 100, 200] {
+            combined.reconcile(vec![alert(&["Sderot"], None)], Vec::new(), at(seconds));
+        }
+        let alerts = combined.reconcile(
+            vec![alert(&["Sderot"], None)],
+            vec![alert(&["Sderot"], Some(at(-2))), alert(&["Sderot"], Some(at(280)))],
+            at(300),
+        );
+        assert_eq!(
+            dates(alerts),
+            vec![
+                (vec!["Sderot".to_string()], Some(at(0))),
+                (vec!["Sderot".to_string()], Some(at(0))),
+                (vec!["Sderot".to_string()], Some(at(280))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// How often and how far apart a category's broadcasts are repeated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepeatPolicy {
+    pub count: u32,
+    pub spacing: Duration,
+}
+
+// Parse a `--repeat` value of the form CATEGORY=COUNT:SECONDS
+pub fn parse_repeat(value: &str) -> Result<(String, RepeatPolicy), String> {
+    let (category, policy) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected CATEGORY=COUNT:SECONDS, got {}", value))?;
+    let (count, spacing) = policy
+        .split_once(':')
+        .ok_or_else(|| format!("Expected CATEGORY=COUNT:SECONDS, got {}", value))?;
+    let count: u32 = count
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid number of repeats in {}", value))?;
+    let spacing: u64 = spacing
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid number of seconds in {}", value))?;
+    if count == 0 || spacing == 0 {
+        return Err(format!("Repeats and their spacing must be positive in {}", value));
+    }
+    Ok((
+        category.trim().to_string(),
+        RepeatPolicy {
+            count,
+            spacing: Duration::from_secs(spacing),
+        },
+    ))
+}
+
+// A broadcast to send again exactly as it went out, sequence number, part tag and
+// signature included, so receivers and peer gateways recognize it as the same message
+#[derive(Debug, Clone)]
+pub struct PendingRepeat {
+    pub due: Instant,
+    pub channel: u32,
+    pub category: String,
+    pub portnum: u64,
+    pub message: String,
+    // Repeats still to go after this one, and of how many in total
+    pub remaining: u32,
+    pub count: u32,
+    pub spacing: Duration,
+}
+
+// Repeat transmissions of the most severe categories: a single LoRa packet is
+// easily lost, and a missed missile or infiltration alert is not acceptable
+#[derive(Debug, Default)]
+pub struct Repeats {
+    policies: HashMap<String, RepeatPolicy>,
+    pending: Vec<PendingRepeat>,
+}
+
+impl Repeats {
+    pub fn new(policies: &[(String, RepeatPolicy)]) -> Self {
+        Repeats {
+            policies: policies.iter().cloned().collect(),
+            pending: Vec::new(),
+        }
+    }
+
+    // Queue the repeats of a broadcast that just went out, if its category has a policy
+    pub fn schedule(&mut self, channel: u32, category: &str, portnum: u64, message: &str) {
+        let Some(policy) = self.policies.get(category) else {
+            return;
+        };
+        self.pending.push(PendingRepeat {
+            due: Instant::now() + policy.spacing,
+            channel,
+            category: category.to_string(),
+            portnum,
+            message: message.to_string(),
+            remaining: policy.count - 1,
+            count: policy.count,
+            spacing: policy.spacing,
+        });
+    }
+
+    // When the next repeat is due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending.iter().map(|repeat| repeat.due).min()
+    }
+
+    // Take the repeats that are due, oldest first, queueing the ones that follow them
+    pub fn take_due(&mut self) -> Vec<PendingRepeat> {
+        let now = Instant::now();
+        let (mut due, pending): (Vec<PendingRepeat>, Vec<PendingRepeat>) =
+            self.pending.drain(..).partition(|repeat| repeat.due <= now);
+        self.pending = pending;
+        due.sort_by_key(|repeat| repeat.due);
+
+        for repeat in &due {
+            if repeat.remaining > 0 {
+                self.pending.push(PendingRepeat {
+                    due: repeat.due + repeat.spacing,
+                    remaining: repeat.remaining - 1,
+                    ..repeat.clone()
+                });
+            }
+        }
+        due
+    }
+}
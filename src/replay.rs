@@ -0,0 +1,80 @@
+use crate::api::{self, AlertResult, ALARMS_HISTORY_API};
+use crate::localtime;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use chrono_tz::Asia::Jerusalem;
+use clap::Args;
+
+// Days searched for an --id without a --day
+const ID_SEARCH_DAYS: u64 = 30;
+
+#[derive(Args, Debug)]
+pub struct ReplayAlertArgs {
+    /// Israel local time of the alert, e.g. "2024-04-14 02:00"; every alert of that minute (or second, if given) is replayed
+    #[arg(long, required_unless_present = "id", conflicts_with = "id")]
+    pub at: Option<String>,
+
+    /// Archive ID (rid) of a row of the alert to replay
+    #[arg(long)]
+    pub id: Option<String>,
+
+    /// Day to look for --id on (default: the last 30 days)
+    #[arg(long, requires = "id")]
+    pub day: Option<NaiveDate>,
+
+    /// Replay only on these zones (or area channels) instead of every zone of the alert's cities
+    #[arg(long, num_args = 1.., value_delimiter = ' ')]
+    pub zone: Vec<u32>,
+
+    /// URL of the alert archive
+    #[arg(long, default_value = ALARMS_HISTORY_API)]
+    pub archive_url: String,
+}
+
+// The alerts to replay, oldest first
+pub async fn find(args: &ReplayAlertArgs) -> Result<Vec<AlertResult>, String> {
+    if let Some(id) = &args.id {
+        let today = Utc::now().with_timezone(&Jerusalem).date_naive();
+        let (from, to) = match args.day {
+            Some(day) => (day, day),
+            None => (today - Days::new(ID_SEARCH_DAYS), today),
+        };
+        let event = api::fetch_archive_event(&args.archive_url, from, to, id)
+            .await
+            .map_err(|e| format!("Failed to fetch the alert archive: {}", e))?;
+        return match event {
+            Some(event) => Ok(vec![event]),
+            None => Err(format!("No alert with ID {} in the archive from {} to {}", id, from, to)),
+        };
+    }
+
+    let at = args.at.as_deref().unwrap_or_default();
+    let start = api::parse_history_date(at).map_err(|e| e.to_string())?;
+    // Without seconds the whole minute matches
+    let precision = if at.matches(':').count() >= 2 { 1 } else { 60 };
+    let end = start + chrono::Duration::seconds(precision);
+    let day = start.with_timezone(&Jerusalem).date_naive();
+
+    let alerts = api::fetch_archive(&args.archive_url, day, day)
+        .await
+        .map_err(|e| format!("Failed to fetch the alerts of {}: {}", day, e))?;
+    let alerts: Vec<AlertResult> = alerts
+        .into_iter()
+        .filter(|alert| alert.alert_date.is_some_and(|date| start <= date && date < end))
+        .collect();
+    if alerts.is_empty() {
+        return Err(format!("No alert in the archive at {}", at));
+    }
+    Ok(alerts)
+}
+
+// Opening of a replayed alert, so nobody takes it for a real one
+pub fn drill_message(alert_type: &str, alert_date: Option<DateTime<Utc>>) -> String {
+    match alert_date {
+        Some(date) => format!(
+            "🧪DRILL - replay of {} {}",
+            alert_type,
+            localtime::to_local(date).format("%d.%m.%Y %H:%M")
+        ),
+        None => format!("🧪DRILL - replay of {}", alert_type),
+    }
+}
@@ -0,0 +1,122 @@
+use crate::localtime;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// Messages kept for nodes asking what they missed
+const RECENT_MESSAGES: usize = 50;
+
+// Messages sent for a bare "resend"
+const DEFAULT_RESEND: usize = 3;
+
+// Most messages sent for one request
+const MAX_RESEND: usize = 10;
+
+// Window of a bare "missed", in minutes
+const DEFAULT_MISSED_MINUTES: u64 = 60;
+
+// Longest window one "missed" may cover, in minutes
+const MAX_MISSED_MINUTES: u64 = 24 * 60;
+
+// How often one node may ask, so a stuck client can't eat the airtime
+const REQUEST_INTERVAL: Duration = Duration::from_secs(60);
+
+// A broadcast that went out, kept for retransmission
+#[derive(Debug, Clone)]
+pub struct SentMessage {
+    pub channel: u32,
+    pub category: String,
+    pub text: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+// What a direct message asks to be sent again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayRequest {
+    // "resend" or "last N": the last N messages
+    Last(usize),
+    // "missed" or "missed MINUTES": everything from that window, for nodes that
+    // were out of range with no store-and-forward server on the mesh to replay it
+    Missed(Duration),
+}
+
+pub fn parse_request(text: &str) -> Option<ReplayRequest> {
+    let text = text.trim().to_lowercase();
+    let mut words = text.split_whitespace();
+    let request = match (words.next()?, words.next(), words.next()) {
+        ("resend", None, None) | ("last", None, None) => ReplayRequest::Last(DEFAULT_RESEND),
+        ("resend" | "last", Some(count), None) => ReplayRequest::Last(count.parse::<usize>().ok()?.clamp(1, MAX_RESEND)),
+        ("missed", None, None) => ReplayRequest::Missed(Duration::from_secs(DEFAULT_MISSED_MINUTES * 60)),
+        ("missed", Some(minutes), None) => {
+            let minutes = minutes.parse::<u64>().ok()?.clamp(1, MAX_MISSED_MINUTES);
+            ReplayRequest::Missed(Duration::from_secs(minutes * 60))
+        }
+        _ => return None,
+    };
+    Some(request)
+}
+
+// Recent broadcasts and who asked for them when
+#[derive(Debug, Default)]
+pub struct RecentMessages {
+    sent: VecDeque<SentMessage>,
+    last_request: HashMap<u32, Instant>,
+}
+
+impl RecentMessages {
+    pub fn new() -> Self {
+        RecentMessages::default()
+    }
+
+    pub fn record(&mut self, channel: u32, category: &str, text: &str) {
+        if self.sent.len() == RECENT_MESSAGES {
+            self.sent.pop_front();
+        }
+        self.sent.push_back(SentMessage {
+            channel,
+            category: category.to_string(),
+            text: text.to_string(),
+            sent_at: Utc::now(),
+        });
+    }
+
+    // Whether the node may be served now; counts the request if so
+    pub fn allow_request(&mut self, node: u32) -> bool {
+        let now = Instant::now();
+        self.last_request.retain(|_, asked| now.duration_since(*asked) < REQUEST_INTERVAL);
+        if self.last_request.contains_key(&node) {
+            return false;
+        }
+        self.last_request.insert(node, now);
+        true
+    }
+
+    // The last `count` messages relevant to a zone channel, oldest first; alerts on
+    // channel 0 covered every zone, and a request on channel 0 gets everything
+    pub fn for_channel(&self, channel: u32, count: usize) -> Vec<SentMessage> {
+        let mut messages: Vec<SentMessage> = self
+            .sent
+            .iter()
+            .rev()
+            .filter(|message| channel == 0 || message.channel == channel || message.channel == 0)
+            .take(count)
+            .cloned()
+            .collect();
+        messages.reverse();
+        messages
+    }
+
+    // Messages relevant to a zone channel sent within the window, oldest first,
+    // capped at the most recent ones a single request may get
+    pub fn missed_on_channel(&self, channel: u32, window: Duration) -> Vec<SentMessage> {
+        let since = Utc::now() - chrono::Duration::from_std(window).unwrap_or_default();
+        let mut messages = self.for_channel(channel, MAX_RESEND);
+        messages.retain(|message| message.sent_at >= since);
+        messages
+    }
+}
+
+// A retransmitted message, marked with the local time it was first sent
+pub fn resent_text(message: &SentMessage) -> String {
+    format!("{} {}", localtime::to_local(message.sent_at).format("%H:%M"), message.text)
+}
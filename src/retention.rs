@@ -0,0 +1,250 @@
+#[cfg(feature = "sqlite")]
+use crate::store::AlertStore;
+use chrono::{DateTime, Utc};
+use clap::Args;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(Args, Debug)]
+pub struct PruneArgs {
+    /// SQLite alert store to prune as well (see backfill)
+    #[cfg(feature = "sqlite")]
+    #[arg(long)]
+    pub store: Option<String>,
+}
+
+// How much of the stored data to keep
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    pub max_age: Option<Duration>,
+    pub max_bytes: Option<u64>,
+}
+
+impl Retention {
+    pub fn new(days: Option<u64>, megabytes: Option<u64>) -> Option<Self> {
+        if days.is_none() && megabytes.is_none() {
+            return None;
+        }
+        Some(Retention {
+            max_age: days.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            max_bytes: megabytes.map(|megabytes| megabytes * 1024 * 1024),
+        })
+    }
+
+    fn cutoff(&self) -> Option<DateTime<Utc>> {
+        self.max_age
+            .and_then(|age| chrono::Duration::from_std(age).ok())
+            .map(|age| Utc::now() - age)
+    }
+}
+
+// Data the gateway stores, each pruned its own way
+#[derive(Debug, Clone)]
+pub enum Stored {
+    // JSON lines with their time under the given key, oldest first
+    JsonLines(PathBuf, &'static str),
+    // Files of a directory with the given extension, by modification time
+    Directory(PathBuf, &'static str),
+    // The dead-letter files of an outbox directory
+    DeadLetters(PathBuf),
+    #[cfg(feature = "sqlite")]
+    AlertStore(PathBuf),
+}
+
+impl Stored {
+    pub fn path(&self) -> &Path {
+        match self {
+            Stored::JsonLines(path, _) | Stored::Directory(path, _) | Stored::DeadLetters(path) => path,
+            #[cfg(feature = "sqlite")]
+            Stored::AlertStore(path) => path,
+        }
+    }
+
+    // Apply the retention, returning how many lines, files or rows were removed
+    pub fn prune(&self, retention: &Retention) -> Result<usize, String> {
+        if !self.path().exists() {
+            return Ok(0);
+        }
+        match self {
+            Stored::JsonLines(path, time_key) => prune_json_lines(path, time_key, retention),
+            Stored::Directory(path, extension) => prune_directory(path, extension, retention),
+            Stored::DeadLetters(dir) => {
+                let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+                let mut removed = 0;
+                for entry in entries.filter_map(Result::ok) {
+                    if entry.file_name().to_string_lossy().ends_with(".dead.jsonl") {
+                        removed += prune_json_lines(&entry.path(), "failed_at", retention)?;
+                    }
+                }
+                Ok(removed)
+            }
+            #[cfg(feature = "sqlite")]
+            Stored::AlertStore(path) => {
+                let path = path.to_string_lossy();
+                AlertStore::open(&path)?.prune(retention.cutoff(), retention.max_bytes)
+            }
+        }
+    }
+}
+
+// Drop lines older than the cutoff, then the oldest lines until the file fits. Lines
+// without a readable time are kept. The file is rewritten under a temporary name
+// first, and only when something is dropped.
+fn prune_json_lines(path: &Path, time_key: &str, retention: &Retention) -> Result<usize, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+    let line_time = |line: &str| {
+        serde_json::from_str::<serde_json::Value>(line)
+            .ok()
+            .and_then(|value| value[time_key].as_str().and_then(|time| DateTime::parse_from_rfc3339(time).ok()))
+            .map(|time| time.with_timezone(&Utc))
+    };
+
+    let mut kept: Vec<&str> = match retention.cutoff() {
+        Some(cutoff) => lines
+            .iter()
+            .copied()
+            .filter(|line| line_time(line).is_none_or(|time| time >= cutoff))
+            .collect(),
+        None => lines.clone(),
+    };
+    if let Some(max_bytes) = retention.max_bytes {
+        let mut size: u64 = kept.iter().map(|line| line.len() as u64 + 1).sum();
+        let mut dropped = 0;
+        while size > max_bytes && dropped < kept.len() {
+            size -= kept[dropped].len() as u64 + 1;
+            dropped += 1;
+        }
+        kept.drain(..dropped);
+    }
+
+    let removed = lines.len() - kept.len();
+    if removed > 0 {
+        let mut text = kept.join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        let tmp = path.with_extension("prune.tmp");
+        std::fs::write(&tmp, text)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    Ok(removed)
+}
+
+// Delete files older than the cutoff, then the oldest files until the directory fits
+fn prune_directory(dir: &Path, extension: &str, retention: &Retention) -> Result<usize, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == extension))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect();
+    files.sort();
+
+    let cutoff = retention.max_age.and_then(|age| SystemTime::now().checked_sub(age));
+    let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+    let mut removed = 0;
+    for (modified, len, path) in &files {
+        let expired = cutoff.is_some_and(|cutoff| *modified < cutoff);
+        let over = retention.max_bytes.is_some_and(|max_bytes| size > max_bytes);
+        if !expired && !over {
+            break;
+        }
+        std::fs::remove_file(path).map_err(|e| format!("Failed to delete {}: {}", path.display(), e))?;
+        size -= len;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+// Apply the retention to every kind of stored data, logging what was removed
+pub fn prune_all(stored: &[Stored], retention: &Retention) -> Result<usize, String> {
+    let mut total = 0;
+    for stored in stored {
+        let removed = stored.prune(retention)?;
+        if removed > 0 {
+            log::info!("Pruned {} old entr{} from {}", removed, if removed == 1 { "y" } else { "ies" }, stored.path().display());
+        }
+        total += removed;
+    }
+    Ok(total)
+}
+
+// Prune stored data now and then for as long as the gateway runs
+pub async fn prune_periodically(stored: Vec<Stored>, retention: Retention, every: Duration) {
+    loop {
+        let stored = stored.clone();
+        let result = tokio::task::spawn_blocking(move || prune_all(&stored, &retention))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r);
+        if let Err(e) = result {
+            log::warn!("Failed to prune stored data: {}", e);
+        }
+        tokio::time::sleep(every).await;
+    }
+}
+
+// Prune stored data once, for cron jobs and timers
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables, unused_mut))]
+pub fn run(args: &PruneArgs, mut stored: Vec<Stored>, retention: Option<Retention>) -> Result<(), String> {
+    let retention = retention.ok_or("Nothing to prune by; set --retention-days or --retention-mb")?;
+    #[cfg(feature = "sqlite")]
+    if let Some(store) = &args.store {
+        stored.push(Stored::AlertStore(PathBuf::from(store)));
+    }
+    let removed = prune_all(&stored, &retention)?;
+    println!("Pruned {} entries", removed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, lines: &[String]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.jsonl", name, std::process::id()));
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        path
+    }
+
+    fn line(days_ago: i64) -> String {
+        let time = Utc::now() - chrono::Duration::days(days_ago);
+        serde_json::json!({ "time": time.to_rfc3339(), "days_ago": days_ago }).to_string()
+    }
+
+    #[test]
+    fn drops_lines_older_than_the_cutoff() {
+        let lines = [line(10), "not json".to_string(), line(3), line(0)];
+        let path = temp_file("retention-age", &lines);
+        let removed = prune_json_lines(&path, "time", &Retention::new(Some(5), None).unwrap()).unwrap();
+        let kept = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(removed, 1);
+        assert_eq!(kept.lines().collect::<Vec<_>>(), vec!["not json", lines[2].as_str(), lines[3].as_str()]);
+    }
+
+    #[test]
+    fn drops_the_oldest_lines_to_fit() {
+        let lines = [line(2), line(1), line(0)];
+        let path = temp_file("retention-size", &lines);
+        let retention = Retention {
+            max_age: None,
+            max_bytes: Some(lines[2].len() as u64 + 1),
+        };
+        let removed = prune_json_lines(&path, "time", &retention).unwrap();
+        let kept = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(removed, 2);
+        assert_eq!(kept, format!("{}\n", lines[2]));
+    }
+
+    #[test]
+    fn nothing_to_prune_by() {
+        assert!(Retention::new(None, None).is_none());
+    }
+}
@@ -0,0 +1,54 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+// Per-channel counters of transmitted messages, persisted so numbering continues
+// across restarts and receivers can spot gaps ("#41" followed by "#43")
+#[derive(Debug)]
+pub struct SequenceCounters {
+    path: PathBuf,
+    // Number of the last message sent on each channel
+    last: BTreeMap<u32, u64>,
+}
+
+impl SequenceCounters {
+    // Load the counters, starting from zero if the file doesn't exist yet
+    pub fn load(path: &str) -> Result<Self, String> {
+        let last = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("Failed to parse sequence file {}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("Failed to read sequence file {}: {}", path, e)),
+        };
+        Ok(SequenceCounters {
+            path: PathBuf::from(path),
+            last,
+        })
+    }
+
+    // Number the next message on the channel will carry
+    pub fn next(&self, channel: u32) -> u64 {
+        self.last.get(&channel).copied().unwrap_or_default() + 1
+    }
+
+    // Count a sent message and save the counters; a failed save only loses numbering continuity
+    pub fn advance(&mut self, channel: u32) {
+        let next = self.next(channel);
+        self.last.insert(channel, next);
+
+        // Write to a temporary file first so a crash can't leave a truncated file behind
+        let temp = self.path.with_extension("tmp");
+        let saved = serde_json::to_string(&self.last)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| std::fs::write(&temp, contents).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&temp, &self.path).map_err(|e| e.to_string()));
+        if let Err(e) = saved {
+            log::warn!("Failed to save sequence numbers to {}: {}", self.path.display(), e);
+        }
+    }
+
+    // Last number sent per channel, for the state dump
+    pub fn debug_state(&self) -> Value {
+        serde_json::to_value(&self.last).unwrap_or_default()
+    }
+}
@@ -0,0 +1,104 @@
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Minutes to stay in the protected space after the last alert of a category,
+// per Home Front Command guidance, unless instructed otherwise. Other categories
+// (infiltration, hazardous materials, ...) last until the authorities say so.
+const BUILTIN_SHELTER_MINUTES: [(&str, u64); 2] = [("missiles", 10), ("hostileAircraftIntrusion", 10)];
+
+// Parse a `--shelter-time` value of the form CATEGORY=MINUTES
+pub fn parse_shelter_time(value: &str) -> Result<(String, u64), String> {
+    let (category, minutes) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected CATEGORY=MINUTES, got {}", value))?;
+    let minutes = minutes
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid number of minutes in {}", value))?;
+    Ok((category.trim().to_string(), minutes))
+}
+
+// A zone whose shelter time ran out without a newer alert
+#[derive(Debug, Clone)]
+pub struct PassedThreat {
+    pub category: String,
+    pub channel: u32,
+    pub last_alert: DateTime<Utc>,
+    pub shelter_time: Duration,
+}
+
+// Zones waiting for their "threat passed" follow-up, keyed by category and
+// channel, with the time of the latest alert there
+#[derive(Debug)]
+pub struct ThreatPassed {
+    shelter_times: HashMap<String, Duration>,
+    pending: HashMap<(String, u32), DateTime<Utc>>,
+}
+
+impl ThreatPassed {
+    pub fn new(overrides: &[(String, u64)]) -> Self {
+        let shelter_times = BUILTIN_SHELTER_MINUTES
+            .iter()
+            .map(|(category, minutes)| (category.to_string(), *minutes))
+            .chain(overrides.iter().cloned())
+            .filter(|(_, minutes)| *minutes > 0)
+            .map(|(category, minutes)| (category, Duration::from_secs(minutes * 60)))
+            .collect();
+        ThreatPassed {
+            shelter_times,
+            pending: HashMap::new(),
+        }
+    }
+
+    // Start or push back the shelter time of the channel; a later alert in the zone restarts it
+    pub fn record(&mut self, category: &str, channel: u32, alert_time: DateTime<Utc>) {
+        if !self.shelter_times.contains_key(category) {
+            return;
+        }
+        let last_alert = self.pending.entry((category.to_string(), channel)).or_insert(alert_time);
+        *last_alert = (*last_alert).max(alert_time);
+    }
+
+    // Drop and return the zones whose shelter time is over
+    pub fn due(&mut self, now: DateTime<Utc>) -> Vec<PassedThreat> {
+        let mut passed = Vec::new();
+        let shelter_times = &self.shelter_times;
+        self.pending.retain(|(category, channel), last_alert| {
+            let Some(shelter_time) = shelter_times.get(category) else {
+                return false;
+            };
+            if now < *last_alert + chrono::Duration::from_std(*shelter_time).unwrap_or_default() {
+                return true;
+            }
+            passed.push(PassedThreat {
+                category: category.clone(),
+                channel: *channel,
+                last_alert: *last_alert,
+                shelter_time: *shelter_time,
+            });
+            false
+        });
+        passed.sort_by(|a, b| (a.channel, &a.category).cmp(&(b.channel, &b.category)));
+        passed
+    }
+
+    // Pending follow-ups, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let mut pending: Vec<(&(String, u32), &DateTime<Utc>)> = self.pending.iter().collect();
+        pending.sort_by_key(|((category, channel), _)| (*channel, category.clone()));
+        let pending: Vec<Value> = pending
+            .into_iter()
+            .map(|((category, channel), last_alert)| {
+                json!({ "category": category, "channel": channel, "last_alert": last_alert.to_rfc3339() })
+            })
+            .collect();
+        let shelter_minutes: HashMap<&String, u64> = self
+            .shelter_times
+            .iter()
+            .map(|(category, time)| (category, time.as_secs() / 60))
+            .collect();
+        json!({ "shelter_minutes": shelter_minutes, "pending": pending })
+    }
+}
@@ -0,0 +1,39 @@
+use crate::notify::{JsonPost, Notification, Notifier};
+use crate::outbox::{Outbox, OutboxSettings};
+use serde_json::json;
+
+// Sends alerts to Signal groups and numbers through a signal-cli REST API
+// (github.com/bbernhard/signal-cli-rest-api), for recipients who use neither
+// Meshtastic apps nor Telegram
+pub struct SignalNotifier {
+    send_url: String,
+    number: String,
+    recipients: Vec<String>,
+    outbox: Outbox,
+}
+
+impl SignalNotifier {
+    pub fn new(url: &str, number: String, recipients: &[String], outbox: &OutboxSettings) -> Result<Self, String> {
+        if recipients.is_empty() {
+            return Err("--signal-url needs --signal-recipient".to_string());
+        }
+        let delivery = JsonPost::new("signal-cli", None)?;
+        Ok(SignalNotifier {
+            send_url: format!("{}/v2/send", url.trim_end_matches('/')),
+            number,
+            recipients: recipients.to_vec(),
+            outbox: Outbox::start("signal", outbox, delivery)?,
+        })
+    }
+}
+
+impl Notifier for SignalNotifier {
+    fn notify(&self, notification: &Notification) {
+        let body = json!({
+            "message": notification.text(),
+            "number": self.number,
+            "recipients": self.recipients,
+        });
+        self.outbox.push(&self.send_url, body.to_string(), false, notification.valid_until);
+    }
+}
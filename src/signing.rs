@@ -0,0 +1,105 @@
+use clap::Args;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::BufRead;
+
+// Separates a message from its signature, e.g. "🚨missiles ~3fa9c01d"
+const SIGNATURE_SEPARATOR: &str = " ~";
+
+// Hex digits of the HMAC kept; 32 bits are plenty against forged texts and cheap in airtime
+const SIGNATURE_HEX_DIGITS: usize = 8;
+
+// Bytes a signature adds to a message
+pub const SIGNATURE_BYTES: usize = SIGNATURE_SEPARATOR.len() + SIGNATURE_HEX_DIGITS;
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// Signed message to check; read line by line from stdin if omitted
+    pub message: Option<String>,
+}
+
+// Truncated HMAC-SHA256 of a message as hex
+fn signature(key: &str, message: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(message.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    digest
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>()[..SIGNATURE_HEX_DIGITS]
+        .to_string()
+}
+
+// Append the signature to a message
+pub fn sign(key: &str, message: &str) -> String {
+    format!("{}{}{}", message, SIGNATURE_SEPARATOR, signature(key, message))
+}
+
+// Check a signed message, returning the message without its signature if it is genuine
+pub fn verify(key: &str, signed: &str) -> Result<String, String> {
+    let signed = signed.trim_end();
+    let (message, provided) = signed
+        .rsplit_once(SIGNATURE_SEPARATOR)
+        .ok_or("The message has no signature")?;
+    if provided.eq_ignore_ascii_case(&signature(key, message)) {
+        Ok(message.to_string())
+    } else {
+        Err("The signature does not match; the message was altered or not sent by the gateway".to_string())
+    }
+}
+
+// Verify the message given on the command line, or every line of stdin
+pub fn run(args: &VerifyArgs, key: Option<&str>) -> Result<(), String> {
+    let key = key.ok_or("verify needs the shared secret (--hmac-key)")?;
+
+    let messages: Vec<String> = match &args.message {
+        Some(message) => vec![message.clone()],
+        None => std::io::stdin()
+            .lock()
+            .lines()
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to read stdin: {}", e))?,
+    };
+
+    let mut forged = 0;
+    for message in messages.iter().filter(|message| !message.trim().is_empty()) {
+        match verify(key, message) {
+            Ok(message) => println!("valid    {}", message),
+            Err(e) => {
+                println!("INVALID  {} ({})", message, e);
+                forged += 1;
+            }
+        }
+    }
+    if forged > 0 {
+        return Err(format!("{} message(s) failed verification", forged));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_truncated_hmac_sha256() {
+        // The widely published HMAC-SHA256 example digest starts with f7bc83f4
+        assert_eq!(signature("key", "The quick brown fox jumps over the lazy dog"), "f7bc83f4");
+        assert_eq!(sign("s3cret", "🚨 צבע אדום: שדרות"), "🚨 צבע אדום: שדרות ~86343217");
+        assert_eq!(sign("s3cret", "x").len(), "x".len() + SIGNATURE_BYTES);
+    }
+
+    #[test]
+    fn verifies_signed_messages() {
+        let signed = sign("s3cret", "#12 Red alert ~ Sderot");
+        assert_eq!(verify("s3cret", &signed), Ok("#12 Red alert ~ Sderot".to_string()));
+        // Trailing newlines from stdin and upper case hex are accepted
+        let (message, digest) = signed.rsplit_once(SIGNATURE_SEPARATOR).unwrap();
+        let relaxed = format!("{}{}{}\n", message, SIGNATURE_SEPARATOR, digest.to_uppercase());
+        assert_eq!(verify("s3cret", &relaxed), Ok("#12 Red alert ~ Sderot".to_string()));
+
+        assert!(verify("other", &signed).is_err());
+        assert!(verify("s3cret", &signed.replace("Sderot", "Ashdod")).is_err());
+        assert!(verify("s3cret", "Red alert").is_err());
+    }
+}
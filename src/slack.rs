@@ -0,0 +1,80 @@
+use crate::notify::{JsonPost, Notification, Notifier};
+use crate::outbox::{Outbox, OutboxSettings};
+use crate::ratelimit;
+use serde_json::{json, Value};
+
+// Slack treats these three as markup
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Side bar color of a category, from the gateway's own ranking
+fn color(category: &str) -> &'static str {
+    match ratelimit::severity(category) {
+        3 => "#d00000",
+        2 => "#ff8c00",
+        1 => "#ffd000",
+        _ => "#808080",
+    }
+}
+
+// The alert as a message attachment, which both Slack and Mattermost render
+fn attachment(notification: &Notification) -> Value {
+    let zones: Vec<&str> = notification.zones.iter().map(|zone| zone.name.as_str()).collect();
+    let cities: Vec<String> = notification
+        .zones
+        .iter()
+        .filter(|zone| !zone.cities.is_empty())
+        .map(|zone| format!("*{}*: {}", escape(&zone.name), escape(&zone.cities.join(", "))))
+        .collect();
+
+    let mut fields = vec![json!({ "title": "Zones", "value": escape(&zones.join(", ")), "short": true })];
+    if !cities.is_empty() {
+        fields.push(json!({ "title": "Cities", "value": cities.join("\n"), "short": false }));
+    }
+    if let Some(instructions) = &notification.instructions {
+        fields.push(json!({ "title": "Instructions", "value": escape(instructions), "short": false }));
+    }
+    let mut attachment = json!({
+        "fallback": notification.text(),
+        "color": color(&notification.category),
+        "title": escape(&notification.headline()),
+        "fields": fields,
+        "footer": "red-alert-meshtastic",
+    });
+    if let Some(date) = notification.alert_date {
+        attachment["ts"] = json!(date.timestamp());
+    }
+    attachment
+}
+
+// Posts alerts to Slack or Mattermost incoming webhooks, colored by category with
+// the zones, cities and instructions as fields, for channels that monitor the gateway
+pub struct SlackNotifier {
+    webhooks: Vec<String>,
+    username: Option<String>,
+    outbox: Outbox,
+}
+
+impl SlackNotifier {
+    pub fn new(webhooks: &[String], username: Option<String>, outbox: &OutboxSettings) -> Result<Self, String> {
+        let delivery = JsonPost::new("the webhook", None)?;
+        Ok(SlackNotifier {
+            webhooks: webhooks.to_vec(),
+            username,
+            outbox: Outbox::start("slack", outbox, delivery)?,
+        })
+    }
+}
+
+impl Notifier for SlackNotifier {
+    fn notify(&self, notification: &Notification) {
+        let mut message = json!({ "attachments": [attachment(notification)] });
+        if let Some(username) = &self.username {
+            message["username"] = json!(username);
+        }
+        for webhook in &self.webhooks {
+            self.outbox.push(webhook, message.to_string(), false, notification.valid_until);
+        }
+    }
+}
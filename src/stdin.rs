@@ -0,0 +1,47 @@
+use crate::api::{parse_alert_json, AlertResult};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+
+// Read newline-delimited alert JSON from stdin and hand each alert to the pipeline
+pub async fn read_alerts(alerts_tx: mpsc::Sender<(&'static str, AlertResult)>) {
+    log::info!("Reading alerts from stdin");
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => {
+                log::warn!("stdin was closed; no more alerts will be read");
+                return;
+            }
+            Err(e) => {
+                log::error!("Failed to read alert from stdin: {}", e);
+                return;
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let json: Value = match serde_json::from_str(&line) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Ignoring stdin line that is not valid JSON: {}. Line was: {}", e, line);
+                continue;
+            }
+        };
+
+        match parse_alert_json(json).await {
+            Ok(alerts) => {
+                for alert in alerts {
+                    if alerts_tx.send(("stdin", alert)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(e) => log::error!("Ignoring stdin line that is not a valid alert: {}", e),
+        }
+    }
+}
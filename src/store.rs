@@ -0,0 +1,116 @@
+use crate::api::AlertResult;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+
+// Local SQLite database of alerts, one row per city of each event, for analysis
+pub struct AlertStore {
+    connection: Connection,
+}
+
+impl AlertStore {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let connection = Connection::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS alerts (
+                    alert_date TEXT NOT NULL,
+                    category TEXT NOT NULL,
+                    city TEXT NOT NULL,
+                    source TEXT NOT NULL,
+                    district TEXT,
+                    district_id INTEGER,
+                    PRIMARY KEY (alert_date, category, city)
+                );
+                CREATE INDEX IF NOT EXISTS alerts_by_city ON alerts (city, alert_date);",
+            )
+            .map_err(|e| format!("Failed to create the alerts table in {}: {}", path, e))?;
+
+        // Stores created before the archive gave districts lack their columns
+        let has_district: bool = connection
+            .query_row("SELECT COUNT(*) > 0 FROM pragma_table_info('alerts') WHERE name = 'district'", [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        if !has_district {
+            connection
+                .execute_batch("ALTER TABLE alerts ADD COLUMN district TEXT; ALTER TABLE alerts ADD COLUMN district_id INTEGER;")
+                .map_err(|e| format!("Failed to add the district columns to {}: {}", path, e))?;
+        }
+        connection
+            .execute_batch("CREATE INDEX IF NOT EXISTS alerts_by_district ON alerts (district, alert_date);")
+            .map_err(|e| format!("Failed to index {} by district: {}", path, e))?;
+        Ok(AlertStore { connection })
+    }
+
+    // Store alerts with a known time and the districts the source gave, returning how
+    // many rows were new; rows already stored (e.g. from an overlapping import) are
+    // left as they are
+    pub fn insert(&mut self, alerts: &[AlertResult], source: &str) -> Result<usize, String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+        let mut added = 0;
+        {
+            let mut insert = transaction
+                .prepare(
+                    "INSERT OR IGNORE INTO alerts (alert_date, category, city, source, district, district_id)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                )
+                .map_err(|e| e.to_string())?;
+            for alert in alerts {
+                let Some(alert_date) = alert.alert_date else {
+                    continue;
+                };
+                for city in &alert.cities {
+                    let area = alert.areas.get(city);
+                    let district = area.and_then(|area| area.district.as_deref());
+                    let district_id = area.and_then(|area| area.district_id);
+                    added += insert
+                        .execute(params![alert_date.to_rfc3339(), alert.alert_type, city, source, district, district_id])
+                        .map_err(|e| format!("Failed to store an alert: {}", e))?;
+                }
+            }
+        }
+        transaction.commit().map_err(|e| e.to_string())?;
+        Ok(added)
+    }
+
+    // Size of the database file in bytes
+    fn size(&self) -> Result<u64, String> {
+        self.connection
+            .query_row("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()", [], |row| row.get(0))
+            .map_err(|e| e.to_string())
+    }
+
+    // Delete rows from before the cutoff, then the oldest tenth of the rows at a time
+    // until the file fits, returning how many rows were deleted
+    pub fn prune(&mut self, before: Option<DateTime<Utc>>, max_bytes: Option<u64>) -> Result<usize, String> {
+        let mut deleted = match before {
+            Some(before) => self
+                .connection
+                .execute("DELETE FROM alerts WHERE alert_date < ?1", params![before.to_rfc3339()])
+                .map_err(|e| format!("Failed to prune the alert store: {}", e))?,
+            None => 0,
+        };
+        if deleted > 0 || max_bytes.is_some() {
+            self.connection.execute_batch("VACUUM").map_err(|e| e.to_string())?;
+        }
+
+        if let Some(max_bytes) = max_bytes {
+            while self.size()? > max_bytes {
+                let rows: usize = self
+                    .connection
+                    .query_row("SELECT COUNT(*) FROM alerts", [], |row| row.get(0))
+                    .map_err(|e| e.to_string())?;
+                if rows == 0 {
+                    break;
+                }
+                deleted += self
+                    .connection
+                    .execute(
+                        "DELETE FROM alerts WHERE rowid IN (SELECT rowid FROM alerts ORDER BY alert_date LIMIT ?1)",
+                        params![(rows / 10).max(1)],
+                    )
+                    .map_err(|e| format!("Failed to prune the alert store: {}", e))?;
+                self.connection.execute_batch("VACUUM").map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(deleted)
+    }
+}
@@ -0,0 +1,91 @@
+use crate::meshmqtt::{format_node_id, DETECTION_SENSOR_APP, TEXT_MESSAGE_APP};
+use crate::multipart::MAX_TEXT_BYTES;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+// How long a store-and-forward server counts as present after it was last heard;
+// routers send a heartbeat every 15 minutes by default
+const SERVER_TIMEOUT: Duration = Duration::from_secs(40 * 60);
+
+// Largest payload a store-and-forward server keeps in its history
+const STORED_PAYLOAD_BYTES: usize = 233;
+
+// Alert texts are split into parts that always fit a server's history
+const _: () = assert!(MAX_TEXT_BYTES <= STORED_PAYLOAD_BYTES);
+
+// Store-and-forward servers heard on the mesh. They keep a history of the text
+// broadcasts on their channel and replay it to nodes that were out of range.
+#[derive(Debug, Default)]
+pub struct StoreForward {
+    // Server node -> channel it was heard on and when
+    servers: HashMap<u32, (u32, Instant)>,
+    // Channels and reasons already warned about, so each is logged once
+    warned: HashSet<(u32, &'static str)>,
+}
+
+pub type SharedStoreForward = Arc<Mutex<StoreForward>>;
+
+pub fn lock_store_forward(store_forward: &SharedStoreForward) -> MutexGuard<'_, StoreForward> {
+    store_forward.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl StoreForward {
+    // Servers are heard through the mesh MQTT transport
+    #[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+    pub fn heard(&mut self, node: u32, channel: u32) {
+        let previous = self.servers.insert(node, (channel, Instant::now()));
+        if previous.is_none_or(|(_, at)| at.elapsed() >= SERVER_TIMEOUT) {
+            log::info!("Store-and-forward server {} heard on channel {}", format_node_id(node), channel);
+        }
+    }
+
+    // A server heard recently on the channel, if any
+    pub fn server_for(&self, channel: u32) -> Option<u32> {
+        self.servers
+            .iter()
+            .find(|(_, (heard_channel, at))| *heard_channel == channel && at.elapsed() < SERVER_TIMEOUT)
+            .map(|(node, _)| *node)
+    }
+
+    fn any_present(&self) -> bool {
+        self.servers.values().any(|(_, at)| at.elapsed() < SERVER_TIMEOUT)
+    }
+
+    // Port to broadcast on so a server on the channel keeps the message for replay:
+    // servers only store text, so detection sensor packets go out as text instead
+    pub fn stored_portnum(&mut self, channel: u32, portnum: u64) -> u64 {
+        if portnum != DETECTION_SENSOR_APP || self.server_for(channel).is_none() {
+            return portnum;
+        }
+        if self.warned.insert((channel, "sensor")) {
+            log::info!("Sending sensor states on channel {} as text so store-and-forward replays them", channel);
+        }
+        TEXT_MESSAGE_APP
+    }
+
+    // Warn once per channel when a server is on the mesh but not on the channel an
+    // alert goes out on; that needs a server on the channel, the gateway can't help it
+    pub fn check_channel(&mut self, channel: u32) {
+        if self.any_present() && self.server_for(channel).is_none() && self.warned.insert((channel, "channel")) {
+            log::warn!(
+                "Alerts on channel {} won't be replayed by store-and-forward: no server was heard on the channel",
+                channel
+            );
+        }
+    }
+
+    // Servers heard and how long ago, for the state dump
+    pub fn debug_state(&self) -> Value {
+        json!(self
+            .servers
+            .iter()
+            .map(|(node, (channel, at))| json!({
+                "node": format_node_id(*node),
+                "channel": channel,
+                "secs_ago": at.elapsed().as_secs(),
+            }))
+            .collect::<Vec<_>>())
+    }
+}
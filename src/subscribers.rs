@@ -0,0 +1,335 @@
+use crate::country::Language;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+// Shortest time between two direct messages to the same subscriber
+const NOTIFY_GAP: Duration = Duration::from_secs(60);
+
+// What a node wants to be told about by direct message
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Preferences {
+    // Channel the direct messages go out on
+    #[serde(default)]
+    pub channel: u32,
+    // Zones (or area channels) to be told about; none means every zone
+    #[serde(default)]
+    pub zones: Vec<u32>,
+    // Alert categories to be told about; none means every category
+    #[serde(default)]
+    pub categories: Vec<String>,
+    // Language of place names ("english" or "local"); the gateway's when unset
+    #[serde(default)]
+    pub language: Option<String>,
+    // Local hours from and until which no direct messages are sent, e.g. [22, 7]
+    #[serde(default)]
+    pub quiet_hours: Option<(u8, u8)>,
+}
+
+impl Preferences {
+    pub fn wants(&self, category: &str) -> bool {
+        self.categories.is_empty() || self.categories.iter().any(|wanted| wanted.eq_ignore_ascii_case(category))
+    }
+
+    pub fn wants_zone(&self, zones: &[u32]) -> bool {
+        self.zones.is_empty() || zones.iter().any(|zone| self.zones.contains(zone))
+    }
+
+    pub fn language(&self) -> Option<Language> {
+        self.language.as_deref().and_then(|language| parse_language(language).ok())
+    }
+
+    // Whether direct messages are held back at this local hour
+    pub fn is_quiet(&self, hour: u8) -> bool {
+        match self.quiet_hours {
+            Some((from, until)) if from <= until => (from..until).contains(&hour),
+            Some((from, until)) => hour >= from || hour < until,
+            None => false,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(language) = &self.language {
+            parse_language(language)?;
+        }
+        if let Some((from, until)) = self.quiet_hours {
+            if from > 23 || until > 23 {
+                return Err("Quiet hours must be between 0 and 23".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // Short summary for a reply over the mesh
+    fn summary(&self) -> String {
+        let list = |items: Vec<String>| if items.is_empty() { "all".to_string() } else { items.join(",") };
+        format!(
+            "zones {}, categories {}, language {}, quiet {}",
+            list(self.zones.iter().map(u32::to_string).collect()),
+            list(self.categories.clone()),
+            self.language.as_deref().unwrap_or("default"),
+            match self.quiet_hours {
+                Some((from, until)) => format!("{}-{}", from, until),
+                None => "never".to_string(),
+            }
+        )
+    }
+}
+
+// "english" or "local", also "en" and "he"
+fn parse_language(value: &str) -> Result<Language, String> {
+    match value.to_lowercase().as_str() {
+        "en" => Ok(Language::English),
+        "he" => Ok(Language::Local),
+        value => Language::from_str(value, true).map_err(|_| format!("Unknown language {}; use english or local", value)),
+    }
+}
+
+// A preference change sent to the gateway by direct message
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscriberCommand {
+    Subscribe(Vec<u32>),
+    Unsubscribe,
+    Zones(Vec<u32>),
+    Categories(Vec<String>),
+    Language(String),
+    Quiet(Option<(u8, u8)>),
+    Show,
+}
+
+fn parse_zones(words: &[&str]) -> Option<Vec<u32>> {
+    if words == ["all"] {
+        return Some(Vec::new());
+    }
+    words.iter().map(|word| word.trim_matches(',').parse().ok()).collect()
+}
+
+// Parse "sub [ZONE...]", "unsub", "zones ZONE...|all", "cats CATEGORY...|all",
+// "lang english|local", "quiet FROM-UNTIL|off" and "prefs"
+pub fn parse_command(text: &str) -> Option<SubscriberCommand> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let (command, rest) = words.split_first()?;
+    match command.to_lowercase().as_str() {
+        "sub" | "subscribe" => parse_zones(rest).map(SubscriberCommand::Subscribe),
+        "unsub" | "unsubscribe" => Some(SubscriberCommand::Unsubscribe),
+        "zones" if !rest.is_empty() => parse_zones(rest).map(SubscriberCommand::Zones),
+        "cats" | "categories" if !rest.is_empty() => Some(SubscriberCommand::Categories(if rest == ["all"] {
+            Vec::new()
+        } else {
+            rest.iter().flat_map(|word| word.split(',')).filter(|c| !c.is_empty()).map(String::from).collect()
+        })),
+        "lang" | "language" if rest.len() == 1 => Some(SubscriberCommand::Language(rest[0].to_lowercase())),
+        "quiet" if rest == ["off"] => Some(SubscriberCommand::Quiet(None)),
+        "quiet" if rest.len() == 1 => {
+            let (from, until) = rest[0].split_once('-')?;
+            Some(SubscriberCommand::Quiet(Some((from.parse().ok()?, until.parse().ok()?))))
+        }
+        "prefs" => Some(SubscriberCommand::Show),
+        _ => None,
+    }
+}
+
+// Preferences of the nodes subscribed to direct-message alerts, kept in SQLite
+pub struct SubscriberStore {
+    connection: Connection,
+    // When each node was last notified, to rate-limit direct messages
+    notified: HashMap<u32, Instant>,
+}
+
+pub type SharedSubscribers = Arc<Mutex<SubscriberStore>>;
+
+// Lock the shared store, still usable if a task panicked while holding it
+pub fn lock_subscribers(subscribers: &SharedSubscribers) -> MutexGuard<'_, SubscriberStore> {
+    subscribers.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+type Row = (u32, u32, String, String, Option<String>, Option<u8>, Option<u8>);
+
+fn preferences((_, channel, zones, categories, language, quiet_from, quiet_until): Row) -> Preferences {
+    Preferences {
+        channel,
+        zones: serde_json::from_str(&zones).unwrap_or_default(),
+        categories: serde_json::from_str(&categories).unwrap_or_default(),
+        language,
+        quiet_hours: quiet_from.zip(quiet_until),
+    }
+}
+
+const SELECT: &str = "SELECT node, channel, zones, categories, language, quiet_from, quiet_until FROM subscribers";
+
+impl SubscriberStore {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let connection = Connection::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS subscribers (
+                    node INTEGER PRIMARY KEY,
+                    channel INTEGER NOT NULL,
+                    zones TEXT NOT NULL,
+                    categories TEXT NOT NULL,
+                    language TEXT,
+                    quiet_from INTEGER,
+                    quiet_until INTEGER,
+                    updated_at TEXT NOT NULL
+                );",
+            )
+            .map_err(|e| format!("Failed to create the subscribers table in {}: {}", path, e))?;
+        Ok(SubscriberStore {
+            connection,
+            notified: HashMap::new(),
+        })
+    }
+
+    fn read_row(row: &rusqlite::Row) -> rusqlite::Result<Row> {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?))
+    }
+
+    pub fn get(&self, node: u32) -> Result<Option<Preferences>, String> {
+        self.connection
+            .query_row(&format!("{} WHERE node = ?1", SELECT), params![node], Self::read_row)
+            .optional()
+            .map(|row| row.map(preferences))
+            .map_err(|e| format!("Failed to read subscriber: {}", e))
+    }
+
+    // Whether a node may be notified now, counting it as notified if so
+    pub fn may_notify(&mut self, node: u32, now: Instant) -> bool {
+        self.notified.retain(|_, at| now.duration_since(*at) < NOTIFY_GAP);
+        if self.notified.contains_key(&node) {
+            return false;
+        }
+        self.notified.insert(node, now);
+        true
+    }
+
+    pub fn all(&self) -> Result<Vec<(u32, Preferences)>, String> {
+        let mut select = self
+            .connection
+            .prepare(&format!("{} ORDER BY node", SELECT))
+            .map_err(|e| e.to_string())?;
+        let rows = select
+            .query_map([], Self::read_row)
+            .map_err(|e| format!("Failed to read subscribers: {}", e))?;
+        rows.map(|row| row.map(|row| (row.0, preferences(row))))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to read subscribers: {}", e))
+    }
+
+    pub fn set(&mut self, node: u32, preferences: &Preferences, now: DateTime<Utc>) -> Result<(), String> {
+        preferences.check()?;
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO subscribers
+                    (node, channel, zones, categories, language, quiet_from, quiet_until, updated_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    node,
+                    preferences.channel,
+                    serde_json::to_string(&preferences.zones).map_err(|e| e.to_string())?,
+                    serde_json::to_string(&preferences.categories).map_err(|e| e.to_string())?,
+                    preferences.language,
+                    preferences.quiet_hours.map(|(from, _)| from),
+                    preferences.quiet_hours.map(|(_, until)| until),
+                    now.to_rfc3339()
+                ],
+            )
+            .map_err(|e| format!("Failed to store subscriber: {}", e))?;
+        Ok(())
+    }
+
+    // Forget a node, returning whether it was subscribed
+    pub fn remove(&mut self, node: u32) -> Result<bool, String> {
+        self.connection
+            .execute("DELETE FROM subscribers WHERE node = ?1", params![node])
+            .map(|deleted| deleted > 0)
+            .map_err(|e| format!("Failed to remove subscriber: {}", e))
+    }
+
+    // Apply a command from a node, answering with the reply to send back
+    pub fn handle(&mut self, node: u32, channel: u32, command: SubscriberCommand) -> Result<String, String> {
+        let current = self.get(node)?;
+        let mut preferences = match (&command, current) {
+            (SubscriberCommand::Unsubscribe, None) | (SubscriberCommand::Show, None) => {
+                return Ok("Not subscribed; send \"sub\" to get alerts by direct message".to_string());
+            }
+            (SubscriberCommand::Unsubscribe, Some(_)) => {
+                self.remove(node)?;
+                return Ok("Unsubscribed".to_string());
+            }
+            (SubscriberCommand::Show, Some(preferences)) => return Ok(preferences.summary()),
+            (_, Some(preferences)) => preferences,
+            // Any other setting subscribes the node, for the zone of the channel it wrote on
+            (_, None) => Preferences {
+                channel,
+                zones: vec![channel].into_iter().filter(|zone| *zone != 0).collect(),
+                ..Preferences::default()
+            },
+        };
+
+        match command {
+            SubscriberCommand::Subscribe(zones) => {
+                preferences.channel = channel;
+                if !zones.is_empty() {
+                    preferences.zones = zones;
+                }
+            }
+            SubscriberCommand::Zones(zones) => preferences.zones = zones,
+            SubscriberCommand::Categories(categories) => preferences.categories = categories,
+            SubscriberCommand::Language(language) => preferences.language = Some(language),
+            SubscriberCommand::Quiet(hours) => preferences.quiet_hours = hours,
+            SubscriberCommand::Unsubscribe | SubscriberCommand::Show => {}
+        }
+        self.set(node, &preferences, Utc::now())?;
+        Ok(format!("Subscribed: {}", preferences.summary()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_subscription_commands() {
+        assert_eq!(parse_command("sub"), Some(SubscriberCommand::Subscribe(Vec::new())));
+        assert_eq!(parse_command("SUB 2 4"), Some(SubscriberCommand::Subscribe(vec![2, 4])));
+        assert_eq!(parse_command("unsubscribe"), Some(SubscriberCommand::Unsubscribe));
+        assert_eq!(parse_command("zones all"), Some(SubscriberCommand::Zones(Vec::new())));
+        assert_eq!(parse_command("prefs"), Some(SubscriberCommand::Show));
+        assert_eq!(parse_command("zones"), None);
+        assert_eq!(parse_command("hello there"), None);
+        assert_eq!(parse_command(""), None);
+    }
+
+    #[test]
+    fn parses_preference_commands() {
+        assert_eq!(
+            parse_command("cats missiles,earthQuake general"),
+            Some(SubscriberCommand::Categories(vec![
+                "missiles".to_string(),
+                "earthQuake".to_string(),
+                "general".to_string()
+            ]))
+        );
+        assert_eq!(parse_command("cats all"), Some(SubscriberCommand::Categories(Vec::new())));
+        assert_eq!(parse_command("lang English"), Some(SubscriberCommand::Language("english".to_string())));
+        assert_eq!(parse_command("quiet 22-7"), Some(SubscriberCommand::Quiet(Some((22, 7)))));
+        assert_eq!(parse_command("quiet off"), Some(SubscriberCommand::Quiet(None)));
+        assert_eq!(parse_command("quiet late"), None);
+    }
+
+    #[test]
+    fn quiet_hours_wrap_around_midnight() {
+        let preferences = Preferences {
+            quiet_hours: Some((22, 7)),
+            ..Preferences::default()
+        };
+        assert!(preferences.is_quiet(23));
+        assert!(preferences.is_quiet(3));
+        assert!(!preferences.is_quiet(7));
+        assert!(!preferences.is_quiet(12));
+    }
+}
@@ -0,0 +1,65 @@
+use crate::events::{self, Event};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+// Wait before restarting a failed task, doubled on every failure in a row
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// A task that ran this long before failing is treated as healthy again
+const HEALTHY_RUN: Duration = Duration::from_secs(5 * 60);
+
+// Run a task and restart it with backoff whenever it fails or panics, so one
+// bad component never takes the whole gateway down. A task returning Ok is
+// done and is not restarted.
+pub fn supervise<F, Fut>(name: &'static str, mut start: F) -> JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let started = Instant::now();
+            match tokio::spawn(start()).await {
+                Ok(Ok(())) => {
+                    log::info!("Task {} finished", name);
+                    return;
+                }
+                Ok(Err(e)) => {
+                    log::error!("Task {} failed: {}", name, e);
+                    events::emit(Event::TaskFailed {
+                        task: name.to_string(),
+                        error: e,
+                    });
+                }
+                Err(e) if e.is_panic() => {
+                    let panic = e.into_panic();
+                    let reason = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string());
+                    log::error!("Task {} panicked: {}", name, reason);
+                    events::emit(Event::TaskFailed {
+                        task: name.to_string(),
+                        error: format!("panicked: {}", reason),
+                    });
+                }
+                Err(e) => {
+                    log::warn!("Task {} was cancelled: {}", name, e);
+                    return;
+                }
+            }
+
+            if started.elapsed() >= HEALTHY_RUN {
+                backoff = INITIAL_BACKOFF;
+            }
+            log::info!("Restarting task {} in {:?}", name, backoff);
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    })
+}
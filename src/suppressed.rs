@@ -0,0 +1,129 @@
+use crate::influx;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+// Why part or all of an alert was not sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Reason {
+    // Already sent by this gateway (history feed repeats)
+    Dedup,
+    // Already sent by another gateway of the cluster
+    #[cfg_attr(not(feature = "cluster"), allow(dead_code))]
+    ClusterDedup,
+    Drill,
+    // Outside --radius-km
+    Radius,
+    // Every zone of the city is in --ignore
+    IgnoredZone,
+    // No zone left to send the alert on
+    NoZones,
+    // Dropped rather than wait too long for duty-cycle airtime
+    DutyCycle,
+    // Held back for duty-cycle airtime and dropped once it missed its deadline
+    ExpiredInQueue,
+    // The zone got the alert's cities within --zone-cooldown
+    Cooldown,
+    // A channel dedicated to the category carried it within its min_gap
+    CategoryGap,
+}
+
+impl Reason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Reason::Dedup => "dedup",
+            Reason::ClusterDedup => "cluster_dedup",
+            Reason::Drill => "drill",
+            Reason::Radius => "radius",
+            Reason::IgnoredZone => "ignored_zone",
+            Reason::NoZones => "no_zones",
+            Reason::DutyCycle => "duty_cycle",
+            Reason::ExpiredInQueue => "expired_in_queue",
+            Reason::Cooldown => "cooldown",
+            Reason::CategoryGap => "category_gap",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counters {
+    // Alerts (or, for the duty cycle, messages) affected
+    alerts: u64,
+    // Cities of those alerts that were not sent
+    cities: u64,
+}
+
+// What the gateway chose not to send since it started, so dedup and filter
+// settings can be checked against what came in
+#[derive(Debug, Default)]
+pub struct Suppressed {
+    counters: BTreeMap<(Reason, String), Counters>,
+    // Cities of alerts that resolved to no zone, with how often they came up
+    unresolved: BTreeMap<String, u64>,
+}
+
+impl Suppressed {
+    pub fn record(&mut self, reason: Reason, category: &str, cities: usize) {
+        let counters = self.counters.entry((reason, category.to_string())).or_default();
+        counters.alerts += 1;
+        counters.cities += cities as u64;
+        influx::record_suppressed(reason.as_str(), category, cities);
+    }
+
+    pub fn record_unresolved(&mut self, city: &str) {
+        *self.unresolved.entry(city.to_string()).or_default() += 1;
+    }
+
+    // Totals per reason for a reply over the mesh, e.g. "suppressed dedup 12 radius 3, 1 unresolved city"
+    pub fn summary(&self) -> String {
+        let mut totals: BTreeMap<Reason, u64> = BTreeMap::new();
+        for ((reason, _), counters) in &self.counters {
+            *totals.entry(*reason).or_default() += counters.alerts;
+        }
+        let mut summary = if totals.is_empty() {
+            "nothing suppressed".to_string()
+        } else {
+            let totals: Vec<String> = totals
+                .iter()
+                .map(|(reason, alerts)| format!("{} {}", reason.as_str(), alerts))
+                .collect();
+            format!("suppressed {}", totals.join(" "))
+        };
+        if !self.unresolved.is_empty() {
+            summary.push_str(&format!(", {} unresolved cities", self.unresolved.len()));
+        }
+        summary
+    }
+
+    // Counters per reason and category and the unresolved cities, for the state dump
+    pub fn debug_state(&self) -> Value {
+        let mut reasons: BTreeMap<&str, BTreeMap<&str, Value>> = BTreeMap::new();
+        for ((reason, category), counters) in &self.counters {
+            reasons
+                .entry(reason.as_str())
+                .or_default()
+                .insert(category, json!({ "alerts": counters.alerts, "cities": counters.cities }));
+        }
+        json!({ "reasons": reasons, "unresolved_cities": self.unresolved })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_alerts_per_reason() {
+        let mut suppressed = Suppressed::default();
+        assert_eq!(suppressed.summary(), "nothing suppressed");
+        suppressed.record(Reason::Dedup, "missiles", 3);
+        suppressed.record(Reason::Dedup, "earthQuake", 0);
+        suppressed.record(Reason::Radius, "missiles", 2);
+        suppressed.record_unresolved("Atlantis");
+        suppressed.record_unresolved("Atlantis");
+        assert_eq!(suppressed.summary(), "suppressed dedup 2 radius 1, 1 unresolved cities");
+
+        let state = suppressed.debug_state();
+        assert_eq!(state["reasons"]["dedup"]["missiles"]["cities"], 3);
+        assert_eq!(state["unresolved_cities"]["Atlantis"], 2);
+    }
+}
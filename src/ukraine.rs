@@ -0,0 +1,162 @@
+use crate::api::{record_response, retry_after, AlertResult};
+use crate::country::{AlertsFuture, CountryProfile, Language, Regions};
+use crate::error::RedAlertError;
+use reqwest::header::{AUTHORIZATION, IF_MODIFIED_SINCE, LAST_MODIFIED};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+const UA_ALERTS_API: &str = "https://api.alerts.in.ua/v1/alerts/active.json";
+
+// The API allows a few requests per minute per token; in between the last answer is reused
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize)]
+struct UaAlertsResponse {
+    alerts: Vec<UaAlert>,
+}
+
+// One active alert; raion and hromada alerts also name their oblast
+#[derive(Debug, Deserialize)]
+struct UaAlert {
+    location_title: String,
+    #[serde(default)]
+    location_oblast: Option<String>,
+    alert_type: String,
+}
+
+// Readable name of an alerts.in.ua alert type
+fn alert_type_name(alert_type: &str) -> String {
+    match alert_type {
+        "air_raid" => "Air raid".to_string(),
+        "artillery_shelling" => "Artillery shelling".to_string(),
+        "urban_fights" => "Urban fights".to_string(),
+        "chemical" => "Chemical threat".to_string(),
+        "nuclear" => "Nuclear threat".to_string(),
+        other => other.to_string(),
+    }
+}
+
+// Polls the alerts.in.ua API for Ukrainian alerts. Alerts are reported per
+// oblast, so an area map lists the oblasts (e.g. "Київська область") of each channel.
+pub struct UkraineSource {
+    client: reqwest::Client,
+    token: String,
+    last_poll: Option<Instant>,
+    last_modified: Option<String>,
+    // Oblasts under alert per alert type, from the last answer
+    alerts: BTreeMap<String, Vec<String>>,
+}
+
+impl UkraineSource {
+    pub fn new(token: String) -> Self {
+        UkraineSource {
+            client: reqwest::Client::new(),
+            token,
+            last_poll: None,
+            last_modified: None,
+            alerts: BTreeMap::new(),
+        }
+    }
+
+    // One alert per alert type, covering the oblasts it is active in
+    async fn current_alerts(&mut self) -> Result<Vec<AlertResult>, RedAlertError> {
+        if self.last_poll.is_none_or(|last_poll| last_poll.elapsed() >= MIN_POLL_INTERVAL) {
+            self.last_poll = Some(Instant::now());
+            self.poll().await?;
+        }
+
+        Ok(self
+            .alerts
+            .iter()
+            .map(|(alert_type, oblasts)| AlertResult {
+                alert_type: alert_type_name(alert_type),
+                cities: oblasts.clone(),
+                instructions: None,
+                zones: vec![],
+                alert_date: None,
+                areas: BTreeMap::new(),
+            })
+            .collect())
+    }
+
+    async fn poll(&mut self) -> Result<(), RedAlertError> {
+        let mut request = self
+            .client
+            .get(UA_ALERTS_API)
+            .header(AUTHORIZATION, format!("Bearer {}", self.token));
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let response = request.send().await.map_err(|e| {
+            record_response(UA_ALERTS_API, None, &e.to_string());
+            RedAlertError::ApiUnreachable(format!("Error making request to alerts.in.ua: {}", e))
+        })?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_MODIFIED {
+            record_response(UA_ALERTS_API, Some(304), "");
+            return Ok(());
+        }
+        if !status.is_success() {
+            record_response(UA_ALERTS_API, Some(status.as_u16()), "");
+            if let Some(retry_after) = retry_after(&response) {
+                return Err(RedAlertError::RateLimited { retry_after });
+            }
+            return Err(RedAlertError::ApiUnreachable(format!(
+                "Failed to retrieve alerts from alerts.in.ua: {} {}",
+                status.as_u16(),
+                status.canonical_reason().unwrap_or("Unknown")
+            )));
+        }
+
+        let last_modified = response
+            .headers()
+            .get(LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = response
+            .text()
+            .await
+            .map_err(|e| RedAlertError::ApiUnreachable(format!("Failed to read the response body: {}", e)))?;
+        record_response(UA_ALERTS_API, Some(status.as_u16()), &body);
+
+        let parsed: UaAlertsResponse = serde_json::from_str(&body).map_err(|e| {
+            RedAlertError::ParseError(format!("Failed to parse the alerts.in.ua response: {}. Body was: {}", e, body))
+        })?;
+
+        let mut alerts: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for alert in parsed.alerts {
+            let oblast = alert
+                .location_oblast
+                .filter(|oblast| !oblast.is_empty())
+                .unwrap_or(alert.location_title);
+            let oblasts = alerts.entry(alert.alert_type).or_default();
+            if !oblasts.contains(&oblast) {
+                oblasts.push(oblast);
+            }
+        }
+        self.alerts = alerts;
+        self.last_modified = last_modified;
+        Ok(())
+    }
+}
+
+impl CountryProfile for UkraineSource {
+    fn source(&self) -> &'static str {
+        "alerts_in_ua"
+    }
+
+    fn fetch_alerts(&mut self) -> AlertsFuture<'_> {
+        Box::pin(self.current_alerts())
+    }
+
+    fn regions(&self) -> Regions {
+        Regions::Areas("the oblasts")
+    }
+
+    fn language(&self) -> Language {
+        Language::Local
+    }
+}
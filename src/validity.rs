@@ -0,0 +1,88 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// How long an alert of a category stays relevant after it was issued, in minutes.
+// Sirens are short events; chemical, radiological and tsunami warnings last longer.
+const BUILTIN_VALIDITY: [(&str, u64); 8] = [
+    ("missiles", 10),
+    ("hostileAircraftIntrusion", 10),
+    ("terroristInfiltration", 60),
+    ("earthQuake", 30),
+    ("tsunami", 120),
+    ("hazardousMaterials", 60),
+    ("radiologicalEvent", 180),
+    ("general", 30),
+];
+
+// Parse a `--validity` value of the form CATEGORY=MINUTES
+pub fn parse_validity(value: &str) -> Result<(String, u64), String> {
+    let (category, minutes) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected CATEGORY=MINUTES, got {}", value))?;
+    let minutes = minutes
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid number of minutes in {}", value))?;
+    Ok((category.trim().to_string(), minutes))
+}
+
+// Until when alerts stay valid, so store-and-forward recipients and apps can drop stale ones
+#[derive(Debug)]
+pub struct Validity {
+    default: Duration,
+    per_category: HashMap<String, Duration>,
+}
+
+impl Validity {
+    pub fn new(default_minutes: u64, overrides: &[(String, u64)]) -> Self {
+        let per_category = BUILTIN_VALIDITY
+            .iter()
+            .map(|(category, minutes)| (category.to_string(), *minutes))
+            .chain(overrides.iter().cloned())
+            .map(|(category, minutes)| (category, Duration::from_secs(minutes * 60)))
+            .collect();
+        Validity {
+            default: Duration::from_secs(default_minutes * 60),
+            per_category,
+        }
+    }
+
+    // End of validity of an alert issued at the given time
+    pub fn valid_until(&self, category: &str, issued: DateTime<Utc>) -> DateTime<Utc> {
+        let validity = self.per_category.get(category).copied().unwrap_or(self.default);
+        issued + chrono::Duration::from_std(validity).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn builtin_and_default_validity() {
+        let validity = Validity::new(15, &[]);
+        let issued = Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap();
+        let minutes = |category| (validity.valid_until(category, issued) - issued).num_minutes();
+        assert_eq!(minutes("missiles"), 10);
+        assert_eq!(minutes("tsunami"), 120);
+        assert_eq!(minutes("radiologicalEvent"), 180);
+        // Categories without a built-in validity get the default
+        assert_eq!(minutes("newCategory"), 15);
+    }
+
+    #[test]
+    fn configured_categories_override_builtin() {
+        let overrides = [parse_validity("missiles=5").unwrap(), parse_validity(" drill = 45 ").unwrap()];
+        let validity = Validity::new(15, &overrides);
+        let issued = Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap();
+        let minutes = |category| (validity.valid_until(category, issued) - issued).num_minutes();
+        assert_eq!(minutes("missiles"), 5);
+        assert_eq!(minutes("drill"), 45);
+        assert_eq!(minutes("earthQuake"), 30);
+
+        assert!(parse_validity("missiles").is_err());
+        assert!(parse_validity("missiles=soon").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::events::{self, Event};
+use crate::localtime;
+use chrono::{DateTime, Utc};
+use clap::Args;
+use std::io::IsTerminal;
+use tokio::sync::broadcast::error::RecvError;
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// Print without colors (also turned off by NO_COLOR or when stdout isn't a terminal)
+    #[arg(long)]
+    pub no_color: bool,
+}
+
+// ANSI styles of the console monitor
+const RESET: &str = "\x1b[0m";
+const BOLD_RED: &str = "\x1b[1;31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+const DIM: &str = "\x1b[2m";
+
+struct Printer {
+    color: bool,
+}
+
+impl Printer {
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn print(&self, time: DateTime<Utc>, event: &Event) {
+        let time = localtime::to_local(time).format("%H:%M:%S").to_string();
+        match event {
+            Event::AlertFetched { source, alert_type, cities, instructions } => {
+                println!();
+                println!("{} {} ({})", self.paint(DIM, &time), self.paint(BOLD_RED, &format!("🚨 {}", alert_type)), source);
+                println!("  {}", cities.join(", "));
+                if let Some(instructions) = instructions {
+                    println!("  {}", self.paint(YELLOW, instructions));
+                }
+            }
+            Event::AlertSkipped { alert_type, reason } => {
+                println!("{} {}", self.paint(DIM, &time), self.paint(DIM, &format!("{} skipped: {}", alert_type, reason)));
+            }
+            Event::AlertParsed { zones, .. } => {
+                let zones: Vec<String> = zones.iter().map(u32::to_string).collect();
+                println!("  {} {}", self.paint(CYAN, "zones"), zones.join(", "));
+            }
+            Event::SendObserved { channel, message, .. } => {
+                println!("  {} {}", self.paint(GREEN, &format!("→ ch{}", channel)), message);
+            }
+            Event::AlertCleared { alert_type, channel, cities } => {
+                println!(
+                    "{} {} {}",
+                    self.paint(DIM, &time),
+                    self.paint(GREEN, &format!("✅ {} over on ch{}", alert_type, channel)),
+                    cities.join(", ")
+                );
+            }
+            Event::ApiRateLimited { source, retry_after_secs } => {
+                let text = format!("{} is rate limiting; pausing {}s", source, retry_after_secs);
+                println!("{} {}", self.paint(DIM, &time), self.paint(YELLOW, &text));
+            }
+            Event::TaskFailed { task, error } => {
+                println!("{} {}", self.paint(DIM, &time), self.paint(BOLD_RED, &format!("{} failed: {}", task, error)));
+            }
+            _ => {}
+        }
+    }
+}
+
+// Pretty-print the alerts the gateway sees, where they are routed and what
+// would be sent, as a console alert monitor
+pub async fn print_events(no_color: bool) {
+    let printer = Printer {
+        color: !no_color && std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal(),
+    };
+    let mut events = events::subscribe();
+    println!("Watching for alerts; nothing is transmitted. Press Ctrl+C to stop.");
+    loop {
+        match events.recv().await {
+            Ok((time, event)) => printer.print(DateTime::from_timestamp_millis(time).unwrap_or_else(Utc::now), &event),
+            Err(RecvError::Lagged(missed)) => println!("({} event(s) skipped)", missed),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
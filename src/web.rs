@@ -0,0 +1,418 @@
+use crate::active::{lock_active, SharedActiveAlerts};
+use crate::api::AlertResult;
+use crate::cityindex::CityIndex;
+use crate::debug::StateRequest;
+use crate::multipart::ResendRequest;
+use crate::events::{self, Event};
+use crate::feed::{lock_feed, SharedFeed};
+#[cfg(feature = "sqlite")]
+use crate::meshmqtt::{format_node_id, parse_node_num};
+#[cfg(feature = "sqlite")]
+use crate::subscribers::{lock_subscribers, Preferences, SharedSubscribers};
+use crate::map::AlertMap;
+use crate::{is_drill, MANUAL_SOURCE};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+#[cfg(feature = "sqlite")]
+use axum::routing::put;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot};
+
+// State shared by all HTTP handlers
+#[derive(Clone)]
+pub struct WebState {
+    pub alerts_tx: mpsc::Sender<(&'static str, AlertResult)>,
+    pub state_tx: mpsc::Sender<StateRequest>,
+    pub resend_tx: mpsc::Sender<ResendRequest>,
+    pub token: Option<String>,
+    pub active: SharedActiveAlerts,
+    pub map: Arc<AlertMap>,
+    pub cities: Arc<CityIndex>,
+    #[cfg(feature = "sqlite")]
+    pub subscribers: Option<SharedSubscribers>,
+    pub feed: SharedFeed,
+}
+
+// Body of POST /alerts/manual
+#[derive(Debug, Deserialize)]
+struct ManualAlert {
+    category: String,
+    #[serde(default)]
+    cities: Vec<String>,
+    #[serde(default)]
+    zones: Vec<u32>,
+    message: String,
+}
+
+// Body of POST /messages/resend
+#[derive(Debug, Deserialize)]
+struct Resend {
+    id: String,
+    #[serde(default)]
+    parts: Vec<usize>,
+}
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn api_error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(json!({ "error": message })))
+}
+
+// Check the bearer token; protected endpoints are disabled when no token is configured
+fn authorize(state: &WebState, headers: &HeaderMap) -> Result<(), ApiError> {
+    let expected = state
+        .token
+        .as_deref()
+        .ok_or_else(|| api_error(StatusCode::FORBIDDEN, "no --http-token configured"))?;
+
+    let provided = headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    if provided == Some(expected) {
+        Ok(())
+    } else {
+        Err(api_error(StatusCode::UNAUTHORIZED, "invalid or missing bearer token"))
+    }
+}
+
+// Push a coordinator-issued alert through the normal zone routing and sending
+async fn manual_alert(
+    State(state): State<WebState>,
+    headers: HeaderMap,
+    Json(manual): Json<ManualAlert>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    authorize(&state, &headers)?;
+
+    if manual.cities.is_empty() && manual.zones.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "at least one city or zone is required"));
+    }
+    if manual.message.trim().is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "message must not be empty"));
+    }
+    // These would be accepted here and dropped before sending
+    if is_drill(&manual.category) || manual.category.contains("none") {
+        return Err(api_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "drill, test and \"none\" categories are never sent to the mesh",
+        ));
+    }
+
+    log::info!(
+        "Manual alert received: category {}, cities {:?}, zones {:?}",
+        manual.category,
+        manual.cities,
+        manual.zones
+    );
+
+    let alert = AlertResult {
+        alert_type: manual.category,
+        cities: manual.cities,
+        instructions: Some(manual.message),
+        zones: manual.zones,
+        alert_date: None,
+        areas: BTreeMap::new(),
+    };
+
+    state
+        .alerts_tx
+        .send((MANUAL_SOURCE, alert))
+        .await
+        .map_err(|_| api_error(StatusCode::SERVICE_UNAVAILABLE, "alert pipeline is not running"))?;
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "status": "queued" }))))
+}
+
+// Body of POST /ingest: one normalized alert or a batch of them
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Ingest {
+    Batch(Vec<AlertResult>),
+    One(AlertResult),
+}
+
+// Feed alerts from an external collector into the same routing and sending as the oref feed
+async fn ingest(
+    State(state): State<WebState>,
+    headers: HeaderMap,
+    Json(ingest): Json<Ingest>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    authorize(&state, &headers)?;
+
+    let alerts = match ingest {
+        Ingest::Batch(alerts) => alerts,
+        Ingest::One(alert) => vec![alert],
+    };
+    if alerts.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "no alerts given"));
+    }
+    // Reject the whole batch before queueing any of it
+    for (index, alert) in alerts.iter().enumerate() {
+        if alert.alert_type.trim().is_empty() {
+            return Err(api_error(StatusCode::BAD_REQUEST, &format!("alert {}: alert_type must not be empty", index)));
+        }
+        if alert.cities.is_empty() && alert.zones.is_empty() {
+            return Err(api_error(StatusCode::BAD_REQUEST, &format!("alert {}: at least one city or zone is required", index)));
+        }
+    }
+
+    let count = alerts.len();
+    for alert in alerts {
+        log::info!("Ingested {} alert for cities {:?}, zones {:?}", alert.alert_type, alert.cities, alert.zones);
+        state
+            .alerts_tx
+            .send(("ingest", alert))
+            .await
+            .map_err(|_| api_error(StatusCode::SERVICE_UNAVAILABLE, "alert pipeline is not running"))?;
+    }
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "status": "queued", "alerts": count }))))
+}
+
+// Retransmit parts of a recent split message, e.g. {"id": "a3", "parts": [2]}
+async fn resend_parts(
+    State(state): State<WebState>,
+    headers: HeaderMap,
+    Json(resend): Json<Resend>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    authorize(&state, &headers)?;
+
+    let (reply, queued) = oneshot::channel();
+    let request = ResendRequest {
+        id: resend.id,
+        parts: resend.parts,
+        reply,
+    };
+    state
+        .resend_tx
+        .send(request)
+        .await
+        .map_err(|_| api_error(StatusCode::SERVICE_UNAVAILABLE, "alert pipeline is not running"))?;
+
+    match tokio::time::timeout(Duration::from_secs(10), queued).await {
+        Ok(Ok(Ok(parts))) => Ok((StatusCode::ACCEPTED, Json(json!({ "status": "queued", "parts": parts })))),
+        Ok(Ok(Err(e))) => Err(api_error(StatusCode::NOT_FOUND, &e)),
+        _ => Err(api_error(StatusCode::SERVICE_UNAVAILABLE, "alert loop is busy; try again")),
+    }
+}
+
+// Centroids of the currently alerted cities as a GeoJSON FeatureCollection
+async fn alerts_geojson(State(state): State<WebState>) -> ([(&'static str, &'static str); 1], Json<Value>) {
+    let geojson = lock_active(&state.active).to_geojson();
+    ([("Content-Type", "application/geo+json")], Json(geojson))
+}
+
+// Atom feed of the latest alerts sent, for feed readers and static site generators
+async fn alerts_feed(State(state): State<WebState>) -> ([(&'static str, &'static str); 1], String) {
+    let atom = lock_feed(&state.feed).atom();
+    ([("Content-Type", "application/atom+xml")], atom)
+}
+
+// Live feed of lifecycle events, one JSON object per message, starting with the latest few
+async fn events_socket(upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(stream_events)
+}
+
+async fn send_event(socket: &mut WebSocket, time_unix_ms: i64, event: &Event) -> Result<(), axum::Error> {
+    let time = DateTime::from_timestamp_millis(time_unix_ms).unwrap_or_else(Utc::now);
+    socket.send(Message::Text(events::to_json(time, event).to_string())).await
+}
+
+async fn stream_events(mut socket: WebSocket) {
+    let (recent, mut live) = events::subscribe_with_replay();
+    for (time, event) in &recent {
+        if send_event(&mut socket, *time, event).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            received = live.recv() => match received {
+                Ok((time, event)) => {
+                    if send_event(&mut socket, time, &event).await.is_err() {
+                        return;
+                    }
+                }
+                // A slow client misses events rather than holding the gateway up
+                Err(RecvError::Lagged(missed)) => {
+                    log::warn!("WebSocket event stream fell behind; {} event(s) skipped", missed);
+                }
+                Err(RecvError::Closed) => return,
+            },
+            // Nothing is expected from the client; stop when it goes away
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+// Map of the currently alerted cities and their zones
+async fn alerts_map(State(state): State<WebState>) -> ([(&'static str, &'static str); 1], String) {
+    let active = lock_active(&state.active).snapshot();
+    ([("Content-Type", "image/svg+xml")], state.map.render_svg(&active))
+}
+
+// District, zones, coordinates and shelter time of a city, by Hebrew or English name
+async fn city(State(state): State<WebState>, Path(name): Path<String>) -> Result<Json<Value>, ApiError> {
+    state
+        .cities
+        .describe(&name)
+        .map(Json)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, &format!("unknown city {}", name)))
+}
+
+#[cfg(feature = "sqlite")]
+fn subscriber_store(state: &WebState) -> Result<&SharedSubscribers, ApiError> {
+    state
+        .subscribers
+        .as_ref()
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "no --subscriber-db configured"))
+}
+
+#[cfg(feature = "sqlite")]
+fn subscriber_node(node: &str) -> Result<u32, ApiError> {
+    parse_node_num(node).map_err(|e| api_error(StatusCode::BAD_REQUEST, &e))
+}
+
+#[cfg(feature = "sqlite")]
+fn storage_error(e: String) -> ApiError {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, &e)
+}
+
+// Every node subscribed to direct-message alerts, with its preferences
+#[cfg(feature = "sqlite")]
+async fn list_subscribers(State(state): State<WebState>, headers: HeaderMap) -> Result<Json<Value>, ApiError> {
+    authorize(&state, &headers)?;
+    let subscribers = lock_subscribers(subscriber_store(&state)?).all().map_err(storage_error)?;
+    let subscribers: Vec<Value> = subscribers
+        .into_iter()
+        .map(|(node, preferences)| json!({ "node": format_node_id(node), "preferences": preferences }))
+        .collect();
+    Ok(Json(json!(subscribers)))
+}
+
+#[cfg(feature = "sqlite")]
+async fn get_subscriber(
+    State(state): State<WebState>,
+    headers: HeaderMap,
+    Path(node): Path<String>,
+) -> Result<Json<Preferences>, ApiError> {
+    authorize(&state, &headers)?;
+    let node = subscriber_node(&node)?;
+    lock_subscribers(subscriber_store(&state)?)
+        .get(node)
+        .map_err(storage_error)?
+        .map(Json)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "not subscribed"))
+}
+
+// Subscribe a node or replace its preferences
+#[cfg(feature = "sqlite")]
+async fn put_subscriber(
+    State(state): State<WebState>,
+    headers: HeaderMap,
+    Path(node): Path<String>,
+    Json(preferences): Json<Preferences>,
+) -> Result<Json<Preferences>, ApiError> {
+    authorize(&state, &headers)?;
+    let node = subscriber_node(&node)?;
+    preferences.check().map_err(|e| api_error(StatusCode::BAD_REQUEST, &e))?;
+    lock_subscribers(subscriber_store(&state)?)
+        .set(node, &preferences, Utc::now())
+        .map_err(storage_error)?;
+    log::info!("Subscription of {} set over HTTP", format_node_id(node));
+    Ok(Json(preferences))
+}
+
+#[cfg(feature = "sqlite")]
+async fn delete_subscriber(
+    State(state): State<WebState>,
+    headers: HeaderMap,
+    Path(node): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    authorize(&state, &headers)?;
+    let node = subscriber_node(&node)?;
+    match lock_subscribers(subscriber_store(&state)?).remove(node).map_err(storage_error)? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(api_error(StatusCode::NOT_FOUND, "not subscribed")),
+    }
+}
+
+// Ask the alert loop for a dump of its state
+async fn request_state(state: &WebState) -> Result<Value, ApiError> {
+    let (reply, dump) = oneshot::channel();
+    state
+        .state_tx
+        .send(reply)
+        .await
+        .map_err(|_| api_error(StatusCode::SERVICE_UNAVAILABLE, "alert pipeline is not running"))?;
+
+    // The alert loop answers between alerts; it may be busy sending
+    match tokio::time::timeout(Duration::from_secs(10), dump).await {
+        Ok(Ok(dump)) => Ok(dump),
+        _ => Err(api_error(StatusCode::SERVICE_UNAVAILABLE, "alert loop is busy; try again")),
+    }
+}
+
+// Dump of the gateway's internal state, also written to the log
+async fn debug_state(State(state): State<WebState>, headers: HeaderMap) -> Result<Json<Value>, ApiError> {
+    authorize(&state, &headers)?;
+    request_state(&state).await.map(Json)
+}
+
+// Messages, bytes, airtime, failures and alert-to-send latency per channel
+async fn channel_stats(State(state): State<WebState>) -> Result<Json<Value>, ApiError> {
+    let mut dump = request_state(&state).await?;
+    Ok(Json(dump["channel_stats"].take()))
+}
+
+// Alerts and cities not sent per reason and category, and cities that resolved to no zone
+async fn suppressed_stats(State(state): State<WebState>) -> Result<Json<Value>, ApiError> {
+    let mut dump = request_state(&state).await?;
+    Ok(Json(dump["suppressed"].take()))
+}
+
+// Run the embedded HTTP server until it fails
+pub async fn serve(addr: SocketAddr, state: WebState) -> Result<(), String> {
+    let app = Router::new()
+        .route("/alerts/manual", post(manual_alert))
+        .route("/ingest", post(ingest))
+        .route("/alerts.geojson", get(alerts_geojson))
+        .route("/alerts/map.svg", get(alerts_map))
+        .route("/feed.atom", get(alerts_feed))
+        .route("/cities/:name", get(city));
+    // Subscriptions live in the SQLite subscriber database
+    #[cfg(feature = "sqlite")]
+    let app = app
+        .route("/subscribers", get(list_subscribers))
+        .route("/subscribers/:node", put(put_subscriber).get(get_subscriber).delete(delete_subscriber));
+    let app = app
+        .route("/messages/resend", post(resend_parts))
+        .route("/debug/state", get(debug_state))
+        .route("/stats/channels", get(channel_stats))
+        .route("/stats/suppressed", get(suppressed_stats))
+        .route("/events", get(events_socket))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+    log::info!("HTTP server listening on {}", addr);
+
+    axum::serve(listener, app).await.map_err(|e| e.to_string())
+}
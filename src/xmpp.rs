@@ -0,0 +1,86 @@
+use crate::notify::{Auth, JsonPost, Notification, Notifier};
+use crate::outbox::{Outbox, OutboxSettings};
+use clap::ValueEnum;
+use serde_json::json;
+
+// HTTP API of the XMPP server that messages are sent through
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum XmppApi {
+    /// Prosody's mod_rest
+    Prosody,
+    /// ejabberd's send_message command of mod_http_api
+    Ejabberd,
+}
+
+// Sends alerts to XMPP rooms and addresses through a self-hosted server's HTTP API,
+// for organizations whose emergency communications run on XMPP
+pub struct XmppNotifier {
+    url: String,
+    api: XmppApi,
+    from: Option<String>,
+    // Addresses with whether each is a multi-user chat room
+    recipients: Vec<(String, bool)>,
+    outbox: Outbox,
+}
+
+impl XmppNotifier {
+    pub fn new(
+        url: &str,
+        api: XmppApi,
+        auth: Option<(String, Option<String>)>,
+        from: Option<String>,
+        to: &[String],
+        rooms: &[String],
+        outbox: &OutboxSettings,
+    ) -> Result<Self, String> {
+        if to.is_empty() && rooms.is_empty() {
+            return Err("--xmpp-url needs --xmpp-to or --xmpp-room".to_string());
+        }
+        if api == XmppApi::Ejabberd && from.is_none() {
+            return Err("--xmpp-api ejabberd needs --xmpp-from".to_string());
+        }
+        let delivery = JsonPost::new("the server", auth.map(|(user, password)| Auth::Basic(user, password)))?;
+        let recipients = to
+            .iter()
+            .map(|jid| (jid.clone(), false))
+            .chain(rooms.iter().map(|room| (room.clone(), true)))
+            .collect();
+        Ok(XmppNotifier {
+            url: url.to_string(),
+            api,
+            from,
+            recipients,
+            outbox: Outbox::start("xmpp", outbox, delivery)?,
+        })
+    }
+
+    // Request body sending a message to one address
+    fn request(&self, to: &str, room: bool, body: &str) -> serde_json::Value {
+        let kind = if room { "groupchat" } else { "chat" };
+        match self.api {
+            XmppApi::Prosody => {
+                let mut request = json!({ "kind": "message", "type": kind, "to": to, "body": body });
+                if let Some(from) = &self.from {
+                    request["from"] = json!(from);
+                }
+                request
+            }
+            XmppApi::Ejabberd => json!({
+                "type": kind,
+                "from": self.from.as_deref().unwrap_or_default(),
+                "to": to,
+                "subject": "",
+                "body": body,
+            }),
+        }
+    }
+}
+
+impl Notifier for XmppNotifier {
+    fn notify(&self, notification: &Notification) {
+        let text = notification.text();
+        for (to, room) in &self.recipients {
+            self.outbox.push(&self.url, self.request(to, *room, &text).to_string(), false, notification.valid_until);
+        }
+    }
+}
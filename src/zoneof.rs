@@ -0,0 +1,92 @@
+use crate::cityindex::{CityIndex, NearbyCity};
+use crate::zones::ZoneScheme;
+use clap::Args;
+use serde_json::{json, Value};
+
+#[derive(Args, Debug)]
+pub struct ZoneOfArgs {
+    /// Latitude in decimal degrees, e.g. 31.25
+    #[arg(long, allow_negative_numbers = true)]
+    pub lat: f64,
+
+    /// Longitude in decimal degrees, e.g. 34.79
+    #[arg(long, allow_negative_numbers = true)]
+    pub lon: f64,
+
+    /// Number of nearby cities to list
+    #[arg(long, default_value_t = 3)]
+    pub count: usize,
+
+    /// Print the result as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+// Zones of a city with their names, e.g. "2 (South)"
+fn zone_names(zones: &ZoneScheme, channels: &[u32]) -> String {
+    if channels.is_empty() {
+        return "none".to_string();
+    }
+    channels
+        .iter()
+        .map(|channel| match zones.name_for(*channel) {
+            Some(name) => format!("{} ({})", channel, name),
+            None => channel.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn nearby_json(nearby: &NearbyCity) -> Value {
+    json!({
+        "name": nearby.city.name,
+        "name_en": nearby.city.name_en,
+        "district_en": nearby.city.zone_en,
+        "distance_km": (nearby.distance_km * 100.0).round() / 100.0,
+        "zones": nearby.zones,
+        "shelter_secs": nearby.city.shelter_time().map(|time| time.as_secs()),
+    })
+}
+
+// Resolve a point to the nearest city or area and the zones its alerts go out on
+pub fn run(args: &ZoneOfArgs, cities: &CityIndex, zones: &ZoneScheme) -> Result<(), String> {
+    if !(-90.0..=90.0).contains(&args.lat) || !(-180.0..=180.0).contains(&args.lon) {
+        return Err(format!("{}, {} is not a valid coordinate", args.lat, args.lon));
+    }
+    let nearby = cities.nearest((args.lat, args.lon), args.count.max(1));
+    let Some(nearest) = nearby.first() else {
+        return Err("The city data has no coordinates".to_string());
+    };
+
+    if args.json {
+        let result = json!({
+            "lat": args.lat,
+            "lon": args.lon,
+            "zones": nearest.zones,
+            "nearby": nearby.iter().map(nearby_json).collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&result).map_err(|e| e.to_string())?);
+        return Ok(());
+    }
+
+    let city = nearest.city;
+    println!("Nearest: {} ({}), {:.1} km", city.name, city.name_en, nearest.distance_km);
+    println!("District: {}", city.zone_en);
+    println!("Zones: {}", zone_names(zones, nearest.zones));
+    if let Some(time) = city.shelter_time() {
+        println!("Shelter time: {}s", time.as_secs());
+    }
+    if nearby.len() > 1 {
+        println!("Also nearby:");
+        for other in &nearby[1..] {
+            println!(
+                "  {} ({}), {:.1} km, zones {}",
+                other.city.name,
+                other.city.name_en,
+                other.distance_km,
+                zone_names(zones, other.zones)
+            );
+        }
+    }
+    Ok(())
+}
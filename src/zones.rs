@@ -0,0 +1,419 @@
+use crate::channels::{ChannelNames, ChannelRef};
+use crate::City;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashSet};
+
+// Built-in zones, numbered by the channel they are sent on, with their districts (zone_en)
+const BUILTIN_ZONES: [(u32, &str, &[&str]); 7] = [
+    // Zone 1: Northern (average time: 24.65 seconds)
+    (
+        1,
+        "Northern",
+        &[
+            "Upper Galilee",
+            "Confrontation Line",
+            "North Golan",
+            "South Golan",
+            "Center Galilee",
+        ],
+    ),
+    // Zone 2: SouthCoast (average time: 51.23 seconds)
+    (2, "SouthCoast", &["Gaza Envelope", "West Lachish", "Lachish", "HaShfela"]),
+    // Zone 3: InterNorth (average time: 65.07 seconds)
+    (3, "InterNorth", &["Lower Galilee", "Beit She'an Valley", "HaAmakim", "Wadi Ara"]),
+    // Zone 4: DesertRegion (average time: 67.70 seconds)
+    (
+        4,
+        "DesertRegion",
+        &["West Negev", "Center Negev", "South Negev", "Dead Sea", "Arava", "Eilat"],
+    ),
+    // Zone 5: NorthCoast (average time: 72.81 seconds)
+    (5, "NorthCoast", &["HaMifratz", "HaCarmel", "Menashe"]),
+    // Zone 6: CentralInter (average time: 86.87 seconds)
+    (
+        6,
+        "CentralInter",
+        &["Shomron", "Jerusalem", "Yehuda", "Shfelat Yehuda", "Bika'a"],
+    ),
+    // Zone 7: CentralCoast (average time: 90.00 seconds)
+    (7, "CentralCoast", &["Sharon", "Yarkon", "Dan"]),
+];
+
+// A zone as written in the config file
+#[derive(Debug, Clone, Deserialize)]
+pub struct ZoneConfig {
+    pub channel: ChannelRef,
+    #[serde(default)]
+    pub name: Option<String>,
+    pub districts: Vec<String>,
+}
+
+// A [[route]] rule of the config file: extra (or replacement) channels for
+// specific cities or whole districts, on top of the zones they belong to
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteConfig {
+    // Cities by Hebrew or English name
+    #[serde(default)]
+    pub cities: Vec<String>,
+    // Districts (zone_en values)
+    #[serde(default)]
+    pub districts: Vec<String>,
+    pub channels: Vec<ChannelRef>,
+    // Name of a dedicated channel, for messages and MQTT topics
+    #[serde(default)]
+    pub name: Option<String>,
+    // Send only on these channels instead of adding them to the zones
+    #[serde(default)]
+    pub replace: bool,
+}
+
+// A resolved [[route]] rule
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub cities: Vec<String>,
+    pub districts: Vec<String>,
+    pub channels: Vec<u32>,
+    pub name: Option<String>,
+    pub replace: bool,
+}
+
+impl Route {
+    fn matches_district(&self, zone_en: &str) -> bool {
+        self.districts.iter().any(|district| district.eq_ignore_ascii_case(zone_en))
+    }
+
+    fn matches(&self, city: &City) -> bool {
+        self.matches_district(&city.zone_en)
+            || self
+                .cities
+                .iter()
+                .any(|name| *name == city.name || name.eq_ignore_ascii_case(&city.name_en))
+    }
+}
+
+// Zone map file given with --zone-map
+#[derive(Debug, Deserialize)]
+struct ZoneMapFile {
+    zones: Vec<ZoneConfig>,
+}
+
+// Read the zones of a YAML zone map file
+pub fn load_zone_map(path: &str) -> Result<Vec<ZoneConfig>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read zone map {}: {}", path, e))?;
+    let file: ZoneMapFile =
+        serde_yaml::from_str(&contents).map_err(|e| format!("Failed to parse zone map {}: {}", path, e))?;
+    Ok(file.zones)
+}
+
+// A group of districts alerted together on one channel
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub channel: u32,
+    pub name: String,
+    pub districts: Vec<String>,
+}
+
+// The set of zones alerts are routed by; zones may overlap
+#[derive(Debug, Clone)]
+pub struct ZoneScheme {
+    zones: Vec<Zone>,
+    // Overrides applied after the district lookup, in config order
+    routes: Vec<Route>,
+}
+
+// Built-in zones every tsunami alert is sent to
+const BUILTIN_TSUNAMI_ZONES: [u32; 3] = [2, 4, 6];
+
+impl ZoneScheme {
+    // The seven zones the gateway has always used
+    pub fn builtin() -> Self {
+        let zones = BUILTIN_ZONES
+            .iter()
+            .map(|(channel, name, districts)| Zone {
+                channel: *channel,
+                name: name.to_string(),
+                districts: districts.iter().map(|district| district.to_string()).collect(),
+            })
+            .collect();
+        ZoneScheme { zones, routes: Vec::new() }
+    }
+
+    // A custom scheme from config; channel 0 is reserved for all-zone alerts
+    pub fn from_config(configs: Vec<ZoneConfig>, names: &mut ChannelNames) -> Result<Self, String> {
+        if configs.is_empty() {
+            return Err("A custom zone scheme needs at least one zone".to_string());
+        }
+
+        let mut channels = BTreeSet::new();
+        let mut zones = Vec::new();
+        for config in configs {
+            let channel = names.resolve(&config.channel)?;
+            if channel == 0 {
+                return Err("Channel 0 is reserved for alerts covering every zone".to_string());
+            }
+            if !channels.insert(channel) {
+                return Err(format!("More than one zone uses channel {}", channel));
+            }
+            zones.push(Zone {
+                channel,
+                name: config.name.unwrap_or_else(|| match &config.channel {
+                    ChannelRef::Name(name) => name.clone(),
+                    ChannelRef::Index(_) => format!("Zone {}", channel),
+                }),
+                districts: config.districts,
+            });
+        }
+        Ok(ZoneScheme { zones, routes: Vec::new() })
+    }
+
+    // Add [[route]] rules overriding the zones of specific cities or districts
+    pub fn with_routes(mut self, configs: Vec<RouteConfig>, names: &mut ChannelNames) -> Result<Self, String> {
+        for (index, config) in configs.into_iter().enumerate() {
+            let label = config.name.clone().unwrap_or_else(|| format!("route {}", index + 1));
+            if config.cities.is_empty() && config.districts.is_empty() {
+                return Err(format!("{} names no cities or districts", label));
+            }
+            if config.channels.is_empty() {
+                return Err(format!("{} has no channels", label));
+            }
+            let mut channels = BTreeSet::new();
+            for channel in &config.channels {
+                let channel = names.resolve(channel).map_err(|e| format!("{} ({})", e, label))?;
+                if channel == 0 {
+                    return Err(format!("Channel 0 is reserved for alerts covering every zone ({})", label));
+                }
+                channels.insert(channel);
+            }
+            self.routes.push(Route {
+                cities: config.cities,
+                districts: config.districts,
+                channels: channels.into_iter().collect(),
+                name: config.name,
+                replace: config.replace,
+            });
+        }
+        Ok(self)
+    }
+
+    // Every zone containing the district (zone_en), in channel order
+    pub fn zones_for_district(&self, zone_en: &str) -> Vec<u32> {
+        let mut channels: Vec<u32> = self
+            .zones
+            .iter()
+            .filter(|zone| zone.districts.iter().any(|district| district == zone_en))
+            .map(|zone| zone.channel)
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    // Channels of a city: the zones of its district, then the [[route]] rules matching it
+    pub fn zones_for_city(&self, city: &City) -> Vec<u32> {
+        self.apply_routes(self.zones_for_district(&city.zone_en), |route| route.matches(city))
+    }
+
+    // Channels of a whole district: its zones, then the [[route]] rules naming the district
+    pub fn zones_for_area(&self, zone_en: &str) -> Vec<u32> {
+        self.apply_routes(self.zones_for_district(zone_en), |route| route.matches_district(zone_en))
+    }
+
+    // Zones covering the districts of the built-in tsunami zones, so a custom
+    // scheme sends tsunami alerts to its own zones for the same coast
+    pub fn tsunami_zones(&self) -> Vec<u32> {
+        let mut channels: Vec<u32> = BUILTIN_ZONES
+            .iter()
+            .filter(|(channel, _, _)| BUILTIN_TSUNAMI_ZONES.contains(channel))
+            .flat_map(|(_, _, districts)| districts.iter())
+            .flat_map(|district| self.zones_for_area(district))
+            .collect();
+        channels.sort();
+        channels.dedup();
+        channels
+    }
+
+    fn apply_routes(&self, mut channels: Vec<u32>, matches: impl Fn(&Route) -> bool) -> Vec<u32> {
+        for route in self.routes.iter().filter(|route| matches(route)) {
+            if route.replace {
+                channels.clear();
+            }
+            channels.extend(&route.channels);
+        }
+        channels.sort();
+        channels.dedup();
+        channels
+    }
+
+    // Route rules naming cities or districts that aren't in the city data
+    pub fn unmatched_routes(&self, cities: &[City]) -> Vec<String> {
+        let mut unmatched = Vec::new();
+        for route in &self.routes {
+            for name in &route.cities {
+                if !cities.iter().any(|city| *name == city.name || name.eq_ignore_ascii_case(&city.name_en)) {
+                    unmatched.push(format!("city \"{}\"", name));
+                }
+            }
+            for district in &route.districts {
+                if !cities.iter().any(|city| district.eq_ignore_ascii_case(&city.zone_en)) {
+                    unmatched.push(format!("district \"{}\"", district));
+                }
+            }
+        }
+        unmatched
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    // Channels of all zones and routes, sorted
+    pub fn channels(&self) -> Vec<u32> {
+        let mut channels: Vec<u32> = self.zones.iter().map(|zone| zone.channel).collect();
+        channels.extend(self.routes.iter().flat_map(|route| route.channels.iter().copied()));
+        channels.sort();
+        channels.dedup();
+        channels
+    }
+
+    // Whether the channels alerted cover every zone that isn't ignored; [[route]]
+    // channels are extras on top of the zones and don't count
+    pub fn all_zones_in(&self, alerted: &[u32], ignored: &HashSet<u32>) -> bool {
+        self.zones
+            .iter()
+            .map(|zone| zone.channel)
+            .filter(|channel| !ignored.contains(channel))
+            .all(|channel| alerted.contains(&channel))
+    }
+
+    // Name of the zone (or named route) sent on the channel
+    pub fn name_for(&self, channel: u32) -> Option<&str> {
+        self.zones
+            .iter()
+            .find(|zone| zone.channel == channel)
+            .map(|zone| zone.name.as_str())
+            .or_else(|| {
+                self.routes
+                    .iter()
+                    .find(|route| route.channels.contains(&channel))
+                    .and_then(|route| route.name.as_deref())
+            })
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meshmqtt::MeshChannel;
+
+    fn names() -> ChannelNames {
+        ChannelNames::from_mesh_channels(&[
+            (1, MeshChannel::new("North", "AQ==").unwrap()),
+            (2, MeshChannel::new("South", "AQ==").unwrap()),
+            (5, MeshChannel::new("Haifa", "AQ==").unwrap()),
+        ])
+    }
+
+    fn zone(channel: ChannelRef, districts: &[&str]) -> ZoneConfig {
+        ZoneConfig {
+            channel,
+            name: None,
+            districts: districts.iter().map(|district| district.to_string()).collect(),
+        }
+    }
+
+    fn route(cities: &[&str], districts: &[&str], channels: Vec<ChannelRef>, replace: bool) -> RouteConfig {
+        RouteConfig {
+            cities: cities.iter().map(|city| city.to_string()).collect(),
+            districts: districts.iter().map(|district| district.to_string()).collect(),
+            channels,
+            name: None,
+            replace,
+        }
+    }
+
+    fn city(name_en: &str, zone_en: &str) -> City {
+        serde_json::from_value(serde_json::json!({ "name": name_en, "name_en": name_en, "zone_en": zone_en })).unwrap()
+    }
+
+    fn custom() -> ZoneScheme {
+        ZoneScheme::from_config(
+            vec![
+                zone(ChannelRef::Name("North".to_string()), &["Upper Galilee", "HaMifratz"]),
+                zone(ChannelRef::Index(3), &["Gaza Envelope", "Dan"]),
+                zone(ChannelRef::Name("south".to_string()), &["West Negev", "Gaza Envelope"]),
+            ],
+            &mut names(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn custom_scheme_resolves_names_and_overlaps() {
+        let scheme = custom();
+        assert_eq!(scheme.name_for(1), Some("North"));
+        assert_eq!(scheme.name_for(3), Some("Zone 3"));
+        assert_eq!(scheme.zones_for_district("Gaza Envelope"), vec![2, 3]);
+        assert_eq!(scheme.zones_for_district("Sharon"), Vec::<u32>::new());
+        assert_eq!(scheme.channels(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn bad_configs_are_rejected() {
+        assert!(ZoneScheme::from_config(vec![], &mut names()).is_err());
+        assert!(ZoneScheme::from_config(vec![zone(ChannelRef::Index(0), &["Dan"])], &mut names()).is_err());
+        let duplicate = vec![
+            zone(ChannelRef::Index(1), &["Dan"]),
+            zone(ChannelRef::Name("North".to_string()), &["Sharon"]),
+        ];
+        assert!(ZoneScheme::from_config(duplicate, &mut names()).is_err());
+        let unknown = vec![zone(ChannelRef::Name("Nowhere".to_string()), &["Dan"])];
+        assert!(ZoneScheme::from_config(unknown, &mut names()).is_err());
+
+        let with_route = |route| ZoneScheme::builtin().with_routes(vec![route], &mut names());
+        assert!(with_route(route(&[], &[], vec![ChannelRef::Index(8)], false)).is_err());
+        assert!(with_route(route(&["Eilat"], &[], vec![], false)).is_err());
+        assert!(with_route(route(&["Eilat"], &[], vec![ChannelRef::Index(0)], false)).is_err());
+    }
+
+    #[test]
+    fn routes_add_to_or_replace_zones() {
+        let scheme = custom()
+            .with_routes(
+                vec![
+                    route(&["Haifa"], &[], vec![ChannelRef::Name("Haifa".to_string())], false),
+                    route(&[], &["dan"], vec![ChannelRef::Index(7)], true),
+                ],
+                &mut names(),
+            )
+            .unwrap();
+        assert_eq!(scheme.zones_for_city(&city("Haifa", "HaMifratz")), vec![1, 5]);
+        assert_eq!(scheme.zones_for_city(&city("Akko", "HaMifratz")), vec![1]);
+        assert_eq!(scheme.zones_for_city(&city("Tel Aviv", "Dan")), vec![7]);
+        assert_eq!(scheme.zones_for_area("Dan"), vec![7]);
+        // Route channels are listed, but only the zones make up the scheme
+        assert_eq!(scheme.channels(), vec![1, 2, 3, 5, 7]);
+        assert_eq!(scheme.zones().len(), 3);
+        assert_eq!(scheme.unmatched_routes(&[city("Akko", "HaMifratz")]), vec!["city \"Haifa\"", "district \"dan\""]);
+    }
+
+    #[test]
+    fn all_zones_ignores_route_channels() {
+        let scheme = custom()
+            .with_routes(vec![route(&["Haifa"], &[], vec![ChannelRef::Index(9)], false)], &mut names())
+            .unwrap();
+        let none = HashSet::new();
+        assert!(scheme.all_zones_in(&[1, 2, 3], &none));
+        assert!(!scheme.all_zones_in(&[1, 3, 9], &none));
+        assert!(scheme.all_zones_in(&[1, 3], &HashSet::from([2])));
+    }
+
+    #[test]
+    fn tsunami_zones_follow_the_coast() {
+        assert_eq!(ZoneScheme::builtin().tsunami_zones(), BUILTIN_TSUNAMI_ZONES.to_vec());
+        // Gaza Envelope and West Negev are on the built-in tsunami coast, Upper Galilee isn't
+        assert_eq!(custom().tsunami_zones(), vec![2, 3]);
+    }
+}